@@ -0,0 +1,87 @@
+//! Compares the cost of verifying chunk checksums while reading one large lz4
+//! entry, without checksums, with CRC-32 and with XXH3. lz4 decompresses fast
+//! enough for the checksum to show up in the throughput. Run with
+//! `cargo bench --bench checksums`, the entry size in MiB can be set with
+//! `ATAF_BENCH_MIB` (defaults to 256).
+
+mod common;
+
+use ataf::{
+    archive::{
+        read::Archive,
+        write::{ArchiveOptions, ArchiveWriter},
+    },
+    compression::{Lz4Compressor, Lz4Decompressor},
+    spec::{ArchiveEntryHeader, ChunkChecksum},
+};
+use std::{
+    io::{BufReader, BufWriter},
+    time::Instant,
+};
+
+const CHUNK_SIZE: u32 = 1024 * 1024;
+const THREADS: usize = 4;
+
+fn temp_archive(data: &[u8], checksum: ChunkChecksum) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "ataf-bench-checksums-{:?}-{}",
+        checksum,
+        std::process::id()
+    ));
+    let file = BufWriter::new(std::fs::File::create(&path).unwrap());
+
+    let mut writer = ArchiveWriter::with_options(
+        file,
+        Box::new(Lz4Compressor::new(THREADS, 1).unwrap()),
+        CHUNK_SIZE,
+        ArchiveOptions {
+            checksum,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let entry = ArchiveEntryHeader::file("large.bin", data.len() as u64);
+    writer.write_entry(entry, data).unwrap();
+    writer.finish().unwrap();
+
+    path
+}
+
+fn main() {
+    let mib = std::env::var("ATAF_BENCH_MIB")
+        .ok()
+        .and_then(|mib| mib.parse::<usize>().ok())
+        .unwrap_or(256);
+
+    let data = common::compressible_data(mib * 1024 * 1024);
+
+    println!("{:<8} {:>12} {:>12}", "checksum", "ms", "MiB/s");
+    for (name, checksum) in [
+        ("none", ChunkChecksum::None),
+        ("crc32", ChunkChecksum::Crc32),
+        ("xxh3", ChunkChecksum::Xxh3),
+    ] {
+        let path = temp_archive(&data, checksum);
+
+        let started = Instant::now();
+
+        let file = std::fs::File::open(&path).unwrap();
+        let mut archive = Archive::new(BufReader::new(file));
+        let mut entries = archive
+            .entries(Box::new(Lz4Decompressor::new(THREADS).unwrap()))
+            .unwrap();
+        let mut entry = entries.next_entry().unwrap().unwrap();
+        let read = std::io::copy(&mut entry, &mut std::io::sink()).unwrap();
+        assert_eq!(read, data.len() as u64);
+
+        let elapsed = started.elapsed().as_secs_f64();
+        println!(
+            "{:<8} {:>12.1} {:>12.1}",
+            name,
+            elapsed * 1000.0,
+            mib as f64 / elapsed
+        );
+
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,16 @@
+//! Helpers shared by the benchmarks, included with `mod common;`.
+
+/// `len` bytes that are compressible but not trivially so, like typical text
+/// or binaries. The same bytes on every call.
+pub fn compressible_data(len: usize) -> Vec<u8> {
+    let mut state = 0x2545f491u32;
+
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            b"abcdefghijklmnop"[(state % 16) as usize]
+        })
+        .collect()
+}
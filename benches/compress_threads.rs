@@ -0,0 +1,78 @@
+//! Measures compressing one large entry at various thread counts. Run with
+//! `cargo bench --bench compress_threads`, the input size in MiB can be set
+//! with `ATAF_BENCH_MIB` (defaults to 4096).
+
+mod common;
+
+use ataf::{archive::write::ArchiveWriter, compression::Compressor, spec::ArchiveEntryHeader};
+use std::{
+    io::{BufReader, Write},
+    time::Instant,
+};
+
+const CHUNK_SIZE: u32 = 1024 * 1024;
+
+type BenchCompressor = Box<dyn Compressor<std::io::Sink, BufReader<std::fs::File>>>;
+type Codec = (&'static str, fn(usize) -> BenchCompressor);
+
+fn temp_file(size: u64) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("ataf-bench-{}", std::process::id()));
+    let mut file = std::io::BufWriter::new(std::fs::File::create(&path).unwrap());
+
+    let block = common::compressible_data(CHUNK_SIZE as usize);
+    for _ in 0..size / CHUNK_SIZE as u64 {
+        file.write_all(&block).unwrap();
+    }
+
+    path
+}
+
+fn main() {
+    let size = std::env::var("ATAF_BENCH_MIB")
+        .ok()
+        .and_then(|mib| mib.parse::<u64>().ok())
+        .unwrap_or(4096)
+        * 1024
+        * 1024;
+    let path = temp_file(size);
+
+    let codecs: Vec<Codec> = vec![
+        #[cfg(feature = "flate2")]
+        ("flate2", |threads| {
+            Box::new(
+                ataf::compression::Flate2Compressor::new(
+                    threads,
+                    ataf::compression::flate2::Compression::fast(),
+                )
+                .unwrap(),
+            )
+        }),
+        #[cfg(feature = "lz4")]
+        ("lz4", |threads| {
+            Box::new(ataf::compression::Lz4Compressor::new(threads, 1).unwrap())
+        }),
+    ];
+
+    println!("{:<8} {:>8} {:>12}", "codec", "threads", "MiB/s");
+    for (name, compressor) in codecs {
+        for threads in [1, 2, 4, 8, 16] {
+            let entry = ArchiveEntryHeader::file("large", size);
+
+            let started = Instant::now();
+            ArchiveWriter::new(std::io::sink(), compressor(threads), CHUNK_SIZE)
+                .unwrap()
+                .write_entry(entry, BufReader::new(std::fs::File::open(&path).unwrap()))
+                .unwrap();
+            let elapsed = started.elapsed();
+
+            println!(
+                "{:<8} {:>8} {:>12.0}",
+                name,
+                threads,
+                size as f64 / (1024.0 * 1024.0) / elapsed.as_secs_f64()
+            );
+        }
+    }
+
+    std::fs::remove_file(path).ok();
+}
@@ -0,0 +1,86 @@
+//! Compares reading one large file with read calls against reading it through
+//! a memory map, stored and with lz4. The file is read once first, so both
+//! start from a warm page cache. Run with `cargo bench --bench mmap_input`, the
+//! file size in MiB can be set with `ATAF_BENCH_MIB` (defaults to 4096).
+
+mod common;
+
+use ataf::{
+    archive::{mmap::MappedFile, write::ArchiveWriter},
+    compression::Compressor,
+    spec::ArchiveEntryHeader,
+};
+use std::{
+    io::{Read, Write},
+    time::Instant,
+};
+
+const CHUNK_SIZE: u32 = 1024 * 1024;
+#[cfg(feature = "lz4")]
+const THREADS: usize = 4;
+
+type BenchCompressor = Box<dyn Compressor<std::io::Sink, Box<dyn Read>>>;
+type Codec = (&'static str, fn() -> BenchCompressor);
+
+fn temp_file(size: u64) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("ataf-bench-mmap-{}", std::process::id()));
+    let mut file = std::io::BufWriter::new(std::fs::File::create(&path).unwrap());
+
+    let block = common::compressible_data(CHUNK_SIZE as usize);
+    for _ in 0..size / CHUNK_SIZE as u64 {
+        file.write_all(&block).unwrap();
+    }
+
+    path
+}
+
+fn main() {
+    let size = std::env::var("ATAF_BENCH_MIB")
+        .ok()
+        .and_then(|mib| mib.parse::<u64>().ok())
+        .unwrap_or(4096)
+        * 1024
+        * 1024;
+    let path = temp_file(size);
+    std::io::copy(
+        &mut std::fs::File::open(&path).unwrap(),
+        &mut std::io::sink(),
+    )
+    .unwrap();
+
+    let codecs: Vec<Codec> = vec![
+        ("none", || Box::new(ataf::compression::NoCompressor::new())),
+        #[cfg(feature = "lz4")]
+        ("lz4", || {
+            Box::new(ataf::compression::Lz4Compressor::new(THREADS, 1).unwrap())
+        }),
+    ];
+
+    println!("{:<8} {:<8} {:>12}", "codec", "input", "MiB/s");
+    for (name, compressor) in codecs {
+        for mapped in [false, true] {
+            let entry = ArchiveEntryHeader::file("large", size);
+
+            let started = Instant::now();
+            let file = std::fs::File::open(&path).unwrap();
+            let input: Box<dyn Read> = match mapped {
+                true => Box::new(MappedFile::map(file, size).unwrap()),
+                false => Box::new(file),
+            };
+            ArchiveWriter::new(std::io::sink(), compressor(), CHUNK_SIZE)
+                .unwrap()
+                .write_entry(entry, input)
+                .unwrap();
+            let elapsed = started.elapsed();
+
+            println!(
+                "{:<8} {:<8} {:>12.0}",
+                name,
+                if mapped { "mmap" } else { "read" },
+                size as f64 / (1024.0 * 1024.0) / elapsed.as_secs_f64()
+            );
+        }
+    }
+
+    std::fs::remove_file(path).ok();
+}
@@ -0,0 +1,120 @@
+//! Measures throughput and peak heap use of compressing one large entry for
+//! various in-flight chunk limits and thread counts. Peak memory follows the
+//! limit, not the thread count. Run with `cargo bench --bench pipeline_memory`,
+//! the input size in MiB can be set with `ATAF_BENCH_MIB` (defaults to 512).
+
+mod common;
+
+use ataf::{archive::write::ArchiveWriter, compression::Compressor, spec::ArchiveEntryHeader};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    io::{BufReader, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+const CHUNK_SIZE: u32 = 1024 * 1024;
+
+/// Tracks the bytes allocated right now and the most ever allocated at once.
+struct PeakAllocator;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(current, Ordering::Relaxed);
+        }
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAllocator = PeakAllocator;
+
+type BenchCompressor = Box<dyn Compressor<std::io::Sink, BufReader<std::fs::File>>>;
+type Codec = (&'static str, fn(usize) -> BenchCompressor);
+
+fn temp_file(size: u64) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("ataf-bench-pipeline-{}", std::process::id()));
+    let mut file = std::io::BufWriter::new(std::fs::File::create(&path).unwrap());
+
+    let block = common::compressible_data(CHUNK_SIZE as usize);
+    for _ in 0..size / CHUNK_SIZE as u64 {
+        file.write_all(&block).unwrap();
+    }
+
+    path
+}
+
+fn main() {
+    let size = std::env::var("ATAF_BENCH_MIB")
+        .ok()
+        .and_then(|mib| mib.parse::<u64>().ok())
+        .unwrap_or(512)
+        * 1024
+        * 1024;
+    let path = temp_file(size);
+
+    let codecs: Vec<Codec> = vec![
+        #[cfg(feature = "flate2")]
+        ("flate2", |threads| {
+            Box::new(
+                ataf::compression::Flate2Compressor::new(
+                    threads,
+                    ataf::compression::flate2::Compression::fast(),
+                )
+                .unwrap(),
+            )
+        }),
+        #[cfg(feature = "lz4")]
+        ("lz4", |threads| {
+            Box::new(ataf::compression::Lz4Compressor::new(threads, 1).unwrap())
+        }),
+    ];
+
+    println!(
+        "{:<8} {:>8} {:>10} {:>12} {:>14}",
+        "codec", "threads", "in flight", "MiB/s", "peak heap MiB"
+    );
+    for (name, compressor) in codecs {
+        for threads in [2, 16] {
+            for in_flight in [1, 2, 4, 8, 32] {
+                let entry = ArchiveEntryHeader::file("large", size);
+
+                let mut writer =
+                    ArchiveWriter::new(std::io::sink(), compressor(threads), CHUNK_SIZE).unwrap();
+                writer.set_in_flight_chunks(in_flight);
+
+                let baseline = CURRENT.load(Ordering::Relaxed);
+                PEAK.store(baseline, Ordering::Relaxed);
+                let started = Instant::now();
+                writer
+                    .write_entry(entry, BufReader::new(std::fs::File::open(&path).unwrap()))
+                    .unwrap();
+                let elapsed = started.elapsed();
+                let peak = PEAK.load(Ordering::Relaxed) - baseline;
+
+                println!(
+                    "{:<8} {:>8} {:>10} {:>12.0} {:>14.1}",
+                    name,
+                    threads,
+                    in_flight,
+                    size as f64 / (1024.0 * 1024.0) / elapsed.as_secs_f64(),
+                    peak as f64 / (1024.0 * 1024.0)
+                );
+            }
+        }
+    }
+
+    std::fs::remove_file(path).ok();
+}
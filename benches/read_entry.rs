@@ -0,0 +1,77 @@
+//! Measures reading one large flate2 entry through `Read` in 8 KiB calls, the
+//! way `io::copy` and most callers consume entries. Run with
+//! `cargo bench --bench read_entry`, the entry size in MiB can be set with
+//! `ATAF_BENCH_MIB` (defaults to 256).
+
+mod common;
+
+use ataf::{
+    archive::{read::Archive, write::ArchiveWriter},
+    compression::{Flate2Compressor, Flate2Decompressor, flate2::Compression},
+    spec::ArchiveEntryHeader,
+};
+use std::{
+    io::{BufReader, BufWriter, Read},
+    time::Instant,
+};
+
+const CHUNK_SIZE: u32 = 1024 * 1024;
+const READ_SIZE: usize = 8 * 1024;
+const THREADS: usize = 4;
+
+fn temp_archive(size: usize) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("ataf-bench-read-{}", std::process::id()));
+    let file = BufWriter::new(std::fs::File::create(&path).unwrap());
+
+    let data = common::compressible_data(size);
+
+    let mut writer = ArchiveWriter::new(
+        file,
+        Box::new(Flate2Compressor::new(THREADS, Compression::fast()).unwrap()),
+        CHUNK_SIZE,
+    )
+    .unwrap();
+    let entry = ArchiveEntryHeader::file("large.bin", data.len() as u64);
+    writer.write_entry(entry, &data[..]).unwrap();
+    writer.finish().unwrap();
+
+    path
+}
+
+fn main() {
+    let mib = std::env::var("ATAF_BENCH_MIB")
+        .ok()
+        .and_then(|mib| mib.parse::<usize>().ok())
+        .unwrap_or(256);
+    let path = temp_archive(mib * 1024 * 1024);
+
+    let started = Instant::now();
+
+    let file = std::fs::File::open(&path).unwrap();
+    let mut archive = Archive::new(BufReader::new(file));
+    let mut entries = archive
+        .entries(Box::new(Flate2Decompressor::new(THREADS).unwrap()))
+        .unwrap();
+    let mut entry = entries.next_entry().unwrap().unwrap();
+
+    let mut buf = [0; READ_SIZE];
+    let mut read = 0u64;
+    loop {
+        match entry.read(&mut buf).unwrap() {
+            0 => break,
+            n => read += n as u64,
+        }
+    }
+    assert_eq!(read, (mib * 1024 * 1024) as u64);
+
+    let elapsed = started.elapsed().as_secs_f64();
+    println!("{:<8} {:>12} {:>12}", "read", "ms", "MiB/s");
+    println!(
+        "{:<8} {:>12.1} {:>12.1}",
+        "8 KiB",
+        elapsed * 1000.0,
+        mib as f64 / elapsed
+    );
+
+    std::fs::remove_file(path).ok();
+}
@@ -0,0 +1,133 @@
+//! Compares filling the compressors' chunk buffers with one `read_vectored`
+//! call per batch against plain `read` calls per chunk, across reader types and
+//! chunk counts. Run with `cargo bench --bench read_strategies`.
+
+use std::{
+    io::{BufReader, IoSliceMut, Read, Write},
+    time::{Duration, Instant},
+};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const TOTAL_SIZE: usize = 256 * 1024 * 1024;
+
+/// Fills the buffers with as few `read_vectored` calls as possible.
+fn fill_vectored(input: &mut dyn Read, buffers: &mut [Vec<u8>]) -> std::io::Result<usize> {
+    let mut slices: Vec<IoSliceMut> = buffers
+        .iter_mut()
+        .map(|buffer| IoSliceMut::new(buffer))
+        .collect();
+    let mut slices = &mut slices[..];
+    let mut total = 0;
+
+    while !slices.is_empty() {
+        let n = input.read_vectored(slices)?;
+        if n == 0 {
+            break;
+        }
+
+        total += n;
+        IoSliceMut::advance_slices(&mut slices, n);
+    }
+
+    Ok(total)
+}
+
+/// Fills the buffers one after another with plain `read` calls.
+fn fill_sequential(input: &mut dyn Read, buffers: &mut [Vec<u8>]) -> std::io::Result<usize> {
+    let mut total = 0;
+
+    for buffer in buffers {
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match input.read(&mut buffer[filled..])? {
+                0 => return Ok(total + filled),
+                n => filled += n,
+            }
+        }
+
+        total += filled;
+    }
+
+    Ok(total)
+}
+
+type Fill = fn(&mut dyn Read, &mut [Vec<u8>]) -> std::io::Result<usize>;
+
+fn run(mut input: impl Read, chunks: usize, fill: Fill) -> Duration {
+    let mut buffers = vec![vec![0; CHUNK_SIZE]; chunks];
+    let started = Instant::now();
+
+    let mut total = 0;
+    loop {
+        match fill(&mut input, &mut buffers).unwrap() {
+            0 => break,
+            n => total += n,
+        }
+    }
+
+    assert_eq!(total, TOTAL_SIZE);
+    started.elapsed()
+}
+
+fn temp_file() -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("ataf-bench-{}", std::process::id()));
+    let data: Vec<u8> = (0..TOTAL_SIZE).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&path, data).unwrap();
+
+    path
+}
+
+fn pipe() -> std::io::PipeReader {
+    let (reader, mut writer) = std::io::pipe().unwrap();
+    std::thread::spawn(move || {
+        let block = vec![7u8; CHUNK_SIZE];
+        for _ in 0..TOTAL_SIZE / CHUNK_SIZE {
+            writer.write_all(&block).unwrap();
+        }
+    });
+
+    reader
+}
+
+fn main() {
+    let path = temp_file();
+    let strategies: [(&str, Fill); 2] =
+        [("vectored", fill_vectored), ("sequential", fill_sequential)];
+
+    println!(
+        "{:<12} {:>6} {:>12} {:>12}",
+        "reader", "chunks", "strategy", "MiB/s"
+    );
+    for chunks in [1, 4, 16] {
+        for (name, fill) in strategies {
+            let mib = TOTAL_SIZE as f64 / (1024.0 * 1024.0);
+            let results = [
+                (
+                    "file",
+                    run(std::fs::File::open(&path).unwrap(), chunks, fill),
+                ),
+                (
+                    "bufreader",
+                    run(
+                        BufReader::new(std::fs::File::open(&path).unwrap()),
+                        chunks,
+                        fill,
+                    ),
+                ),
+                ("pipe", run(pipe(), chunks, fill)),
+            ];
+
+            for (reader, elapsed) in results {
+                println!(
+                    "{:<12} {:>6} {:>12} {:>12.0}",
+                    reader,
+                    chunks,
+                    name,
+                    mib / elapsed.as_secs_f64()
+                );
+            }
+        }
+    }
+
+    std::fs::remove_file(path).ok();
+}
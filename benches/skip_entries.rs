@@ -0,0 +1,89 @@
+//! Compares moving past unread entries of a brotli archive by decompressing
+//! them (dropping the entry), by reading past the chunks (`skip`) and by
+//! seeking past them (`skip_seeking`). Run with
+//! `cargo bench --bench skip_entries`, the archive size in MiB can be set with
+//! `ATAF_BENCH_MIB` (defaults to 256).
+
+mod common;
+
+use ataf::{
+    archive::{
+        read::{Archive, ArchiveEntry},
+        write::ArchiveWriter,
+    },
+    compression::{BrotliCompressor, BrotliDecompressor, brotli::enc::BrotliEncoderParams},
+    spec::ArchiveEntryHeader,
+};
+use std::{
+    io::{BufReader, BufWriter},
+    time::Instant,
+};
+
+const ENTRY_SIZE: usize = 1024 * 1024;
+const CHUNK_SIZE: u32 = 256 * 1024;
+const THREADS: usize = 4;
+
+type Input = BufReader<std::fs::File>;
+type Strategy = (&'static str, fn(ArchiveEntry<'_, Input>));
+
+fn temp_archive(entries: usize) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("ataf-bench-skip-{}", std::process::id()));
+    let file = BufWriter::new(std::fs::File::create(&path).unwrap());
+
+    let data = common::compressible_data(ENTRY_SIZE);
+
+    let params = BrotliEncoderParams {
+        quality: 5,
+        ..Default::default()
+    };
+    let mut writer = ArchiveWriter::new(
+        file,
+        Box::new(BrotliCompressor::new(THREADS, params).unwrap()),
+        CHUNK_SIZE,
+    )
+    .unwrap();
+    for i in 0..entries {
+        let entry = ArchiveEntryHeader::file(format!("{i}.bin"), data.len() as u64);
+
+        writer.write_entry(entry, &data[..]).unwrap();
+    }
+    writer.finish().unwrap();
+
+    path
+}
+
+fn main() {
+    let mib = std::env::var("ATAF_BENCH_MIB")
+        .ok()
+        .and_then(|mib| mib.parse::<usize>().ok())
+        .unwrap_or(256);
+    let path = temp_archive(mib * 1024 * 1024 / ENTRY_SIZE);
+
+    let strategies: [Strategy; 3] = [
+        ("drop", |entry| drop(entry)),
+        ("skip", |entry| entry.skip().unwrap()),
+        ("seek", |entry| entry.skip_seeking().unwrap()),
+    ];
+
+    println!("{:<8} {:>12}", "strategy", "ms");
+    for (name, strategy) in strategies {
+        let started = Instant::now();
+
+        let file = std::fs::File::open(&path).unwrap();
+        let mut archive = Archive::new(BufReader::new(file));
+        let mut entries = archive
+            .entries(Box::new(BrotliDecompressor::new(THREADS).unwrap()))
+            .unwrap();
+        while let Some(entry) = entries.next_entry() {
+            strategy(entry.unwrap());
+        }
+
+        println!(
+            "{:<8} {:>12.1}",
+            name,
+            started.elapsed().as_secs_f64() * 1000.0
+        );
+    }
+
+    std::fs::remove_file(path).ok();
+}
@@ -0,0 +1,209 @@
+use crate::{
+    Error,
+    archive::write::{ArchiveOptions, ArchiveWriter},
+    compression::Compressor,
+    spec::{ARCHIVE_MAGIC, ARCHIVE_VERSION, ArchiveEntryHeader, ArchiveHeader, Deserialize},
+};
+use std::io::Cursor;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The compressors `AsyncArchiveWriter` takes, which compress into memory.
+pub type AsyncCompressor = Box<dyn Compressor<Vec<u8>, Cursor<Vec<u8>>>>;
+
+/// Reads the header of an archive from an async source. The header is read
+/// byte for byte as it is laid out and then deserialized like `Archive` does,
+/// so nothing past it is consumed.
+///
+/// ```
+/// use ataf::{
+///     archive::{
+///         async_io::{AsyncArchive, AsyncArchiveWriter},
+///         write::ArchiveOptions,
+///     },
+///     compression::NoCompressor,
+///     spec::ArchiveEntryHeader,
+/// };
+///
+/// let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+/// runtime.block_on(async {
+///     let header = ArchiveEntryHeader::file("hello.txt", 5);
+///     let options = ArchiveOptions {
+///         comment: String::from("streamed"),
+///         ..Default::default()
+///     };
+///
+///     let mut writer =
+///         AsyncArchiveWriter::new(Vec::new(), Box::new(NoCompressor::new()), 1024, options)
+///             .await
+///             .unwrap();
+///     writer.write_entry(header, &b"hello"[..]).await.unwrap();
+///     let bytes = writer.finish().await.unwrap();
+///
+///     let mut archive = AsyncArchive::new(&bytes[..]);
+///     let header = archive.header().await.unwrap();
+///     assert_eq!((header.compression.as_str(), header.comment.as_str()), ("none", "streamed"));
+///
+///     let header_length = bytes.len() - archive.into_inner().len();
+///     assert!(header_length < bytes.len());
+/// });
+/// ```
+pub struct AsyncArchive<R: AsyncRead + Unpin> {
+    reader: R,
+    header: Option<ArchiveHeader>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncArchive<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            header: None,
+        }
+    }
+
+    pub async fn header(&mut self) -> crate::Result<&ArchiveHeader> {
+        if self.header.is_none() {
+            let bytes = read_header_bytes(&mut self.reader).await?;
+            self.header = Some(ArchiveHeader::deserialize(&bytes[..])?);
+        }
+
+        self.header
+            .as_ref()
+            .ok_or_else(|| Error::InvalidData(String::from("Failed to read start data")))
+    }
+
+    /// The source, positioned after the header once it was read.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Reads `length` more bytes of the header onto `bytes`.
+async fn append(
+    reader: &mut (impl AsyncRead + Unpin),
+    bytes: &mut Vec<u8>,
+    length: usize,
+) -> std::io::Result<()> {
+    let start = bytes.len();
+    bytes.resize(start + length, 0);
+    reader.read_exact(&mut bytes[start..]).await?;
+
+    Ok(())
+}
+
+/// Reads a `u16` length onto `bytes` followed by that many bytes.
+async fn append_string(
+    reader: &mut (impl AsyncRead + Unpin),
+    bytes: &mut Vec<u8>,
+) -> std::io::Result<()> {
+    append(reader, bytes, 2).await?;
+    let length = u16::from_le_bytes([bytes[bytes.len() - 2], bytes[bytes.len() - 1]]);
+
+    append(reader, bytes, length as usize).await
+}
+
+/// Collects exactly the bytes of an archive header, following the layout of
+/// its version.
+async fn read_header_bytes(reader: &mut (impl AsyncRead + Unpin)) -> crate::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    append(reader, &mut bytes, 4).await?;
+
+    let version = if bytes[..] == ARCHIVE_MAGIC {
+        append(reader, &mut bytes, 4).await?;
+        match u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) {
+            version @ 5..=ARCHIVE_VERSION => version,
+            version => return Err(Error::UnsupportedVersion(version)),
+        }
+    } else {
+        // archives before version 5 have no magic
+        match u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) {
+            version @ 1..=4 => version,
+            _ => return Err(Error::BadMagic),
+        }
+    };
+
+    // the compression and the chunk size
+    append_string(reader, &mut bytes).await?;
+    append(reader, &mut bytes, 4).await?;
+    if version >= 2 {
+        append(reader, &mut bytes, 1).await?;
+    }
+    if version >= 9 {
+        append_string(reader, &mut bytes).await?;
+    }
+    if version >= 12 {
+        // the entry count and total uncompressed size
+        append(reader, &mut bytes, 16).await?;
+    }
+    if version >= 11 {
+        // the checksum, verified when the bytes are parsed
+        append(reader, &mut bytes, 4).await?;
+    }
+
+    Ok(bytes)
+}
+
+/// Writes an archive to an async sink. Every entry is read into memory and
+/// compressed there by an `ArchiveWriter`, whose compressors do the work on
+/// their own threads, before it is written out. Memory use is bounded by the
+/// largest entry, solid archives aren't supported.
+pub struct AsyncArchiveWriter<W: AsyncWrite + Unpin> {
+    writer: W,
+    inner: ArchiveWriter<Vec<u8>, Cursor<Vec<u8>>>,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncArchiveWriter<W> {
+    /// Writes the archive header right away.
+    pub async fn new(
+        writer: W,
+        compressor: AsyncCompressor,
+        compression_chunk_size: u32,
+        options: ArchiveOptions,
+    ) -> crate::Result<Self> {
+        if options.solid {
+            return Err(Error::InvalidOperation(
+                "solid archives can't be written asynchronously",
+            ));
+        }
+
+        let inner =
+            ArchiveWriter::with_options(Vec::new(), compressor, compression_chunk_size, options)?;
+        let mut writer = Self { writer, inner };
+        writer.write_buffered().await?;
+
+        Ok(writer)
+    }
+
+    /// Reads the entry's `size` bytes from `input` and writes the entry.
+    pub async fn write_entry(
+        &mut self,
+        entry: ArchiveEntryHeader,
+        input: impl AsyncRead + Unpin,
+    ) -> crate::Result<()> {
+        let mut data = Vec::new();
+        input.take(*entry.size).read_to_end(&mut data).await?;
+
+        self.inner.write_entry(entry, Cursor::new(data))?;
+        self.write_buffered().await
+    }
+
+    /// Writes the index if enabled and flushes the sink, returning it.
+    pub async fn finish(mut self) -> crate::Result<W> {
+        let rest = self.inner.finish()?;
+        self.writer.write_all(&rest).await?;
+        self.writer.flush().await?;
+
+        Ok(self.writer)
+    }
+
+    #[inline]
+    pub fn set_compression_level(&mut self, level: u32) {
+        self.inner.set_compression_level(level);
+    }
+
+    async fn write_buffered(&mut self) -> crate::Result<()> {
+        let buffered = std::mem::take(self.inner.get_mut());
+        self.writer.write_all(&buffered).await?;
+
+        Ok(())
+    }
+}
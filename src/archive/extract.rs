@@ -0,0 +1,1083 @@
+use crate::{
+    Error,
+    archive::{read::ArchiveEntriesReader, sparse::SparseWriter, write::ArchiveStats},
+    spec::{ArchiveEntryHeader, ArchiveEntryHeaderType},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Read,
+    path::{Component, Path, PathBuf},
+    sync::mpsc::{Receiver, Sender, channel},
+    time::SystemTime,
+};
+
+/// What `extract_filtered` does with an entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtractAction {
+    Extract,
+    /// Leaves the entry out. Its data is skipped without decompressing it where
+    /// the archive allows, so the entries after it are unaffected.
+    Skip,
+    /// Extracts the entry as if it was archived under another path.
+    Rename(String),
+}
+
+/// Resolves an archived path below `output`. Absolute paths are made relative,
+/// paths with `..` components are refused since they could point anywhere.
+///
+/// ```
+/// use ataf::archive::extract::entry_destination;
+/// use std::path::Path;
+///
+/// let output = Path::new("output");
+/// assert_eq!(entry_destination(output, "/a/./b").unwrap(), Path::new("output/a/b"));
+/// assert!(matches!(entry_destination(output, "a/../../evil"), Err(ataf::Error::UnsafePath(_))));
+/// ```
+pub fn entry_destination(output: &Path, path: impl AsRef<Path>) -> crate::Result<PathBuf> {
+    let path = path.as_ref();
+    let mut destination = output.to_path_buf();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => destination.push(name),
+            Component::ParentDir => return Err(Error::UnsafePath(path.to_path_buf())),
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+        }
+    }
+
+    Ok(destination)
+}
+
+/// Removes the first `count` components of an archived path, like tar's
+/// `--strip-components`. The path is normalized as in `entry_destination`
+/// first: root, prefix and `.` components are dropped and paths with `..`
+/// are refused, so only names are counted and a stripped path can't climb
+/// out of the output directory. `None` when no names are left, which is the
+/// case for the stripped directories themselves.
+///
+/// ```
+/// use ataf::archive::extract::strip_components;
+/// use std::path::Path;
+///
+/// let strip = |path: &str, count| strip_components(path, count).unwrap();
+///
+/// assert_eq!(strip("project/src/main.rs", 1).as_deref(), Some(Path::new("src/main.rs")));
+/// assert_eq!(strip("/project/src/main.rs", 2).as_deref(), Some(Path::new("main.rs")));
+/// assert_eq!(strip("./project//src/./main.rs", 2).as_deref(), Some(Path::new("main.rs")));
+/// assert_eq!(strip("project/src", 0).as_deref(), Some(Path::new("project/src")));
+/// assert_eq!(strip("project", 1), None);
+/// assert_eq!(strip("project/src", 3), None);
+/// assert_eq!(strip("/", 1), None);
+///
+/// for path in ["../project/evil", "project/../../evil", "a/b/../c"] {
+///     assert!(matches!(strip_components(path, 1), Err(ataf::Error::UnsafePath(_))));
+/// }
+/// ```
+pub fn strip_components(path: impl AsRef<Path>, count: usize) -> crate::Result<Option<PathBuf>> {
+    let path = path.as_ref();
+    let mut names = Vec::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => names.push(name),
+            Component::ParentDir => return Err(Error::UnsafePath(path.to_path_buf())),
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+        }
+    }
+
+    if names.len() <= count {
+        return Ok(None);
+    }
+
+    Ok(Some(names[count..].iter().collect()))
+}
+
+/// Makes sure nothing that already exists redirects `destination` outside of
+/// `output`, like a symlink extracted earlier, by canonicalizing the deepest
+/// existing part of it. Has to be called before creating anything there.
+pub fn check_destination(output: &Path, destination: &Path) -> crate::Result<()> {
+    // nothing below output can exist yet when output itself doesn't
+    let Ok(output) = output.canonicalize() else {
+        return Ok(());
+    };
+
+    for ancestor in destination.ancestors() {
+        if ancestor.symlink_metadata().is_ok() {
+            return match ancestor.canonicalize() {
+                Ok(ancestor) if ancestor.starts_with(&output) => Ok(()),
+                _ => Err(Error::UnsafePath(destination.to_path_buf())),
+            };
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks a destination against everything extracted so far, returning why it
+/// conflicts. Repeated directory entries are harmless and allowed, anything
+/// else at a path extracted before, or below something that isn't a
+/// directory, conflicts.
+///
+/// ```
+/// use ataf::{archive::extract::find_conflict, spec::ArchiveEntryHeaderType};
+/// use std::{collections::HashMap, path::Path};
+///
+/// let output = Path::new("output");
+/// let extracted = HashMap::from([
+///     (output.join("dir"), ArchiveEntryHeaderType::Directory),
+///     (output.join("file"), ArchiveEntryHeaderType::File),
+/// ]);
+///
+/// let conflict = |path, r#type| find_conflict(&extracted, output, &output.join(path), r#type);
+/// assert_eq!(conflict("dir", ArchiveEntryHeaderType::Directory), None);
+/// assert_eq!(conflict("dir/file", ArchiveEntryHeaderType::File), None);
+/// assert!(conflict("file", ArchiveEntryHeaderType::File).is_some());
+/// assert!(conflict("file/below", ArchiveEntryHeaderType::File).is_some());
+/// ```
+pub fn find_conflict(
+    extracted: &HashMap<PathBuf, ArchiveEntryHeaderType>,
+    output: &Path,
+    destination: &Path,
+    r#type: ArchiveEntryHeaderType,
+) -> Option<String> {
+    if let Some(previous) = extracted.get(destination)
+        && (previous != &ArchiveEntryHeaderType::Directory
+            || r#type != ArchiveEntryHeaderType::Directory)
+    {
+        return Some(format!(
+            "{:?} entry conflicts with an earlier {:?} entry at the same path",
+            r#type, previous
+        ));
+    }
+
+    for parent in destination
+        .ancestors()
+        .skip(1)
+        .take_while(|parent| parent.starts_with(output) && *parent != output)
+    {
+        if let Some(previous) = extracted.get(parent)
+            && previous != &ArchiveEntryHeaderType::Directory
+        {
+            return Some(format!(
+                "parent {} was extracted as a {:?} entry",
+                parent.display(),
+                previous
+            ));
+        }
+    }
+
+    None
+}
+
+/// Refuses symlink targets leading outside of `output` from the link at
+/// `destination`, which includes all absolute targets. Targets are followed
+/// through whatever exists below `output` already, so links extracted before
+/// can't be used to leave it.
+pub fn check_symlink_target(output: &Path, destination: &Path, target: &str) -> crate::Result<()> {
+    // most platforms can't create these, which would fail with a vague error
+    if target.is_empty() {
+        return Err(Error::InvalidData(format!(
+            "symlink {} has an empty target",
+            destination.display()
+        )));
+    }
+
+    let mut depth = destination
+        .parent()
+        .and_then(|parent| parent.strip_prefix(output).ok())
+        .map_or(0, |parent| parent.components().count());
+
+    for component in Path::new(target).components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            Component::ParentDir | Component::Prefix(_) | Component::RootDir => {
+                return Err(Error::UnsafePath(destination.with_file_name(target)));
+            }
+        }
+    }
+
+    // nothing below output can exist yet when output itself doesn't
+    let Ok(canonical_output) = output.canonicalize() else {
+        return Ok(());
+    };
+
+    let parent = destination
+        .parent()
+        .and_then(|parent| parent.strip_prefix(output).ok())
+        .unwrap_or(Path::new(""));
+    let mut resolved = canonical_output.clone();
+    for component in parent.components().chain(Path::new(target).components()) {
+        match component {
+            Component::Normal(name) => {
+                resolved.push(name);
+                // parts that don't exist yet can only be taken as written
+                if let Ok(canonical) = resolved.canonicalize() {
+                    resolved = canonical;
+                }
+            }
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            _ => {}
+        }
+    }
+
+    if !resolved.starts_with(&canonical_output) {
+        return Err(Error::UnsafePath(destination.with_file_name(target)));
+    }
+
+    Ok(())
+}
+
+/// Sets the modification time of whatever is at `path`, for a symlink that of
+/// the link itself rather than its target.
+///
+/// ```
+/// use ataf::archive::extract::set_path_modified;
+/// use std::time::{Duration, SystemTime};
+///
+/// let path = std::env::temp_dir().join(format!("ataf-doctest-times-{}", std::process::id()));
+/// std::fs::write(&path, b"data").unwrap();
+///
+/// let modified = SystemTime::UNIX_EPOCH + Duration::new(1_000_000_000, 500);
+/// set_path_modified(&path, modified).unwrap();
+/// assert_eq!(std::fs::metadata(&path).unwrap().modified().unwrap(), modified);
+///
+/// std::fs::remove_file(path).unwrap();
+/// ```
+#[cfg(target_family = "unix")]
+pub fn set_path_modified(path: &Path, modified: SystemTime) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    let since_epoch = modified
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+
+    // the access time is left alone
+    let times = [
+        libc::timespec {
+            tv_sec: 0,
+            tv_nsec: libc::UTIME_OMIT,
+        },
+        libc::timespec {
+            tv_sec: since_epoch.as_secs() as libc::time_t,
+            tv_nsec: since_epoch.subsec_nanos() as libc::c_long,
+        },
+    ];
+    if unsafe {
+        libc::utimensat(
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            times.as_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    } != 0
+    {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(target_family = "windows")]
+pub fn set_path_modified(path: &Path, modified: SystemTime) -> std::io::Result<()> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    const FILE_WRITE_ATTRIBUTES: u32 = 0x0100;
+    // needed to open directories, and links instead of their targets
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+    const FILE_FLAG_OPEN_REPARSE_POINT: u32 = 0x0020_0000;
+
+    std::fs::File::options()
+        .access_mode(FILE_WRITE_ATTRIBUTES)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OPEN_REPARSE_POINT)
+        .open(path)?
+        .set_modified(modified)
+}
+
+/// Creates the FIFO, socket or device node of a special entry at
+/// `destination` with the entry's mode, `data` being the entry's data. Fails
+/// with `ErrorKind::Unsupported` on platforms without special files.
+///
+/// ```
+/// use ataf::{
+///     archive::extract::create_special_file,
+///     spec::{ArchiveEntryHeader, ArchiveEntryHeaderType},
+/// };
+///
+/// let header = ArchiveEntryHeader {
+///     r#type: ArchiveEntryHeaderType::Fifo,
+///     mode: 0o640,
+///     ..ArchiveEntryHeader::file("pipe", 0)
+/// };
+///
+/// let path = std::env::temp_dir().join(format!("ataf-doctest-fifo-{}", std::process::id()));
+/// match create_special_file(&path, &header, &[]) {
+///     Err(err) if err.kind() == std::io::ErrorKind::Unsupported => {}
+///     result => {
+///         result.unwrap();
+///         std::fs::remove_file(path).unwrap();
+///     }
+/// }
+/// ```
+#[cfg_attr(not(target_family = "unix"), allow(unused_variables))]
+pub fn create_special_file(
+    destination: &Path,
+    header: &ArchiveEntryHeader,
+    data: &[u8],
+) -> std::io::Result<()> {
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::{ffi::OsStrExt, fs::PermissionsExt};
+
+        let kind = match header.r#type {
+            ArchiveEntryHeaderType::Fifo => libc::S_IFIFO,
+            ArchiveEntryHeaderType::Socket => libc::S_IFSOCK,
+            ArchiveEntryHeaderType::BlockDevice => libc::S_IFBLK,
+            ArchiveEntryHeaderType::CharDevice => libc::S_IFCHR,
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("{} is not a special file", header.path),
+                ));
+            }
+        };
+        let device = match <[u8; 8]>::try_from(data) {
+            Ok(device) => u64::from_le_bytes(device),
+            Err(_) if matches!(kind, libc::S_IFIFO | libc::S_IFSOCK) => 0,
+            Err(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "device {} has a device number of {} bytes instead of 8",
+                        header.path,
+                        data.len()
+                    ),
+                ));
+            }
+        };
+
+        let path = std::ffi::CString::new(destination.as_os_str().as_bytes())?;
+        let permissions = (header.mode & 0o7777) as libc::mode_t;
+        let result = if kind == libc::S_IFIFO {
+            unsafe { libc::mkfifo(path.as_ptr(), permissions) }
+        } else {
+            unsafe { libc::mknod(path.as_ptr(), kind | permissions, device as libc::dev_t) }
+        };
+        if result != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        // the umask applied when creating it
+        std::fs::set_permissions(
+            destination,
+            std::fs::Permissions::from_mode(header.mode & 0o7777),
+        )
+    }
+    #[cfg(not(target_family = "unix"))]
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!("{} can't be created on this platform", header.path),
+    ))
+}
+
+/// Extracts every entry to `output`, asking `filter` before each one whether
+/// and where to extract it, which is enough to build include, exclude or path
+/// remapping policies on. The first error ends the extraction.
+///
+/// References are copied from wherever their original entry was extracted to,
+/// so skipping an original makes its references fail. Hard links are linked to
+/// it the same way. FIFOs, sockets and devices are left out on platforms that
+/// can't create them.
+///
+/// ```
+/// use ataf::compression::CompressionFormat;
+/// use ataf::{
+///     archive::{extract::{ExtractAction, extract_filtered}, read::Archive},
+///     compression::NoDecompressor,
+///     spec::ArchiveEntryHeader,
+/// };
+///
+/// fn file(path: &str, data: &'static [u8]) -> (ArchiveEntryHeader, Box<dyn std::io::Read>) {
+///     let header = ArchiveEntryHeader::file(path, data.len() as u64);
+///
+///     (header, Box::new(data))
+/// }
+///
+/// let mut archive = Vec::new();
+/// ataf::build_archive(
+///     &mut archive,
+///     CompressionFormat::None,
+///     [file("keep.txt", b"keep"), file("skip.log", b"skip"), file("old.txt", b"moved")],
+/// )
+/// .unwrap();
+///
+/// let output = std::env::temp_dir().join(format!("ataf-doctest-{}", std::process::id()));
+/// let mut archive = Archive::new(&archive[..]);
+/// let mut entries = archive.entries(Box::new(NoDecompressor)).unwrap();
+///
+/// let stats = extract_filtered(&mut entries, &output, |header| {
+///     if header.path.ends_with(".log") {
+///         ExtractAction::Skip
+///     } else if header.path == "old.txt" {
+///         ExtractAction::Rename(String::from("new/name.txt"))
+///     } else {
+///         ExtractAction::Extract
+///     }
+/// })
+/// .unwrap();
+///
+/// assert_eq!(stats.entries, 2);
+/// assert_eq!(std::fs::read(output.join("keep.txt")).unwrap(), b"keep");
+/// assert_eq!(std::fs::read(output.join("new/name.txt")).unwrap(), b"moved");
+/// assert!(!output.join("skip.log").exists());
+///
+/// std::fs::remove_dir_all(output).unwrap();
+/// ```
+pub fn extract_filtered<R: Read>(
+    entries: &mut ArchiveEntriesReader<'_, R>,
+    output: &Path,
+    filter: impl FnMut(&ArchiveEntryHeader) -> ExtractAction,
+) -> crate::Result<ArchiveStats> {
+    extract_with(entries, output, filter, None)
+}
+
+/// Like `extract_filtered`, with files of up to `MAX_PARALLEL_FILE` bytes
+/// written by `threads` threads while the archive is read on. Everything else
+/// still happens in archive order on the calling thread, so directories exist
+/// before anything is written into them, and writing waits for the files in
+/// flight before references and hard links are made from them or a path
+/// overlaps one of them. Files get their modification time and mode as soon
+/// as they are written, directories once everything is extracted.
+///
+/// ```
+/// use ataf::compression::CompressionFormat;
+/// use ataf::{
+///     archive::{extract::{ExtractAction, extract_parallel}, read::Archive},
+///     compression::NoDecompressor,
+///     spec::ArchiveEntryHeader,
+/// };
+///
+/// let header = ArchiveEntryHeader::file("file.txt", 4);
+/// let data: Box<dyn std::io::Read> = Box::new(&b"data"[..]);
+/// let mut archive = Vec::new();
+/// ataf::build_archive(&mut archive, CompressionFormat::None, [(header, data)]).unwrap();
+///
+/// let output = std::env::temp_dir().join(format!("ataf-doctest-parallel-{}", std::process::id()));
+/// let mut archive = Archive::new(&archive[..]);
+/// let mut entries = archive.entries(Box::new(NoDecompressor)).unwrap();
+/// let stats = extract_parallel(&mut entries, &output, 4, |_| ExtractAction::Extract).unwrap();
+/// assert_eq!(stats.entries, 1);
+/// assert_eq!(std::fs::read(output.join("file.txt")).unwrap(), b"data");
+///
+/// std::fs::remove_dir_all(output).unwrap();
+/// ```
+pub fn extract_parallel<R: Read>(
+    entries: &mut ArchiveEntriesReader<'_, R>,
+    output: &Path,
+    threads: usize,
+    filter: impl FnMut(&ArchiveEntryHeader) -> ExtractAction,
+) -> crate::Result<ArchiveStats> {
+    extract_with(
+        entries,
+        output,
+        filter,
+        Some(&mut FileWriters::new(threads)?),
+    )
+}
+
+/// Files larger than this are written by the reading thread as they are read,
+/// rather than buffered for `extract_parallel`'s writers.
+pub const MAX_PARALLEL_FILE: u64 = 4 * 1024 * 1024;
+
+/// How much buffered file data may wait for or be in the writers at once.
+const MAX_IN_FLIGHT: u64 = 64 * 1024 * 1024;
+
+/// Writes whole buffered files on a thread pool, each with its metadata.
+struct FileWriters {
+    thread_pool: rayon::ThreadPool,
+    sender: Sender<(PathBuf, u64, crate::Result<()>)>,
+    receiver: Receiver<(PathBuf, u64, crate::Result<()>)>,
+    pending: HashSet<PathBuf>,
+    pending_bytes: u64,
+}
+
+impl FileWriters {
+    fn new(threads: usize) -> crate::Result<Self> {
+        let (sender, receiver) = channel();
+
+        Ok(Self {
+            thread_pool: rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()?,
+            sender,
+            receiver,
+            pending: HashSet::new(),
+            pending_bytes: 0,
+        })
+    }
+
+    /// Whether `destination` or one of its parents is still being written.
+    fn overlaps(&self, destination: &Path) -> bool {
+        destination
+            .ancestors()
+            .any(|path| self.pending.contains(path))
+    }
+
+    fn spawn(&mut self, destination: PathBuf, header: ArchiveEntryHeader, data: Vec<u8>) {
+        let size = data.len() as u64;
+        let sender = self.sender.clone();
+
+        self.pending.insert(destination.clone());
+        self.pending_bytes += size;
+        self.thread_pool.spawn(move || {
+            let result = write_file(&destination, &header, &mut &data[..]);
+            sender.send((destination, size, result)).ok();
+        });
+    }
+
+    /// Waits for the next file to be written, returning its error if any.
+    fn wait_one(&mut self) -> crate::Result<()> {
+        let (destination, size, result) = self
+            .receiver
+            .recv()
+            .expect("the writers hold a sender while files are pending");
+
+        self.pending.remove(&destination);
+        self.pending_bytes -= size;
+
+        result
+    }
+
+    /// Waits until `size` more bytes fit into `MAX_IN_FLIGHT`.
+    fn make_room(&mut self, size: u64) -> crate::Result<()> {
+        while !self.pending.is_empty() && self.pending_bytes + size > MAX_IN_FLIGHT {
+            self.wait_one()?;
+        }
+
+        Ok(())
+    }
+
+    fn wait_all(&mut self) -> crate::Result<()> {
+        while !self.pending.is_empty() {
+            self.wait_one()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Creates the file at `destination` from an entry's data, then sets its
+/// modification time and mode.
+fn write_file(
+    destination: &Path,
+    header: &ArchiveEntryHeader,
+    data: &mut impl Read,
+) -> crate::Result<()> {
+    let mut file = std::fs::File::create(destination)?;
+    match &header.sparse {
+        Some(sparse) => {
+            file.set_len(sparse.size)?;
+            std::io::copy(data, &mut SparseWriter::new(&file, sparse, 0))?;
+        }
+        None => {
+            std::io::copy(data, &mut file)?;
+        }
+    }
+
+    file.set_modified(header.modified())?;
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        file.set_permissions(std::fs::Permissions::from_mode(header.mode))?;
+    }
+
+    Ok(())
+}
+
+fn extract_with<R: Read>(
+    entries: &mut ArchiveEntriesReader<'_, R>,
+    output: &Path,
+    mut filter: impl FnMut(&ArchiveEntryHeader) -> ExtractAction,
+    mut writers: Option<&mut FileWriters>,
+) -> crate::Result<ArchiveStats> {
+    let mut stats = ArchiveStats::default();
+    let mut extracted = HashMap::new();
+    // extracting into a directory changes its modification time and its mode
+    // may not allow it, so both are only set once everything is extracted
+    let mut directories = Vec::new();
+
+    while let Some(entry) = entries.next_entry() {
+        let mut entry = entry?;
+        let header = entry.header().clone();
+
+        let destination = match filter(&header) {
+            ExtractAction::Extract => entry_destination(output, header.native_path())?,
+            ExtractAction::Skip => {
+                entry.skip()?;
+                continue;
+            }
+            ExtractAction::Rename(path) => entry_destination(output, &path)?,
+        };
+
+        // links are made from files that have to be complete, and nothing may
+        // be extracted to or below a file that is still being written
+        if let Some(writers) = &mut writers
+            && (matches!(
+                header.r#type,
+                ArchiveEntryHeaderType::Reference | ArchiveEntryHeaderType::HardLink
+            ) || writers.overlaps(&destination))
+        {
+            writers.wait_all()?;
+        }
+        check_destination(output, &destination)?;
+
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        match header.r#type {
+            ArchiveEntryHeaderType::File => match &mut writers {
+                Some(writers) if *header.size <= MAX_PARALLEL_FILE => {
+                    let data = entry.read_to_vec()?;
+                    writers.make_room(data.len() as u64)?;
+                    writers.spawn(destination.clone(), header.clone(), data);
+                }
+                _ => write_file(&destination, &header, &mut entry)?,
+            },
+            ArchiveEntryHeaderType::Directory => {
+                std::fs::create_dir_all(&destination)?;
+                directories.push((destination.clone(), header.modified(), header.mode));
+            }
+            ArchiveEntryHeaderType::Reference => {
+                let mut original = String::new();
+                entry.read_to_string(&mut original)?;
+
+                check_destination(output, &entry_destination(output, &original)?)?;
+                let source = extracted.get(&original).ok_or_else(|| {
+                    Error::Io(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!(
+                            "{} references {}, which was not extracted",
+                            header.path, original
+                        ),
+                    ))
+                })?;
+                std::fs::copy(source, &destination)?;
+            }
+            ArchiveEntryHeaderType::HardLink => {
+                let mut original = String::new();
+                entry.read_to_string(&mut original)?;
+
+                check_destination(output, &entry_destination(output, &original)?)?;
+                let source = extracted.get(&original).ok_or_else(|| {
+                    Error::Io(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!(
+                            "{} links to {}, which was not extracted",
+                            header.path, original
+                        ),
+                    ))
+                })?;
+                std::fs::hard_link(source, &destination)?;
+            }
+            ArchiveEntryHeaderType::SymlinkFile | ArchiveEntryHeaderType::SymlinkDirectory => {
+                let mut target = String::new();
+                entry.read_to_string(&mut target)?;
+                check_symlink_target(output, &destination, &target)?;
+
+                #[cfg(target_family = "unix")]
+                std::os::unix::fs::symlink(&target, &destination)?;
+                #[cfg(target_family = "windows")]
+                if header.r#type == ArchiveEntryHeaderType::SymlinkDirectory {
+                    std::os::windows::fs::symlink_dir(&target, &destination)?;
+                } else {
+                    std::os::windows::fs::symlink_file(&target, &destination)?;
+                }
+
+                set_path_modified(&destination, header.modified())?;
+            }
+            ArchiveEntryHeaderType::Fifo
+            | ArchiveEntryHeaderType::Socket
+            | ArchiveEntryHeaderType::BlockDevice
+            | ArchiveEntryHeaderType::CharDevice => {
+                match create_special_file(&destination, &header, &entry.read_to_vec()?) {
+                    Err(err) if err.kind() == std::io::ErrorKind::Unsupported => continue,
+                    result => result?,
+                }
+
+                set_path_modified(&destination, header.modified())?;
+            }
+        }
+
+        stats.entries += 1;
+        stats.uncompressed_bytes += *header.size;
+        extracted.insert(header.path, destination);
+    }
+
+    if let Some(writers) = &mut writers {
+        writers.wait_all()?;
+    }
+
+    #[cfg_attr(not(target_family = "unix"), allow(unused_variables))]
+    for (directory, modified, mode) in directories {
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            std::fs::set_permissions(&directory, std::fs::Permissions::from_mode(mode))?;
+        }
+
+        set_path_modified(&directory, modified)?;
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        archive::read::Archive,
+        compression::{Decompressor, NoDecompressor},
+        spec::{VariableSizedU32, VariableSizedU64},
+        test_util::{TempDir, archive, entry, extract},
+    };
+    use std::time::Duration;
+
+    fn modified(path: &Path) -> Duration {
+        let metadata = std::fs::symlink_metadata(path).unwrap();
+        metadata
+            .modified()
+            .unwrap()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+    }
+
+    fn mode(path: &Path) -> u32 {
+        #[cfg(target_family = "unix")]
+        return std::os::unix::fs::PermissionsExt::mode(
+            &std::fs::metadata(path).unwrap().permissions(),
+        ) & 0o7777;
+        #[cfg(not(target_family = "unix"))]
+        0
+    }
+
+    #[test]
+    fn traversal_is_refused() {
+        use ArchiveEntryHeaderType::{File, Reference, SymlinkDirectory, SymlinkFile};
+
+        // crafted archives, each trying to write outside of the output directory
+        let attacks = [
+            vec![entry(File, "../evil", "x")],
+            vec![entry(File, "a/../../evil", "x")],
+            vec![entry(SymlinkDirectory, "link", "..")],
+            vec![entry(SymlinkDirectory, "link", "/tmp")],
+            vec![entry(SymlinkFile, "a/b/link", "../../../evil")],
+            vec![entry(Reference, "copy", "../secret")],
+            // reads as staying inside, but leads through the link extracted first
+            vec![
+                entry(SymlinkDirectory, "c/d/up", "../.."),
+                entry(SymlinkDirectory, "escape", "c/d/up/.."),
+            ],
+        ];
+
+        let root = TempDir::new("traversal");
+        let output = root.join("output");
+        for attack in attacks {
+            std::fs::create_dir_all(&output).unwrap();
+            let err = extract(&archive(attack), &output).unwrap_err();
+            assert!(matches!(err, Error::UnsafePath(_)));
+            assert!(!root.join("evil").exists());
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn writing_through_links_is_checked() {
+        let output = TempDir::new("through-links");
+        std::fs::create_dir_all(&*output).unwrap();
+        std::os::unix::fs::symlink("/tmp", output.join("outside")).unwrap();
+
+        // links that stay inside are fine
+        let err = extract(
+            &archive([
+                entry(ArchiveEntryHeaderType::SymlinkDirectory, "a/b/up", "../.."),
+                entry(ArchiveEntryHeaderType::File, "outside/evil", "x"),
+            ]),
+            &output,
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::UnsafePath(_)));
+        assert!(output.join("a/b/up").is_symlink());
+    }
+
+    #[test]
+    fn conflicts_are_found() {
+        use ArchiveEntryHeaderType::{Directory, File};
+
+        let archive = archive([
+            entry(Directory, "a", ""),
+            entry(Directory, "a/", ""),
+            entry(File, "a/b", ""),
+            entry(File, "./a/b", ""),
+            entry(Directory, "a/b", ""),
+            entry(File, "a/b/c", ""),
+            entry(File, "a", ""),
+            entry(File, "d", ""),
+        ]);
+
+        let output = Path::new("/output");
+        let mut extracted = HashMap::new();
+        let mut conflicts = Vec::new();
+        let mut archive = Archive::new(&archive[..]);
+        let mut entries = archive.entries(Box::new(NoDecompressor)).unwrap();
+        while let Some(entry) = entries.next_entry() {
+            let header = entry.unwrap().header().clone();
+            let destination = entry_destination(output, &header.path).unwrap();
+            match find_conflict(&extracted, output, &destination, header.r#type) {
+                Some(_) => conflicts.push(header.path),
+                None => {
+                    extracted.insert(destination, header.r#type);
+                }
+            }
+        }
+
+        assert_eq!(conflicts, ["./a/b", "a/b", "a/b/c", "a"]);
+        assert_eq!(extracted.len(), 3);
+    }
+
+    #[test]
+    fn times_are_restored() {
+        let timed = |r#type, path, seconds, data| {
+            let (header, data) = entry(r#type, path, data);
+            let header = ArchiveEntryHeader {
+                mode: 0o755,
+                mtime: VariableSizedU64::new(seconds),
+                mtime_nanos: VariableSizedU32::new(500),
+                ..header
+            };
+
+            (header, data)
+        };
+
+        // the directory comes first, extracting into it would change its time
+        let output = TempDir::new("times");
+        extract(
+            &archive([
+                timed(ArchiveEntryHeaderType::Directory, "dir", 1_000_000_000, ""),
+                timed(
+                    ArchiveEntryHeaderType::File,
+                    "dir/file",
+                    1_100_000_000,
+                    "data",
+                ),
+                timed(
+                    ArchiveEntryHeaderType::SymlinkFile,
+                    "dir/link",
+                    1_200_000_000,
+                    "file",
+                ),
+            ]),
+            &output,
+        )
+        .unwrap();
+
+        assert_eq!(
+            modified(&output.join("dir")),
+            Duration::new(1_000_000_000, 500)
+        );
+        assert_eq!(
+            modified(&output.join("dir/file")),
+            Duration::new(1_100_000_000, 500)
+        );
+        #[cfg(target_family = "unix")]
+        assert_eq!(
+            modified(&output.join("dir/link")),
+            Duration::new(1_200_000_000, 500)
+        );
+    }
+
+    #[test]
+    fn fifos_are_created() {
+        let (header, data) = entry(ArchiveEntryHeaderType::Fifo, "pipe", "");
+        let header = ArchiveEntryHeader {
+            mode: 0o640,
+            mtime: VariableSizedU64::new(1_000_000_000),
+            ..header
+        };
+
+        let output = TempDir::new("fifo");
+        let stats = extract(&archive([(header, data)]), &output).unwrap();
+
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+            let metadata = std::fs::symlink_metadata(output.join("pipe")).unwrap();
+            assert!(metadata.file_type().is_fifo());
+            assert_eq!(metadata.permissions().mode() & 0o7777, 0o640);
+            assert_eq!(stats.entries, 1);
+        }
+        #[cfg(not(target_family = "unix"))]
+        assert_eq!(stats.entries, 0);
+    }
+
+    #[test]
+    fn empty_entries_read_no_chunks() {
+        struct NoChunks;
+
+        impl Decompressor for NoChunks {
+            fn decompress_inputs(&mut self) -> usize {
+                1
+            }
+
+            fn decompress(
+                &mut self,
+                _: Vec<Vec<u8>>,
+                _: &mut Vec<u8>,
+                _: u32,
+            ) -> std::io::Result<()> {
+                panic!("empty entries have no chunks");
+            }
+        }
+
+        let with_mode = |r#type, path, mode| {
+            let (header, data) = entry(r#type, path, "");
+            let header = ArchiveEntryHeader {
+                mode,
+                mtime: VariableSizedU64::new(1_000_000_000),
+                ..header
+            };
+
+            (header, data)
+        };
+        let extract_without_chunks = |archive: Vec<u8>, output: &Path| {
+            let mut archive = Archive::new(&archive[..]);
+            let mut entries = archive.entries(Box::new(NoChunks)).unwrap();
+            extract_filtered(&mut entries, output, |_| ExtractAction::Extract)
+        };
+
+        // modes keep their setuid, setgid and sticky bits
+        let modes = [
+            (ArchiveEntryHeaderType::Directory, "empty", 0o750),
+            (ArchiveEntryHeaderType::File, "empty.txt", 0o600),
+            (ArchiveEntryHeaderType::Directory, "sticky", 0o1777),
+            (ArchiveEntryHeaderType::File, "setuid", 0o4755),
+        ];
+        let output = TempDir::new("empty");
+        let entries = modes.map(|(r#type, path, mode)| with_mode(r#type, path, mode));
+        extract_without_chunks(archive(entries), &output).unwrap();
+
+        for (_, path, expected) in modes {
+            let path = output.join(path);
+            assert_eq!(modified(&path), Duration::from_secs(1_000_000_000));
+            assert_eq!(mode(&path), if cfg!(unix) { expected } else { 0 });
+        }
+        assert_eq!(std::fs::read_dir(output.join("empty")).unwrap().count(), 0);
+        assert_eq!(
+            std::fs::metadata(output.join("empty.txt")).unwrap().len(),
+            0
+        );
+
+        // while a symlink with an empty target is refused
+        let link = with_mode(ArchiveEntryHeaderType::SymlinkFile, "link", 0o777);
+        let err = extract_without_chunks(archive([link]), &output).unwrap_err();
+        assert!(matches!(err, Error::InvalidData(_)));
+        assert!(output.join("link").symlink_metadata().is_err());
+    }
+
+    #[test]
+    fn parallel_extraction_of_a_deep_tree() {
+        fn mtime(path: &str) -> u64 {
+            1_000_000_000 + path.len() as u64
+        }
+
+        let timed = |r#type, path: &str, mode, data: Vec<u8>| {
+            let (header, data) = entry(r#type, path, data);
+            let header = ArchiveEntryHeader {
+                mode,
+                mtime: VariableSizedU64::new(mtime(path)),
+                ..header
+            };
+
+            (header, data)
+        };
+
+        // a 20 levels deep tree with a few files on every level, plus links
+        let mut tree = Vec::new();
+        let mut directory = String::from("root");
+        for depth in 0..20 {
+            tree.push(timed(
+                ArchiveEntryHeaderType::Directory,
+                &directory,
+                0o750,
+                Vec::new(),
+            ));
+            for i in 0..5 {
+                let path = format!("{directory}/{i}.txt");
+                let data = path.repeat(depth * 100 + i).into_bytes();
+                tree.push(timed(
+                    ArchiveEntryHeaderType::File,
+                    &path,
+                    0o600 + i as u32,
+                    data,
+                ));
+            }
+            directory.push_str(&format!("/{depth}"));
+        }
+        let deepest = format!("{directory}/last.txt");
+        tree.push(timed(
+            ArchiveEntryHeaderType::File,
+            &deepest,
+            0o644,
+            b"last".to_vec(),
+        ));
+        let original = b"root/0/1/3.txt".to_vec();
+        tree.push(timed(
+            ArchiveEntryHeaderType::Reference,
+            "copy.txt",
+            0o644,
+            original.clone(),
+        ));
+        tree.push(timed(
+            ArchiveEntryHeaderType::HardLink,
+            "link.txt",
+            0o644,
+            original,
+        ));
+
+        let archive = archive(tree);
+        let output = TempDir::new("parallel");
+        let mut archive = Archive::new(&archive[..]);
+        let mut entries = archive.entries(Box::new(NoDecompressor)).unwrap();
+        let stats = extract_parallel(&mut entries, &output, 4, |_| ExtractAction::Extract).unwrap();
+        assert_eq!(stats.entries, 20 * 6 + 3);
+
+        let mut directory = String::from("root");
+        for depth in 0..20 {
+            let path = output.join(&directory);
+            assert_eq!(modified(&path), Duration::from_secs(mtime(&directory)));
+            assert_eq!(mode(&path), if cfg!(unix) { 0o750 } else { 0 });
+            for i in 0..5 {
+                let name = format!("{directory}/{i}.txt");
+                let path = output.join(&name);
+                assert_eq!(
+                    std::fs::read(&path).unwrap(),
+                    name.repeat(depth * 100 + i).into_bytes()
+                );
+                assert_eq!(modified(&path), Duration::from_secs(mtime(&name)));
+                assert_eq!(mode(&path), if cfg!(unix) { 0o600 + i as u32 } else { 0 });
+            }
+            directory.push_str(&format!("/{depth}"));
+        }
+        assert_eq!(std::fs::read(output.join(&deepest)).unwrap(), b"last");
+        let copied = std::fs::read(output.join("root/0/1/3.txt")).unwrap();
+        assert_eq!(std::fs::read(output.join("copy.txt")).unwrap(), copied);
+        assert_eq!(std::fs::read(output.join("link.txt")).unwrap(), copied);
+    }
+}
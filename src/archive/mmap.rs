@@ -0,0 +1,89 @@
+use std::io::Read;
+
+/// Reads a file through a memory map of `size` bytes, its size when it was
+/// stat'd for its entry header, which saves a read call and a copy out of the
+/// page cache for every chunk. Data appended later is left out like with any
+/// bounded read.
+///
+/// A file truncated while mapped faults on the pages past its new end, so
+/// every read first checks the current size and stops there, as reading the
+/// file normally would. The check and the copy can still race with a
+/// truncation, so only files that aren't written to while archived should be
+/// mapped.
+///
+/// ```
+/// use ataf::archive::mmap::MappedFile;
+/// use std::io::Read;
+///
+/// let path = std::env::temp_dir().join(format!("ataf-doctest-mmap-{}", std::process::id()));
+/// std::fs::write(&path, vec![7; 3 * 4096]).unwrap();
+///
+/// let file = std::fs::File::open(&path).unwrap();
+/// let mut mapped = MappedFile::map(file, 3 * 4096).unwrap();
+/// let mut first = vec![0; 4096];
+/// mapped.read_exact(&mut first).unwrap();
+/// assert!(first.iter().all(|&byte| byte == 7));
+///
+/// // shrinking the file ends the map there instead of faulting
+/// std::fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(4096 + 100).unwrap();
+/// let mut rest = Vec::new();
+/// mapped.read_to_end(&mut rest).unwrap();
+/// assert_eq!(rest.len(), 100);
+///
+/// // nothing to map, the file is handed back to be read normally
+/// let empty = std::fs::File::create(&path).unwrap();
+/// assert!(MappedFile::map(empty, 0).is_err());
+///
+/// std::fs::remove_file(path).unwrap();
+/// ```
+pub struct MappedFile {
+    file: std::fs::File,
+    map: memmap2::Mmap,
+    offset: usize,
+}
+
+impl MappedFile {
+    /// Maps the first `size` bytes of `file`. When it can't be mapped, because
+    /// it's empty or on a filesystem without support for it, the file is
+    /// returned to be read normally.
+    pub fn map(file: std::fs::File, size: u64) -> Result<Self, std::fs::File> {
+        let Ok(length) = usize::try_from(size) else {
+            return Err(file);
+        };
+        if length == 0 {
+            return Err(file);
+        }
+
+        // SAFETY: the map is never read past the current size of the file,
+        // see `read`
+        let map = match unsafe { memmap2::MmapOptions::new().len(length).map(&file) } {
+            Ok(map) => map,
+            Err(_) => return Err(file),
+        };
+        #[cfg(unix)]
+        map.advise(memmap2::Advice::Sequential).ok();
+
+        Ok(Self {
+            file,
+            map,
+            offset: 0,
+        })
+    }
+}
+
+impl Read for MappedFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() || self.offset == self.map.len() {
+            return Ok(0);
+        }
+
+        let size = usize::try_from(self.file.metadata()?.len()).unwrap_or(usize::MAX);
+        let end = size.min(self.map.len());
+        let length = buf.len().min(end.saturating_sub(self.offset));
+
+        buf[..length].copy_from_slice(&self.map[self.offset..self.offset + length]);
+        self.offset += length;
+
+        Ok(length)
+    }
+}
@@ -1,2 +1,9 @@
+#[cfg(feature = "async")]
+pub mod async_io;
+pub mod extract;
+pub mod mmap;
+pub mod progress;
 pub mod read;
+pub mod sparse;
+pub mod volume;
 pub mod write;
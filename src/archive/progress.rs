@@ -0,0 +1,68 @@
+/// Whether an entry is about to be written or read, or is done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressStage {
+    Started,
+    Finished,
+}
+
+/// Passed to the callback set with `ArchiveWriter::set_progress` or
+/// `ArchiveEntriesReader::set_progress` for every entry, once when it starts
+/// and once when it is finished. Nothing about the rest of the archive has to
+/// be known, `processed_bytes` only counts what came before.
+///
+/// ```
+/// use ataf::{
+///     archive::{
+///         progress::{ProgressEvent, ProgressStage},
+///         read::Archive,
+///         write::ArchiveWriter,
+///     },
+///     compression::{NoCompressor, NoDecompressor},
+///     spec::ArchiveEntryHeader,
+/// };
+/// use std::sync::{Arc, Mutex};
+///
+/// fn recorder(events: Arc<Mutex<Vec<String>>>) -> impl FnMut(ProgressEvent<'_>) + Send {
+///     move |event| {
+///         let stage = match event.stage {
+///             ProgressStage::Started => "start",
+///             ProgressStage::Finished => "done",
+///         };
+///         events.lock().unwrap().push(format!(
+///             "{stage} {} {}/{}",
+///             event.path, event.processed_bytes, event.size
+///         ));
+///     }
+/// }
+///
+/// let expected = ["start a 0/3", "done a 3/3", "start b 3/5", "done b 8/5"];
+///
+/// let events = Arc::new(Mutex::new(Vec::new()));
+/// let mut writer = ArchiveWriter::new(Vec::new(), Box::new(NoCompressor::new()), 1024).unwrap();
+/// writer.set_progress(Box::new(recorder(Arc::clone(&events))));
+/// writer.write_entry(ArchiveEntryHeader::file("a", 3), &b"aaa"[..]).unwrap();
+/// writer.write_entry(ArchiveEntryHeader::file("b", 5), &b"bbbbb"[..]).unwrap();
+/// let archive = writer.finish().unwrap();
+/// assert_eq!(*events.lock().unwrap(), expected);
+///
+/// let events = Arc::new(Mutex::new(Vec::new()));
+/// let mut archive = Archive::new(&archive[..]);
+/// let mut entries = archive.entries(Box::new(NoDecompressor)).unwrap();
+/// entries.set_progress(Box::new(recorder(Arc::clone(&events))));
+/// while let Some(entry) = entries.next_entry() {
+///     entry.unwrap();
+/// }
+/// assert_eq!(*events.lock().unwrap(), expected);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgressEvent<'a> {
+    pub stage: ProgressStage,
+    pub path: &'a str,
+    /// The uncompressed size of the entry.
+    pub size: u64,
+    /// The uncompressed bytes of all finished entries, including this one
+    /// once it is finished.
+    pub processed_bytes: u64,
+}
+
+pub type ProgressCallback = Box<dyn FnMut(ProgressEvent<'_>) + Send>;
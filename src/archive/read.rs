@@ -1,106 +1,837 @@
 use crate::{
-    compression::Decompressor,
-    spec::{ArchiveEntryHeader, ArchiveHeader, Deserialize},
+    Error,
+    archive::progress::{ProgressCallback, ProgressEvent, ProgressStage},
+    compression::{CompressionRegistry, Decompressor, NoDecompressor},
+    spec::{
+        ARCHIVE_INDEX_MAGIC, ARCHIVE_INDEX_TRAILER_SIZE, ARCHIVE_VERSION, ArchiveEntryHeader,
+        ArchiveEntryHeaderType, ArchiveHeader, ArchiveIndex, ChunkChecksum, Deserialize,
+        ENTRIES_END, VariableSizedU64,
+    },
 };
-use std::io::Read;
+use std::{
+    collections::VecDeque,
+    io::{Read, Seek, SeekFrom},
+};
+
+/// Reads a big-endian chunk length, 4 bytes long since version 10 and 3 before.
+fn read_chunk_length(mut reader: impl Read, wide: bool) -> std::io::Result<u32> {
+    let mut bytes = [0; 4];
+    let start = if wide { 0 } else { 1 };
+    reader.read_exact(&mut bytes[start..])?;
 
-fn u24_bytes_to_u32(bytes: [u8; 3]) -> u32 {
-    ((bytes[0] as u32) << 16) | ((bytes[1] as u32) << 8) | (bytes[2] as u32)
+    Ok(u32::from_be_bytes(bytes))
 }
 
-pub struct Archive<R: Read> {
+struct PositionReader<R: Read> {
     reader: R,
+    position: u64,
+}
+
+impl<R: Read> Read for PositionReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let bytes_read = self.reader.read(buf)?;
+        self.position += bytes_read as u64;
+
+        Ok(bytes_read)
+    }
+}
+
+pub struct Archive<R: Read> {
+    reader: PositionReader<R>,
     header: Option<ArchiveHeader>,
+    ignore_header_checksum: bool,
 }
 
 impl<R: Read> Archive<R> {
     pub fn new(reader: R) -> Self {
         Self {
-            reader,
+            reader: PositionReader {
+                reader,
+                position: 0,
+            },
             header: None,
+            ignore_header_checksum: false,
         }
     }
 
-    pub fn header(&mut self) -> std::io::Result<&ArchiveHeader> {
+    /// Salvage option for archives whose header is damaged: the header is read
+    /// without verifying its checksum. Has to be set before the header is read.
+    pub fn ignore_header_checksum(&mut self, ignore: bool) {
+        self.ignore_header_checksum = ignore;
+    }
+
+    /// The offset in the archive up to which data has been consumed.
+    #[inline]
+    pub fn position(&self) -> u64 {
+        self.reader.position
+    }
+
+    pub fn header(&mut self) -> crate::Result<&ArchiveHeader> {
         if let Some(ref data) = self.header {
             return Ok(data);
         }
 
-        self.header = Some(ArchiveHeader::deserialize(&mut self.reader)?);
-        self.header.as_ref().ok_or_else(|| {
-            std::io::Error::new(std::io::ErrorKind::InvalidData, "Failed to read start data")
-        })
+        self.header = Some(if self.ignore_header_checksum {
+            ArchiveHeader::deserialize_unverified(&mut self.reader)?
+        } else {
+            ArchiveHeader::deserialize(&mut self.reader)?
+        });
+        self.header
+            .as_ref()
+            .ok_or_else(|| Error::InvalidData(String::from("Failed to read start data")))
     }
 
     pub fn entries(
         &mut self,
         decompressor: Box<dyn Decompressor>,
-    ) -> std::io::Result<ArchiveEntriesReader<'_, R>> {
+    ) -> crate::Result<ArchiveEntriesReader<'_, R>> {
         self.header()?;
 
-        Ok(ArchiveEntriesReader {
-            archive: self,
-            decompressor,
+        Ok(ArchiveEntriesReader::new(self, decompressor, None))
+    }
+
+    /// Like `entries`, with the decompressor for the header's compression
+    /// taken from `registry`.
+    pub fn entries_from<W: std::io::Write + Send, C: Read>(
+        &mut self,
+        registry: &CompressionRegistry<W, C>,
+        threads: usize,
+    ) -> crate::Result<ArchiveEntriesReader<'_, R>> {
+        let decompressor = registry.decompressor(&self.header()?.compression, threads)?;
+
+        self.entries(decompressor)
+    }
+
+    /// Iterates over the entry headers alone, reading past each entry's chunks
+    /// without decompressing them, which also works on readers that can't
+    /// seek. Solid archives list every header up front, so their data isn't
+    /// read at all.
+    ///
+    /// ```
+    /// use ataf::{
+    ///     archive::{read::Archive, write::ArchiveWriter},
+    ///     compression::NoCompressor,
+    ///     spec::ArchiveEntryHeader,
+    /// };
+    /// use std::io::Read;
+    ///
+    /// let sizes = [("large", 100_000), ("empty", 0), ("small", 5)];
+    ///
+    /// let mut writer = ArchiveWriter::new(Vec::new(), Box::new(NoCompressor::new()), 4096).unwrap();
+    /// for (path, size) in sizes {
+    ///     let input = std::io::repeat(1).take(size as u64);
+    ///     writer.write_entry(ArchiveEntryHeader::file(path, size as u64), input).unwrap();
+    /// }
+    /// let bytes = writer.finish().unwrap();
+    ///
+    /// let mut solid = ArchiveWriter::new_solid(Vec::new(), Box::new(NoCompressor::new()), 4096)
+    ///     .unwrap();
+    /// let entries = sizes
+    ///     .iter()
+    ///     .map(|&(path, size)| {
+    ///         let input: Box<dyn Read> = Box::new(std::io::repeat(1).take(size as u64));
+    ///         (ArchiveEntryHeader::file(path, size as u64), input)
+    ///     })
+    ///     .collect();
+    /// solid.write_solid(entries).unwrap();
+    /// let solid = solid.finish().unwrap();
+    ///
+    /// for bytes in [bytes, solid] {
+    ///     // slices can't seek
+    ///     let mut archive = Archive::new(&bytes[..]);
+    ///     let headers = archive
+    ///         .headers()
+    ///         .unwrap()
+    ///         .map(|header| header.map(|header| (header.path, *header.size)))
+    ///         .collect::<Result<Vec<_>, _>>()
+    ///         .unwrap();
+    ///     assert_eq!(headers, sizes.map(|(path, size)| (String::from(path), size as u64)));
+    /// }
+    /// ```
+    pub fn headers(&mut self) -> crate::Result<ArchiveHeaders<'_, R>> {
+        Ok(ArchiveHeaders {
+            // chunks are only ever read past, never decompressed
+            entries: self.entries(Box::new(NoDecompressor))?,
         })
     }
 }
 
+impl<R: Read + Seek> Archive<R> {
+    /// Reads the index from the end of the archive without reading through
+    /// the entries, `None` for archives written without one or never finished.
+    /// The read position is left where it was.
+    ///
+    /// ```
+    /// use ataf::{
+    ///     archive::{
+    ///         read::Archive,
+    ///         write::{ArchiveOptions, ArchiveWriter},
+    ///     },
+    ///     compression::{NoCompressor, NoDecompressor},
+    ///     spec::ArchiveEntryHeader,
+    /// };
+    /// use std::io::Cursor;
+    ///
+    /// let data: Vec<Vec<u8>> = (0..3).map(|i| vec![i; 5000]).collect();
+    /// let options = ArchiveOptions { index: true, ..Default::default() };
+    /// let mut writer =
+    ///     ArchiveWriter::with_options(Vec::new(), Box::new(NoCompressor::new()), 1024, options)
+    ///         .unwrap();
+    /// for (i, data) in data.iter().enumerate() {
+    ///     let header = ArchiveEntryHeader::file(format!("{i}.bin"), data.len() as u64);
+    ///     writer.write_entry(header, &data[..]).unwrap();
+    /// }
+    /// let bytes = writer.finish().unwrap();
+    ///
+    /// let mut archive = Archive::new(Cursor::new(&bytes[..]));
+    /// let index = archive.index().unwrap().unwrap();
+    /// let paths: Vec<&str> = index.entries.iter().map(|entry| entry.path.as_str()).collect();
+    /// assert_eq!(paths, ["0.bin", "1.bin", "2.bin"]);
+    ///
+    /// // the offsets lead straight to an entry
+    /// let range = index.entries[1].offset..index.entries[2].offset;
+    /// let mut entries = archive.entries_in_range(Box::new(NoDecompressor), range).unwrap();
+    /// let (header, data) = entries.next_file().unwrap().unwrap();
+    /// assert_eq!((header.path.as_str(), data), ("1.bin", vec![1; 5000]));
+    /// assert!(entries.next_entry().is_none());
+    ///
+    /// // sequential readers stop in front of the index
+    /// let mut archive = Archive::new(&bytes[..]);
+    /// let mut entries = archive.entries(Box::new(NoDecompressor)).unwrap();
+    /// let mut count = 0;
+    /// while let Some(entry) = entries.next_entry() {
+    ///     entry.unwrap();
+    ///     count += 1;
+    /// }
+    /// assert_eq!(count, 3);
+    /// ```
+    pub fn index(&mut self) -> crate::Result<Option<ArchiveIndex>> {
+        if !self.header()?.index {
+            return Ok(None);
+        }
+
+        let index = read_index(&mut self.reader.reader);
+        self.reader
+            .reader
+            .seek(SeekFrom::Start(self.reader.position))?;
+
+        index
+    }
+
+    /// Iterates only the entries located in `range`, which must start and end
+    /// on entry boundaries (for example offsets taken from the archive index).
+    /// Entries starting at or after `range.end` are not returned.
+    pub fn entries_in_range(
+        &mut self,
+        decompressor: Box<dyn Decompressor>,
+        range: std::ops::Range<u64>,
+    ) -> crate::Result<ArchiveEntriesReader<'_, R>> {
+        let header = self.header()?;
+        if header.solid || header.prefix_paths {
+            return Err(Error::InvalidOperation(
+                "solid archives and archives with front-coded paths can only be read sequentially",
+            ));
+        }
+
+        self.reader.reader.seek(SeekFrom::Start(range.start))?;
+        self.reader.position = range.start;
+
+        Ok(ArchiveEntriesReader::new(
+            self,
+            decompressor,
+            Some(range.end),
+        ))
+    }
+}
+
+fn report_progress(
+    progress: &mut Option<ProgressCallback>,
+    stage: ProgressStage,
+    header: &ArchiveEntryHeader,
+    processed_bytes: u64,
+) {
+    if let Some(progress) = progress {
+        progress(ProgressEvent {
+            stage,
+            path: &header.path,
+            size: *header.size,
+            processed_bytes,
+        });
+    }
+}
+
+/// Reads the index the trailer at the end of `reader` points to, if there is
+/// a trailer.
+fn read_index(mut reader: impl Read + Seek) -> crate::Result<Option<ArchiveIndex>> {
+    let length = reader.seek(SeekFrom::End(0))?;
+    if length < ARCHIVE_INDEX_TRAILER_SIZE {
+        return Ok(None);
+    }
+
+    let trailer_offset = length - ARCHIVE_INDEX_TRAILER_SIZE;
+    reader.seek(SeekFrom::Start(trailer_offset))?;
+    let mut trailer = [0; ARCHIVE_INDEX_TRAILER_SIZE as usize];
+    reader.read_exact(&mut trailer)?;
+    if trailer[8..] != ARCHIVE_INDEX_MAGIC {
+        return Ok(None);
+    }
+
+    let offset = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+    if offset > trailer_offset {
+        return Err(Error::InvalidData(String::from(
+            "index offset points past the end of the archive",
+        )));
+    }
+
+    reader.seek(SeekFrom::Start(offset))?;
+    ArchiveIndex::deserialize(reader.take(trailer_offset - offset)).map(Some)
+}
+
+/// Reads an entry header, front-coded against `previous_path` when it is set,
+/// which is then updated to the path just read.
+fn read_header(
+    input: impl Read,
+    version: u32,
+    previous_path: &mut Option<String>,
+) -> crate::Result<ArchiveEntryHeader> {
+    let header =
+        ArchiveEntryHeader::deserialize_version_after(input, version, previous_path.as_deref())?;
+
+    if let Some(previous_path) = previous_path {
+        previous_path.clone_from(&header.path);
+    }
+
+    Ok(header)
+}
+
+/// Decompressed data and chunk progress of the data stream currently being
+/// read, which is a single entry's data or, in solid archives, all entries.
+struct ChunkStream {
+    /// The decompressed data of the last batch of chunks, of which everything
+    /// before `offset` was read already.
+    buffer: Vec<u8>,
+    offset: usize,
+    chunks: u64,
+    read_chunks: u64,
+
+    /// Ignores the chunk count derived from the header's chunk size and reads
+    /// framed chunks until `size` bytes have been decoded instead.
+    framed: bool,
+    size: u64,
+    decoded: u64,
+}
+
+impl ChunkStream {
+    fn reset(&mut self, size: u64, chunk_size: u32) {
+        self.clear();
+        if !self.framed {
+            self.chunks = size.div_ceil(chunk_size as u64);
+        }
+        self.read_chunks = 0;
+        self.size = size;
+        self.decoded = 0;
+    }
+
+    fn clear(&mut self) {
+        self.buffer.clear();
+        self.offset = 0;
+    }
+
+    fn unread(&self) -> &[u8] {
+        &self.buffer[self.offset..]
+    }
+
+    fn has_chunks(&self) -> bool {
+        if self.framed {
+            self.decoded < self.size
+        } else {
+            self.read_chunks < self.chunks
+        }
+    }
+}
+
 pub struct ArchiveEntriesReader<'a, R: Read> {
     archive: &'a mut Archive<R>,
     decompressor: Box<dyn Decompressor>,
+    raw_decompressor: Box<dyn Decompressor>,
+    end: Option<u64>,
+
+    stream: ChunkStream,
+    solid_headers: Option<VecDeque<ArchiveEntryHeader>>,
+    previous_path: Option<String>,
+
+    progress: Option<ProgressCallback>,
+    processed_bytes: u64,
 }
 
 impl<'a, R: Read> ArchiveEntriesReader<'a, R> {
-    pub fn next_entry<'b>(&'b mut self) -> Option<std::io::Result<ArchiveEntry<'b, R>>> {
-        let header = match ArchiveEntryHeader::deserialize(&mut self.archive.reader) {
-            Ok(header) => header,
+    fn new(
+        archive: &'a mut Archive<R>,
+        decompressor: Box<dyn Decompressor>,
+        end: Option<u64>,
+    ) -> Self {
+        let previous_path = archive
+            .header
+            .as_ref()
+            .is_some_and(|h| h.prefix_paths)
+            .then(String::new);
+
+        Self {
+            archive,
+            decompressor,
+            raw_decompressor: Box::new(NoDecompressor),
+            end,
+            stream: ChunkStream {
+                buffer: Vec::new(),
+                offset: 0,
+                chunks: 0,
+                read_chunks: 0,
+                framed: false,
+                size: 0,
+                decoded: 0,
+            },
+            solid_headers: None,
+            previous_path,
+            progress: None,
+            processed_bytes: 0,
+        }
+    }
+
+    /// Calls `progress` when an entry is returned and once it is dropped,
+    /// which is when its remaining data is read past.
+    pub fn set_progress(&mut self, progress: ProgressCallback) {
+        self.progress = Some(progress);
+    }
+
+    /// Salvage option for archives whose header has a corrupt chunk size: data
+    /// is read chunk by chunk, following the chunk framing until each entry's
+    /// declared size is decoded, so the header's chunk size is never used.
+    ///
+    /// This only helps when the chunk framing and entry sizes are intact and
+    /// is best-effort, an entry whose size doesn't match its chunks still
+    /// desynchronizes every entry after it.
+    pub fn ignore_header_chunk_size(&mut self, ignore: bool) {
+        self.stream.framed = ignore;
+    }
+
+    fn read_solid_headers(&mut self) -> crate::Result<VecDeque<ArchiveEntryHeader>> {
+        let version = self
+            .archive
+            .header
+            .as_ref()
+            .map_or(ARCHIVE_VERSION, |h| h.version);
+        let count = *VariableSizedU64::deserialize(&mut self.archive.reader)?;
+
+        let mut headers = VecDeque::new();
+        let mut total_size = 0u64;
+        for _ in 0..count {
+            let header = read_header(&mut self.archive.reader, version, &mut self.previous_path)?;
+            total_size += *header.size;
+            headers.push_back(header);
+        }
+
+        let compression_chunk_size = self
+            .archive
+            .header
+            .as_ref()
+            .map_or(0, |h| h.compression_chunk_size);
+        self.stream.reset(total_size, compression_chunk_size);
+
+        Ok(headers)
+    }
+
+    /// Returns the next file entry with its data read into memory, skipping
+    /// all other entry types.
+    pub fn next_file(&mut self) -> Option<crate::Result<(ArchiveEntryHeader, Vec<u8>)>> {
+        loop {
+            let entry = match self.next_entry()? {
+                Ok(entry) => entry,
+                Err(err) => return Some(Err(err)),
+            };
+
+            if entry.header().r#type == ArchiveEntryHeaderType::File {
+                let header = entry.header().clone();
+                return Some(entry.read_to_vec().map(|data| (header, data)));
+            }
+        }
+    }
+
+    /// Reads the header of the next entry, `None` once the archive ends. An
+    /// archive that ends partway through a header is an error rather than its
+    /// end, so a cut off archive isn't mistaken for a complete one:
+    ///
+    /// ```
+    /// use ataf::{
+    ///     archive::{read::Archive, write::ArchiveWriter},
+    ///     compression::{NoCompressor, NoDecompressor},
+    ///     spec::ArchiveEntryHeader,
+    /// };
+    ///
+    /// let mut writer = ArchiveWriter::new(Vec::new(), Box::new(NoCompressor::new()), 1024).unwrap();
+    /// writer.write_entry(ArchiveEntryHeader::file("first", 5), &b"first"[..]).unwrap();
+    /// let first_end = writer.get_mut().len();
+    /// writer.write_entry(ArchiveEntryHeader::file("second", 6), &b"second"[..]).unwrap();
+    /// let archive = writer.finish().unwrap();
+    ///
+    /// // ending right after an entry is a clean end
+    /// let mut reader = Archive::new(&archive[..first_end]);
+    /// let mut entries = reader.entries(Box::new(NoDecompressor)).unwrap();
+    /// entries.next_entry().unwrap().unwrap().skip().unwrap();
+    /// assert!(entries.next_entry().is_none());
+    ///
+    /// // ending within the next header isn't
+    /// for end in first_end + 1..first_end + 8 {
+    ///     let mut reader = Archive::new(&archive[..end]);
+    ///     let mut entries = reader.entries(Box::new(NoDecompressor)).unwrap();
+    ///     entries.next_entry().unwrap().unwrap().skip().unwrap();
+    ///     let err = entries.next_entry().unwrap().err().unwrap();
+    ///     assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    ///     assert!(err.to_string().contains("partway through an entry header"));
+    /// }
+    /// ```
+    pub fn next_entry<'b>(&'b mut self) -> Option<crate::Result<ArchiveEntry<'b, R>>> {
+        let compression_chunk_size = self
+            .archive
+            .header
+            .as_ref()
+            .map_or(0, |h| h.compression_chunk_size);
+        let checksum = self
+            .archive
+            .header
+            .as_ref()
+            .map_or(ChunkChecksum::None, |h| h.checksum);
+        let wide_chunk_lengths = self.archive.header.as_ref().is_none_or(|h| h.version >= 10);
+
+        if self.archive.header.as_ref().is_some_and(|h| h.solid) {
+            if self.solid_headers.is_none() {
+                match self.read_solid_headers() {
+                    Ok(headers) => self.solid_headers = Some(headers),
+                    Err(err) => return Some(Err(err)),
+                }
+            }
+
+            let header = self.solid_headers.as_mut()?.pop_front()?;
+            report_progress(
+                &mut self.progress,
+                ProgressStage::Started,
+                &header,
+                self.processed_bytes,
+            );
+
+            return Some(Ok(ArchiveEntry {
+                reader: &mut self.archive.reader,
+                decompressor: &mut self.decompressor,
+                compression_chunk_size,
+                checksum,
+                wide_chunk_lengths,
+                solid: true,
+                stream: &mut self.stream,
+                progress: &mut self.progress,
+                processed_bytes: &mut self.processed_bytes,
+                read_bytes: 0,
+                header,
+            }));
+        }
+
+        if self
+            .end
+            .is_some_and(|end| self.archive.reader.position >= end)
+        {
+            return None;
+        }
+
+        let mut first_byte = [0; 1];
+        match self.archive.reader.read_exact(&mut first_byte) {
+            Ok(()) => {}
             Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return None,
+            Err(err) => return Some(Err(err.into())),
+        }
+        if first_byte[0] == ENTRIES_END && self.archive.header.as_ref().is_some_and(|h| h.index) {
+            return None;
+        }
+
+        let version = self
+            .archive
+            .header
+            .as_ref()
+            .map_or(ARCHIVE_VERSION, |h| h.version);
+        let input = (&first_byte[..]).chain(&mut self.archive.reader);
+
+        let header = match read_header(input, version, &mut self.previous_path) {
+            Ok(header) => header,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                return Some(Err(Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "archive ended partway through an entry header",
+                ))));
+            }
             Err(err) => return Some(Err(err)),
         };
 
-        let compression_chunk_size = self
+        let compression = self
             .archive
             .header
             .as_ref()
-            .map_or(0, |h| h.compression_chunk_size);
+            .map_or("none", |h| h.compression.as_str());
+
+        let decompressor = match header.compression.as_deref() {
+            None => &mut self.decompressor,
+            Some(entry_compression) if entry_compression == compression => &mut self.decompressor,
+            Some("none") => &mut self.raw_decompressor,
+            Some(entry_compression) => {
+                return Some(Err(Error::UnsupportedCompression(String::from(
+                    entry_compression,
+                ))));
+            }
+        };
+
+        self.stream.reset(*header.size, compression_chunk_size);
+        report_progress(
+            &mut self.progress,
+            ProgressStage::Started,
+            &header,
+            self.processed_bytes,
+        );
 
         Some(Ok(ArchiveEntry {
             reader: &mut self.archive.reader,
-            decompressor: &mut self.decompressor,
+            decompressor,
             compression_chunk_size,
-            compression_chunk_buffer: Vec::new(),
+            checksum,
+            wide_chunk_lengths,
+            solid: false,
+            stream: &mut self.stream,
+            progress: &mut self.progress,
+            processed_bytes: &mut self.processed_bytes,
             read_bytes: 0,
-            chunks: *header.size / compression_chunk_size as u64
-                + if *header.size % compression_chunk_size as u64 > 0 {
-                    1
-                } else {
-                    0
-                },
-            read_chunks: 0,
             header,
         }))
     }
 }
 
+/// The entry headers of an archive, see `Archive::headers`.
+pub struct ArchiveHeaders<'a, R: Read> {
+    entries: ArchiveEntriesReader<'a, R>,
+}
+
+impl<'a, R: Read> Iterator for ArchiveHeaders<'a, R> {
+    type Item = crate::Result<ArchiveEntryHeader>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entries = &mut self.entries;
+
+        if entries.archive.header.as_ref().is_some_and(|h| h.solid) {
+            if entries.solid_headers.is_none() {
+                match entries.read_solid_headers() {
+                    Ok(headers) => entries.solid_headers = Some(headers),
+                    Err(err) => return Some(Err(err)),
+                }
+            }
+
+            return entries.solid_headers.as_mut()?.pop_front().map(Ok);
+        }
+
+        let entry = match entries.next_entry()? {
+            Ok(entry) => entry,
+            Err(err) => return Some(Err(err)),
+        };
+        let header = entry.header().clone();
+
+        Some(entry.skip().map(|()| header))
+    }
+}
+
 pub struct ArchiveEntry<'a, R: Read> {
-    reader: &'a mut R,
+    reader: &'a mut PositionReader<R>,
     decompressor: &'a mut Box<dyn Decompressor>,
 
     compression_chunk_size: u32,
-    compression_chunk_buffer: Vec<u8>,
+    checksum: ChunkChecksum,
+    wide_chunk_lengths: bool,
+    solid: bool,
+    stream: &'a mut ChunkStream,
+    progress: &'a mut Option<ProgressCallback>,
+    processed_bytes: &'a mut u64,
 
     header: ArchiveEntryHeader,
     read_bytes: u64,
-
-    chunks: u64,
-    read_chunks: u64,
 }
 
+/// The most a declared entry size preallocates, larger entries grow as read
+/// so a corrupt or hostile size can't trigger a huge allocation up front.
+const MAX_PREALLOCATION: u64 = 16 * 1024 * 1024;
+
 impl<'a, R: Read> ArchiveEntry<'a, R> {
     #[inline]
     pub fn header(&self) -> &ArchiveEntryHeader {
         &self.header
     }
+
+    /// Reads the whole entry data into memory. An archive ending within the
+    /// data fails with `Error::Truncated` and a chunk not matching its stored
+    /// checksum with `Error::ChecksumMismatch`.
+    ///
+    /// ```
+    /// use ataf::compression::CompressionFormat;
+    /// use ataf::{archive::read::Archive, compression::NoDecompressor, spec::ArchiveEntryHeader};
+    ///
+    /// let header = ArchiveEntryHeader::file("file.txt", 4);
+    /// let data: Box<dyn std::io::Read> = Box::new(&b"data"[..]);
+    /// let mut archive = Vec::new();
+    /// ataf::build_archive(&mut archive, CompressionFormat::None, [(header, data)]).unwrap();
+    ///
+    /// let mut archive = Archive::new(&archive[..]);
+    /// let mut entries = archive.entries(Box::new(NoDecompressor)).unwrap();
+    /// let entry = entries.next_entry().unwrap().unwrap();
+    /// assert_eq!(entry.read_to_vec().unwrap(), b"data");
+    /// ```
+    pub fn read_to_vec(mut self) -> crate::Result<Vec<u8>> {
+        let mut data =
+            Vec::with_capacity(std::cmp::min(*self.header.size, MAX_PREALLOCATION) as usize);
+        self.read_to_end(&mut data)?;
+
+        Ok(data)
+    }
+
+    /// Moves past the rest of the entry's data without decompressing it, only
+    /// the chunk lengths are read. Checksums aren't verified. Entries of solid
+    /// archives share their chunks with the entries around them and entries
+    /// read with `ignore_header_chunk_size` have no known chunk count, both are
+    /// still decompressed.
+    ///
+    /// ```
+    /// use ataf::compression::CompressionFormat;
+    /// use ataf::{
+    ///     archive::read::Archive,
+    ///     compression::{Decompressor, NoDecompressor},
+    ///     spec::ArchiveEntryHeader,
+    /// };
+    /// use std::{cell::Cell, rc::Rc};
+    ///
+    /// struct CountingDecompressor(Rc<Cell<usize>>);
+    ///
+    /// impl Decompressor for CountingDecompressor {
+    ///     fn decompress_inputs(&mut self) -> usize {
+    ///         1
+    ///     }
+    ///
+    ///     fn decompress(
+    ///         &mut self,
+    ///         inputs: Vec<Vec<u8>>,
+    ///         output: &mut Vec<u8>,
+    ///         chunk_size: u32,
+    ///     ) -> std::io::Result<()> {
+    ///         self.0.set(self.0.get() + inputs.len());
+    ///         NoDecompressor.decompress(inputs, output, chunk_size)
+    ///     }
+    /// }
+    ///
+    /// fn file(path: &str, data: &'static [u8]) -> (ArchiveEntryHeader, Box<dyn std::io::Read>) {
+    ///     let header = ArchiveEntryHeader::file(path, data.len() as u64);
+    ///
+    ///     (header, Box::new(data))
+    /// }
+    ///
+    /// let mut archive = Vec::new();
+    /// ataf::build_archive(
+    ///     &mut archive,
+    ///     CompressionFormat::None,
+    ///     [file("skipped", &[7; 100_000]), file("read", b"data")],
+    /// )
+    /// .unwrap();
+    ///
+    /// let chunks = Rc::new(Cell::new(0));
+    /// let mut archive = Archive::new(&archive[..]);
+    /// let mut entries = archive
+    ///     .entries(Box::new(CountingDecompressor(Rc::clone(&chunks))))
+    ///     .unwrap();
+    ///
+    /// entries.next_entry().unwrap().unwrap().skip().unwrap();
+    /// assert_eq!(chunks.get(), 0);
+    ///
+    /// let (header, data) = entries.next_file().unwrap().unwrap();
+    /// assert_eq!((header.path.as_str(), &data[..]), ("read", &b"data"[..]));
+    /// assert_eq!(chunks.get(), 1);
+    /// ```
+    pub fn skip(self) -> crate::Result<()> {
+        self.skip_chunks(|reader, to_skip| {
+            let skipped = std::io::copy(&mut reader.take(to_skip), &mut std::io::sink())?;
+            if skipped < to_skip {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Walks the remaining chunk lengths, leaving the stored chunks to `advance`.
+    fn skip_chunks(
+        mut self,
+        mut advance: impl FnMut(&mut PositionReader<R>, u64) -> std::io::Result<()>,
+    ) -> crate::Result<()> {
+        if self.solid || self.stream.framed {
+            std::io::copy(&mut self, &mut std::io::sink())?;
+            return Ok(());
+        }
+
+        // anything already decompressed belongs to this entry alone
+        self.stream.clear();
+
+        while self.stream.has_chunks() {
+            let to_skip = read_chunk_length(&mut self.reader, self.wide_chunk_lengths)
+                .map_err(|err| self.truncated(err))? as u64
+                + self.checksum.size() as u64;
+
+            advance(self.reader, to_skip).map_err(|err| self.truncated(err))?;
+            self.stream.read_chunks += 1;
+        }
+
+        self.read_bytes = *self.header.size;
+
+        Ok(())
+    }
+}
+
+impl<'a, R: Read + Seek> ArchiveEntry<'a, R> {
+    /// Like `skip`, but seeks past the stored chunks instead of reading them,
+    /// so only the chunk lengths are read. Seeking past the end of a truncated
+    /// archive goes unnoticed until the next read.
+    ///
+    /// ```
+    /// use ataf::{
+    ///     archive::{read::Archive, write::ArchiveWriter},
+    ///     compression::{NoCompressor, NoDecompressor},
+    ///     spec::ArchiveEntryHeader,
+    /// };
+    /// use std::io::Cursor;
+    ///
+    /// let (large, small) = (vec![1; 100_000], b"small");
+    /// let mut writer = ArchiveWriter::new(Vec::new(), Box::new(NoCompressor::new()), 4096).unwrap();
+    /// writer
+    ///     .write_entry(ArchiveEntryHeader::file("large", large.len() as u64), &large[..])
+    ///     .unwrap();
+    /// writer
+    ///     .write_entry(ArchiveEntryHeader::file("small", small.len() as u64), &small[..])
+    ///     .unwrap();
+    /// let bytes = writer.finish().unwrap();
+    ///
+    /// let mut archive = Archive::new(Cursor::new(&bytes[..]));
+    /// let mut entries = archive.entries(Box::new(NoDecompressor)).unwrap();
+    /// entries.next_entry().unwrap().unwrap().skip_seeking().unwrap();
+    ///
+    /// let (header, data) = entries.next_file().unwrap().unwrap();
+    /// assert_eq!((header.path.as_str(), &data[..]), ("small", &small[..]));
+    /// drop(entries);
+    /// assert_eq!(archive.position(), bytes.len() as u64);
+    /// ```
+    pub fn skip_seeking(self) -> crate::Result<()> {
+        self.skip_chunks(|reader, to_skip| {
+            reader.reader.seek_relative(to_skip as i64)?;
+            reader.position += to_skip;
+
+            Ok(())
+        })
+    }
 }
 
 impl<'a, R: Read> Read for ArchiveEntry<'a, R> {
@@ -109,23 +840,37 @@ impl<'a, R: Read> Read for ArchiveEntry<'a, R> {
             return Ok(0);
         }
 
-        if !self.compression_chunk_buffer.is_empty() {
-            let to_read = std::cmp::min(buf.len(), self.compression_chunk_buffer.len());
-            let data = self.compression_chunk_buffer.drain(0..to_read);
-            for (i, byte) in data.enumerate() {
-                buf[i] = byte;
-            }
+        let unread = self.stream.unread();
+        if !unread.is_empty() {
+            // in solid archives the buffer may already hold the next entry's data
+            let to_read = buf
+                .len()
+                .min(unread.len())
+                .min((*self.header.size - self.read_bytes).min(usize::MAX as u64) as usize);
+            buf[..to_read].copy_from_slice(&unread[..to_read]);
 
+            self.stream.offset += to_read;
             self.read_bytes += to_read as u64;
 
             Ok(to_read)
         } else {
-            let decompress_inputs = self.decompressor.decompress_inputs();
+            // the batch is used up, its buffer is refilled with the next one
+            self.stream.clear();
 
-            if self.compression_chunk_buffer.capacity()
-                < self.compression_chunk_size as usize * decompress_inputs
+            // without a trusted chunk size there is no telling how many chunks
+            // the remaining data spans, so they are decoded one at a time
+            let decompress_inputs = if self.stream.framed {
+                1
+            } else {
+                self.decompressor.decompress_inputs()
+            };
+
+            if !self.stream.framed
+                && self.stream.buffer.capacity()
+                    < self.compression_chunk_size as usize * decompress_inputs
             {
-                self.compression_chunk_buffer
+                self.stream
+                    .buffer
                     .reserve_exact(self.compression_chunk_size as usize * decompress_inputs);
             }
 
@@ -133,37 +878,174 @@ impl<'a, R: Read> Read for ArchiveEntry<'a, R> {
             chunk_buffers.reserve_exact(decompress_inputs);
 
             for _ in 0..decompress_inputs {
-                if self.read_chunks >= self.chunks {
+                if !self.stream.has_chunks() {
                     break;
                 }
 
-                let mut raw_chunk_size_bytes = [0; 3];
-                self.reader.read_exact(&mut raw_chunk_size_bytes)?;
-                let raw_chunk_size = u24_bytes_to_u32(raw_chunk_size_bytes);
-
-                let mut chunk_buffer = vec![0; raw_chunk_size as usize];
-                self.reader.read_exact(&mut chunk_buffer)?;
-
-                self.read_chunks += 1;
+                let chunk_buffer = self.read_chunk().map_err(|err| self.truncated(err))?;
+                self.stream.read_chunks += 1;
 
                 chunk_buffers.push(chunk_buffer);
             }
 
-            self.decompressor.decompress(
-                chunk_buffers,
-                &mut self.compression_chunk_buffer,
-                self.compression_chunk_size,
-            )?;
+            if chunk_buffers.is_empty() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "entry data extends past the end of its chunk stream",
+                ));
+            }
+
+            let chunk_size = if self.stream.framed {
+                chunk_buffers[0].len() as u32
+            } else {
+                self.compression_chunk_size
+            };
+            self.decompressor
+                .decompress(chunk_buffers, &mut self.stream.buffer, chunk_size)?;
+            self.stream.decoded += self.stream.buffer.len() as u64;
 
             self.read(buf)
         }
     }
 }
 
+impl<'a, R: Read> ArchiveEntry<'a, R> {
+    /// Reads the next stored chunk, checking its checksum.
+    fn read_chunk(&mut self) -> std::io::Result<Vec<u8>> {
+        let raw_chunk_size = read_chunk_length(&mut self.reader, self.wide_chunk_lengths)?;
+
+        let mut checksum_bytes = [0; 8];
+        let checksum_bytes = &mut checksum_bytes[..self.checksum.size()];
+        self.reader.read_exact(checksum_bytes)?;
+
+        // chunks can be up to 4 GiB, a corrupt length mustn't allocate that
+        let mut chunk_buffer =
+            Vec::with_capacity(std::cmp::min(raw_chunk_size as u64, MAX_PREALLOCATION) as usize);
+        (&mut self.reader)
+            .take(raw_chunk_size as u64)
+            .read_to_end(&mut chunk_buffer)?;
+        if chunk_buffer.len() < raw_chunk_size as usize {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+
+        if !self.checksum.matches(&chunk_buffer, checksum_bytes) {
+            return Err(Error::ChecksumMismatch {
+                path: self.header.path.clone(),
+                chunk: self.stream.read_chunks,
+            }
+            .into());
+        }
+
+        Ok(chunk_buffer)
+    }
+
+    /// Names this entry and where its data ended when `err` is the archive
+    /// running out, which a bare end of file doesn't tell.
+    fn truncated(&self, err: std::io::Error) -> std::io::Error {
+        match err.kind() {
+            std::io::ErrorKind::UnexpectedEof => Error::Truncated {
+                path: self.header.path.clone(),
+                offset: self.reader.position,
+            }
+            .into(),
+            _ => err,
+        }
+    }
+}
+
 impl<'a, R: Read> Drop for ArchiveEntry<'a, R> {
     fn drop(&mut self) {
+        // corrupt data was already reported by the read that hit it, the
+        // next entry header read fails as well since the stream is out of sync
         if self.read_bytes < *self.header.size {
-            std::io::copy(self, &mut std::io::sink()).unwrap();
+            std::io::copy(self, &mut std::io::sink()).ok();
         }
+
+        *self.processed_bytes += *self.header.size;
+        report_progress(
+            self.progress,
+            ProgressStage::Finished,
+            &self.header,
+            *self.processed_bytes,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        archive::write::{ArchiveOptions, ArchiveWriter},
+        compression::NoCompressor,
+        test_util::{archive, entry},
+    };
+
+    #[test]
+    fn truncated_entries_name_where_the_archive_ends() {
+        let mut archive = archive([entry(ArchiveEntryHeaderType::File, "large", [7; 100_000])]);
+        archive.truncate(archive.len() / 2);
+
+        let mut reader = Archive::new(&archive[..]);
+        let mut entries = reader.entries(Box::new(NoDecompressor)).unwrap();
+        match entries.next_file().unwrap() {
+            Err(Error::Truncated { path, offset }) => {
+                assert_eq!((path.as_str(), offset), ("large", archive.len() as u64));
+            }
+            other => panic!("expected a truncated entry, got {other:?}"),
+        }
+
+        // skipping the entry instead runs into the same end
+        let mut reader = Archive::new(&archive[..]);
+        let mut entries = reader.entries(Box::new(NoDecompressor)).unwrap();
+        let entry = entries.next_entry().unwrap().unwrap();
+        assert!(matches!(entry.skip(), Err(Error::Truncated { .. })));
+
+        // and dropping it half read doesn't panic
+        let mut reader = Archive::new(&archive[..]);
+        let mut entries = reader.entries(Box::new(NoDecompressor)).unwrap();
+        drop(entries.next_entry().unwrap().unwrap());
+    }
+
+    #[test]
+    fn corrupt_chunks_fail_their_checksum() {
+        let options = ArchiveOptions {
+            checksum: ChunkChecksum::Crc32,
+            ..Default::default()
+        };
+
+        // chunks of 1024, 1024 and 952 bytes, each with its length and CRC-32
+        let mut writer =
+            ArchiveWriter::with_options(Vec::new(), Box::new(NoCompressor::new()), 1024, options)
+                .unwrap();
+        writer
+            .write_entry(ArchiveEntryHeader::file("data", 3000), &[7; 3000][..])
+            .unwrap();
+        let mut archive = writer.finish().unwrap();
+
+        // the last byte of the second chunk, before the last chunk and the end marker
+        let flipped = archive.len() - 1 - (4 + 4 + 952) - 1;
+        archive[flipped] ^= 0xff;
+
+        let mut reader = Archive::new(&archive[..]);
+        let mut entries = reader.entries(Box::new(NoDecompressor)).unwrap();
+        let err = entries.next_file().unwrap().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        match err {
+            Error::ChecksumMismatch { path, chunk } => {
+                assert_eq!((path.as_str(), chunk), ("data", 1))
+            }
+            other => panic!("expected a checksum mismatch, got {other:?}"),
+        }
+
+        // std::io errors keep the kind and convert back
+        let err = std::io::Error::from(Error::ChecksumMismatch {
+            path: String::from("data"),
+            chunk: 1,
+        });
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(matches!(
+            Error::from(err),
+            Error::ChecksumMismatch { chunk: 1, .. }
+        ));
     }
 }
@@ -0,0 +1,300 @@
+use crate::spec::{SparseMap, SparseSegment};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// Finds the holes of `file` with `SEEK_DATA` and `SEEK_HOLE`. Returns `None`
+/// when it has none, or the platform or filesystem can't tell. The position of
+/// `file` is reset to its start.
+///
+/// A mostly empty file is archived as its data only, and extracted with the
+/// holes recreated:
+///
+/// ```
+/// use ataf::compression::CompressionFormat;
+/// use ataf::{
+///     archive::{
+///         extract::{ExtractAction, extract_filtered},
+///         read::Archive,
+///         sparse::{SparseReader, find_holes},
+///     },
+///     compression::NoDecompressor,
+///     spec::ArchiveEntryHeader,
+/// };
+/// use std::io::{Seek, SeekFrom, Write};
+///
+/// let root = std::env::temp_dir().join(format!("ataf-doctest-sparse-{}", std::process::id()));
+/// std::fs::create_dir_all(&root).unwrap();
+///
+/// // 16 MiB with a little data at the start and the end
+/// let path = root.join("disk.img");
+/// let mut file = std::fs::File::create(&path).unwrap();
+/// file.write_all(b"boot").unwrap();
+/// file.seek(SeekFrom::Start(16 * 1024 * 1024 - 4)).unwrap();
+/// file.write_all(b"tail").unwrap();
+/// drop(file);
+///
+/// let file = std::fs::File::open(&path).unwrap();
+/// let map = match find_holes(&file).unwrap() {
+///     Some(map) => map,
+///     // the temporary directory doesn't support holes
+///     None => return std::fs::remove_dir_all(root).unwrap(),
+/// };
+/// let stored: u64 = map.segments.iter().map(|segment| segment.length).sum();
+///
+/// let header = ArchiveEntryHeader {
+///     sparse: Some(map.clone()),
+///     ..ArchiveEntryHeader::file("disk.img", stored)
+/// };
+/// let data: Box<dyn std::io::Read> = Box::new(SparseReader::new(file, &map));
+///
+/// let mut archive = Vec::new();
+/// ataf::build_archive(&mut archive, CompressionFormat::None, [(header, data)]).unwrap();
+/// assert!(archive.len() < 1024 * 1024);
+///
+/// let output = root.join("output");
+/// let mut reader = Archive::new(&archive[..]);
+/// let mut entries = reader.entries(Box::new(NoDecompressor)).unwrap();
+/// extract_filtered(&mut entries, &output, |_| ExtractAction::Extract).unwrap();
+/// assert!(std::fs::read(&path).unwrap() == std::fs::read(output.join("disk.img")).unwrap());
+///
+/// std::fs::remove_dir_all(root).unwrap();
+/// ```
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "macos"
+))]
+pub fn find_holes(file: &std::fs::File) -> std::io::Result<Option<SparseMap>> {
+    use std::os::fd::AsRawFd;
+
+    let size = file.metadata()?.len();
+    let mut segments = Vec::new();
+
+    let mut offset = 0;
+    while offset < size {
+        let data = unsafe { libc::lseek(file.as_raw_fd(), offset as libc::off_t, libc::SEEK_DATA) };
+        if data < 0 {
+            let err = std::io::Error::last_os_error();
+            match err.raw_os_error() {
+                // no more data, the rest of the file is a hole
+                Some(libc::ENXIO) => break,
+                // the filesystem doesn't support looking for holes
+                Some(libc::EINVAL) => return Ok(None),
+                _ => return Err(err),
+            }
+        }
+
+        let hole = unsafe { libc::lseek(file.as_raw_fd(), data, libc::SEEK_HOLE) };
+        if hole < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        // the file may grow while looking, only what it had at first counts
+        let end = (hole as u64).min(size);
+        if end <= data as u64 {
+            break;
+        }
+
+        segments.push(SparseSegment {
+            offset: data as u64,
+            length: end - data as u64,
+        });
+        offset = end;
+    }
+
+    let mut file = file;
+    file.rewind()?;
+
+    if size == 0
+        || segments
+            == [SparseSegment {
+                offset: 0,
+                length: size,
+            }]
+    {
+        return Ok(None);
+    }
+
+    Ok(Some(SparseMap { size, segments }))
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "macos"
+)))]
+pub fn find_holes(_file: &std::fs::File) -> std::io::Result<Option<SparseMap>> {
+    Ok(None)
+}
+
+/// Reads the data segments of a sparse file one after another, which is what
+/// is archived for it. `inner` is dropped once the last segment was read.
+pub struct SparseReader<R> {
+    inner: Option<R>,
+    segments: Vec<SparseSegment>,
+    next: usize,
+    remaining: u64,
+}
+
+impl<R: Read + Seek> SparseReader<R> {
+    pub fn new(inner: R, map: &SparseMap) -> Self {
+        Self {
+            inner: Some(inner),
+            segments: map.segments.clone(),
+            next: 0,
+            remaining: 0,
+        }
+    }
+}
+
+impl<R: Read + Seek> Read for SparseReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Some(inner) = &mut self.inner else {
+            return Ok(0);
+        };
+
+        while self.remaining == 0 {
+            let Some(segment) = self.segments.get(self.next) else {
+                self.inner = None;
+                return Ok(0);
+            };
+
+            inner.seek(SeekFrom::Start(segment.offset))?;
+            self.remaining = segment.length;
+            self.next += 1;
+        }
+
+        let length = buf
+            .len()
+            .min(self.remaining.min(usize::MAX as u64) as usize);
+        let read = inner.read(&mut buf[..length])?;
+        if read == 0 && length > 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "sparse file shrank while reading it",
+            ));
+        }
+
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Writes the data of a sparse entry to where its segments belong in the file.
+/// The file should be set to the full size of the map first, which leaves the
+/// holes. `position` is the amount of data written before, usually 0.
+pub struct SparseWriter<'a, W> {
+    inner: W,
+    map: &'a SparseMap,
+    position: u64,
+}
+
+impl<'a, W: Write + Seek> SparseWriter<'a, W> {
+    pub fn new(inner: W, map: &'a SparseMap, position: u64) -> Self {
+        Self {
+            inner,
+            map,
+            position,
+        }
+    }
+}
+
+impl<W: Write + Seek> Write for SparseWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut start = 0;
+        for segment in &self.map.segments {
+            if self.position < start + segment.length {
+                let within = self.position - start;
+                self.inner.seek(SeekFrom::Start(segment.offset + within))?;
+
+                let length = buf
+                    .len()
+                    .min((segment.length - within).min(usize::MAX as u64) as usize);
+                let written = self.inner.write(&buf[..length])?;
+
+                self.position += written as u64;
+                return Ok(written);
+            }
+
+            start += segment.length;
+        }
+
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "entry has more data than its sparse map holds",
+        ))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads the data of a sparse entry with its holes filled in with zeros, as
+/// the whole file, for writing it where holes can't be made.
+pub struct ExpandingReader<'a, R> {
+    inner: R,
+    map: &'a SparseMap,
+    position: u64,
+    next: usize,
+}
+
+impl<'a, R: Read> ExpandingReader<'a, R> {
+    pub fn new(inner: R, map: &'a SparseMap) -> Self {
+        Self {
+            inner,
+            map,
+            position: 0,
+            next: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for ExpandingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while let Some(segment) = self.map.segments.get(self.next) {
+            if self.position < segment.offset {
+                let length = buf
+                    .len()
+                    .min((segment.offset - self.position).min(usize::MAX as u64) as usize);
+                buf[..length].fill(0);
+
+                self.position += length as u64;
+                return Ok(length);
+            }
+
+            let end = segment.offset + segment.length;
+            if self.position < end {
+                let length = buf
+                    .len()
+                    .min((end - self.position).min(usize::MAX as u64) as usize);
+                let read = self.inner.read(&mut buf[..length])?;
+                if read == 0 && length > 0 {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "entry has less data than its sparse map holds",
+                    ));
+                }
+
+                self.position += read as u64;
+                return Ok(read);
+            }
+
+            self.next += 1;
+        }
+
+        // the hole up to the end of the file
+        let length = buf
+            .len()
+            .min((self.map.size.saturating_sub(self.position)).min(usize::MAX as u64) as usize);
+        buf[..length].fill(0);
+
+        self.position += length as u64;
+        Ok(length)
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// The path of volume `number` of the archive at `base`, `archive.ataf.001`
+/// for the first volume of `archive.ataf`.
+pub fn volume_path(base: &Path, number: u32) -> PathBuf {
+    let mut path = base.as_os_str().to_owned();
+    path.push(format!(".{number:03}"));
+    PathBuf::from(path)
+}
+
+/// The archive path a first volume like `archive.ataf.001` belongs to, `None`
+/// for paths that aren't a first volume.
+pub fn first_volume_base(path: &Path) -> Option<PathBuf> {
+    let name = path.to_str()?;
+
+    name.strip_suffix(".001").map(PathBuf::from)
+}
+
+/// Writes an archive split into volumes of about `volume_size` bytes. Once a
+/// volume reached the size, the next one is created on the write after the
+/// next flush, so with `ArchiveOptions::flush_chunks` volumes end between
+/// chunks and every volume can be read on its own up to its last whole entry.
+/// They can be larger than `volume_size` by up to a chunk. `VolumeReader` joins
+/// them again.
+///
+/// ```
+/// use ataf::{
+///     archive::{
+///         read::Archive,
+///         volume::{VolumeReader, VolumeWriter, volume_path},
+///         write::{ArchiveOptions, ArchiveWriter},
+///     },
+///     compression::{NoCompressor, NoDecompressor},
+///     spec::ArchiveEntryHeader,
+/// };
+/// use std::io::Read;
+///
+/// let root = std::env::temp_dir().join(format!("ataf-doctest-volume-{}", std::process::id()));
+/// std::fs::create_dir_all(&root).unwrap();
+/// let base = root.join("archive.ataf");
+///
+/// let header = |path: String| ArchiveEntryHeader::file(path, 1000);
+///
+/// let options = ArchiveOptions {
+///     flush_chunks: true,
+///     ..Default::default()
+/// };
+/// let volumes = VolumeWriter::new(base.clone(), 4096).unwrap();
+/// let mut writer =
+///     ArchiveWriter::with_options(volumes, Box::new(NoCompressor::new()), 1024, options).unwrap();
+/// for n in 0..10 {
+///     let data = std::io::repeat(n).take(1000);
+///     writer.write_entry(header(format!("{n}.bin")), data).unwrap();
+/// }
+/// let volumes = writer.finish().unwrap();
+/// assert_eq!(volumes.volumes(), 3);
+/// assert!(std::fs::metadata(volume_path(&base, 1)).unwrap().len() >= 4096);
+/// assert!(!volume_path(&base, 4).exists());
+///
+/// // the first volume alone ends after its last whole entry
+/// let mut archive = Archive::new(std::fs::File::open(volume_path(&base, 1)).unwrap());
+/// let mut entries = archive.entries(Box::new(NoDecompressor)).unwrap();
+/// for n in 0..4 {
+///     let (header, data) = entries.next_file().unwrap().unwrap();
+///     assert_eq!((header.path, data), (format!("{n}.bin"), vec![n; 1000]));
+/// }
+/// assert!(entries.next_file().is_none());
+///
+/// let reader = VolumeReader::open(&volume_path(&base, 1)).unwrap();
+/// let mut archive = Archive::new(reader);
+/// let mut entries = archive.entries(Box::new(NoDecompressor)).unwrap();
+/// for n in 0..10 {
+///     let (header, data) = entries.next_file().unwrap().unwrap();
+///     assert_eq!((header.path, data), (format!("{n}.bin"), vec![n; 1000]));
+/// }
+///
+/// std::fs::remove_dir_all(root).unwrap();
+/// ```
+pub struct VolumeWriter {
+    base: PathBuf,
+    volume_size: u64,
+    number: u32,
+    file: File,
+    written: u64,
+    full: bool,
+}
+
+impl VolumeWriter {
+    /// Creates the first volume right away, so an unwritable path fails early.
+    pub fn new(base: PathBuf, volume_size: u64) -> std::io::Result<Self> {
+        if volume_size == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the volume size must be at least 1 byte",
+            ));
+        }
+
+        let file = File::create(volume_path(&base, 1))?;
+
+        Ok(Self {
+            base,
+            volume_size,
+            number: 1,
+            file,
+            written: 0,
+            full: false,
+        })
+    }
+
+    /// The number of volumes created so far.
+    pub fn volumes(&self) -> u32 {
+        self.number
+    }
+}
+
+impl Write for VolumeWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // only started here rather than on the flush, so the last volume is
+        // never left empty
+        if self.full {
+            self.file = File::create(volume_path(&self.base, self.number + 1))?;
+            self.number += 1;
+            self.written = 0;
+            self.full = false;
+        }
+
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        self.full = self.written >= self.volume_size;
+
+        Ok(())
+    }
+}
+
+/// Reads the volumes written by `VolumeWriter` as one archive, continuing with
+/// the next volume at the end of each until there is none.
+pub struct VolumeReader {
+    base: PathBuf,
+    number: u32,
+    file: File,
+}
+
+impl VolumeReader {
+    /// Opens the first volume, `path` has to end with `.001`.
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let base = first_volume_base(path).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} is not a first volume", path.display()),
+            )
+        })?;
+        let file = File::open(path)?;
+
+        Ok(Self {
+            base,
+            number: 1,
+            file,
+        })
+    }
+}
+
+impl Read for VolumeReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let read = self.file.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+
+            match File::open(volume_path(&self.base, self.number + 1)) {
+                Ok(file) => {
+                    self.file = file;
+                    self.number += 1;
+                }
+                // the last volume, reading past it is reported as a truncated
+                // archive by the reader
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
@@ -1,86 +1,777 @@
 use crate::{
-    compression::Compressor,
-    spec::{ArchiveEntryHeader, ArchiveHeader, Serialize},
+    Error,
+    archive::progress::{ProgressCallback, ProgressEvent, ProgressStage},
+    compression::{CompressionFormat, CompressionRegistry, Compressor, NoCompressor, WriteCounter},
+    spec::{
+        ARCHIVE_INDEX_MAGIC, ARCHIVE_VERSION, ArchiveEntryHeader, ArchiveEntryHeaderType,
+        ArchiveHeader, ArchiveIndex, ArchiveIndexEntry, ChunkChecksum, Deserialize, ENTRIES_END,
+        Serialize, VariableSizedU64,
+    },
 };
 use std::{
-    io::{Read, Write},
+    collections::VecDeque,
+    io::{Read, Seek, SeekFrom, Write},
     marker::PhantomData,
 };
 
+/// Writes the chunks of an entry, each prefixed with its length as a big-endian
+/// `u32` (3 bytes before version 10, which limited chunks to 16 MiB).
+///
+/// ```
+/// use ataf::{
+///     archive::{read::Archive, write::ArchiveWriter},
+///     compression::{NoCompressor, NoDecompressor},
+///     spec::ArchiveEntryHeader,
+/// };
+///
+/// // incompressible data stored as a single 17 MiB chunk
+/// let mut state = 0x2545f491u32;
+/// let large: Vec<u8> = (0..17 * 1024 * 1024)
+///     .map(|_| {
+///         state ^= state << 13;
+///         state ^= state >> 17;
+///         state ^= state << 5;
+///         state as u8
+///     })
+///     .collect();
+/// let chunk_size = large.len() as u32;
+///
+/// let mut writer = ArchiveWriter::new(Vec::new(), Box::new(NoCompressor::new()), chunk_size).unwrap();
+/// writer
+///     .write_entry(ArchiveEntryHeader::file("large", large.len() as u64), &large[..])
+///     .unwrap();
+/// writer
+///     .write_entry(ArchiveEntryHeader::file("skipped", large.len() as u64), &large[..])
+///     .unwrap();
+/// writer.write_entry(ArchiveEntryHeader::file("small", 5), &b"small"[..]).unwrap();
+/// let bytes = writer.finish().unwrap();
+///
+/// let mut archive = Archive::new(&bytes[..]);
+/// let mut entries = archive.entries(Box::new(NoDecompressor)).unwrap();
+/// let (header, data) = entries.next_file().unwrap().unwrap();
+/// assert_eq!(header.path, "large");
+/// assert!(data == large);
+///
+/// entries.next_entry().unwrap().unwrap().skip().unwrap();
+/// let (header, data) = entries.next_file().unwrap().unwrap();
+/// assert_eq!((header.path.as_str(), &data[..]), ("small", &b"small"[..]));
+/// ```
 pub struct ChunkWriter<W: Write + Send> {
     writer: W,
     chunk_count: u64,
+    checksum: ChunkChecksum,
+    written: u64,
+    flush: bool,
 }
 
 impl<W: Write + Send> ChunkWriter<W> {
+    /// Writes the next chunk of the entry. Readers expect exactly as many
+    /// chunks as the entry size spans, so writing more fails before anything
+    /// is written.
+    ///
+    /// ```
+    /// use ataf::{
+    ///     archive::write::{ArchiveWriter, ChunkWriter},
+    ///     compression::Compressor,
+    ///     spec::ArchiveEntryHeader,
+    /// };
+    /// use std::io::{Read, Write};
+    ///
+    /// // stores every chunk twice
+    /// struct TwiceCompressor;
+    ///
+    /// impl<W: Write + Send, R: Read> Compressor<W, R> for TwiceCompressor {
+    ///     fn name(&self) -> &'static str {
+    ///         "none"
+    ///     }
+    ///
+    ///     fn compress(
+    ///         &mut self,
+    ///         input: &mut R,
+    ///         _remaining_chunks: usize,
+    ///         chunk_size: u32,
+    ///         chunk_writer: &mut ChunkWriter<&mut W>,
+    ///     ) -> std::io::Result<()> {
+    ///         let mut chunk = Vec::new();
+    ///         input.take(chunk_size as u64).read_to_end(&mut chunk)?;
+    ///         chunk_writer.write_chunk(&chunk)?;
+    ///         chunk_writer.write_chunk(&chunk)
+    ///     }
+    /// }
+    ///
+    /// // 3 chunks, the last one partial
+    /// let data = vec![1; 2500];
+    /// let header = ArchiveEntryHeader::file("data", data.len() as u64);
+    ///
+    /// let mut writer = ArchiveWriter::new(Vec::new(), Box::new(TwiceCompressor), 1024).unwrap();
+    /// assert!(writer.write_entry(header, &data[..]).is_err());
+    /// ```
     pub fn write_chunk(&mut self, chunk: &[u8]) -> std::io::Result<()> {
-        self.writer
-            .write_all(&u32_to_u24_bytes(chunk.len() as u32))?;
+        if self.chunk_count == 0 {
+            return Err(std::io::Error::other(
+                "compressor wrote more chunks than the entry spans",
+            ));
+        }
+
+        let length = u32::try_from(chunk.len()).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "chunks can be at most 4 GiB",
+            )
+        })?;
+
+        self.writer.write_all(&length.to_be_bytes())?;
+        self.checksum.write(chunk, &mut self.writer)?;
         self.writer.write_all(chunk)?;
         self.chunk_count -= 1;
+        self.written += 4 + self.checksum.size() as u64 + chunk.len() as u64;
+
+        if self.flush {
+            self.writer.flush()?;
+        }
 
         Ok(())
     }
 }
 
-#[inline]
-fn u32_to_u24_bytes(value: u32) -> [u8; 3] {
-    [(value >> 16) as u8, (value >> 8) as u8, value as u8]
+/// The chunk size used when none is given explicitly.
+pub const DEFAULT_CHUNK_SIZE: u32 = 65535;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArchiveStats {
+    pub entries: u64,
+    pub uncompressed_bytes: u64,
+}
+
+/// Format options that can't change after the archive header is written.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveOptions {
+    /// See `ArchiveWriter::new_solid`.
+    pub solid: bool,
+    /// Front-codes entry paths against the previous entry's path, shrinking
+    /// deep trees at the cost of random access.
+    pub prefix_paths: bool,
+    /// Stores a checksum with every chunk, so corrupted data is reported
+    /// instead of being decompressed into garbage.
+    pub checksum: ChunkChecksum,
+    /// Writes an index of all entries on `finish`, so seekable readers can
+    /// list or locate entries without reading through the archive. Not
+    /// available for solid archives, which list their entries up front.
+    pub index: bool,
+    /// A free-form note stored in the archive header, at most 65535 bytes.
+    pub comment: String,
+    /// Flushes the sink after every chunk, so it only ever sees flushes
+    /// between chunks. `VolumeWriter` starts its next volume on those.
+    pub flush_chunks: bool,
 }
 
+/// Writes an archive front to back to any `Write` sink. Nothing is ever
+/// seeked or rewritten, so sinks like pipes, sockets or multipart uploads to
+/// object storage work as well as files; wrap slow sinks in a `BufWriter` so
+/// they see large writes, and call `finish` to get them back flushed.
 pub struct ArchiveWriter<W: Write + Send, R: Read> {
     writer: W,
     _reader: PhantomData<R>,
     compressor: Box<dyn Compressor<W, R>>,
+    raw_compressor: NoCompressor,
     header: ArchiveHeader,
+    previous_path: String,
+    position: u64,
+    index: Option<ArchiveIndex>,
+    progress: Option<ProgressCallback>,
+    processed_bytes: u64,
+    stats: ArchiveStats,
+    flush_chunks: bool,
 }
 
 impl<W: Write + Send, R: Read> ArchiveWriter<W, R> {
     pub fn new(
+        writer: W,
+        compressor: Box<dyn Compressor<W, R>>,
+        compression_chunk_size: u32,
+    ) -> crate::Result<Self> {
+        Self::with_options(
+            writer,
+            compressor,
+            compression_chunk_size,
+            ArchiveOptions::default(),
+        )
+    }
+
+    /// Creates a writer for a solid archive, whose entries are all written at
+    /// once with `write_solid` so their data shares one compression stream.
+    pub fn new_solid(
+        writer: W,
+        compressor: Box<dyn Compressor<W, R>>,
+        compression_chunk_size: u32,
+    ) -> crate::Result<Self> {
+        Self::with_options(
+            writer,
+            compressor,
+            compression_chunk_size,
+            ArchiveOptions {
+                solid: true,
+                ..Default::default()
+            },
+        )
+    }
+
+    pub fn with_options(
         mut writer: W,
         compressor: Box<dyn Compressor<W, R>>,
         compression_chunk_size: u32,
-    ) -> std::io::Result<Self> {
+        options: ArchiveOptions,
+    ) -> crate::Result<Self> {
+        if options.solid && options.index {
+            return Err(Error::InvalidOperation(
+                "solid archives list their entries up front and have no index",
+            ));
+        }
+
         let header = ArchiveHeader {
-            version: 1,
+            version: ARCHIVE_VERSION,
             compression: String::from(compressor.name()),
             compression_chunk_size,
+            solid: options.solid,
+            prefix_paths: options.prefix_paths,
+            checksum: options.checksum,
+            index: options.index,
+            comment: options.comment,
+            entry_count: 0,
+            total_uncompressed_size: 0,
         };
 
-        header.serialize(&mut writer)?;
+        let mut counter = WriteCounter::new(&mut writer);
+        header.serialize(&mut counter)?;
+        let position = counter.into_written() as u64;
 
         Ok(Self {
             writer,
             _reader: PhantomData,
             compressor,
+            raw_compressor: NoCompressor::new(),
             header,
+            previous_path: String::new(),
+            position,
+            index: options.index.then(ArchiveIndex::default),
+            progress: None,
+            processed_bytes: 0,
+            stats: ArchiveStats::default(),
+            flush_chunks: options.flush_chunks,
         })
     }
 
-    pub fn write_entry(&mut self, entry: ArchiveEntryHeader, mut input: R) -> std::io::Result<()> {
-        entry.serialize(&mut self.writer)?;
+    /// Writes the index if enabled, then flushes the sink and hands it back,
+    /// for example to complete a multipart upload. Errors surface here instead
+    /// of being lost on drop.
+    ///
+    /// ```
+    /// use ataf::{
+    ///     archive::{read::Archive, write::ArchiveWriter},
+    ///     compression::{NoCompressor, NoDecompressor},
+    ///     spec::ArchiveEntryHeader,
+    /// };
+    /// use std::io::{Read, Write};
+    ///
+    /// /// Uploads fixed-size parts, like an S3 multipart upload would, with a
+    /// /// delay per part standing in for the network.
+    /// struct MultipartUpload {
+    ///     part: Vec<u8>,
+    ///     uploaded: Vec<Vec<u8>>,
+    /// }
+    ///
+    /// impl MultipartUpload {
+    ///     const PART_SIZE: usize = 1000;
+    ///
+    ///     fn upload_part(&mut self) {
+    ///         std::thread::sleep(std::time::Duration::from_millis(1));
+    ///         self.uploaded.push(std::mem::take(&mut self.part));
+    ///     }
+    ///
+    ///     fn complete(self) -> Vec<u8> {
+    ///         assert!(self.part.is_empty(), "complete called before flush");
+    ///         self.uploaded.concat()
+    ///     }
+    /// }
+    ///
+    /// impl Write for MultipartUpload {
+    ///     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    ///         let n = std::cmp::min(buf.len(), Self::PART_SIZE - self.part.len());
+    ///         self.part.extend_from_slice(&buf[..n]);
+    ///         if self.part.len() == Self::PART_SIZE {
+    ///             self.upload_part();
+    ///         }
+    ///         Ok(n)
+    ///     }
+    ///
+    ///     fn flush(&mut self) -> std::io::Result<()> {
+    ///         if !self.part.is_empty() {
+    ///             self.upload_part();
+    ///         }
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+    /// let entry = ArchiveEntryHeader::file("data", data.len() as u64);
+    ///
+    /// let upload = MultipartUpload { part: Vec::new(), uploaded: Vec::new() };
+    /// let mut writer = ArchiveWriter::new(
+    ///     std::io::BufWriter::with_capacity(4096, upload),
+    ///     Box::new(NoCompressor::new()),
+    ///     4096,
+    /// )
+    /// .unwrap();
+    /// writer.write_entry(entry, &data[..]).unwrap();
+    ///
+    /// let (upload, _) = writer.finish().unwrap().into_parts();
+    /// assert!(upload.uploaded.len() > 1);
+    /// let archive = upload.complete();
+    ///
+    /// let mut archive = Archive::new(&archive[..]);
+    /// let mut entries = archive.entries(Box::new(NoDecompressor)).unwrap();
+    /// let (header, read) = entries.next_file().unwrap().unwrap();
+    /// assert_eq!(header.path, "data");
+    /// assert_eq!(read, data);
+    /// ```
+    pub fn finish(mut self) -> crate::Result<W> {
+        if let Some(index) = &self.index {
+            self.writer.write_all(&[ENTRIES_END])?;
+            let offset = self.position + 1;
 
-        let chunk_count = *entry.size / self.header.compression_chunk_size as u64
-            + if !(*entry.size).is_multiple_of(self.header.compression_chunk_size as u64) {
-                1
-            } else {
-                0
-            };
+            index.serialize(&mut self.writer)?;
+            self.writer.write_all(&offset.to_le_bytes())?;
+            self.writer.write_all(&ARCHIVE_INDEX_MAGIC)?;
+        }
+
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+
+    /// The sink written to, for example to take what was written so far out
+    /// of an in-memory buffer.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Calls `progress` before and after writing every entry. Solid archives
+    /// write the data of all entries at once, so their entries are reported
+    /// after it was written.
+    pub fn set_progress(&mut self, progress: ProgressCallback) {
+        self.progress = Some(progress);
+    }
+
+    fn report_progress(&mut self, stage: ProgressStage, entry: &ArchiveEntryHeader) {
+        if stage == ProgressStage::Finished {
+            self.processed_bytes += *entry.size;
+        }
+
+        if let Some(progress) = &mut self.progress {
+            progress(ProgressEvent {
+                stage,
+                path: &entry.path,
+                size: *entry.size,
+                processed_bytes: self.processed_bytes,
+            });
+        }
+    }
+
+    /// Changes the compression level for all following entries. Archives may
+    /// freely mix levels, decompression does not depend on them.
+    #[inline]
+    pub fn set_compression_level(&mut self, level: u32) {
+        self.compressor.set_level(level);
+    }
+
+    /// Caps the chunks of an entry held in memory while it is compressed, see
+    /// `Compressor::set_in_flight`.
+    #[inline]
+    pub fn set_in_flight_chunks(&mut self, chunks: usize) {
+        self.compressor.set_in_flight(chunks);
+    }
+
+    #[inline]
+    pub fn compression_levels(&self) -> std::ops::RangeInclusive<u32> {
+        self.compressor.levels()
+    }
+
+    /// The entries written so far and the sum of their sizes, to be stored
+    /// with `write_totals` once the archive is finished.
+    #[inline]
+    pub fn stats(&self) -> ArchiveStats {
+        self.stats
+    }
+
+    /// Writes every entry in order, stopping at the first error.
+    pub fn write_entries(
+        &mut self,
+        entries: impl IntoIterator<Item = (ArchiveEntryHeader, R)>,
+    ) -> crate::Result<ArchiveStats> {
+        let mut stats = ArchiveStats::default();
+
+        for (entry, input) in entries {
+            stats.entries += 1;
+            stats.uncompressed_bytes += *entry.size;
+
+            self.write_entry(entry, input)?;
+        }
+
+        Ok(stats)
+    }
+
+    /// Writes one entry and its data. Link targets and device numbers are
+    /// stored uncompressed whatever the archive's compressor, recorded as
+    /// `none` in the entry:
+    ///
+    /// ```
+    /// use ataf::{
+    ///     archive::{
+    ///         read::Archive,
+    ///         write::{ArchiveWriter, ChunkWriter},
+    ///     },
+    ///     compression::{Compressor, NoDecompressor},
+    ///     spec::{ArchiveEntryHeader, ArchiveEntryHeaderType},
+    /// };
+    /// use std::io::{Read, Write};
+    ///
+    /// // fails the archive if anything reaches it
+    /// struct UnusedCompressor;
+    ///
+    /// impl<W: Write + Send, R: Read> Compressor<W, R> for UnusedCompressor {
+    ///     fn name(&self) -> &'static str {
+    ///         "unused"
+    ///     }
+    ///
+    ///     fn compress(
+    ///         &mut self,
+    ///         _input: &mut R,
+    ///         _remaining_chunks: usize,
+    ///         _chunk_size: u32,
+    ///         _chunk_writer: &mut ChunkWriter<&mut W>,
+    ///     ) -> std::io::Result<()> {
+    ///         Err(std::io::Error::other("link targets went through the compressor"))
+    ///     }
+    /// }
+    ///
+    /// // one target shorter than a chunk length prefix, one spanning 3 chunks
+    /// let short = String::from("a");
+    /// let long = "directory/".repeat(300);
+    ///
+    /// let mut writer = ArchiveWriter::new(Vec::new(), Box::new(UnusedCompressor), 1024).unwrap();
+    /// for (path, target, r#type) in [
+    ///     ("short", &short, ArchiveEntryHeaderType::SymlinkFile),
+    ///     ("long", &long, ArchiveEntryHeaderType::SymlinkDirectory),
+    /// ] {
+    ///     let header = ArchiveEntryHeader {
+    ///         r#type,
+    ///         ..ArchiveEntryHeader::file(path, target.len() as u64)
+    ///     };
+    ///     writer.write_entry(header, target.as_bytes()).unwrap();
+    /// }
+    /// let bytes = writer.finish().unwrap();
+    ///
+    /// let mut archive = Archive::new(&bytes[..]);
+    /// let mut entries = archive.entries(Box::new(NoDecompressor)).unwrap();
+    /// for target in [&short, &long] {
+    ///     let entry = entries.next_entry().unwrap().unwrap();
+    ///     assert_eq!(entry.header().compression.as_deref(), Some("none"));
+    ///     assert_eq!(&entry.read_to_vec().unwrap(), target.as_bytes());
+    /// }
+    /// assert!(entries.next_entry().is_none());
+    /// ```
+    pub fn write_entry(
+        &mut self,
+        mut entry: ArchiveEntryHeader,
+        mut input: R,
+    ) -> crate::Result<()> {
+        if self.header.solid {
+            return Err(Error::InvalidOperation(
+                "entries of solid archives have to be written with write_solid",
+            ));
+        }
+
+        // link targets and device numbers are tiny, compressing them only
+        // costs time and space
+        let raw = entry.r#type.is_special()
+            || matches!(
+                entry.r#type,
+                ArchiveEntryHeaderType::SymlinkFile
+                    | ArchiveEntryHeaderType::SymlinkDirectory
+                    | ArchiveEntryHeaderType::Reference
+                    | ArchiveEntryHeaderType::HardLink
+            );
+        if raw {
+            entry.compression = Some(String::from("none"));
+        }
+
+        if let Some(index) = &mut self.index {
+            index.entries.push(ArchiveIndexEntry {
+                r#type: entry.r#type,
+                path: entry.path.clone(),
+                size: *entry.size,
+                offset: self.position,
+            });
+        }
+
+        self.report_progress(ProgressStage::Started, &entry);
+        self.write_header(&entry)?;
+        self.write_data(&mut input, *entry.size, raw)?;
+        self.report_progress(ProgressStage::Finished, &entry);
+
+        self.stats.entries += 1;
+        self.stats.uncompressed_bytes += *entry.size;
+
+        Ok(())
+    }
+
+    fn write_header(&mut self, entry: &ArchiveEntryHeader) -> crate::Result<()> {
+        let mut counter = WriteCounter::new(&mut self.writer);
+
+        if self.header.prefix_paths {
+            entry.serialize_after(&mut counter, &self.previous_path)?;
+            self.previous_path.clone_from(&entry.path);
+        } else {
+            entry.serialize(&mut counter)?;
+        }
+
+        self.position += counter.into_written() as u64;
+        Ok(())
+    }
+
+    fn write_data(&mut self, input: &mut R, size: u64, raw: bool) -> crate::Result<()> {
+        let chunk_count = size.div_ceil(self.header.compression_chunk_size as u64);
 
         let mut chunk_writer = ChunkWriter {
             writer: &mut self.writer,
             chunk_count,
+            checksum: self.header.checksum,
+            written: 0,
+            flush: self.flush_chunks,
         };
 
         while chunk_writer.chunk_count > 0 {
-            self.compressor.compress(
-                &mut input,
-                chunk_count as usize,
+            let remaining_chunks = chunk_writer.chunk_count as usize;
+
+            let compressor: &mut dyn Compressor<W, R> = if raw {
+                &mut self.raw_compressor
+            } else {
+                self.compressor.as_mut()
+            };
+
+            compressor.compress(
+                input,
+                remaining_chunks,
                 self.header.compression_chunk_size,
                 &mut chunk_writer,
             )?;
+
+            // a compressor writing nothing would never finish the entry
+            if chunk_writer.chunk_count as usize == remaining_chunks {
+                return Err(Error::InvalidOperation(
+                    "compressor returned without writing a chunk",
+                ));
+            }
         }
 
+        self.position += chunk_writer.written;
         Ok(())
     }
 }
+
+impl<W: Write + Send> ArchiveWriter<W, Box<dyn Read>> {
+    /// Writes all entries of a solid archive: the entry count and every header
+    /// first, followed by the data of all entries as one continuous chunk
+    /// stream. Small files compress much better this way, but entries can only
+    /// be extracted sequentially. Inputs are read one after another, so large
+    /// numbers of files should open lazily on their first read.
+    pub fn write_solid(
+        &mut self,
+        entries: Vec<(ArchiveEntryHeader, Box<dyn Read>)>,
+    ) -> crate::Result<ArchiveStats> {
+        if !self.header.solid {
+            return Err(Error::InvalidOperation(
+                "write_solid requires a writer created with new_solid",
+            ));
+        }
+
+        let mut stats = ArchiveStats::default();
+        let mut inputs = VecDeque::with_capacity(entries.len());
+        let mut headers = Vec::new();
+
+        VariableSizedU64::new(entries.len() as u64).serialize(&mut self.writer)?;
+        for (entry, input) in entries {
+            self.write_header(&entry)?;
+
+            stats.entries += 1;
+            stats.uncompressed_bytes += *entry.size;
+            inputs.push_back((*entry.size, input));
+            if self.progress.is_some() {
+                headers.push(entry);
+            }
+        }
+
+        let mut input: Box<dyn Read> = Box::new(SolidReader { inputs });
+        self.write_data(&mut input, stats.uncompressed_bytes, false)?;
+
+        for entry in &headers {
+            self.report_progress(ProgressStage::Started, entry);
+            self.report_progress(ProgressStage::Finished, entry);
+        }
+
+        self.stats.entries += stats.entries;
+        self.stats.uncompressed_bytes += stats.uncompressed_bytes;
+
+        Ok(stats)
+    }
+}
+
+/// Concatenates the inputs of a solid archive, making sure every input provides
+/// exactly as many bytes as its header announced.
+struct SolidReader {
+    inputs: VecDeque<(u64, Box<dyn Read>)>,
+}
+
+impl Read for SolidReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while let Some((remaining, input)) = self.inputs.front_mut() {
+            if *remaining == 0 {
+                self.inputs.pop_front();
+                continue;
+            }
+
+            let to_read = std::cmp::min(buf.len() as u64, *remaining) as usize;
+            match input.read(&mut buf[..to_read])? {
+                0 => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "entry input ended before its recorded size",
+                    ));
+                }
+                n => {
+                    *remaining -= n as u64;
+                    return Ok(n);
+                }
+            }
+        }
+
+        Ok(0)
+    }
+}
+
+/// Builds an archive from in-memory or otherwise heterogeneous sources in one
+/// call, compressed with `compression` at its default chunk size. The writer is
+/// finished, so errors flushing it are returned rather than lost when it's
+/// dropped.
+///
+/// ```
+/// use ataf::compression::CompressionFormat;
+/// use ataf::spec::ArchiveEntryHeader;
+///
+/// let hello: &[u8] = b"hello";
+/// let world: &[u8] = b"world!";
+///
+/// let mut archive = Vec::new();
+/// let stats = ataf::build_archive(
+///     &mut archive,
+///     CompressionFormat::None,
+///     [
+///         (
+///             ArchiveEntryHeader::file("hello.txt", hello.len() as u64),
+///             Box::new(hello) as Box<dyn std::io::Read>,
+///         ),
+///         (ArchiveEntryHeader::file("world.txt", world.len() as u64), Box::new(world)),
+///     ],
+/// )
+/// .unwrap();
+///
+/// assert_eq!(stats.entries, 2);
+/// assert_eq!(stats.uncompressed_bytes, 11);
+///
+/// // a sink that takes everything but can't persist it
+/// struct FullDisk;
+///
+/// impl std::io::Write for FullDisk {
+///     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+///         Ok(buf.len())
+///     }
+///
+///     fn flush(&mut self) -> std::io::Result<()> {
+///         Err(std::io::Error::other("no space left"))
+///     }
+/// }
+///
+/// let entries = [(
+///     ArchiveEntryHeader::file("hello.txt", hello.len() as u64),
+///     Box::new(hello) as Box<dyn std::io::Read>,
+/// )];
+/// assert!(ataf::build_archive(FullDisk, CompressionFormat::None, entries).is_err());
+/// ```
+pub fn build_archive<W: Write + Send>(
+    writer: W,
+    compression: CompressionFormat,
+    entries: impl IntoIterator<Item = (ArchiveEntryHeader, Box<dyn Read>)>,
+) -> crate::Result<ArchiveStats> {
+    let compressor = CompressionRegistry::default().compressor(compression.name(), 1)?;
+    let mut archive = ArchiveWriter::new(writer, compressor, compression.default_chunk_size())?;
+    let stats = archive.write_entries(entries)?;
+    archive.finish()?;
+
+    Ok(stats)
+}
+
+/// Stores the entry count and total uncompressed size of a finished archive in
+/// its header, which starts at the current position of `archive`. The header
+/// is rewritten in place with its checksum, nothing after it moves. Only
+/// version 12 headers have room for the totals.
+///
+/// ```
+/// use ataf::{
+///     archive::{read::Archive, write::ArchiveWriter},
+///     compression::NoCompressor,
+///     spec::ArchiveEntryHeader,
+/// };
+/// use std::io::Cursor;
+///
+/// let mut writer =
+///     ArchiveWriter::new(Cursor::new(Vec::new()), Box::new(NoCompressor::new()), 1024).unwrap();
+/// writer.write_entry(ArchiveEntryHeader::file("a", 3), &b"aaa"[..]).unwrap();
+/// writer.write_entry(ArchiveEntryHeader::file("b", 5), &b"bbbbb"[..]).unwrap();
+/// let stats = writer.stats();
+///
+/// let mut archive = writer.finish().unwrap();
+/// let length = archive.get_ref().len();
+/// archive.set_position(0);
+/// ataf::archive::write::write_totals(&mut archive, stats).unwrap();
+/// assert_eq!(archive.get_ref().len(), length);
+///
+/// let mut archive = Archive::new(&archive.get_ref()[..]);
+/// let header = archive.header().unwrap();
+/// assert_eq!((header.entry_count, header.total_uncompressed_size), (2, 8));
+/// ```
+pub fn write_totals<F: Read + Write + Seek>(
+    mut archive: F,
+    stats: ArchiveStats,
+) -> crate::Result<()> {
+    let start = archive.stream_position()?;
+    let mut header = ArchiveHeader::deserialize(&mut archive)?;
+    let length = archive.stream_position()? - start;
+    if header.version < 12 {
+        return Err(Error::InvalidOperation(
+            "only version 12 archive headers store totals",
+        ));
+    }
+
+    header.entry_count = stats.entries;
+    header.total_uncompressed_size = stats.uncompressed_bytes;
+    let mut bytes = Vec::with_capacity(length as usize);
+    header.serialize(&mut bytes)?;
+    debug_assert_eq!(bytes.len() as u64, length);
+
+    archive.seek(SeekFrom::Start(start))?;
+    archive.write_all(&bytes)?;
+    archive.flush()?;
+
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use clap::ArgMatches;
+use std::time::{Duration, Instant};
+
+/// Emits a status line every N entries and/or every N seconds, independent of
+/// the per-entry output so it stays readable in log files.
+pub struct Checkpoint {
+    every_entries: Option<u64>,
+    interval: Option<Duration>,
+
+    entries: u64,
+    bytes: u64,
+
+    started: Instant,
+    last: Instant,
+}
+
+impl Checkpoint {
+    pub fn from_matches(matches: &ArgMatches) -> Option<Self> {
+        let every_entries = matches.get_one::<u64>("checkpoint").copied();
+        let interval = matches
+            .get_one::<u64>("checkpoint_interval")
+            .map(|secs| Duration::from_secs(*secs));
+
+        if every_entries.is_none() && interval.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            every_entries,
+            interval,
+            entries: 0,
+            bytes: 0,
+            started: Instant::now(),
+            last: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, bytes: u64) {
+        self.entries += 1;
+        self.bytes += bytes;
+
+        let entries_due = self
+            .every_entries
+            .is_some_and(|every| every > 0 && self.entries.is_multiple_of(every));
+        let interval_due = self
+            .interval
+            .is_some_and(|interval| self.last.elapsed() >= interval);
+
+        if entries_due || interval_due {
+            self.last = Instant::now();
+
+            eprintln!(
+                "checkpoint: {} entries, {} bytes, {}s elapsed",
+                self.entries,
+                self.bytes,
+                self.started.elapsed().as_secs()
+            );
+        }
+    }
+}
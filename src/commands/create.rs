@@ -1,12 +1,22 @@
+use crate::{checkpoint::Checkpoint, glob::Patterns, tee::Tee, users::Accounts};
 use ataf::{
-    compression::{CompressionFormat, Compressor},
-    spec::{VariableSizedU32, VariableSizedU64},
+    archive::volume::VolumeWriter,
+    compression::CompressionFormat,
+    spec::{ChunkChecksum, VariableSizedU32, VariableSizedU64},
 };
 use clap::ArgMatches;
 use std::{
-    io::{BufWriter, IsTerminal, Write},
+    cell::Cell,
+    collections::HashMap,
+    hash::{DefaultHasher, Hasher},
+    io::{BufRead, BufReader, BufWriter, IsTerminal, Read, Write},
     path::{Path, PathBuf},
-    time::SystemTime,
+    rc::Rc,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
 };
 
 macro_rules! println_if_terminal {
@@ -19,72 +29,859 @@ macro_rules! println_if_terminal {
     };
 }
 
+/// Lowers the compression level between entries whenever the throughput so far
+/// projects the whole archive to take longer than the time budget.
+struct AutoLevel {
+    started: Instant,
+    budget: Duration,
+    total_bytes: u64,
+    processed_bytes: u64,
+    level: u32,
+    min_level: u32,
+}
+
+impl AutoLevel {
+    fn new(budget: Duration, total_bytes: u64, levels: std::ops::RangeInclusive<u32>) -> Self {
+        Self {
+            started: Instant::now(),
+            budget,
+            total_bytes,
+            processed_bytes: 0,
+            level: *levels.end(),
+            // level 0 only stores for some formats, which is never worth it
+            min_level: (*levels.start()).max(1),
+        }
+    }
+
+    fn record(&mut self, bytes: u64) -> Option<u32> {
+        self.processed_bytes += bytes;
+        if self.processed_bytes == 0 || self.level <= self.min_level {
+            return None;
+        }
+
+        let projected = self.started.elapsed().as_secs_f64() * self.total_bytes as f64
+            / self.processed_bytes as f64;
+        if projected > self.budget.as_secs_f64() {
+            self.level -= 1;
+            Some(self.level)
+        } else {
+            None
+        }
+    }
+}
+
+/// Remembers the content hash of every archived file so files with identical
+/// contents are only stored once. Memory grows by one map entry (hash, size and
+/// both paths) per unique file.
+struct Dedup {
+    seen: HashMap<(u64, u64), Vec<(PathBuf, String)>>,
+}
+
+impl Dedup {
+    /// Returns the archive path of a previously added file with the same
+    /// contents, or remembers this file for later duplicates.
+    fn find_or_insert(
+        &mut self,
+        input: &Path,
+        size: u64,
+        path: &str,
+    ) -> std::io::Result<Option<String>> {
+        let mut hasher = DefaultHasher::new();
+        let mut file = std::fs::File::open(input)?;
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            match file.read(&mut buffer)? {
+                0 => break,
+                n => hasher.write(&buffer[..n]),
+            }
+        }
+
+        let candidates = self.seen.entry((size, hasher.finish())).or_default();
+        for (original_input, original_path) in candidates.iter() {
+            // hashes may collide, only the actual contents are authoritative
+            if files_equal(input, original_input)? {
+                return Ok(Some(original_path.clone()));
+            }
+        }
+
+        candidates.push((input.to_path_buf(), path.to_string()));
+        Ok(None)
+    }
+}
+
+fn files_equal(a: &Path, b: &Path) -> std::io::Result<bool> {
+    let mut a = BufReader::new(std::fs::File::open(a)?);
+    let mut b = BufReader::new(std::fs::File::open(b)?);
+
+    loop {
+        let (a_buffer, b_buffer) = (a.fill_buf()?, b.fill_buf()?);
+        if a_buffer.is_empty() || b_buffer.is_empty() {
+            return Ok(a_buffer.is_empty() && b_buffer.is_empty());
+        }
+
+        let n = std::cmp::min(a_buffer.len(), b_buffer.len());
+        if a_buffer[..n] != b_buffer[..n] {
+            return Ok(false);
+        }
+
+        a.consume(n);
+        b.consume(n);
+    }
+}
+
+type CreateArchiveWriter = ataf::archive::write::ArchiveWriter<
+    BufWriter<Box<dyn std::io::Write + Send>>,
+    Box<dyn std::io::Read>,
+>;
+
+struct CreateState {
+    auto_level: Option<AutoLevel>,
+    checkpoint: Option<Checkpoint>,
+    dedup: Option<Dedup>,
+
+    /// With `--no-empty-dirs`, directory entries are held back until their
+    /// first descendant is written instead of being written when visited, so
+    /// directories without any written descendants never reach the archive.
+    pending_directories: Option<Vec<ataf::spec::ArchiveEntryHeader>>,
+
+    /// With `--solid`, entries are collected and written together once every
+    /// input has been visited.
+    solid: Option<Vec<(ataf::spec::ArchiveEntryHeader, Box<dyn std::io::Read>)>>,
+
+    tee: Option<Tee>,
+    record_abspath: bool,
+    excludes: Patterns,
+
+    /// With `--dereference`, symlinks are archived as what they point to.
+    dereference: bool,
+
+    /// The directories currently being descended into. Reaching one of them
+    /// again below itself, through a followed symlink or a bind mount, would
+    /// recurse forever.
+    ancestors: Vec<(DirectoryId, PathBuf)>,
+
+    /// With `--sparse`, files with holes are stored as their data only.
+    sparse: bool,
+
+    /// With `--mmap`, regular files are read through a memory map.
+    mmap: bool,
+
+    /// With `--max-depth`, the directory levels below each input that are
+    /// still descended into.
+    max_depth: Option<u64>,
+
+    accounts: Accounts,
+
+    /// The sum of the sizes of all written entries.
+    input_bytes: u64,
+
+    /// The number of entries that failed and were skipped or archived
+    /// incomplete, shared with the inputs that are read after the walk.
+    failures: Rc<Cell<u64>>,
+
+    /// With `--one-file-system`, the device of the input currently being
+    /// added, entries on any other device are skipped. Device ids are only
+    /// compared on unix, on Windows the option has no effect.
+    #[cfg(target_family = "unix")]
+    root_device: Option<u64>,
+
+    /// The archive path of the first file seen for each `(dev, ino)` with more
+    /// than one link, later names of it are written as hard links to it.
+    #[cfg(target_family = "unix")]
+    hard_links: HashMap<(u64, u64), String>,
+}
+
+impl CreateState {
+    fn failed(&self) {
+        self.failures.set(self.failures.get() + 1);
+    }
+}
+
+/// The work left while walking an input. Directories are walked with a stack
+/// instead of recursion, so the depth of a tree isn't bounded by the stack size.
+enum Visit {
+    Entry {
+        input: PathBuf,
+        depth: u64,
+    },
+
+    /// Runs once everything below a directory has been visited.
+    LeaveDirectory {
+        ancestor: bool,
+        pending_index: usize,
+    },
+}
+
+/// Tells directories apart however they are reached, by device and inode on
+/// unix and by canonical path elsewhere.
+#[cfg(target_family = "unix")]
+type DirectoryId = (u64, u64);
+#[cfg(not(target_family = "unix"))]
+type DirectoryId = PathBuf;
+
+#[cfg(target_family = "unix")]
+fn directory_id(_input: &Path, metadata: &std::fs::Metadata) -> Option<DirectoryId> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(target_family = "unix"))]
+fn directory_id(input: &Path, _metadata: &std::fs::Metadata) -> Option<DirectoryId> {
+    input.canonicalize().ok()
+}
+
+fn write_entry(
+    archive: &mut CreateArchiveWriter,
+    state: &mut CreateState,
+    entry: ataf::spec::ArchiveEntryHeader,
+    input: Box<dyn std::io::Read>,
+) {
+    let pending_directories = state
+        .pending_directories
+        .as_mut()
+        .map(|pending_directories| pending_directories.drain(..))
+        .into_iter()
+        .flatten()
+        .map(|directory| {
+            (
+                directory,
+                Box::new(std::io::empty()) as Box<dyn std::io::Read>,
+            )
+        });
+
+    for (entry, input) in pending_directories.chain([(entry, input)]) {
+        state.input_bytes += *entry.size;
+
+        if let Some(checkpoint) = &mut state.checkpoint {
+            checkpoint.record(*entry.size);
+        }
+
+        let input = match &state.tee {
+            Some(tee) => tee.entry(&entry, input),
+            None => input,
+        };
+
+        if let Some(solid) = &mut state.solid {
+            solid.push((entry, input));
+        } else {
+            let path = entry.path.clone();
+            if let Err(err) = archive.write_entry(entry, input) {
+                super::exit_on_error(
+                    "archive_entry",
+                    Some(Path::new(&path)),
+                    err,
+                    format_args!("failed to archive {path}"),
+                );
+            }
+        }
+    }
+}
+
+/// Fills a file up to the size in its entry header with zeros once reading it
+/// fails or it turns out to have shrunk. The header is written by then, so the
+/// entry can't be left out anymore, but the rest of the archive stays intact.
+struct CheckedInput {
+    input: Box<dyn std::io::Read>,
+    path: PathBuf,
+    remaining: u64,
+    failed: bool,
+    failures: Rc<Cell<u64>>,
+}
+
+impl CheckedInput {
+    fn fail(&mut self, err: Option<&std::io::Error>) {
+        crate::report::error(
+            "read_file",
+            Some(&self.path),
+            err.map(|err| err as &dyn crate::report::Reportable),
+            format_args!(
+                "failed to read {}, its last {} bytes are archived as zeros",
+                self.path.display(),
+                self.remaining
+            ),
+        );
+        self.failures.set(self.failures.get() + 1);
+        self.failed = true;
+    }
+}
+
+impl std::io::Read for CheckedInput {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if !self.failed {
+            match self.input.read(buf) {
+                Ok(0) if self.remaining > 0 && !buf.is_empty() => self.fail(None),
+                Ok(read) => {
+                    self.remaining = self.remaining.saturating_sub(read as u64);
+                    return Ok(read);
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => return Err(err),
+                Err(err) => self.fail(Some(&err)),
+            }
+        }
+
+        let length = buf
+            .len()
+            .min(self.remaining.min(usize::MAX as u64) as usize);
+        buf[..length].fill(0);
+        self.remaining -= length as u64;
+
+        Ok(length)
+    }
+}
+
+/// Counts the bytes written to the archive output, however many volumes or
+/// which pipe they end up in.
+struct CountingWriter {
+    writer: Box<dyn std::io::Write + Send>,
+    written: Arc<AtomicU64>,
+}
+
+impl std::io::Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.written.fetch_add(written as u64, Ordering::Relaxed);
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Opens a file on its first read, so solid archives don't keep a descriptor
+/// open for every input until the data is written.
+struct LazyFile {
+    path: PathBuf,
+    file: Option<std::fs::File>,
+}
+
+impl std::io::Seek for LazyFile {
+    fn seek(&mut self, position: std::io::SeekFrom) -> std::io::Result<u64> {
+        match &mut self.file {
+            Some(file) => file.seek(position),
+            None => self
+                .file
+                .insert(std::fs::File::open(&self.path)?)
+                .seek(position),
+        }
+    }
+}
+
+impl std::io::Read for LazyFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => self.file.insert(std::fs::File::open(&self.path)?),
+        };
+
+        let read = file.read(buf)?;
+        if read == 0 {
+            self.file = None;
+        }
+
+        Ok(read)
+    }
+}
+
+fn input_size(input: &Path) -> u64 {
+    let mut size = 0;
+    let mut stack = vec![input.to_path_buf()];
+
+    while let Some(input) = stack.pop() {
+        match std::fs::symlink_metadata(&input) {
+            Ok(metadata) if metadata.is_dir() => {
+                if let Ok(entries) = std::fs::read_dir(&input) {
+                    stack.extend(entries.filter_map(Result::ok).map(|entry| entry.path()));
+                }
+            }
+            Ok(metadata) if metadata.is_file() => size += metadata.len(),
+            _ => {}
+        }
+    }
+
+    size
+}
+
+/// The entry type and data of FIFOs, sockets and device nodes, the data being
+/// the device number of devices.
+#[cfg(target_family = "unix")]
+fn special_file(
+    metadata: &std::fs::Metadata,
+) -> Option<(ataf::spec::ArchiveEntryHeaderType, Vec<u8>)> {
+    use ataf::spec::ArchiveEntryHeaderType;
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+    let file_type = metadata.file_type();
+    if file_type.is_fifo() {
+        Some((ArchiveEntryHeaderType::Fifo, Vec::new()))
+    } else if file_type.is_socket() {
+        Some((ArchiveEntryHeaderType::Socket, Vec::new()))
+    } else if file_type.is_block_device() {
+        Some((
+            ArchiveEntryHeaderType::BlockDevice,
+            metadata.rdev().to_le_bytes().to_vec(),
+        ))
+    } else if file_type.is_char_device() {
+        Some((
+            ArchiveEntryHeaderType::CharDevice,
+            metadata.rdev().to_le_bytes().to_vec(),
+        ))
+    } else {
+        None
+    }
+}
+
+#[cfg(not(target_family = "unix"))]
+fn special_file(
+    _metadata: &std::fs::Metadata,
+) -> Option<(ataf::spec::ArchiveEntryHeaderType, Vec<u8>)> {
+    None
+}
+
+/// Creates the archive file, or its first volume with `--volume-size`.
+fn create_output(
+    path: &Path,
+    volume_size: Option<u64>,
+) -> std::io::Result<Box<dyn std::io::Write + Send>> {
+    Ok(match volume_size {
+        Some(volume_size) => Box::new(VolumeWriter::new(path.to_path_buf(), volume_size)?),
+        None => Box::new(std::fs::File::create(path)?),
+    })
+}
+
 pub fn run(matches: &ArgMatches) -> i32 {
     let compression_format = matches
         .get_one::<CompressionFormat>("compression_format")
         .unwrap();
     let threads = matches.get_one::<usize>("threads").unwrap();
-    let chunk_size = matches.get_one::<u32>("chunk_size").unwrap();
+    let chunk_size = matches
+        .get_one::<u32>("chunk_size")
+        .copied()
+        .unwrap_or_else(|| compression_format.default_chunk_size());
     let output = matches.get_one::<PathBuf>("output");
     let inputs = matches.get_many::<PathBuf>("input").unwrap();
+    let time_budget = matches.get_one::<u64>("time_budget");
+
+    let comment = matches
+        .get_one::<String>("comment")
+        .cloned()
+        .unwrap_or_default();
+    if comment.len() > usize::from(u16::MAX) {
+        crate::report::error(
+            "invalid_comment",
+            None,
+            None,
+            format_args!(
+                "the comment is {} bytes long, at most {} fit in the archive header",
+                comment.len(),
+                u16::MAX
+            ),
+        );
+        return 1;
+    }
+
+    let mut exclude_patterns: Vec<String> = matches
+        .get_many::<String>("exclude")
+        .map(|patterns| patterns.cloned().collect())
+        .unwrap_or_default();
+    for path in matches
+        .get_many::<PathBuf>("exclude_from")
+        .into_iter()
+        .flatten()
+    {
+        match Patterns::read_patterns(path) {
+            Ok(patterns) => exclude_patterns.extend(patterns),
+            Err(err) => {
+                crate::report::error(
+                    "exclude_from",
+                    Some(path),
+                    Some(&err),
+                    format_args!("failed to read exclude patterns from {}", path.display()),
+                );
+                return 1;
+            }
+        }
+    }
+    let level = matches.get_one::<u32>("level");
 
     println_if_terminal!("creating archive with the following options:");
     println_if_terminal!("compression format: {:?}", compression_format);
     println_if_terminal!("number of threads: {}", threads);
     println_if_terminal!("chunk size: {}", chunk_size);
 
-    type DynCompressor =
-        dyn Compressor<BufWriter<Box<dyn std::io::Write + Send>>, Box<dyn std::io::Read>>;
-
-    let compressor: Box<DynCompressor> = match compression_format {
-        CompressionFormat::None => Box::new(ataf::compression::NoCompressor::new()),
-        #[cfg(feature = "flate2")]
-        CompressionFormat::Flate2 => Box::new(ataf::compression::Flate2Compressor::new(
-            *threads,
-            flate2::Compression::best(),
-        )),
-        #[cfg(feature = "brotli")]
-        CompressionFormat::Brotli => Box::new(ataf::compression::BrotliCompressor::new(
-            *threads,
-            brotli::enc::BrotliEncoderParams::default(),
-        )),
-        #[cfg(feature = "lz4")]
-        CompressionFormat::Lz4 => Box::new(ataf::compression::Lz4Compressor::new(*threads, 17)),
+    #[cfg_attr(not(feature = "brotli"), allow(unused_mut))]
+    let mut registry = super::Registry::default();
+    #[cfg(feature = "brotli")]
+    if *compression_format == CompressionFormat::Brotli {
+        let mut params = brotli::enc::BrotliEncoderParams::default();
+        if let Some(quality) = matches.get_one::<i32>("brotli_quality") {
+            params.quality = *quality;
+        }
+        if let Some(window) = matches.get_one::<i32>("brotli_window") {
+            params.lgwin = *window;
+        }
+        println_if_terminal!("brotli quality: {}", params.quality);
+        println_if_terminal!("brotli window: {}", params.lgwin);
+
+        registry.register(
+            "brotli",
+            move |threads| {
+                Ok(Box::new(ataf::compression::BrotliCompressor::new(
+                    threads,
+                    params.clone(),
+                )?))
+            },
+            |threads| {
+                Ok(Box::new(ataf::compression::BrotliDecompressor::new(
+                    threads,
+                )?))
+            },
+        );
+    }
+
+    let mut compressor = match registry.compressor(compression_format.name(), *threads) {
+        Ok(compressor) => compressor,
+        Err(err) => {
+            crate::report::error(
+                "start_compressor",
+                None,
+                Some(&err),
+                format_args!(
+                    "failed to start the {} compressor",
+                    compression_format.name()
+                ),
+            );
+            return 1;
+        }
     };
 
+    // levels mean different things per format, so are checked against its own,
+    // formats with a single level ignore it
+    let levels = compressor.levels();
+    if let Some(level) = level
+        && levels.start() != levels.end()
+    {
+        if !levels.contains(level) {
+            crate::report::error(
+                "invalid_level",
+                None,
+                None,
+                format_args!(
+                    "compression level {} is out of range for {}, which accepts {} to {}",
+                    level,
+                    compressor.name(),
+                    levels.start(),
+                    levels.end()
+                ),
+            );
+            return 1;
+        }
+
+        compressor.set_level(*level);
+        println_if_terminal!("compression level: {}", level);
+    }
+    if let Some(chunks) = matches.get_one::<usize>("in_flight") {
+        compressor.set_in_flight(*chunks);
+    }
+
+    // read before anything is written, so a bad key doesn't leave an archive behind
+    #[cfg(feature = "ed25519")]
+    let signing_key = match matches.get_one::<PathBuf>("sign") {
+        Some(path) => match crate::signature::signing_key(path) {
+            Ok(key) => Some(key),
+            Err(err) => {
+                crate::report::error(
+                    "read_key",
+                    Some(path),
+                    Some(&err),
+                    format_args!("failed to read signing key {}", path.display()),
+                );
+                return 1;
+            }
+        },
+        None => None,
+    };
+    #[cfg(not(feature = "ed25519"))]
+    if matches.get_one::<PathBuf>("sign").is_some() {
+        crate::report::error(
+            "unsupported_signature",
+            None,
+            None,
+            format_args!("--sign needs ataf built with the ed25519 feature"),
+        );
+        return 1;
+    }
+
+    #[cfg(feature = "encryption")]
+    let encryption_key = match matches.get_flag("encrypt") {
+        true => match super::KeySource::from_matches(matches).encryption(
+            ataf::encryption::Argon2Parameters::new(
+                matches
+                    .get_one::<u32>("kdf_memory")
+                    .copied()
+                    .unwrap_or(ataf::encryption::Argon2Parameters::DEFAULT_MEMORY),
+                matches
+                    .get_one::<u32>("kdf_iterations")
+                    .copied()
+                    .unwrap_or(ataf::encryption::Argon2Parameters::DEFAULT_ITERATIONS),
+                matches
+                    .get_one::<u32>("kdf_parallelism")
+                    .copied()
+                    .unwrap_or(ataf::encryption::Argon2Parameters::DEFAULT_PARALLELISM),
+            ),
+        ) {
+            Ok(encryption) => Some(encryption),
+            Err(err) => {
+                crate::report::error(
+                    "read_key",
+                    None,
+                    Some(&err),
+                    format_args!("failed to read the encryption key"),
+                );
+                return 1;
+            }
+        },
+        false => None,
+    };
+    #[cfg(not(feature = "encryption"))]
+    if matches.get_flag("encrypt") {
+        crate::report::error(
+            "unsupported_encryption",
+            None,
+            None,
+            format_args!("--encrypt needs ataf built with the encryption feature"),
+        );
+        return 1;
+    }
+
+    let volume_size = matches.get_one::<u64>("volume_size");
     let writer: Box<dyn std::io::Write + Send> = match output {
-        Some(path) => Box::new(std::fs::File::create(path).unwrap()),
+        Some(path) => match create_output(path, volume_size.copied()) {
+            Ok(writer) => writer,
+            Err(err) => {
+                crate::report::error(
+                    "create_archive",
+                    Some(path),
+                    Some(&err),
+                    format_args!("failed to create archive {}", path.display()),
+                );
+                return 1;
+            }
+        },
         None => Box::new(std::io::stdout()),
     };
-    let mut archive = ataf::archive::write::ArchiveWriter::new(
-        BufWriter::with_capacity(1024 * 1024, writer),
+    let output_bytes = Arc::new(AtomicU64::new(0));
+    let writer: Box<dyn std::io::Write + Send> = Box::new(CountingWriter {
+        writer,
+        written: output_bytes.clone(),
+    });
+    #[cfg(feature = "ed25519")]
+    let hasher = Arc::new(std::sync::Mutex::new(sha2::Sha256::default()));
+    #[cfg(feature = "ed25519")]
+    let writer: Box<dyn std::io::Write + Send> = match signing_key {
+        Some(_) => Box::new(crate::signature::HashingWriter {
+            writer,
+            hasher: hasher.clone(),
+        }),
+        None => writer,
+    };
+    // outermost, so the size and signature are those of the encrypted archive
+    #[cfg(feature = "encryption")]
+    let (writer, encryption) = match encryption_key {
+        Some((header, key)) => match super::SharedEncryption::new(writer, &key, header) {
+            Ok(encryption) => (
+                Box::new(encryption.clone()) as Box<dyn std::io::Write + Send>,
+                Some(encryption),
+            ),
+            Err(err) => super::exit_on_error(
+                "encrypt_archive",
+                None,
+                err,
+                format_args!("failed to set up the encryption"),
+            ),
+        },
+        None => (writer, None),
+    };
+    let started = Instant::now();
+    let solid = matches.get_flag("solid");
+    let writer = BufWriter::with_capacity(1024 * 1024, writer);
+    let mut archive = match ataf::archive::write::ArchiveWriter::with_options(
+        writer,
         compressor,
-        *chunk_size,
-    )
-    .unwrap();
+        chunk_size,
+        ataf::archive::write::ArchiveOptions {
+            solid,
+            prefix_paths: matches.get_flag("prefix_paths"),
+            checksum: match matches.get_one::<String>("checksum").map(String::as_str) {
+                Some("xxh3") => ChunkChecksum::Xxh3,
+                Some("crc32") => ChunkChecksum::Crc32,
+                None if matches.get_flag("checksums") => ChunkChecksum::Crc32,
+                _ => ChunkChecksum::None,
+            },
+            index: matches.get_flag("index"),
+            comment,
+            flush_chunks: volume_size.is_some(),
+        },
+    ) {
+        Ok(archive) => archive,
+        Err(err) => super::exit_on_error(
+            "create_archive",
+            None,
+            err,
+            format_args!("failed to start the archive"),
+        ),
+    };
+    let progress = matches.get_flag("progress");
+    if progress {
+        archive.set_progress(crate::progress::status_line(0));
+    }
+
+    let auto_level = if matches.get_flag("compress_level_auto") {
+        let levels = archive.compression_levels();
+        archive.set_compression_level(*levels.end());
+
+        Some(AutoLevel::new(
+            Duration::from_secs(*time_budget.unwrap()),
+            inputs.clone().map(|input| input_size(input)).sum(),
+            levels,
+        ))
+    } else {
+        None
+    };
+
+    let mut state = CreateState {
+        auto_level,
+        checkpoint: Checkpoint::from_matches(matches),
+        dedup: matches.get_flag("dedup").then(|| Dedup {
+            seen: HashMap::new(),
+        }),
+        pending_directories: matches.get_flag("no_empty_dirs").then(Vec::new),
+        solid: solid.then(Vec::new),
+        tee: matches.get_one::<PathBuf>("tee").cloned().map(Tee::new),
+        record_abspath: matches.get_flag("record_abspath"),
+        excludes: Patterns::new(exclude_patterns),
+        dereference: matches.get_flag("dereference"),
+        ancestors: Vec::new(),
+        sparse: matches.get_flag("sparse"),
+        mmap: matches.get_flag("mmap"),
+        max_depth: matches.get_one::<u64>("max_depth").copied(),
+        accounts: Accounts::default(),
+        input_bytes: 0,
+        failures: Rc::new(Cell::new(0)),
+        #[cfg(target_family = "unix")]
+        root_device: None,
+        #[cfg(target_family = "unix")]
+        hard_links: HashMap::new(),
+    };
+    #[cfg(target_family = "unix")]
+    let one_file_system = matches.get_flag("one_file_system");
 
     fn add_to_archive(
-        archive: &mut ataf::archive::write::ArchiveWriter<
-            BufWriter<Box<dyn std::io::Write + Send>>,
-            Box<dyn std::io::Read>,
-        >,
+        archive: &mut CreateArchiveWriter,
+        input: &Path,
+        root: &Path,
+        state: &mut CreateState,
+    ) {
+        let mut stack = vec![Visit::Entry {
+            input: input.to_path_buf(),
+            depth: 0,
+        }];
+
+        while let Some(visit) = stack.pop() {
+            match visit {
+                Visit::Entry { input, depth } => {
+                    add_entry(archive, &input, root, depth, state, &mut stack)
+                }
+                Visit::LeaveDirectory {
+                    ancestor,
+                    pending_index,
+                } => {
+                    if ancestor {
+                        state.ancestors.pop();
+                    }
+
+                    // still pending means nothing below this directory was written
+                    if let Some(pending_directories) = &mut state.pending_directories {
+                        pending_directories.truncate(pending_index);
+                    }
+                }
+            }
+        }
+    }
+
+    fn add_entry(
+        archive: &mut CreateArchiveWriter,
         input: &PathBuf,
         root: &Path,
+        depth: u64,
+        state: &mut CreateState,
+        stack: &mut Vec<Visit>,
     ) {
         println_if_terminal!("adding {} to archive...", input.display());
 
-        let metadata = match std::fs::symlink_metadata(input) {
+        // dangling symlinks are archived as they are
+        let metadata = match state.dereference {
+            true => std::fs::metadata(input).or_else(|_| std::fs::symlink_metadata(input)),
+            false => std::fs::symlink_metadata(input),
+        };
+        let metadata = match metadata {
             Ok(metadata) => metadata,
             Err(err) => {
+                crate::report::error(
+                    "metadata",
+                    Some(input),
+                    Some(&err),
+                    format_args!("failed to read metadata for {}", input.display()),
+                );
+                state.failed();
+                return;
+            }
+        };
+
+        #[cfg(target_family = "unix")]
+        if let Some(root_device) = state.root_device {
+            use std::os::unix::fs::MetadataExt;
+
+            if metadata.dev() != root_device {
                 eprintln!(
-                    "ERROR failed to read metadata for {}: {}",
-                    input.display(),
-                    err
+                    "WARN skipping {}, it is on another filesystem",
+                    input.display()
                 );
                 return;
             }
+        }
+
+        let relative = input.strip_prefix(root).unwrap_or(input);
+        let path = relative.to_string_lossy().to_string();
+
+        // names that aren't valid unicode keep their exact bytes next to the
+        // lossy path, which is what gets extracted on unix
+        #[cfg(target_family = "unix")]
+        let raw_path = relative.to_str().is_none().then(|| {
+            use std::os::unix::ffi::OsStrExt;
+
+            relative.as_os_str().as_bytes().to_vec()
+        });
+        #[cfg(not(target_family = "unix"))]
+        let raw_path = {
+            if relative.to_str().is_none() {
+                eprintln!(
+                    "WARN {} isn't valid unicode, archived as {}",
+                    input.display(),
+                    path
+                );
+            }
+            None
         };
 
+        // an input given directly is its own root with an empty path
+        if !path.is_empty() && state.excludes.is_match(&path, metadata.is_dir()) {
+            println_if_terminal!("excluding {}", input.display());
+            return;
+        }
+
         #[cfg(target_family = "unix")]
         let mode = {
             use std::os::unix::fs::PermissionsExt;
@@ -116,18 +913,174 @@ pub fn run(matches: &ArgMatches) -> i32 {
         #[cfg(target_family = "windows")]
         let gid = 0;
 
-        let path = input
-            .strip_prefix(root)
-            .unwrap_or(input)
-            .to_string_lossy()
-            .to_string();
+        let uname = state.accounts.user_name(uid);
+        let gname = state.accounts.group_name(gid);
+
+        let mtime = metadata
+            .modified()
+            .unwrap_or_else(|_| SystemTime::now())
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let original_path = state.record_abspath.then(|| {
+            std::path::absolute(input)
+                .unwrap_or_else(|_| input.clone())
+                .to_string_lossy()
+                .to_string()
+        });
+
+        // unix modes carry the exec bits themselves
+        #[cfg(target_family = "unix")]
+        let executable = false;
+        #[cfg(target_family = "windows")]
+        let executable = metadata.is_file() && crate::fs::is_executable(input);
 
         if metadata.is_file() {
-            let file = match std::fs::File::open(input) {
-                Ok(file) => file,
-                Err(err) => {
-                    eprintln!("ERROR failed to open {}: {}", input.display(), err);
-                    return;
+            // the first link is recorded once it's written, so later links
+            // never point to an entry that failed and isn't in the archive
+            #[cfg(target_family = "unix")]
+            let mut first_link = None;
+            #[cfg(target_family = "unix")]
+            {
+                use std::os::unix::fs::MetadataExt;
+
+                if metadata.nlink() > 1 {
+                    let key = (metadata.dev(), metadata.ino());
+                    match state.hard_links.get(&key) {
+                        Some(original) => {
+                            let original = original.clone();
+                            let entry = ataf::spec::ArchiveEntryHeader {
+                                r#type: ataf::spec::ArchiveEntryHeaderType::HardLink,
+                                path,
+                                mode,
+                                uid: VariableSizedU32::new(uid),
+                                gid: VariableSizedU32::new(gid),
+                                uname,
+                                gname,
+                                mtime: VariableSizedU64::new(mtime.as_secs()),
+                                mtime_nanos: VariableSizedU32::new(mtime.subsec_nanos()),
+                                size: VariableSizedU64::new(original.len() as u64),
+                                flags: crate::fs::get_flags(input),
+                                compression: None,
+                                executable,
+                                original_path,
+                                sparse: None,
+                                raw_path: raw_path.clone(),
+                            };
+                            write_entry(
+                                archive,
+                                state,
+                                entry,
+                                Box::new(std::io::Cursor::new(original.into_bytes())),
+                            );
+
+                            return;
+                        }
+                        None => first_link = Some((key, path.clone())),
+                    }
+                }
+            }
+
+            if let Some(dedup) = &mut state.dedup
+                && metadata.len() > 0
+            {
+                match dedup.find_or_insert(input, metadata.len(), &path) {
+                    Ok(Some(original)) => {
+                        let entry = ataf::spec::ArchiveEntryHeader {
+                            r#type: ataf::spec::ArchiveEntryHeaderType::Reference,
+                            path,
+                            mode,
+                            uid: VariableSizedU32::new(uid),
+                            gid: VariableSizedU32::new(gid),
+                            uname,
+                            gname,
+                            mtime: VariableSizedU64::new(mtime.as_secs()),
+                            mtime_nanos: VariableSizedU32::new(mtime.subsec_nanos()),
+                            size: VariableSizedU64::new(original.len() as u64),
+                            flags: crate::fs::get_flags(input),
+                            compression: None,
+                            executable,
+                            original_path: original_path.clone(),
+                            sparse: None,
+                            raw_path: raw_path.clone(),
+                        };
+                        write_entry(
+                            archive,
+                            state,
+                            entry,
+                            Box::new(std::io::Cursor::new(original.into_bytes())),
+                        );
+
+                        return;
+                    }
+                    Ok(None) => {}
+                    Err(err) => {
+                        crate::report::error(
+                            "hash",
+                            Some(input),
+                            Some(&err),
+                            format_args!("failed to hash {}", input.display()),
+                        );
+                        state.failed();
+                        return;
+                    }
+                }
+            }
+
+            let sparse = if state.sparse {
+                match std::fs::File::open(input)
+                    .and_then(|file| ataf::archive::sparse::find_holes(&file))
+                {
+                    Ok(sparse) => sparse,
+                    Err(err) => {
+                        crate::report::error(
+                            "find_holes",
+                            Some(input),
+                            Some(&err),
+                            format_args!("failed to look for holes in {}", input.display()),
+                        );
+                        state.failed();
+                        return;
+                    }
+                }
+            } else {
+                None
+            };
+
+            // the file was only opened to look for holes, its data is read
+            // through another one opened when it's needed
+            let file: Box<dyn std::io::Read> = if let Some(sparse) = &sparse {
+                Box::new(ataf::archive::sparse::SparseReader::new(
+                    LazyFile {
+                        path: input.clone(),
+                        file: None,
+                    },
+                    sparse,
+                ))
+            } else if state.solid.is_some() {
+                Box::new(LazyFile {
+                    path: input.clone(),
+                    file: None,
+                })
+            } else {
+                match std::fs::File::open(input) {
+                    Ok(file) if state.mmap => {
+                        match ataf::archive::mmap::MappedFile::map(file, metadata.len()) {
+                            Ok(mapped) => Box::new(mapped),
+                            Err(file) => Box::new(file),
+                        }
+                    }
+                    Ok(file) => Box::new(file),
+                    Err(err) => {
+                        crate::report::error(
+                            "open",
+                            Some(input),
+                            Some(&err),
+                            format_args!("failed to open {}", input.display()),
+                        );
+                        state.failed();
+                        return;
+                    }
                 }
             };
 
@@ -137,17 +1090,41 @@ pub fn run(matches: &ArgMatches) -> i32 {
                 mode,
                 uid: VariableSizedU32::new(uid),
                 gid: VariableSizedU32::new(gid),
-                mtime: VariableSizedU64::new(
-                    metadata
-                        .modified()
-                        .unwrap_or_else(|_| SystemTime::now())
-                        .duration_since(SystemTime::UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs(),
-                ),
-                size: VariableSizedU64::new(metadata.len()),
+                uname,
+                gname,
+                mtime: VariableSizedU64::new(mtime.as_secs()),
+                mtime_nanos: VariableSizedU32::new(mtime.subsec_nanos()),
+                size: VariableSizedU64::new(sparse.as_ref().map_or(metadata.len(), |sparse| {
+                    sparse.segments.iter().map(|segment| segment.length).sum()
+                })),
+                flags: crate::fs::get_flags(input),
+                compression: None,
+                executable,
+                original_path,
+                sparse,
+                raw_path: raw_path.clone(),
             };
-            archive.write_entry(entry, Box::new(file)).unwrap();
+            let file = Box::new(CheckedInput {
+                input: file,
+                path: input.clone(),
+                remaining: *entry.size,
+                failed: false,
+                failures: state.failures.clone(),
+            });
+            write_entry(archive, state, entry, file);
+            #[cfg(target_family = "unix")]
+            if let Some((key, path)) = first_link {
+                state.hard_links.insert(key, path);
+            }
+
+            if let Some(level) = state
+                .auto_level
+                .as_mut()
+                .and_then(|auto_level| auto_level.record(metadata.len()))
+            {
+                println_if_terminal!("lowering compression level to {}", level);
+                archive.set_compression_level(level);
+            }
         } else if metadata.is_dir() {
             let entry = ataf::spec::ArchiveEntryHeader {
                 r#type: ataf::spec::ArchiveEntryHeaderType::Directory,
@@ -155,52 +1132,114 @@ pub fn run(matches: &ArgMatches) -> i32 {
                 mode,
                 uid: VariableSizedU32::new(uid),
                 gid: VariableSizedU32::new(gid),
-                mtime: VariableSizedU64::new(
-                    metadata
-                        .modified()
-                        .unwrap_or_else(|_| SystemTime::now())
-                        .duration_since(SystemTime::UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs(),
-                ),
+                uname,
+                gname,
+                mtime: VariableSizedU64::new(mtime.as_secs()),
+                mtime_nanos: VariableSizedU32::new(mtime.subsec_nanos()),
                 size: VariableSizedU64::new(0),
+                flags: crate::fs::get_flags(input),
+                compression: None,
+                executable,
+                original_path,
+                sparse: None,
+                raw_path: raw_path.clone(),
+            };
+            let id = directory_id(input, &metadata);
+            if let Some(id) = &id
+                && let Some((_, ancestor)) = state.ancestors.iter().find(|(other, _)| other == id)
+            {
+                eprintln!(
+                    "WARN skipping {}, it leads back to {} which is being archived",
+                    input.display(),
+                    ancestor.display()
+                );
+                return;
+            }
+
+            let pending_index = match &mut state.pending_directories {
+                Some(pending_directories) => {
+                    pending_directories.push(entry);
+                    pending_directories.len() - 1
+                }
+                None => {
+                    write_entry(archive, state, entry, Box::new(std::io::empty()));
+                    0
+                }
             };
-            archive
-                .write_entry(entry, Box::new(std::io::empty()))
-                .unwrap();
 
             let entries = match std::fs::read_dir(input) {
                 Ok(entries) => entries,
                 Err(err) => {
-                    eprintln!(
-                        "ERROR failed to read directory {}: {}",
-                        input.display(),
-                        err
+                    if let Some(pending_directories) = &mut state.pending_directories {
+                        pending_directories.truncate(pending_index);
+                    }
+
+                    crate::report::error(
+                        "read_dir",
+                        Some(input),
+                        Some(&err),
+                        format_args!("failed to read directory {}", input.display()),
                     );
+                    state.failed();
                     return;
                 }
             };
 
+            let mut children = Vec::new();
             for entry in entries {
-                let entry = match entry {
-                    Ok(entry) => entry,
+                match entry {
+                    Ok(entry) => children.push(entry.path()),
                     Err(err) => {
-                        eprintln!(
-                            "ERROR failed to read directory entry {}: {}",
-                            input.display(),
-                            err
+                        crate::report::error(
+                            "read_dir_entry",
+                            Some(input),
+                            Some(&err),
+                            format_args!("failed to read directory entry {}", input.display()),
                         );
-                        continue;
+                        state.failed();
                     }
-                };
+                }
+            }
 
-                add_to_archive(archive, &entry.path(), root);
+            if let Some(max_depth) = state.max_depth
+                && depth >= max_depth
+                && !children.is_empty()
+            {
+                eprintln!(
+                    "WARN skipping {} entries in {}, they are deeper than --max-depth {}",
+                    children.len(),
+                    input.display(),
+                    max_depth
+                );
+                children.clear();
             }
+
+            let ancestor = id.is_some();
+            if let Some(id) = id {
+                state.ancestors.push((id, input.clone()));
+            }
+
+            // children are popped in the order they were read, before the
+            // directory is left
+            stack.push(Visit::LeaveDirectory {
+                ancestor,
+                pending_index,
+            });
+            stack.extend(children.into_iter().rev().map(|input| Visit::Entry {
+                input,
+                depth: depth + 1,
+            }));
         } else if metadata.is_symlink() {
             let symlink_target = match std::fs::read_link(input) {
                 Ok(target) => target,
                 Err(err) => {
-                    eprintln!("ERROR failed to read symlink {}: {}", input.display(), err);
+                    crate::report::error(
+                        "read_symlink",
+                        Some(input),
+                        Some(&err),
+                        format_args!("failed to read symlink {}", input.display()),
+                    );
+                    state.failed();
                     return;
                 }
             };
@@ -215,28 +1254,67 @@ pub fn run(matches: &ArgMatches) -> i32 {
                 mode,
                 uid: VariableSizedU32::new(uid),
                 gid: VariableSizedU32::new(gid),
-                mtime: VariableSizedU64::new(
-                    metadata
-                        .modified()
-                        .unwrap_or_else(|_| SystemTime::now())
-                        .duration_since(SystemTime::UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs(),
-                ),
+                uname,
+                gname,
+                mtime: VariableSizedU64::new(mtime.as_secs()),
+                mtime_nanos: VariableSizedU32::new(mtime.subsec_nanos()),
                 size: VariableSizedU64::new(symlink_target.to_string_lossy().len() as u64),
+                flags: None,
+                compression: None,
+                executable,
+                original_path,
+                sparse: None,
+                raw_path: raw_path.clone(),
             };
-            archive
-                .write_entry(
-                    entry,
-                    Box::new(std::io::Cursor::new(
-                        symlink_target.to_string_lossy().as_bytes().to_vec(),
-                    )),
-                )
-                .unwrap();
+            write_entry(
+                archive,
+                state,
+                entry,
+                Box::new(std::io::Cursor::new(
+                    symlink_target.to_string_lossy().as_bytes().to_vec(),
+                )),
+            );
+        } else if let Some((r#type, data)) = special_file(&metadata) {
+            let entry = ataf::spec::ArchiveEntryHeader {
+                r#type,
+                path,
+                mode,
+                uid: VariableSizedU32::new(uid),
+                gid: VariableSizedU32::new(gid),
+                uname,
+                gname,
+                mtime: VariableSizedU64::new(mtime.as_secs()),
+                mtime_nanos: VariableSizedU32::new(mtime.subsec_nanos()),
+                size: VariableSizedU64::new(data.len() as u64),
+                flags: None,
+                compression: None,
+                executable,
+                original_path,
+                sparse: None,
+                raw_path: raw_path.clone(),
+            };
+            write_entry(archive, state, entry, Box::new(std::io::Cursor::new(data)));
+        } else {
+            eprintln!(
+                "WARN skipping {}, its file type can't be archived",
+                input.display()
+            );
         }
     }
 
     for input in inputs {
+        #[cfg(target_family = "unix")]
+        if one_file_system {
+            use std::os::unix::fs::MetadataExt;
+
+            // the same metadata the walk compares against
+            let metadata = match state.dereference {
+                true => std::fs::metadata(input).or_else(|_| std::fs::symlink_metadata(input)),
+                false => std::fs::symlink_metadata(input),
+            };
+            state.root_device = metadata.ok().map(|metadata| metadata.dev());
+        }
+
         add_to_archive(
             &mut archive,
             input,
@@ -245,7 +1323,115 @@ pub fn run(matches: &ArgMatches) -> i32 {
             } else {
                 Path::new("")
             },
+            &mut state,
+        );
+    }
+
+    if let Some(entries) = state.solid.take() {
+        println_if_terminal!("writing {} entries as one solid stream...", entries.len());
+
+        if let Err(err) = archive.write_solid(entries) {
+            super::exit_on_error(
+                "archive_entry",
+                None,
+                err,
+                format_args!("failed to archive the solid stream"),
+            );
+        }
+    }
+
+    // only a plain archive file can have its header rewritten, everything
+    // else keeps zero totals
+    let stats = archive.stats();
+    let totals_path = output.filter(|_| volume_size.is_none());
+    #[cfg(feature = "encryption")]
+    let totals_path = totals_path.filter(|_| encryption.is_none());
+    #[cfg(feature = "ed25519")]
+    let totals_path = totals_path.filter(|_| signing_key.is_none());
+
+    let mut writer = match archive.finish() {
+        Ok(writer) => writer,
+        Err(err) => super::exit_on_write_error(err),
+    };
+    if let Err(err) = writer.flush() {
+        super::exit_on_write_error(err);
+    }
+    drop(writer);
+
+    #[cfg(feature = "encryption")]
+    if let Some(encryption) = encryption
+        && let Err(err) = encryption.finish()
+    {
+        super::exit_on_write_error(err);
+    }
+
+    if let Some(path) = totals_path
+        && let Err(err) = std::fs::File::options()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(ataf::Error::from)
+            .and_then(|file| ataf::archive::write::write_totals(file, stats))
+    {
+        eprintln!(
+            "WARN failed to store the entry count in {}: {}",
+            path.display(),
+            err
+        );
+    }
+
+    #[cfg(feature = "ed25519")]
+    if let (Some(key), Some(output)) = (&signing_key, output) {
+        let digest = sha2::Digest::finalize(std::mem::take(&mut *hasher.lock().unwrap())).into();
+        let path = crate::signature::signature_path(output);
+        if let Err(err) = crate::signature::write_signature(key, digest, &path) {
+            crate::report::error(
+                "write_signature",
+                Some(&path),
+                Some(&err),
+                format_args!("failed to write signature {}", path.display()),
+            );
+            return 1;
+        }
+    }
+
+    if progress {
+        crate::progress::finish();
+    }
+
+    if matches.get_flag("stats") || std::io::stdout().is_terminal() {
+        let elapsed = started.elapsed().as_secs_f64();
+        let output_bytes = output_bytes.load(Ordering::Relaxed);
+
+        println_if_terminal!(
+            "uncompressed size: {}",
+            super::human_size(state.input_bytes)
+        );
+        println_if_terminal!("compressed size: {}", super::human_size(output_bytes));
+        println_if_terminal!(
+            "compression ratio: {:.2}",
+            state.input_bytes as f64 / output_bytes.max(1) as f64
+        );
+        println_if_terminal!("elapsed time: {:.2}s", elapsed);
+        if elapsed > 0.0 {
+            println_if_terminal!(
+                "throughput: {}/s",
+                super::human_size((state.input_bytes as f64 / elapsed) as u64)
+            );
+        }
+    }
+
+    if state.failures.get() > 0 {
+        crate::report::error(
+            "entries_failed",
+            None,
+            None,
+            format_args!(
+                "{} entries could not be archived completely",
+                state.failures.get()
+            ),
         );
+        return 1;
     }
 
     0
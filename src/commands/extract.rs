@@ -1,9 +1,16 @@
-use ataf::compression::Decompressor;
+use crate::{checkpoint::Checkpoint, commands::OpenedArchive, glob::Patterns, users::Accounts};
+use ataf::{
+    archive::{
+        read::{ArchiveEntriesReader, ArchiveEntry},
+        sparse::{ExpandingReader, SparseWriter},
+    },
+    spec::ArchiveEntryHeaderType,
+};
 use clap::ArgMatches;
 use std::{
-    io::{BufReader, IsTerminal, Read},
+    collections::{HashMap, HashSet},
+    io::{IsTerminal, Read, Write},
     path::{Path, PathBuf},
-    time::{Duration, SystemTime},
 };
 
 macro_rules! println_if_terminal {
@@ -14,63 +21,996 @@ macro_rules! println_if_terminal {
     };
 }
 
+/// The owners to restore, uids and gids are remapped separately since they are
+/// different namespaces.
+struct OwnerMap {
+    uids: IdMap,
+    gids: IdMap,
+    /// With `--preserve-owner` alone the stored user and group names are
+    /// looked up first, the ids are only used for names that don't exist
+    /// here. `--numeric-owner` and the id maps leave the names out.
+    accounts: Option<Accounts>,
+}
+
+/// Stored ids mapped to local ones, read from `old:new` lines.
+#[derive(Default)]
+struct IdMap {
+    ids: HashMap<u32, u32>,
+    default: Option<u32>,
+}
+
+impl IdMap {
+    fn load(path: &Path, default: Option<u32>) -> std::io::Result<Self> {
+        let mut ids = HashMap::new();
+
+        for (i, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (old, new) = line
+                .split_once(':')
+                .and_then(|(old, new)| Some((old.trim().parse().ok()?, new.trim().parse().ok()?)))
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("invalid id map entry on line {}: {}", i + 1, line),
+                    )
+                })?;
+
+            ids.insert(old, new);
+        }
+
+        Ok(Self { ids, default })
+    }
+
+    #[inline]
+    fn map(&self, id: u32) -> u32 {
+        self.ids.get(&id).copied().or(self.default).unwrap_or(id)
+    }
+}
+
+impl OwnerMap {
+    #[cfg(target_family = "unix")]
+    fn uid(&mut self, header: &ataf::spec::ArchiveEntryHeader) -> u32 {
+        self.accounts
+            .as_mut()
+            .zip(header.uname.as_deref())
+            .and_then(|(accounts, name)| accounts.user_id(name))
+            .unwrap_or_else(|| self.uids.map(*header.uid))
+    }
+
+    #[cfg(target_family = "unix")]
+    fn gid(&mut self, header: &ataf::spec::ArchiveEntryHeader) -> u32 {
+        self.accounts
+            .as_mut()
+            .zip(header.gname.as_deref())
+            .and_then(|(accounts, name)| accounts.group_id(name))
+            .unwrap_or_else(|| self.gids.map(*header.gid))
+    }
+}
+
+/// Expected SHA-256 hashes of extracted files, read from `sha256sum` style
+/// lines (`<hex>  <path>`) with paths relative to the archive root.
+struct Manifest {
+    hashes: HashMap<String, String>,
+}
+
+impl Manifest {
+    fn load(path: &Path) -> std::io::Result<Self> {
+        let mut hashes = HashMap::new();
+
+        for (i, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (hash, path) = line
+                .split_once(char::is_whitespace)
+                .map(|(hash, path)| (hash, path.trim_start().trim_start_matches('*')))
+                .filter(|(hash, path)| {
+                    hash.len() == 64
+                        && hash.bytes().all(|byte| byte.is_ascii_hexdigit())
+                        && !path.is_empty()
+                })
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("invalid manifest entry on line {}: {}", i + 1, line),
+                    )
+                })?;
+
+            hashes.insert(normalize_path(path).to_string(), hash.to_ascii_lowercase());
+        }
+
+        Ok(Self { hashes })
+    }
+}
+
+/// Archive paths as given on the command line or in a manifest, which may
+/// start with `./` or `/` where the archived ones don't.
+#[inline]
+fn normalize_path(path: &str) -> &str {
+    path.trim_start_matches("./").trim_start_matches('/')
+}
+
+/// An archived path with `--strip-components` applied when given, `None` for
+/// entries that are stripped entirely.
+fn stripped_path(
+    path: impl AsRef<Path>,
+    strip_components: Option<usize>,
+) -> ataf::Result<Option<PathBuf>> {
+    match strip_components {
+        Some(count) => ataf::archive::extract::strip_components(path, count),
+        None => Ok(Some(path.as_ref().to_path_buf())),
+    }
+}
+
+/// Where the original of a hard link or reference was extracted to, its
+/// stored path is stripped like every entry path.
+fn original_destination(
+    output: &Path,
+    original: &str,
+    strip_components: Option<usize>,
+) -> ataf::Result<PathBuf> {
+    let path = stripped_path(original, strip_components)?.ok_or_else(|| {
+        ataf::Error::InvalidData(format!("{original} is removed by --strip-components"))
+    })?;
+
+    super::entry_destination(output, &path)
+}
+
+/// Refuses the original of a hard link or reference unless it was extracted
+/// in this run, as it was filtered out or failed otherwise.
+fn check_original(completed: &HashSet<PathBuf>, source: &Path, original: &str) -> ataf::Result<()> {
+    if completed.contains(source) {
+        return Ok(());
+    }
+
+    Err(ataf::Error::Io(std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("{original} was not extracted"),
+    )))
+}
+
+/// Reads the stored path of a hard link or reference, refusing sizes no path
+/// can have before anything is allocated for them.
+fn read_original_path<R: Read>(entry: &mut ArchiveEntry<'_, R>) -> ataf::Result<String> {
+    let size = *entry.header().size;
+    if size > ataf::spec::MAX_PATH_LENGTH {
+        return Err(ataf::Error::PathTooLong(size));
+    }
+
+    let mut original = String::with_capacity(size as usize);
+    entry.read_to_string(&mut original)?;
+
+    Ok(original)
+}
+
+/// Whether an earlier, interrupted run already extracted the entry to
+/// `destination`. A file counts once it has its full size and the stored
+/// modification time, which is only set after all of it was written, so a
+/// file cut off midway is extracted again. Everything else is created in one
+/// step and counts as soon as it exists, references once the copy got its
+/// time.
+fn already_extracted(destination: &Path, header: &ataf::spec::ArchiveEntryHeader) -> bool {
+    let Ok(metadata) = std::fs::symlink_metadata(destination) else {
+        return false;
+    };
+
+    match header.r#type {
+        ArchiveEntryHeaderType::File => {
+            let size = header
+                .sparse
+                .as_ref()
+                .map_or(*header.size, |sparse| sparse.size);
+            metadata.is_file()
+                && metadata.len() == size
+                && metadata
+                    .modified()
+                    .is_ok_and(|modified| modified == header.modified())
+        }
+        ArchiveEntryHeaderType::Reference => {
+            metadata.is_file()
+                && metadata
+                    .modified()
+                    .is_ok_and(|modified| modified == header.modified())
+        }
+        ArchiveEntryHeaderType::Directory => metadata.is_dir(),
+        _ => true,
+    }
+}
+
+/// The mode to give an extracted file, adding exec bits wherever the mode has
+/// read bits when the entry is marked executable or matches `exec_extensions`,
+/// limited to the bits in `mode_mask`.
+#[cfg(target_family = "unix")]
+fn file_mode(
+    header: &ataf::spec::ArchiveEntryHeader,
+    exec_extensions: &[String],
+    mode_mask: u32,
+) -> u32 {
+    let executable = header.executable
+        || Path::new(&header.path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| exec_extensions.iter().any(|known| known == extension));
+
+    let mode = if executable {
+        header.mode | (header.mode & 0o444) >> 2
+    } else {
+        header.mode
+    };
+
+    mode & mode_mask
+}
+
+/// Removes everything below `output` that is neither an extracted entry nor a
+/// parent directory of one. Symlinks are removed rather than followed, so
+/// nothing outside of `output` is ever touched.
+fn delete_extraneous(output: &Path, extracted: &HashMap<PathBuf, ArchiveEntryHeaderType>) {
+    let mut keep = HashSet::new();
+    for path in extracted.keys() {
+        keep.extend(path.ancestors().take_while(|ancestor| *ancestor != output));
+    }
+
+    fn delete_in(directory: &Path, keep: &HashSet<&Path>) {
+        let entries = match std::fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(err) => {
+                crate::report::error(
+                    "read_dir",
+                    Some(directory),
+                    Some(&err),
+                    format_args!("failed to read directory {}", directory.display()),
+                );
+                return;
+            }
+        };
+
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+
+            if keep.contains(path.as_path()) {
+                if is_dir {
+                    delete_in(&path, keep);
+                }
+                continue;
+            }
+
+            if let Err(err) = writeln!(std::io::stdout(), "deleting: {}", path.display()) {
+                super::exit_on_write_error(err);
+            }
+
+            let result = if is_dir {
+                std::fs::remove_dir_all(&path)
+            } else {
+                std::fs::remove_file(&path)
+            };
+            if let Err(err) = result {
+                crate::report::error(
+                    "delete",
+                    Some(&path),
+                    Some(&err),
+                    format_args!("failed to delete {}", path.display()),
+                );
+            }
+        }
+    }
+
+    delete_in(output, &keep);
+}
+
+/// Writes the data of a file entry to stdout, holes as zeros since stdout
+/// can't have any. Read errors are reported.
+fn write_file_data<R: Read>(
+    entry: &mut ArchiveEntry<'_, R>,
+    stdout: &mut std::io::StdoutLock<'_>,
+) -> Result<(), ()> {
+    let mut buffer = vec![0; 64 * 1024];
+    let path = entry.header().path.clone();
+    let sparse = entry.header().sparse.clone();
+    let mut reader: Box<dyn Read + '_> = match &sparse {
+        Some(sparse) => Box::new(ExpandingReader::new(entry, sparse)),
+        None => Box::new(entry),
+    };
+
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => read,
+            Err(err) => {
+                crate::report::error(
+                    "read_file",
+                    Some(Path::new(&path)),
+                    Some(&err),
+                    format_args!("error reading file {}", path),
+                );
+                return Err(());
+            }
+        };
+
+        if let Err(err) = stdout.write_all(&buffer[..read]) {
+            super::exit_on_write_error(err);
+        }
+    }
+}
+
+/// Opens the archive again with `reopen` and writes the data of the file entry
+/// at `index` to stdout, for entries whose data was passed already.
+fn write_file_data_at(
+    reopen: &dyn Fn() -> Option<OpenedArchive>,
+    ignore_header_chunk_size: bool,
+    index: u64,
+    stdout: &mut std::io::StdoutLock<'_>,
+) -> Result<(), ()> {
+    let (mut archive, decompressor, _) = reopen().ok_or(())?;
+    let mut entries = match archive.entries(decompressor) {
+        Ok(entries) => entries,
+        Err(err) => {
+            crate::report::error(
+                "read_header",
+                None,
+                Some(&err),
+                format_args!("failed to read archive header"),
+            );
+            return Err(());
+        }
+    };
+    entries.ignore_header_chunk_size(ignore_header_chunk_size);
+
+    for position in 0..=index {
+        let mut entry = match entries.next_entry() {
+            Some(Ok(entry)) => entry,
+            Some(Err(err)) => {
+                crate::report::error(
+                    "read_entry",
+                    None,
+                    Some(&err),
+                    format_args!("error reading entry"),
+                );
+                return Err(());
+            }
+            None => {
+                crate::report::error(
+                    "read_entry",
+                    None,
+                    None,
+                    format_args!("the archive changed while it was read"),
+                );
+                return Err(());
+            }
+        };
+
+        if position < index {
+            if let Err(err) = entry.skip() {
+                crate::report::error(
+                    "read_entry",
+                    None,
+                    Some(&err),
+                    format_args!("error reading entry"),
+                );
+                return Err(());
+            }
+        } else {
+            return write_file_data(&mut entry, stdout);
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the data of the selected file entries to stdout, one after another,
+/// and skips everything else. Hard links and references write the data of the
+/// entry they point to, read from the archive opened again with `reopen`.
+/// With `--duplicate-policy warn` the first of entries sharing a path is
+/// written and reading stops once every path was found, `error` reads on to
+/// report later ones and `overwrite` writes the last of them once the whole
+/// archive was read, through `reopen` as well. `files` records which paths
+/// were found. Selected entries that aren't files or links are reported.
+fn stream_files<R: Read>(
+    entries: &mut ArchiveEntriesReader<'_, R>,
+    files: &mut HashMap<String, bool>,
+    duplicate_policy: &str,
+    reopen: &dyn Fn() -> Option<OpenedArchive>,
+    ignore_header_chunk_size: bool,
+) -> i32 {
+    let mut stdout = std::io::stdout().lock();
+    let overwrite = duplicate_policy == "overwrite";
+    // the index of the file entry holding the data of every path seen so far
+    let mut sources: HashMap<String, u64> = HashMap::new();
+    // with overwrite, the index of the last selected entry of every path and
+    // that of the entry holding its data
+    let mut last: HashMap<String, (u64, u64)> = HashMap::new();
+    let mut status = 0;
+
+    for index in 0.. {
+        if duplicate_policy == "warn" && files.values().all(|found| *found) {
+            break;
+        }
+
+        let mut entry = match entries.next_entry() {
+            Some(Ok(entry)) => entry,
+            Some(Err(err)) => {
+                crate::report::error(
+                    "read_entry",
+                    None,
+                    Some(&err),
+                    format_args!("error reading entry"),
+                );
+                return 1;
+            }
+            None => break,
+        };
+
+        let path = normalize_path(&entry.header().path).to_owned();
+        let r#type = entry.header().r#type;
+        let source = match r#type {
+            ArchiveEntryHeaderType::File => Some(index),
+            ArchiveEntryHeaderType::HardLink | ArchiveEntryHeaderType::Reference => {
+                match read_original_path(&mut entry) {
+                    Ok(original) => sources.get(normalize_path(&original)).copied(),
+                    Err(err) => {
+                        crate::report::error(
+                            "read_entry",
+                            Some(Path::new(&path)),
+                            Some(&err),
+                            format_args!("error reading entry {}", path),
+                        );
+                        return 1;
+                    }
+                }
+            }
+            _ => None,
+        };
+        match source {
+            Some(source) => sources.insert(path.clone(), source),
+            None => sources.remove(&path),
+        };
+
+        let write = match files.get_mut(&path) {
+            Some(found) if *found && !overwrite => {
+                if duplicate_policy == "error" {
+                    crate::report::error(
+                        "duplicate_path",
+                        Some(Path::new(&path)),
+                        None,
+                        format_args!(
+                            "{path} is in the archive more than once, only the first was written"
+                        ),
+                    );
+                    status = 1;
+                }
+                None
+            }
+            Some(found) => {
+                *found = true;
+                if source.is_none() {
+                    crate::report::error(
+                        "not_a_file",
+                        Some(Path::new(&path)),
+                        None,
+                        format_args!(
+                            "{path} is a {type:?} entry, only files and links to them can be written to stdout"
+                        ),
+                    );
+                    status = 1;
+                    last.remove(&path);
+                }
+                source
+            }
+            None => None,
+        };
+
+        match write {
+            Some(source) if overwrite => {
+                last.insert(path, (index, source));
+            }
+            Some(source) if source == index => {
+                if write_file_data(&mut entry, &mut stdout).is_err() {
+                    return 1;
+                }
+                continue;
+            }
+            Some(source) => {
+                drop(entry);
+                if write_file_data_at(reopen, ignore_header_chunk_size, source, &mut stdout)
+                    .is_err()
+                {
+                    return 1;
+                }
+                continue;
+            }
+            None => {}
+        }
+
+        if let Err(err) = entry.skip() {
+            crate::report::error(
+                "read_entry",
+                None,
+                Some(&err),
+                format_args!("error reading entry"),
+            );
+            return 1;
+        }
+    }
+
+    let mut last: Vec<_> = last.into_values().collect();
+    last.sort_unstable();
+    for (_, source) in last {
+        if write_file_data_at(reopen, ignore_header_chunk_size, source, &mut stdout).is_err() {
+            return 1;
+        }
+    }
+
+    if let Err(err) = stdout.flush() {
+        super::exit_on_write_error(err);
+    }
+
+    status
+}
+
+/// Reports every path given with `--file` that matched no entry.
+fn report_missing_files(files: &HashMap<String, bool>) -> usize {
+    let mut missing = 0;
+    for (path, _) in files.iter().filter(|(_, found)| !**found) {
+        crate::report::error(
+            "file_not_found",
+            Some(Path::new(path)),
+            None,
+            format_args!("{} is not in the archive", path),
+        );
+        missing += 1;
+    }
+
+    missing
+}
+
 pub fn run(matches: &ArgMatches) -> i32 {
     let threads = matches.get_one::<usize>("threads").unwrap();
     let input = matches.get_one::<PathBuf>("input");
-    let output = matches.get_one::<PathBuf>("output").unwrap();
+    // `-o -` like many tools take it
+    let to_stdout = matches.get_flag("stdout")
+        || matches
+            .get_one::<PathBuf>("output")
+            .is_some_and(|output| output.as_os_str() == "-");
+    let mut files = matches.get_many::<String>("file").map(|files| {
+        files
+            .map(|path| (normalize_path(path).to_string(), false))
+            .collect::<HashMap<_, _>>()
+    });
+    if to_stdout && files.is_none() {
+        crate::report::error(
+            "missing_file",
+            None,
+            None,
+            format_args!("writing to stdout needs the files to write given with --file"),
+        );
+        return 1;
+    }
+    let includes = matches
+        .get_many::<String>("include")
+        .map(|patterns| Patterns::new(patterns.cloned()));
+    let preserve_flags = matches.get_flag("preserve_flags");
+    #[cfg(target_family = "unix")]
+    let exec_extensions: Vec<String> = matches
+        .get_many::<String>("exec_ext")
+        .map(|extensions| {
+            extensions
+                .map(|extension| extension.trim_start_matches('.').to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    // the stored mode as it is, or without the special bits and the umask
+    // applied like any newly created file
+    #[cfg(target_family = "unix")]
+    let mode_mask = if matches.get_flag("no_same_permissions") {
+        0o777 & !crate::fs::umask()
+    } else {
+        0o7777
+    };
+    let duplicate_policy = matches.get_one::<String>("duplicate_policy").unwrap();
+    let strip_components = matches.get_one::<usize>("strip_components").copied();
+    let keep_old_files = matches.get_flag("keep_old_files");
+    let resume = matches.get_flag("resume");
+    let load_ids = |map: &str, default: &str, kind: &str| -> Result<Option<IdMap>, ()> {
+        let Some(path) = matches.get_one::<PathBuf>(map) else {
+            return Ok(None);
+        };
 
-    println_if_terminal!("extracting archive with the following options:");
-    println_if_terminal!("number of threads: {}", threads);
+        match IdMap::load(path, matches.get_one::<u32>(default).copied()) {
+            Ok(ids) => Ok(Some(ids)),
+            Err(err) => {
+                crate::report::error(
+                    map,
+                    Some(path),
+                    Some(&err),
+                    format_args!("failed to load {} map {}", kind, path.display()),
+                );
+                Err(())
+            }
+        }
+    };
+    let Ok(uids) = load_ids("owner_map", "owner_map_default", "owner") else {
+        return 1;
+    };
+    let Ok(gids) = load_ids("group_map", "group_map_default", "group") else {
+        return 1;
+    };
+    let numeric_owner = matches.get_flag("numeric_owner") || uids.is_some() || gids.is_some();
+    let mut owner_map = (numeric_owner || matches.get_flag("preserve_owner")).then(|| OwnerMap {
+        uids: uids.unwrap_or_default(),
+        gids: gids.unwrap_or_default(),
+        accounts: (!numeric_owner).then(Accounts::default),
+    });
+    #[cfg(target_family = "unix")]
+    let mut chown_denied = false;
+    let mut manifest = match matches.get_one::<PathBuf>("verify_manifest") {
+        Some(path) => match Manifest::load(path) {
+            Ok(manifest) => Some(manifest),
+            Err(err) => {
+                crate::report::error(
+                    "manifest",
+                    Some(path),
+                    Some(&err),
+                    format_args!("failed to load manifest {}", path.display()),
+                );
+                return 1;
+            }
+        },
+        None => None,
+    };
+    let mut manifest_failures = 0;
+
+    if !to_stdout {
+        println_if_terminal!("extracting archive with the following options:");
+        println_if_terminal!("number of threads: {}", threads);
+    }
+
+    #[cfg(not(feature = "ed25519"))]
+    if matches.get_one::<PathBuf>("verify").is_some() {
+        crate::report::error(
+            "unsupported_signature",
+            None,
+            None,
+            format_args!("--verify needs ataf built with the ed25519 feature"),
+        );
+        return 1;
+    }
 
-    let reader: Box<dyn std::io::Read> = match input {
-        Some(path) => Box::new(std::fs::File::open(path).unwrap()),
-        None => Box::new(std::io::stdin()),
+    // the chunk size is covered by the header checksum, so a damaged one fails it
+    let ignore_header_chunk_size = matches.get_flag("ignore_header_chunk_size");
+    let keys = super::KeySource::from_matches(matches);
+    // fails closed, nothing is extracted unless the signature matches, and
+    // what is extracted is read from the files that were verified
+    let open = || -> Option<OpenedArchive> {
+        #[cfg(feature = "ed25519")]
+        if let (Some(public_key), Some(input)) = (matches.get_one::<PathBuf>("verify"), input) {
+            return match crate::signature::verifying_key(public_key)
+                .and_then(|key| crate::signature::verify_archive(input, &key))
+            {
+                Ok(archive) => super::open_archive_reader(
+                    archive,
+                    Some(input),
+                    *threads,
+                    ignore_header_chunk_size,
+                    &keys,
+                ),
+                Err(err) => {
+                    crate::report::error(
+                        "verify_signature",
+                        Some(input),
+                        Some(&err),
+                        format_args!(
+                            "refusing to extract {}, its signature can't be verified",
+                            input.display()
+                        ),
+                    );
+                    None
+                }
+            };
+        }
+
+        super::open_archive(input, *threads, ignore_header_chunk_size, &keys)
     };
-    let mut archive =
-        ataf::archive::read::Archive::new(BufReader::with_capacity(1024 * 1024, reader));
-
-    let decompressor: Box<dyn Decompressor> = match archive.header().unwrap().compression.as_str() {
-        "none" => Box::new(ataf::compression::NoDecompressor),
-        #[cfg(feature = "flate2")]
-        "flate2" => Box::new(ataf::compression::Flate2Decompressor::new(*threads)),
-        #[cfg(feature = "brotli")]
-        "brotli" => Box::new(ataf::compression::BrotliDecompressor::new(*threads)),
-        #[cfg(feature = "lz4")]
-        "lz4" => Box::new(ataf::compression::Lz4Decompressor::new(*threads)),
-        _ => {
-            eprintln!(
-                "ERROR unsupported compression format: {}",
-                archive.header().unwrap().compression
+    let Some((mut archive, decompressor, _)) = open() else {
+        return 1;
+    };
+    // zero when the archive doesn't store it
+    let total_entries = archive.header().map_or(0, |header| header.entry_count);
+
+    let mut entries = match archive.entries(decompressor) {
+        Ok(entries) => entries,
+        Err(err) => {
+            crate::report::error(
+                "read_header",
+                None,
+                Some(&err),
+                format_args!("failed to read archive header"),
             );
             return 1;
         }
     };
+    entries.ignore_header_chunk_size(ignore_header_chunk_size);
+    let progress = matches.get_flag("progress");
+    if progress {
+        entries.set_progress(crate::progress::status_line(total_entries));
+    }
+
+    if to_stdout && let Some(files) = &mut files {
+        // stdin can't be read a second time for the data of links
+        let reopen = || match input {
+            Some(_) => open(),
+            None => {
+                crate::report::error(
+                    "read_again",
+                    None,
+                    None,
+                    format_args!(
+                        "links and --duplicate-policy overwrite need the archive read again, which stdin can't be"
+                    ),
+                );
+                None
+            }
+        };
+        let status = stream_files(
+            &mut entries,
+            files,
+            duplicate_policy,
+            &reopen,
+            ignore_header_chunk_size,
+        );
+        return if report_missing_files(files) > 0 {
+            1
+        } else {
+            status
+        };
+    }
+
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+
+    if let Some(writers) = matches.get_one::<u64>("parallel") {
+        println_if_terminal!("writing files on {} threads", writers);
+
+        return match ataf::archive::extract::extract_parallel(
+            &mut entries,
+            output,
+            *writers as usize,
+            |_| ataf::archive::extract::ExtractAction::Extract,
+        ) {
+            Ok(stats) => {
+                println_if_terminal!(
+                    "extracted {} entries, {} bytes",
+                    stats.entries,
+                    stats.uncompressed_bytes
+                );
+                0
+            }
+            Err(err) => {
+                crate::report::error(
+                    "extract",
+                    Some(output),
+                    Some(&err),
+                    format_args!("error extracting to {}", output.display()),
+                );
+                1
+            }
+        };
+    }
+    let mut deferred_flags = Vec::new();
+    let mut deferred_directories = Vec::new();
+    let mut checkpoint = Checkpoint::from_matches(matches);
+    let mut extracted = HashMap::new();
+    // what references and hard links may point to, `extracted` also has what
+    // failed, was kept from before or is still to be replaced
+    let mut completed = HashSet::new();
+    let mut skipped_directories = HashMap::new();
+    let strict = matches.get_flag("strict");
+    let mut failures = 0;
 
-    let mut entries = archive.entries(decompressor).unwrap();
+    // an entry that couldn't be extracted stops everything with --strict
+    macro_rules! entry_failed {
+        () => {{
+            failures += 1;
+            if strict {
+                return 1;
+            }
+            continue;
+        }};
+    }
 
     while let Some(entry) = entries.next_entry() {
         match entry {
             Ok(mut entry) => {
-                println!(
+                if files.is_some() || includes.is_some() {
+                    let path = normalize_path(&entry.header().path).to_string();
+                    let is_dir = entry.header().r#type == ArchiveEntryHeaderType::Directory;
+
+                    let listed = files
+                        .as_mut()
+                        .and_then(|files| files.get_mut(&path))
+                        .map(|found| *found = true)
+                        .is_some();
+                    let included = includes
+                        .as_ref()
+                        .is_some_and(|includes| includes.is_match(&path, is_dir));
+                    if !listed && !included {
+                        // directories are only extracted once something below
+                        // them is, which can't be known yet
+                        if is_dir {
+                            skipped_directories.insert(
+                                path.trim_end_matches('/').to_string(),
+                                entry.header().clone(),
+                            );
+                        }
+
+                        if let Err(err) = entry.skip() {
+                            crate::report::error(
+                                "read_entry",
+                                None,
+                                Some(&err),
+                                format_args!("error reading entry"),
+                            );
+                            return 1;
+                        }
+                        continue;
+                    }
+
+                    // the skipped directories above it, outermost first, with
+                    // their mode and time set at the end like any other
+                    let ancestors: Vec<&Path> = Path::new(&path).ancestors().skip(1).collect();
+                    for ancestor in ancestors.into_iter().rev() {
+                        let Some(directory) =
+                            skipped_directories.remove(&*ancestor.to_string_lossy())
+                        else {
+                            continue;
+                        };
+
+                        if let Ok(Some(directory_path)) =
+                            stripped_path(directory.native_path(), strip_components)
+                            && let Ok(destination) =
+                                super::entry_destination(output, &directory_path)
+                            && ataf::archive::extract::check_destination(output, &destination)
+                                .is_ok()
+                            && std::fs::create_dir_all(&destination).is_ok()
+                        {
+                            extracted
+                                .insert(destination.clone(), ArchiveEntryHeaderType::Directory);
+                            deferred_directories.push((
+                                destination,
+                                directory.modified(),
+                                directory.mode,
+                            ));
+                        }
+                    }
+                }
+
+                if let Err(err) = writeln!(
+                    std::io::stdout(),
                     "processing: {}, size: {}",
                     entry.header().path,
                     *entry.header().size
-                );
+                ) {
+                    super::exit_on_write_error(err);
+                }
 
-                let mut path = Path::new(&entry.header().path);
-                if path.is_absolute() {
-                    let mut components = path.components();
-                    components.next();
+                let path = match stripped_path(entry.header().native_path(), strip_components) {
+                    Ok(Some(path)) => path,
+                    // the leading directories that were stripped off
+                    Ok(None) => {
+                        if let Err(err) = entry.skip() {
+                            crate::report::error(
+                                "read_entry",
+                                None,
+                                Some(&err),
+                                format_args!("error reading entry"),
+                            );
+                            return 1;
+                        }
+                        continue;
+                    }
+                    Err(err) => {
+                        crate::report::error(
+                            "unsafe_path",
+                            Some(Path::new(&entry.header().path)),
+                            Some(&err),
+                            format_args!("refusing to extract {}", entry.header().path),
+                        );
+                        continue;
+                    }
+                };
 
-                    path = components.as_path();
+                let destination =
+                    match super::entry_destination(output, &path).and_then(|destination| {
+                        ataf::archive::extract::check_destination(output, &destination)
+                            .map(|()| destination)
+                    }) {
+                        Ok(destination) => destination,
+                        Err(err) => {
+                            crate::report::error(
+                                "unsafe_path",
+                                Some(Path::new(&entry.header().path)),
+                                Some(&err),
+                                format_args!("refusing to extract {}", entry.header().path),
+                            );
+                            continue;
+                        }
+                    };
+
+                if let Some(conflict) = ataf::archive::extract::find_conflict(
+                    &extracted,
+                    output,
+                    &destination,
+                    entry.header().r#type,
+                ) {
+                    match duplicate_policy.as_str() {
+                        "error" => {
+                            crate::report::error(
+                                "duplicate_path",
+                                Some(&destination),
+                                None,
+                                format_args!("{}: {}", destination.display(), conflict),
+                            );
+                            return 1;
+                        }
+                        "warn" => {
+                            eprintln!("WARN skipping {}: {}", destination.display(), conflict);
+                            continue;
+                        }
+                        _ => {}
+                    }
+                } else if keep_old_files
+                    && !extracted.contains_key(&destination)
+                    && std::fs::symlink_metadata(&destination).is_ok()
+                {
+                    // still recorded, so --delete keeps what was there
+                    eprintln!("WARN skipping {}: it already exists", destination.display());
+                    extracted.insert(destination, entry.header().r#type);
+                    continue;
+                } else if resume
+                    && !extracted.contains_key(&destination)
+                    && already_extracted(&destination, entry.header())
+                {
+                    // directories still get their mode and time at the end,
+                    // the interrupted run never got that far
+                    if entry.header().r#type == ArchiveEntryHeaderType::Directory {
+                        deferred_directories.push((
+                            destination.clone(),
+                            entry.header().modified(),
+                            entry.header().mode,
+                        ));
+                    }
+                    completed.insert(destination.clone());
+                    extracted.insert(destination, entry.header().r#type);
+
+                    if let Err(err) = entry.skip() {
+                        crate::report::error(
+                            "read_entry",
+                            None,
+                            Some(&err),
+                            format_args!("error reading entry"),
+                        );
+                        return 1;
+                    }
+                    continue;
                 }
-                let destination = output.join(path);
+                extracted.insert(destination.clone(), entry.header().r#type);
+                // an earlier entry at this path is replaced from here on
+                completed.remove(&destination);
 
                 if let Some(parent) = destination.parent()
                     && !parent.exists()
                     && let Err(err) = std::fs::create_dir_all(parent)
                 {
-                    eprintln!("ERROR error creating parent directory: {}", err);
+                    crate::report::error(
+                        "create_parent_directory",
+                        destination.parent(),
+                        Some(&err),
+                        format_args!("error creating parent directory"),
+                    );
                 }
 
                 match entry.header().r#type {
@@ -78,48 +1018,145 @@ pub fn run(matches: &ArgMatches) -> i32 {
                         let mut writer = match std::fs::File::create(&destination) {
                             Ok(file) => file,
                             Err(err) => {
-                                eprintln!(
-                                    "ERROR error creating file {}: {}",
-                                    destination.display(),
-                                    err
+                                crate::report::error(
+                                    "create_file",
+                                    Some(&destination),
+                                    Some(&err),
+                                    format_args!("error creating file {}", destination.display()),
                                 );
-                                continue;
+                                entry_failed!();
                             }
                         };
 
-                        if let Err(err) = std::io::copy(&mut entry, &mut writer) {
-                            eprintln!(
-                                "ERROR error writing to file {}: {}",
-                                destination.display(),
-                                err
+                        let sparse = entry.header().sparse.clone();
+                        let result = match &sparse {
+                            Some(sparse) => writer.set_len(sparse.size).and_then(|()| {
+                                std::io::copy(
+                                    &mut entry,
+                                    &mut SparseWriter::new(&writer, sparse, 0),
+                                )
+                            }),
+                            None => std::io::copy(&mut entry, &mut writer),
+                        };
+                        if let Err(err) = result {
+                            crate::report::error(
+                                "write_file",
+                                Some(&destination),
+                                Some(&err),
+                                format_args!("error writing to file {}", destination.display()),
                             );
-                            continue;
+                            entry_failed!();
                         }
 
-                        writer
-                            .set_modified(
-                                SystemTime::UNIX_EPOCH + Duration::from_secs(*entry.header().mtime),
-                            )
-                            .unwrap();
-                        #[cfg(target_family = "unix")]
+                        if let Err(err) = writer.set_modified(entry.header().modified()) {
+                            crate::report::error(
+                                "set_mtime",
+                                Some(&destination),
+                                Some(&err),
+                                format_args!(
+                                    "error setting modification time of {}",
+                                    destination.display()
+                                ),
+                            );
+                        }
+                    }
+                    ataf::spec::ArchiveEntryHeaderType::Reference => {
+                        let original = match read_original_path(&mut entry) {
+                            Ok(original) => original,
+                            Err(err) => {
+                                crate::report::error(
+                                    "read_reference",
+                                    Some(Path::new(&entry.header().path)),
+                                    Some(&err),
+                                    format_args!("error reading reference {}", entry.header().path),
+                                );
+                                entry_failed!();
+                            }
+                        };
+
+                        // the original has to be extracted in this run, whatever
+                        // else is at its path isn't the data referenced
+                        if let Err(err) = original_destination(output, &original, strip_components)
+                            .and_then(|source| {
+                                check_original(&completed, &source, &original)?;
+                                ataf::archive::extract::check_destination(output, &source)?;
+                                Ok(std::fs::copy(source, &destination)?)
+                            })
                         {
-                            use std::os::unix::fs::PermissionsExt;
+                            crate::report::error(
+                                "copy_reference",
+                                Some(&destination),
+                                Some(&err),
+                                format_args!(
+                                    "error copying {} from {}",
+                                    destination.display(),
+                                    original
+                                ),
+                            );
+                            entry_failed!();
+                        }
 
-                            writer
-                                .set_permissions(std::fs::Permissions::from_mode(
-                                    entry.header().mode,
-                                ))
-                                .unwrap();
+                        if let Err(err) = std::fs::File::options()
+                            .write(true)
+                            .open(&destination)
+                            .and_then(|file| file.set_modified(entry.header().modified()))
+                        {
+                            crate::report::error(
+                                "set_mtime",
+                                Some(&destination),
+                                Some(&err),
+                                format_args!(
+                                    "error setting modification time of {}",
+                                    destination.display()
+                                ),
+                            );
+                        }
+                    }
+                    ataf::spec::ArchiveEntryHeaderType::HardLink => {
+                        let original = match read_original_path(&mut entry) {
+                            Ok(original) => original,
+                            Err(err) => {
+                                crate::report::error(
+                                    "read_hard_link",
+                                    Some(Path::new(&entry.header().path)),
+                                    Some(&err),
+                                    format_args!("error reading hard link {}", entry.header().path),
+                                );
+                                entry_failed!();
+                            }
+                        };
+
+                        // the link shares its metadata with the original, so
+                        // there is nothing to set afterwards
+                        if let Err(err) = original_destination(output, &original, strip_components)
+                            .and_then(|source| {
+                                check_original(&completed, &source, &original)?;
+                                ataf::archive::extract::check_destination(output, &source)?;
+                                Ok(std::fs::hard_link(source, &destination)?)
+                            })
+                        {
+                            crate::report::error(
+                                "create_hard_link",
+                                Some(&destination),
+                                Some(&err),
+                                format_args!(
+                                    "error linking {} to {}",
+                                    destination.display(),
+                                    original
+                                ),
+                            );
+                            entry_failed!();
                         }
                     }
                     ataf::spec::ArchiveEntryHeaderType::Directory => {
                         if let Err(err) = std::fs::create_dir(&destination) {
-                            eprintln!(
-                                "ERROR error creating directory {}: {}",
-                                destination.display(),
-                                err
+                            crate::report::error(
+                                "create_directory",
+                                Some(&destination),
+                                Some(&err),
+                                format_args!("error creating directory {}", destination.display()),
                             );
-                            continue;
+                            entry_failed!();
                         }
                     }
                     ataf::spec::ArchiveEntryHeaderType::SymlinkFile => {
@@ -127,12 +1164,34 @@ pub fn run(matches: &ArgMatches) -> i32 {
                         symlink_target.reserve_exact(*entry.header().size as usize);
 
                         if let Err(err) = entry.read_to_string(&mut symlink_target) {
-                            eprintln!(
-                                "ERROR error reading symlink target {}: {}",
-                                entry.header().path,
-                                err
+                            crate::report::error(
+                                "read_symlink_target",
+                                Some(Path::new(&entry.header().path)),
+                                Some(&err),
+                                format_args!(
+                                    "error reading symlink target {}",
+                                    entry.header().path
+                                ),
                             );
-                            continue;
+                            entry_failed!();
+                        }
+
+                        if let Err(err) = ataf::archive::extract::check_symlink_target(
+                            output,
+                            &destination,
+                            &symlink_target,
+                        ) {
+                            crate::report::error(
+                                "unsafe_symlink",
+                                Some(&destination),
+                                Some(&err),
+                                format_args!(
+                                    "refusing to create symlink {} to {}",
+                                    destination.display(),
+                                    symlink_target
+                                ),
+                            );
+                            entry_failed!();
                         }
 
                         #[cfg(target_family = "unix")]
@@ -140,12 +1199,16 @@ pub fn run(matches: &ArgMatches) -> i32 {
                             if let Err(err) =
                                 std::os::unix::fs::symlink(symlink_target, &destination)
                             {
-                                eprintln!(
-                                    "ERROR error creating symlink {}: {}",
-                                    destination.display(),
-                                    err
+                                crate::report::error(
+                                    "create_symlink",
+                                    Some(&destination),
+                                    Some(&err),
+                                    format_args!(
+                                        "error creating symlink {}",
+                                        destination.display()
+                                    ),
                                 );
-                                continue;
+                                entry_failed!();
                             }
                         }
                         #[cfg(target_family = "windows")]
@@ -153,12 +1216,16 @@ pub fn run(matches: &ArgMatches) -> i32 {
                             if let Err(err) =
                                 std::os::windows::fs::symlink_file(symlink_target, &destination)
                             {
-                                eprintln!(
-                                    "ERROR error creating symlink {}: {}",
-                                    destination.display(),
-                                    err
+                                crate::report::error(
+                                    "create_symlink",
+                                    Some(&destination),
+                                    Some(&err),
+                                    format_args!(
+                                        "error creating symlink {}",
+                                        destination.display()
+                                    ),
                                 );
-                                continue;
+                                entry_failed!();
                             }
                         }
                     }
@@ -167,12 +1234,34 @@ pub fn run(matches: &ArgMatches) -> i32 {
                         symlink_target.reserve_exact(*entry.header().size as usize);
 
                         if let Err(err) = entry.read_to_string(&mut symlink_target) {
-                            eprintln!(
-                                "ERROR error reading symlink target {}: {}",
-                                entry.header().path,
-                                err
+                            crate::report::error(
+                                "read_symlink_target",
+                                Some(Path::new(&entry.header().path)),
+                                Some(&err),
+                                format_args!(
+                                    "error reading symlink target {}",
+                                    entry.header().path
+                                ),
                             );
-                            continue;
+                            entry_failed!();
+                        }
+
+                        if let Err(err) = ataf::archive::extract::check_symlink_target(
+                            output,
+                            &destination,
+                            &symlink_target,
+                        ) {
+                            crate::report::error(
+                                "unsafe_symlink",
+                                Some(&destination),
+                                Some(&err),
+                                format_args!(
+                                    "refusing to create symlink {} to {}",
+                                    destination.display(),
+                                    symlink_target
+                                ),
+                            );
+                            entry_failed!();
                         }
 
                         #[cfg(target_family = "unix")]
@@ -180,12 +1269,16 @@ pub fn run(matches: &ArgMatches) -> i32 {
                             if let Err(err) =
                                 std::os::unix::fs::symlink(symlink_target, &destination)
                             {
-                                eprintln!(
-                                    "ERROR error creating symlink {}: {}",
-                                    destination.display(),
-                                    err
+                                crate::report::error(
+                                    "create_symlink",
+                                    Some(&destination),
+                                    Some(&err),
+                                    format_args!(
+                                        "error creating symlink {}",
+                                        destination.display()
+                                    ),
                                 );
-                                continue;
+                                entry_failed!();
                             }
                         }
                         #[cfg(target_family = "windows")]
@@ -193,23 +1286,292 @@ pub fn run(matches: &ArgMatches) -> i32 {
                             if let Err(err) =
                                 std::os::windows::fs::symlink_dir(symlink_target, &destination)
                             {
-                                eprintln!(
-                                    "ERROR error creating symlink {}: {}",
-                                    destination.display(),
-                                    err
+                                crate::report::error(
+                                    "create_symlink",
+                                    Some(&destination),
+                                    Some(&err),
+                                    format_args!(
+                                        "error creating symlink {}",
+                                        destination.display()
+                                    ),
                                 );
+                                entry_failed!();
+                            }
+                        }
+                    }
+                    ArchiveEntryHeaderType::Fifo
+                    | ArchiveEntryHeaderType::Socket
+                    | ArchiveEntryHeaderType::BlockDevice
+                    | ArchiveEntryHeaderType::CharDevice => {
+                        let mut data = Vec::new();
+                        if let Err(err) = entry.read_to_end(&mut data) {
+                            crate::report::error(
+                                "read_entry",
+                                Some(Path::new(&entry.header().path)),
+                                Some(&err),
+                                format_args!("error reading entry {}", entry.header().path),
+                            );
+                            entry_failed!();
+                        }
+
+                        match ataf::archive::extract::create_special_file(
+                            &destination,
+                            entry.header(),
+                            &data,
+                        ) {
+                            Ok(()) => {}
+                            Err(err) if err.kind() == std::io::ErrorKind::Unsupported => {
+                                eprintln!("WARN skipping {}: {}", destination.display(), err);
                                 continue;
                             }
+                            Err(err) => {
+                                crate::report::error(
+                                    "create_special_file",
+                                    Some(&destination),
+                                    Some(&err),
+                                    format_args!("error creating {}", destination.display()),
+                                );
+                                entry_failed!();
+                            }
+                        }
+                    }
+                }
+
+                // files got theirs while writing them, directories get theirs
+                // once nothing is extracted into them anymore, along with their
+                // mode which may not allow that
+                match entry.header().r#type {
+                    ArchiveEntryHeaderType::Directory => {
+                        deferred_directories.push((
+                            destination.clone(),
+                            entry.header().modified(),
+                            entry.header().mode,
+                        ));
+                    }
+                    ArchiveEntryHeaderType::SymlinkFile
+                    | ArchiveEntryHeaderType::SymlinkDirectory
+                    | ArchiveEntryHeaderType::Fifo
+                    | ArchiveEntryHeaderType::Socket
+                    | ArchiveEntryHeaderType::BlockDevice
+                    | ArchiveEntryHeaderType::CharDevice => {
+                        if let Err(err) = ataf::archive::extract::set_path_modified(
+                            &destination,
+                            entry.header().modified(),
+                        ) {
+                            crate::report::error(
+                                "set_mtime",
+                                Some(&destination),
+                                Some(&err),
+                                format_args!(
+                                    "error setting modification time of {}",
+                                    destination.display()
+                                ),
+                            );
+                        }
+                    }
+                    _ => {}
+                }
+
+                #[cfg(target_family = "unix")]
+                if let Some(owner_map) = &mut owner_map
+                    && !chown_denied
+                    && let Err(err) = std::os::unix::fs::lchown(
+                        &destination,
+                        Some(owner_map.uid(entry.header())),
+                        Some(owner_map.gid(entry.header())),
+                    )
+                {
+                    // only root may give files away, which won't change midway
+                    if err.kind() == std::io::ErrorKind::PermissionDenied {
+                        eprintln!(
+                            "WARN not permitted to change ownership ({}), extracted entries keep the extracting user as owner",
+                            err
+                        );
+                        chown_denied = true;
+                    } else {
+                        crate::report::error(
+                            "chown",
+                            Some(&destination),
+                            Some(&err),
+                            format_args!("error changing ownership of {}", destination.display()),
+                        );
+                    }
+                }
+
+                // only after the owner, changing it clears the setuid and
+                // setgid bits
+                #[cfg(target_family = "unix")]
+                if matches!(
+                    entry.header().r#type,
+                    ArchiveEntryHeaderType::File | ArchiveEntryHeaderType::Reference
+                ) {
+                    use std::os::unix::fs::PermissionsExt;
+
+                    if let Err(err) = std::fs::set_permissions(
+                        &destination,
+                        std::fs::Permissions::from_mode(file_mode(
+                            entry.header(),
+                            &exec_extensions,
+                            mode_mask,
+                        )),
+                    ) {
+                        crate::report::error(
+                            "set_permissions",
+                            Some(&destination),
+                            Some(&err),
+                            format_args!("error setting permissions of {}", destination.display()),
+                        );
+                    }
+                }
+
+                if let Some(manifest) = &mut manifest
+                    && let Some(expected) =
+                        manifest.hashes.remove(normalize_path(&entry.header().path))
+                {
+                    match std::fs::File::open(&destination).and_then(crate::sha256::hex_digest) {
+                        Ok(actual) if actual == expected => {}
+                        Ok(actual) => {
+                            crate::report::error(
+                                "manifest_mismatch",
+                                Some(&destination),
+                                None,
+                                format_args!(
+                                    "{} does not match the manifest: expected {}, got {}",
+                                    destination.display(),
+                                    expected,
+                                    actual
+                                ),
+                            );
+                            manifest_failures += 1;
+                        }
+                        Err(err) => {
+                            crate::report::error(
+                                "manifest_hash",
+                                Some(&destination),
+                                Some(&err),
+                                format_args!("failed to hash {}", destination.display()),
+                            );
+                            manifest_failures += 1;
                         }
                     }
                 }
+
+                if let Some(checkpoint) = &mut checkpoint {
+                    checkpoint.record(*entry.header().size);
+                }
+                completed.insert(destination.clone());
+
+                // flags like immutable are applied last so they don't block
+                // writing the contents of directories extracted later on
+                if preserve_flags && let Some(flags) = entry.header().flags {
+                    deferred_flags.push((destination, flags));
+                }
             }
             Err(err) => {
-                eprintln!("ERROR error reading entry: {}", err);
+                crate::report::error(
+                    "read_entry",
+                    None,
+                    Some(&err),
+                    format_args!("error reading entry"),
+                );
                 return 1;
             }
         }
     }
 
+    if progress {
+        crate::progress::finish();
+    }
+
+    // an entry that failed isn't in `extracted`, deleting would remove the
+    // copy it should have replaced
+    if matches.get_flag("delete") {
+        if failures > 0 {
+            eprintln!(
+                "WARN not deleting anything not in the archive, {} entries could not be extracted",
+                failures
+            );
+        } else {
+            delete_extraneous(output, &extracted);
+        }
+    }
+
+    // before the flags, an immutable directory can't get a new time
+    #[cfg_attr(not(target_family = "unix"), allow(unused_variables))]
+    for (destination, modified, mode) in deferred_directories {
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            if let Err(err) = std::fs::set_permissions(
+                &destination,
+                std::fs::Permissions::from_mode(mode & mode_mask),
+            ) {
+                crate::report::error(
+                    "set_permissions",
+                    Some(&destination),
+                    Some(&err),
+                    format_args!("error setting permissions of {}", destination.display()),
+                );
+            }
+        }
+
+        if let Err(err) = ataf::archive::extract::set_path_modified(&destination, modified) {
+            crate::report::error(
+                "set_mtime",
+                Some(&destination),
+                Some(&err),
+                format_args!(
+                    "error setting modification time of {}",
+                    destination.display()
+                ),
+            );
+        }
+    }
+
+    for (destination, flags) in deferred_flags.into_iter().rev() {
+        if let Err(err) = crate::fs::set_flags(&destination, flags) {
+            eprintln!("WARN skipping flags for {}: {}", destination.display(), err);
+        }
+    }
+
+    if let Some(manifest) = manifest {
+        for path in manifest.hashes.keys() {
+            crate::report::error(
+                "manifest_missing",
+                Some(Path::new(path)),
+                None,
+                format_args!("{} is in the manifest but was not extracted", path),
+            );
+            manifest_failures += 1;
+        }
+    }
+
+    if let Some(files) = &files
+        && report_missing_files(files) > 0
+    {
+        return 1;
+    }
+
+    if failures > 0 {
+        crate::report::error(
+            "entries_failed",
+            None,
+            None,
+            format_args!("{} entries could not be extracted", failures),
+        );
+        return 1;
+    }
+
+    if manifest_failures > 0 {
+        crate::report::error(
+            "manifest_failed",
+            None,
+            None,
+            format_args!("{} files failed manifest verification", manifest_failures),
+        );
+        return 1;
+    }
+
     0
 }
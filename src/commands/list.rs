@@ -0,0 +1,271 @@
+use ataf::{
+    archive::{read::Archive, volume::first_volume_base},
+    spec::{
+        ArchiveEntryHeader, ArchiveEntryHeaderType, ArchiveHeader, ArchiveIndex, ChunkChecksum,
+    },
+};
+use clap::ArgMatches;
+use std::{
+    io::{BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// The `ls -l` style type and permission string, e.g. `-rwxr-xr-x`.
+fn mode_string(header: &ArchiveEntryHeader) -> String {
+    let mut mode = String::with_capacity(10);
+    mode.push(match header.r#type {
+        ArchiveEntryHeaderType::File => '-',
+        ArchiveEntryHeaderType::Directory => 'd',
+        ArchiveEntryHeaderType::SymlinkFile | ArchiveEntryHeaderType::SymlinkDirectory => 'l',
+        ArchiveEntryHeaderType::Reference | ArchiveEntryHeaderType::HardLink => 'h',
+        ArchiveEntryHeaderType::Fifo => 'p',
+        ArchiveEntryHeaderType::Socket => 's',
+        ArchiveEntryHeaderType::BlockDevice => 'b',
+        ArchiveEntryHeaderType::CharDevice => 'c',
+    });
+
+    for shift in [6, 3, 0] {
+        let bits = header.mode >> shift;
+        mode.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        mode.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        mode.push(match (bits & 0o1 != 0, shift) {
+            (true, 6) if header.mode & 0o4000 != 0 => 's',
+            (false, 6) if header.mode & 0o4000 != 0 => 'S',
+            (true, 3) if header.mode & 0o2000 != 0 => 's',
+            (false, 3) if header.mode & 0o2000 != 0 => 'S',
+            (true, 0) if header.mode & 0o1000 != 0 => 't',
+            (false, 0) if header.mode & 0o1000 != 0 => 'T',
+            (true, _) => 'x',
+            (false, _) => '-',
+        });
+    }
+
+    mode
+}
+
+/// Formats seconds since the unix epoch as `YYYY-MM-DD HH:MM` in UTC.
+fn format_mtime(mtime: u64) -> String {
+    let days = (mtime / 86400) as i64;
+    let seconds = mtime % 86400;
+
+    // civil date from days since 1970-01-01, after Howard Hinnant's algorithm
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60
+    )
+}
+
+/// Reads the index of an archive file, `None` when it has none or it can't be
+/// read, in which case the entries are listed by reading through them.
+fn read_index(path: &Path) -> Option<ArchiveIndex> {
+    // the index is at the end of the last volume, split archives are read
+    // through, and so are downloads
+    if first_volume_base(path).is_some() || crate::commands::is_url(path) {
+        return None;
+    }
+
+    let file = std::fs::File::open(path).ok()?;
+
+    Archive::new(BufReader::new(file)).index().ok()?
+}
+
+/// Writes the archive header as `name: value` lines, followed by an empty
+/// line separating it from the entries.
+fn write_header(mut output: impl Write, header: &ArchiveHeader) -> std::io::Result<()> {
+    let options: Vec<&str> = [
+        (header.solid, "solid"),
+        (header.prefix_paths, "prefix-paths"),
+        (header.checksum == ChunkChecksum::Crc32, "checksums"),
+        (header.checksum == ChunkChecksum::Xxh3, "xxh3-checksums"),
+        (header.index, "index"),
+    ]
+    .into_iter()
+    .filter_map(|(set, name)| set.then_some(name))
+    .collect();
+
+    writeln!(output, "version: {}", header.version)?;
+    writeln!(output, "compression: {}", header.compression)?;
+    writeln!(output, "chunk size: {}", header.compression_chunk_size)?;
+    if options.is_empty() {
+        writeln!(output, "options: none")?;
+    } else {
+        writeln!(output, "options: {}", options.join(", "))?;
+    }
+    if !header.comment.is_empty() {
+        writeln!(output, "comment: {}", header.comment)?;
+    }
+    // unknown for archives that were streamed
+    if header.entry_count > 0 {
+        writeln!(output, "entries: {}", header.entry_count)?;
+        writeln!(
+            output,
+            "uncompressed size: {}",
+            header.total_uncompressed_size
+        )?;
+    }
+    writeln!(output)
+}
+
+pub fn run(matches: &ArgMatches) -> i32 {
+    let threads = matches.get_one::<usize>("threads").unwrap();
+    let input = matches.get_one::<PathBuf>("input");
+    let long = matches.get_flag("long");
+    let show_header = matches.get_flag("header");
+    let Some(json) = super::json_format(matches) else {
+        return 1;
+    };
+
+    // the index lacks the metadata of the long and JSON formats and the header
+    if !long
+        && !show_header
+        && !json
+        && let Some(index) = input.and_then(|input| read_index(input))
+    {
+        let mut stdout = std::io::stdout().lock();
+        for entry in index.entries {
+            if let Err(err) = writeln!(stdout, "{}", entry.path) {
+                super::exit_on_write_error(err);
+            }
+        }
+
+        return 0;
+    }
+
+    let Some((mut archive, decompressor, _)) = super::open_archive(
+        input,
+        *threads,
+        false,
+        &super::KeySource::from_matches(matches),
+    ) else {
+        return 1;
+    };
+
+    // open_archive has read the header already
+    if show_header
+        && let Ok(header) = archive.header()
+        && let Err(err) = write_header(std::io::stdout().lock(), header)
+    {
+        super::exit_on_write_error(err);
+    }
+
+    let mut entries = match archive.entries(decompressor) {
+        Ok(entries) => entries,
+        Err(err) => {
+            crate::report::error(
+                "read_header",
+                None,
+                Some(&err),
+                format_args!("failed to read archive header"),
+            );
+            return 1;
+        }
+    };
+    let mut stdout = std::io::stdout().lock();
+
+    while let Some(entry) = entries.next_entry() {
+        let mut entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                crate::report::error(
+                    "read_entry",
+                    None,
+                    Some(&err),
+                    format_args!("failed to read entry"),
+                );
+                return 1;
+            }
+        };
+
+        let result = if json {
+            writeln!(stdout, "{}", super::entry_json(entry.header(), None))
+        } else if long {
+            let header = entry.header().clone();
+            let mut name = header.path.clone();
+
+            if matches!(
+                header.r#type,
+                ArchiveEntryHeaderType::SymlinkFile | ArchiveEntryHeaderType::SymlinkDirectory
+            ) {
+                let mut target = String::new();
+                if let Err(err) = entry.read_to_string(&mut target) {
+                    crate::report::error(
+                        "read_symlink",
+                        None,
+                        Some(&err),
+                        format_args!("error reading symlink {}", header.path),
+                    );
+                    return 1;
+                }
+
+                name = format!("{name} -> {target}");
+            } else if header.r#type == ArchiveEntryHeaderType::HardLink {
+                let mut original = String::new();
+                if let Err(err) = entry.read_to_string(&mut original) {
+                    crate::report::error(
+                        "read_hard_link",
+                        None,
+                        Some(&err),
+                        format_args!("error reading hard link {}", header.path),
+                    );
+                    return 1;
+                }
+
+                name = format!("{name} link to {original}");
+            }
+
+            writeln!(
+                stdout,
+                "{} {:>5} {:>5} {:>6} {} {}",
+                mode_string(&header),
+                *header.uid,
+                *header.gid,
+                super::human_size(
+                    header
+                        .sparse
+                        .as_ref()
+                        .map_or(*header.size, |sparse| sparse.size)
+                ),
+                format_mtime(*header.mtime),
+                name
+            )
+        } else {
+            writeln!(stdout, "{}", entry.header().path)
+        };
+
+        if let Err(err) = result {
+            super::exit_on_write_error(err);
+        }
+
+        // only the headers are needed, the data isn't decompressed
+        if let Err(err) = entry.skip() {
+            crate::report::error(
+                "read_entry",
+                None,
+                Some(&err),
+                format_args!("failed to read entry"),
+            );
+            return 1;
+        }
+    }
+
+    0
+}
@@ -1,2 +1,467 @@
 pub mod create;
 pub mod extract;
+pub mod list;
+pub mod recompress;
+pub mod verify;
+
+pub use ataf::archive::extract::entry_destination;
+use ataf::{
+    archive::{
+        read::Archive,
+        volume::{VolumeReader, first_volume_base},
+    },
+    compression::{CompressionRegistry, Decompressor},
+    spec::{ArchiveEntryHeader, ENCRYPTION_MAGIC},
+};
+use clap::ArgMatches;
+use std::{
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+pub type InputArchive = Archive<BufReader<Box<dyn Read>>>;
+
+/// An archive ready to read its entries, see `open_archive`.
+pub type OpenedArchive = (InputArchive, Box<dyn Decompressor>, Option<Encryption>);
+
+/// Where the key of encrypted archives comes from, given by the global
+/// `--key-file`, `--passphrase` or `--passphrase-file`.
+#[derive(Debug, Clone, Default)]
+// only read with the encryption feature
+#[cfg_attr(not(feature = "encryption"), allow(dead_code))]
+pub struct KeySource {
+    pub key_file: Option<PathBuf>,
+    pub passphrase: Option<String>,
+    pub passphrase_file: Option<PathBuf>,
+}
+
+/// How an opened archive is encrypted. Without the encryption feature no
+/// encrypted archive can be opened, so there is no value of it.
+#[cfg(feature = "encryption")]
+pub type Encryption = ataf::encryption::EncryptionHeader;
+#[cfg(not(feature = "encryption"))]
+pub enum Encryption {}
+
+impl KeySource {
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        Self {
+            key_file: matches.get_one::<PathBuf>("key_file").cloned(),
+            passphrase: matches.get_one::<String>("passphrase").cloned(),
+            passphrase_file: matches.get_one::<PathBuf>("passphrase_file").cloned(),
+        }
+    }
+
+    /// The passphrase when one was given, a file holding it may end with one
+    /// line break that isn't part of it.
+    #[cfg(feature = "encryption")]
+    fn passphrase(&self) -> std::io::Result<Option<zeroize::Zeroizing<Vec<u8>>>> {
+        let passphrase = match (&self.passphrase, &self.passphrase_file) {
+            (Some(passphrase), _) => zeroize::Zeroizing::new(passphrase.as_bytes().to_vec()),
+            (None, Some(path)) => {
+                let mut passphrase = zeroize::Zeroizing::new(std::fs::read(path)?);
+                if passphrase.ends_with(b"\n") {
+                    passphrase.pop();
+                    if passphrase.ends_with(b"\r") {
+                        passphrase.pop();
+                    }
+                }
+
+                passphrase
+            }
+            (None, None) => return Ok(None),
+        };
+
+        if passphrase.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the passphrase is empty",
+            ));
+        }
+
+        Ok(Some(passphrase))
+    }
+
+    /// The AES-256 key of an archive with `header`, derived from the
+    /// passphrase when the archive was encrypted with one.
+    #[cfg(feature = "encryption")]
+    pub fn key(
+        &self,
+        header: &ataf::encryption::EncryptionHeader,
+    ) -> std::io::Result<zeroize::Zeroizing<[u8; 32]>> {
+        match &header.kdf {
+            Some(kdf) => match self.passphrase()? {
+                Some(passphrase) => Ok(kdf.derive_key(&passphrase)?),
+                None => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "the archive is encrypted with a passphrase, given by --passphrase or --passphrase-file",
+                )),
+            },
+            None => match &self.key_file {
+                Some(path) => Ok(zeroize::Zeroizing::new(crate::keys::read_bytes(path)?)),
+                None => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "the archive is encrypted with a key, given by --key-file",
+                )),
+            },
+        }
+    }
+
+    /// The header and key to encrypt a new archive with. A passphrase is
+    /// stretched with `kdf`, a key file is used as is.
+    #[cfg(feature = "encryption")]
+    pub fn encryption(
+        &self,
+        kdf: ataf::encryption::Argon2Parameters,
+    ) -> std::io::Result<(
+        ataf::encryption::EncryptionHeader,
+        zeroize::Zeroizing<[u8; 32]>,
+    )> {
+        let mut header = ataf::encryption::EncryptionHeader::new();
+        if self.passphrase.is_some() || self.passphrase_file.is_some() {
+            header.kdf = Some(kdf);
+        } else if self.key_file.is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "no key given, it is read from --key-file or derived from --passphrase or --passphrase-file",
+            ));
+        }
+
+        let key = self.key(&header)?;
+        Ok((header, key))
+    }
+}
+
+#[cfg(feature = "encryption")]
+type EncryptingOutput = ataf::encryption::EncryptingWriter<Box<dyn Write + Send>>;
+
+/// The encryption of an output, shared with the archive writer that writes
+/// through it so the last frame can be written once the archive is finished.
+#[cfg(feature = "encryption")]
+#[derive(Clone)]
+pub struct SharedEncryption(std::sync::Arc<std::sync::Mutex<Option<EncryptingOutput>>>);
+
+#[cfg(feature = "encryption")]
+impl SharedEncryption {
+    /// Writes the encryption header to `writer` right away.
+    pub fn new(
+        writer: Box<dyn Write + Send>,
+        key: &[u8; 32],
+        header: ataf::encryption::EncryptionHeader,
+    ) -> ataf::Result<Self> {
+        let writer = ataf::encryption::EncryptingWriter::new(writer, key, header)?;
+
+        Ok(Self(std::sync::Arc::new(std::sync::Mutex::new(Some(
+            writer,
+        )))))
+    }
+
+    /// Writes the last frame, nothing may be written afterwards.
+    pub fn finish(self) -> std::io::Result<()> {
+        let writer = self.0.lock().unwrap().take();
+        writer
+            .expect("the encryption is only finished once")
+            .finish()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl Write for SharedEncryption {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.0.lock().unwrap().as_mut() {
+            Some(writer) => writer.write(buf),
+            None => Err(std::io::Error::other("the encryption is already finished")),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.0.lock().unwrap().as_mut() {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Wraps an encrypted archive in its decryption, failures are reported.
+#[cfg(feature = "encryption")]
+fn decrypt(
+    mut reader: BufReader<Box<dyn Read>>,
+    input: Option<&PathBuf>,
+    keys: &KeySource,
+) -> Option<(BufReader<Box<dyn Read>>, Encryption)> {
+    use ataf::{
+        encryption::{DecryptingReader, EncryptionHeader},
+        spec::Deserialize,
+    };
+
+    let decrypted = EncryptionHeader::deserialize(&mut reader)
+        .map_err(std::io::Error::from)
+        .and_then(|header| {
+            let key = keys.key(&header)?;
+            Ok((DecryptingReader::new(reader, &key, header.clone()), header))
+        });
+
+    match decrypted {
+        Ok((reader, header)) => Some((
+            BufReader::with_capacity(1024 * 1024, Box::new(reader)),
+            header,
+        )),
+        Err(err) => {
+            crate::report::error(
+                "decrypt_archive",
+                input.map(PathBuf::as_path),
+                Some(&err),
+                format_args!("failed to decrypt archive"),
+            );
+            None
+        }
+    }
+}
+
+#[cfg(not(feature = "encryption"))]
+fn decrypt(
+    _: BufReader<Box<dyn Read>>,
+    input: Option<&PathBuf>,
+    _: &KeySource,
+) -> Option<(BufReader<Box<dyn Read>>, Encryption)> {
+    crate::report::error(
+        "unsupported_encryption",
+        input.map(PathBuf::as_path),
+        None,
+        format_args!(
+            "the archive is encrypted, which needs ataf built with the encryption feature"
+        ),
+    );
+    None
+}
+
+/// Formats a size like `ls -lh`, with one decimal below 10 of a unit.
+pub fn human_size(size: u64) -> String {
+    if size < 1024 {
+        return size.to_string();
+    }
+
+    let mut value = size as f64;
+    let mut unit = 'B';
+    for next in ['K', 'M', 'G', 'T', 'P', 'E'] {
+        if value < 1024.0 {
+            break;
+        }
+
+        value /= 1024.0;
+        unit = next;
+    }
+
+    if value < 10.0 {
+        format!("{value:.1}{unit}")
+    } else {
+        format!("{value:.0}{unit}")
+    }
+}
+
+/// Whether `--format json` was given, reporting an error and returning `None`
+/// when this build leaves out JSON output.
+pub fn json_format(matches: &ArgMatches) -> Option<bool> {
+    let json = matches
+        .get_one::<String>("format")
+        .is_some_and(|format| format == "json");
+
+    #[cfg(not(feature = "json"))]
+    if json {
+        crate::report::error(
+            "unsupported_json",
+            None,
+            None,
+            format_args!("--format json needs ataf built with the json feature"),
+        );
+        return None;
+    }
+
+    Some(json)
+}
+
+/// An entry as a single line JSON object for `--format json`, with a `status`
+/// when given.
+#[cfg(feature = "json")]
+pub fn entry_json(header: &ArchiveEntryHeader, status: Option<&str>) -> String {
+    use ataf::spec::ArchiveEntryHeaderType;
+
+    let r#type = match header.r#type {
+        ArchiveEntryHeaderType::File => "file",
+        ArchiveEntryHeaderType::Directory => "directory",
+        ArchiveEntryHeaderType::SymlinkFile => "symlink_file",
+        ArchiveEntryHeaderType::SymlinkDirectory => "symlink_directory",
+        ArchiveEntryHeaderType::Reference => "reference",
+        ArchiveEntryHeaderType::HardLink => "hard_link",
+        ArchiveEntryHeaderType::Fifo => "fifo",
+        ArchiveEntryHeaderType::Socket => "socket",
+        ArchiveEntryHeaderType::BlockDevice => "block_device",
+        ArchiveEntryHeaderType::CharDevice => "char_device",
+    };
+
+    let mut json = serde_json::json!({
+        "path": header.path,
+        "type": r#type,
+        "mode": header.mode,
+        "uid": *header.uid,
+        "gid": *header.gid,
+        "mtime": *header.mtime,
+        "size": header.sparse.as_ref().map_or(*header.size, |sparse| sparse.size),
+    });
+    if let Some(status) = status {
+        json["status"] = status.into();
+    }
+
+    json.to_string()
+}
+
+/// Never called, `json_format` refuses `--format json` in builds without it.
+#[cfg(not(feature = "json"))]
+pub fn entry_json(_: &ArchiveEntryHeader, _: Option<&str>) -> String {
+    unreachable!("--format json needs the json feature")
+}
+
+/// Ends the process after writing to the output failed. A closed pipe (for
+/// example `| head`) is not an error and exits silently with the conventional
+/// SIGPIPE status.
+pub fn exit_on_write_error(err: impl crate::report::Reportable) -> ! {
+    exit_on_error(
+        "write_output",
+        None,
+        err,
+        format_args!("failed to write output"),
+    )
+}
+
+/// Like [`exit_on_write_error`], for failures that may also come from reading
+/// the input or setting up the archive, reported as `code` and `message`.
+pub fn exit_on_error(
+    code: &str,
+    path: Option<&std::path::Path>,
+    err: impl crate::report::Reportable,
+    message: std::fmt::Arguments,
+) -> ! {
+    if err.kind() == std::io::ErrorKind::BrokenPipe {
+        std::process::exit(141);
+    }
+
+    crate::report::error(code, path, Some(&err), message);
+    std::process::exit(1);
+}
+
+/// The compression formats of the command line, all built into the crate.
+pub type Registry = CompressionRegistry<BufWriter<Box<dyn Write + Send>>, Box<dyn Read>>;
+
+/// Picks the decompressor for the compression format named in an archive
+/// header, reporting an error for formats this build does not support.
+pub fn decompressor(compression: &str, threads: usize) -> Option<Box<dyn Decompressor>> {
+    match Registry::default().decompressor(compression, threads) {
+        Ok(decompressor) => Some(decompressor),
+        Err(err @ ataf::Error::UnsupportedCompression(_)) => {
+            crate::report::error("unsupported_compression", None, None, format_args!("{err}"));
+            None
+        }
+        Err(err) => {
+            crate::report::error(
+                "start_decompressor",
+                None,
+                Some(&err),
+                format_args!("failed to start the {compression} decompressor"),
+            );
+            None
+        }
+    }
+}
+
+/// Whether an input is an `http://` or `https://` URL rather than a path.
+pub fn is_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.starts_with("http://") || path.starts_with("https://"))
+}
+
+/// Opens an archive file, all of its volumes given the first one, or the
+/// archive at a URL with the http feature.
+pub fn open_input(path: &Path) -> std::io::Result<Box<dyn Read>> {
+    if is_url(path) {
+        #[cfg(feature = "http")]
+        return crate::http::open(&path.to_string_lossy());
+        #[cfg(not(feature = "http"))]
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "reading archives from URLs needs ataf built with the http feature",
+        ));
+    }
+
+    Ok(match first_volume_base(path) {
+        Some(_) => Box::new(VolumeReader::open(path)?),
+        None => Box::new(std::fs::File::open(path)?),
+    })
+}
+
+/// Opens the archive at `input` (stdin without one) and reads its header,
+/// returning it with a matching decompressor and its encryption, if any.
+/// Failures are reported.
+pub fn open_archive(
+    input: Option<&PathBuf>,
+    threads: usize,
+    ignore_header_checksum: bool,
+    keys: &KeySource,
+) -> Option<OpenedArchive> {
+    let reader: Box<dyn Read> = match input {
+        Some(path) => match open_input(path) {
+            Ok(reader) => reader,
+            Err(err) => {
+                crate::report::error(
+                    "open_archive",
+                    Some(path),
+                    Some(&err),
+                    format_args!("failed to open archive {}", path.display()),
+                );
+                return None;
+            }
+        },
+        None => Box::new(std::io::stdin()),
+    };
+
+    open_archive_reader(reader, input, threads, ignore_header_checksum, keys)
+}
+
+/// Like `open_archive`, for an archive already opened from `input`.
+pub fn open_archive_reader(
+    reader: Box<dyn Read>,
+    input: Option<&PathBuf>,
+    threads: usize,
+    ignore_header_checksum: bool,
+    keys: &KeySource,
+) -> Option<OpenedArchive> {
+    let mut reader = BufReader::with_capacity(1024 * 1024, reader);
+    let mut encryption = None;
+    if reader
+        .fill_buf()
+        .is_ok_and(|buffer| buffer.starts_with(&ENCRYPTION_MAGIC))
+    {
+        let (decrypted, header) = decrypt(reader, input, keys)?;
+        reader = decrypted;
+        encryption = Some(header);
+    }
+
+    let mut archive = Archive::new(reader);
+    archive.ignore_header_checksum(ignore_header_checksum);
+
+    let compression = match archive.header() {
+        Ok(header) => header.compression.clone(),
+        Err(err) => {
+            crate::report::error(
+                "read_header",
+                input.map(PathBuf::as_path),
+                Some(&err),
+                format_args!("failed to read archive header"),
+            );
+            return None;
+        }
+    };
+    let decompressor = decompressor(&compression, threads)?;
+
+    Some((archive, decompressor, encryption))
+}
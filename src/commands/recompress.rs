@@ -0,0 +1,408 @@
+use ataf::{
+    archive::{
+        volume::first_volume_base,
+        write::{ArchiveOptions, ArchiveWriter},
+    },
+    compression::CompressionFormat,
+    spec::ArchiveEntryHeader,
+};
+use clap::ArgMatches;
+use std::{
+    io::{BufWriter, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{Receiver, SyncSender, sync_channel},
+};
+
+macro_rules! println_if_terminal {
+    ($fmt:expr $(, $args:expr)* $(,)?) => {
+        if std::io::stdout().is_terminal() {
+            println!($fmt $(, $args)*);
+        }
+    };
+}
+
+type Entry = (ArchiveEntryHeader, Receiver<std::io::Result<Vec<u8>>>);
+
+/// An entry's data as sent by the reading thread, ending when it hangs up.
+struct ChannelReader {
+    receiver: Receiver<std::io::Result<Vec<u8>>>,
+    buffer: Vec<u8>,
+    offset: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.offset == self.buffer.len() {
+            match self.receiver.recv() {
+                Ok(data) => {
+                    self.buffer = data?;
+                    self.offset = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let length = buf.len().min(self.buffer.len() - self.offset);
+        buf[..length].copy_from_slice(&self.buffer[self.offset..self.offset + length]);
+        self.offset += length;
+
+        Ok(length)
+    }
+}
+
+/// Sends the data of `entry` to the writing thread, stopping early when it
+/// is gone. Read errors are passed on as well, so the entry being written
+/// fails instead of ending early.
+fn send_data(
+    entry: &mut impl Read,
+    sender: &SyncSender<std::io::Result<Vec<u8>>>,
+) -> std::io::Result<()> {
+    loop {
+        let mut buffer = vec![0; 64 * 1024];
+        match entry.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => {
+                buffer.truncate(read);
+                if sender.send(Ok(buffer)).is_err() {
+                    return Ok(());
+                }
+            }
+            Err(err) => {
+                sender
+                    .send(Err(std::io::Error::new(
+                        err.kind(),
+                        "the source entry could not be read",
+                    )))
+                    .ok();
+                return Err(err);
+            }
+        }
+    }
+}
+
+/// Streams every entry of an archive into a new one with another compression
+/// format and chunk size. Headers are copied as they are, so owners, modes and
+/// times survive exactly, only the data is decompressed and compressed again.
+/// Entries are read on this thread and written on another, since the writer
+/// only takes inputs that outlive it.
+pub fn run(matches: &ArgMatches) -> i32 {
+    let compression_format = matches
+        .get_one::<CompressionFormat>("compression_format")
+        .unwrap();
+    let threads = *matches.get_one::<usize>("threads").unwrap();
+    let chunk_size = matches
+        .get_one::<u32>("chunk_size")
+        .copied()
+        .unwrap_or_else(|| compression_format.default_chunk_size());
+    let level = matches.get_one::<u32>("level").copied();
+    let in_flight = matches.get_one::<usize>("in_flight").copied();
+    let input = matches.get_one::<PathBuf>("input");
+    let output = matches.get_one::<PathBuf>("output");
+
+    // without an output the input is replaced once the new archive is complete
+    let destination = match (output, input) {
+        (Some(output), _) => output.clone(),
+        (None, Some(input)) if first_volume_base(input).is_none() && !super::is_url(input) => {
+            let mut temporary = input.as_os_str().to_owned();
+            temporary.push(".recompress");
+            PathBuf::from(temporary)
+        }
+        (None, _) => {
+            crate::report::error(
+                "missing_output",
+                None,
+                None,
+                format_args!("--output is required when reading stdin, volumes or a URL"),
+            );
+            return 1;
+        }
+    };
+    if input.is_some_and(|input| input == &destination) {
+        crate::report::error(
+            "same_output",
+            Some(&destination),
+            None,
+            format_args!(
+                "the output would overwrite the archive while it is read, leave --output out to replace it"
+            ),
+        );
+        return 1;
+    }
+
+    let Some((mut archive, decompressor, encryption)) = super::open_archive(
+        input,
+        threads,
+        false,
+        &super::KeySource::from_matches(matches),
+    ) else {
+        return 1;
+    };
+    let options = match archive.header() {
+        Ok(header) => ArchiveOptions {
+            // entries are written one at a time, which solid archives don't allow
+            solid: false,
+            prefix_paths: header.prefix_paths,
+            checksum: header.checksum,
+            index: header.index,
+            comment: header.comment.clone(),
+            flush_chunks: false,
+        },
+        Err(err) => {
+            crate::report::error(
+                "read_header",
+                None,
+                Some(&err),
+                format_args!("failed to read archive header"),
+            );
+            return 1;
+        }
+    };
+
+    let mut compressor =
+        match super::Registry::default().compressor(compression_format.name(), threads) {
+            Ok(compressor) => compressor,
+            Err(err) => {
+                crate::report::error(
+                    "start_compressor",
+                    None,
+                    Some(&err),
+                    format_args!(
+                        "failed to start the {} compressor",
+                        compression_format.name()
+                    ),
+                );
+                return 1;
+            }
+        };
+    let levels = compressor.levels();
+    if let Some(level) = level
+        && levels.start() != levels.end()
+    {
+        if !levels.contains(&level) {
+            crate::report::error(
+                "invalid_level",
+                None,
+                None,
+                format_args!(
+                    "compression level {} is out of range for {}, which accepts {} to {}",
+                    level,
+                    compressor.name(),
+                    levels.start(),
+                    levels.end()
+                ),
+            );
+            return 1;
+        }
+
+        compressor.set_level(level);
+    }
+    // the writer has to be created on the thread that uses it
+    drop(compressor);
+
+    // an encrypted archive stays encrypted with the same key or passphrase,
+    // under a new nonce and salt
+    #[cfg(feature = "encryption")]
+    let encryption_key = match encryption {
+        Some(header) => match super::KeySource::from_matches(matches).encryption(
+            header.kdf.map_or_else(Default::default, |kdf| {
+                ataf::encryption::Argon2Parameters::new(kdf.memory, kdf.iterations, kdf.parallelism)
+            }),
+        ) {
+            Ok(encryption) => Some(encryption),
+            Err(err) => {
+                crate::report::error(
+                    "read_key",
+                    None,
+                    Some(&err),
+                    format_args!("failed to read the encryption key"),
+                );
+                return 1;
+            }
+        },
+        None => None,
+    };
+    #[cfg(not(feature = "encryption"))]
+    if let Some(encryption) = encryption {
+        match encryption {}
+    }
+
+    let file = match std::fs::File::create(&destination) {
+        Ok(file) => file,
+        Err(err) => {
+            crate::report::error(
+                "create_archive",
+                Some(&destination),
+                Some(&err),
+                format_args!("failed to create archive {}", destination.display()),
+            );
+            return 1;
+        }
+    };
+
+    println_if_terminal!("recompressing archive with the following options:");
+    println_if_terminal!("compression format: {:?}", compression_format);
+    println_if_terminal!("number of threads: {}", threads);
+    println_if_terminal!("chunk size: {}", chunk_size);
+
+    let mut entries = match archive.entries(decompressor) {
+        Ok(entries) => entries,
+        Err(err) => {
+            crate::report::error(
+                "read_header",
+                None,
+                Some(&err),
+                format_args!("failed to read archive header"),
+            );
+            return 1;
+        }
+    };
+
+    let (sender, receiver) = sync_channel::<Entry>(1);
+    let mut read_failed = false;
+    let archive_path = destination.clone();
+    let written = std::thread::scope(|scope| {
+        let writing = scope.spawn(move || -> ataf::Result<u64> {
+            let mut compressor =
+                super::Registry::default().compressor(compression_format.name(), threads)?;
+            if let Some(level) = level {
+                compressor.set_level(level);
+            }
+            if let Some(chunks) = in_flight {
+                compressor.set_in_flight(chunks);
+            }
+
+            let writer: Box<dyn Write + Send> = Box::new(file);
+            #[cfg(feature = "encryption")]
+            let (writer, encryption) = match encryption_key {
+                Some((header, key)) => {
+                    let encryption = super::SharedEncryption::new(writer, &key, header)?;
+                    (
+                        Box::new(encryption.clone()) as Box<dyn Write + Send>,
+                        Some(encryption),
+                    )
+                }
+                None => (writer, None),
+            };
+            let mut archive = ArchiveWriter::with_options(
+                BufWriter::with_capacity(1024 * 1024, writer),
+                compressor,
+                chunk_size,
+                options,
+            )?;
+
+            let mut written = 0;
+            for (header, data) in receiver {
+                let input: Box<dyn Read> = Box::new(ChannelReader {
+                    receiver: data,
+                    buffer: Vec::new(),
+                    offset: 0,
+                });
+                archive.write_entry(header, input)?;
+                written += 1;
+            }
+            let stats = archive.stats();
+            archive.finish()?.flush()?;
+            // an encrypted header can't be rewritten in place
+            #[cfg(feature = "encryption")]
+            if let Some(encryption) = encryption {
+                encryption.finish()?;
+                return Ok(written);
+            }
+
+            ataf::archive::write::write_totals(
+                std::fs::File::options()
+                    .read(true)
+                    .write(true)
+                    .open(&archive_path)?,
+                stats,
+            )?;
+
+            Ok(written)
+        });
+
+        while let Some(entry) = entries.next_entry() {
+            let mut entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    crate::report::error(
+                        "read_entry",
+                        None,
+                        Some(&err),
+                        format_args!("failed to read entry"),
+                    );
+                    read_failed = true;
+                    break;
+                }
+            };
+
+            // stored data is compressed with the new format, whatever it was
+            let mut header = entry.header().clone();
+            header.compression = None;
+
+            let (data_sender, data_receiver) = sync_channel(4);
+            if sender.send((header, data_receiver)).is_err() {
+                break;
+            }
+            if let Err(err) = send_data(&mut entry, &data_sender) {
+                crate::report::error(
+                    "read_entry",
+                    Some(Path::new(&entry.header().path)),
+                    Some(&err),
+                    format_args!("error reading entry {}", entry.header().path),
+                );
+                read_failed = true;
+                break;
+            }
+        }
+        drop(sender);
+
+        writing.join().expect("the writing thread doesn't panic")
+    });
+
+    let written = match written {
+        // a failed read also fails the entry it was sent to, which isn't
+        // worth a second error
+        _ if read_failed => None,
+        Ok(written) => Some(written),
+        Err(err) => {
+            crate::report::error(
+                "write_archive",
+                Some(&destination),
+                Some(&err),
+                format_args!("failed to write archive {}", destination.display()),
+            );
+            None
+        }
+    };
+    let Some(written) = written else {
+        if output.is_none() {
+            std::fs::remove_file(&destination).ok();
+        }
+        return 1;
+    };
+
+    if output.is_none()
+        && let Some(input) = input
+        && let Err(err) = std::fs::rename(&destination, input)
+    {
+        crate::report::error(
+            "replace_archive",
+            Some(input),
+            Some(&err),
+            format_args!(
+                "failed to replace {} with {}",
+                input.display(),
+                destination.display()
+            ),
+        );
+        return 1;
+    }
+
+    println_if_terminal!(
+        "recompressed {} entries into {}",
+        written,
+        output.or(input).unwrap_or(&destination).display()
+    );
+
+    0
+}
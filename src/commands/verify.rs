@@ -0,0 +1,110 @@
+use clap::ArgMatches;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Decompresses every entry into nothing, printing `OK` or `FAILED` for each,
+/// or a JSON object with a `status` of `ok` or `failed` with `--format json`.
+/// Truncated archives, decoder and checksum errors and entries whose data is
+/// shorter than their header says fail, as does the whole run then.
+pub fn run(matches: &ArgMatches) -> i32 {
+    let threads = matches.get_one::<usize>("threads").unwrap();
+    let input = matches.get_one::<PathBuf>("input");
+    let Some(json) = super::json_format(matches) else {
+        return 1;
+    };
+
+    let Some((mut archive, decompressor, _)) = super::open_archive(
+        input,
+        *threads,
+        false,
+        &super::KeySource::from_matches(matches),
+    ) else {
+        return 1;
+    };
+
+    let mut entries = match archive.entries(decompressor) {
+        Ok(entries) => entries,
+        Err(err) => {
+            crate::report::error(
+                "read_header",
+                None,
+                Some(&err),
+                format_args!("failed to read archive header"),
+            );
+            return 1;
+        }
+    };
+    let mut stdout = std::io::stdout().lock();
+    let mut passed = 0u64;
+    let mut failed = 0u64;
+
+    while let Some(entry) = entries.next_entry() {
+        let mut entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                // the entries after a broken header can't be found
+                crate::report::error(
+                    "read_entry",
+                    None,
+                    Some(&err),
+                    format_args!("failed to read entry"),
+                );
+                failed += 1;
+                break;
+            }
+        };
+        let header = entry.header().clone();
+        let path = header.path.clone();
+        let size = *entry.header().size;
+
+        let ok = match std::io::copy(&mut entry, &mut std::io::sink()) {
+            Ok(read) if read == size => true,
+            Ok(read) => {
+                crate::report::error(
+                    "size_mismatch",
+                    Some(Path::new(&path)),
+                    None,
+                    format_args!("{path} decompressed to {read} bytes instead of {size}"),
+                );
+                false
+            }
+            Err(err) => {
+                crate::report::error(
+                    "verify_entry",
+                    Some(Path::new(&path)),
+                    Some(&err),
+                    format_args!("failed to decompress {path}"),
+                );
+                false
+            }
+        };
+
+        if ok {
+            passed += 1;
+        } else {
+            failed += 1;
+        }
+
+        let result = match (json, ok) {
+            (true, _) => writeln!(
+                stdout,
+                "{}",
+                super::entry_json(&header, Some(if ok { "ok" } else { "failed" }))
+            ),
+            (false, true) => writeln!(stdout, "OK {path}"),
+            (false, false) => writeln!(stdout, "FAILED {path}"),
+        };
+        if let Err(err) = result {
+            super::exit_on_write_error(err);
+        }
+    }
+
+    // the exit code tells the same, without a line that isn't an entry
+    if !json && let Err(err) = writeln!(stdout, "{passed} entries ok, {failed} failed") {
+        super::exit_on_write_error(err);
+    }
+
+    i32::from(failed > 0)
+}
@@ -1,16 +1,19 @@
 use crate::archive::write::ChunkWriter;
 use clap::ValueEnum;
-use std::{
-    io::{Read, Write},
-    sync::{Arc, Mutex},
-};
+use std::io::{Read, Write};
+#[cfg(feature = "brotli")]
+use std::sync::Arc;
 
 #[cfg(feature = "brotli")]
 pub use brotli;
+#[cfg(feature = "bzip2")]
+pub use bzip2;
 #[cfg(feature = "flate2")]
 pub use flate2;
 #[cfg(feature = "lz4")]
 pub use lz4;
+#[cfg(feature = "snap")]
+pub use snap;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CompressionFormat {
@@ -21,6 +24,50 @@ pub enum CompressionFormat {
     Brotli,
     #[cfg(feature = "lz4")]
     Lz4,
+    #[cfg(feature = "snap")]
+    Snappy,
+    #[cfg(feature = "bzip2")]
+    Bzip2,
+}
+
+impl CompressionFormat {
+    /// The name stored in archive headers, under which the format is in the
+    /// default `CompressionRegistry`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            #[cfg(feature = "flate2")]
+            Self::Flate2 => "flate2",
+            #[cfg(feature = "brotli")]
+            Self::Brotli => "brotli",
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => "lz4",
+            #[cfg(feature = "snap")]
+            Self::Snappy => "snappy",
+            #[cfg(feature = "bzip2")]
+            Self::Bzip2 => "bzip2",
+        }
+    }
+
+    /// The chunk size used when none is given explicitly. Faster codecs gain
+    /// from larger chunks, while deflate's small window gains little past the
+    /// global default.
+    pub fn default_chunk_size(&self) -> u32 {
+        match self {
+            Self::None => crate::archive::write::DEFAULT_CHUNK_SIZE,
+            #[cfg(feature = "flate2")]
+            Self::Flate2 => crate::archive::write::DEFAULT_CHUNK_SIZE,
+            #[cfg(feature = "brotli")]
+            Self::Brotli => 256 * 1024,
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => 1024 * 1024,
+            #[cfg(feature = "snap")]
+            Self::Snappy => 1024 * 1024,
+            // the largest block bzip2 sorts at once
+            #[cfg(feature = "bzip2")]
+            Self::Bzip2 => 900 * 1000,
+        }
+    }
 }
 
 impl ValueEnum for CompressionFormat {
@@ -33,18 +80,34 @@ impl ValueEnum for CompressionFormat {
             Self::Brotli,
             #[cfg(feature = "lz4")]
             Self::Lz4,
+            #[cfg(feature = "snap")]
+            Self::Snappy,
+            #[cfg(feature = "bzip2")]
+            Self::Bzip2,
         ]
     }
 
+    /// Archive headers always store the canonical name (the compressor's
+    /// `name`), the aliases only help users coming from other tools. All
+    /// deflate flavours map to flate2, whose chunks are zlib streams.
     fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
         match self {
-            Self::None => Some(clap::builder::PossibleValue::new("none")),
+            Self::None => Some(clap::builder::PossibleValue::new("none").alias("store")),
             #[cfg(feature = "flate2")]
-            Self::Flate2 => Some(clap::builder::PossibleValue::new("flate2")),
+            Self::Flate2 => Some(
+                clap::builder::PossibleValue::new("flate2")
+                    .aliases(["gzip", "gz", "zlib", "deflate"]),
+            ),
             #[cfg(feature = "brotli")]
-            Self::Brotli => Some(clap::builder::PossibleValue::new("brotli")),
+            Self::Brotli => Some(clap::builder::PossibleValue::new("brotli").alias("br")),
             #[cfg(feature = "lz4")]
             Self::Lz4 => Some(clap::builder::PossibleValue::new("lz4")),
+            #[cfg(feature = "snap")]
+            Self::Snappy => Some(clap::builder::PossibleValue::new("snappy").alias("snap")),
+            #[cfg(feature = "bzip2")]
+            Self::Bzip2 => {
+                Some(clap::builder::PossibleValue::new("bzip2").aliases(["bz2", "bzip"]))
+            }
         }
     }
 }
@@ -56,7 +119,7 @@ pub struct WriteCounter<W: Write> {
 
 impl<W: Write> WriteCounter<W> {
     #[inline]
-    fn new(writer: W) -> Self {
+    pub fn new(writer: W) -> Self {
         Self {
             writer,
             bytes_written: 0,
@@ -64,7 +127,7 @@ impl<W: Write> WriteCounter<W> {
     }
 
     #[inline]
-    fn into_written(self) -> usize {
+    pub fn into_written(self) -> usize {
         self.bytes_written
     }
 }
@@ -82,9 +145,197 @@ impl<W: Write> Write for WriteCounter<W> {
     }
 }
 
+/// Fills `buffer` with up to `chunk_size` bytes from `input`, reading through
+/// short reads so only the final chunk of an input can be shorter. A plain
+/// read loop performs the same as `read_vectored` for files and pipes (see
+/// `benches/read_strategies.rs`).
+#[cfg(any(
+    feature = "flate2",
+    feature = "brotli",
+    feature = "lz4",
+    feature = "snap",
+    feature = "bzip2"
+))]
+fn read_chunk<R: Read>(
+    input: &mut R,
+    buffer: &mut Vec<u8>,
+    chunk_size: u32,
+) -> std::io::Result<()> {
+    buffer.resize(chunk_size as usize, 0);
+
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match input.read(&mut buffer[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    buffer.truncate(filled);
+
+    Ok(())
+}
+
+/// Compresses the chunks of an entry on a thread pool and writes them in
+/// input order.
+///
+/// The calling thread reads numbered chunks and hands each to a worker, then
+/// collects the results and writes every chunk as soon as all chunks before it
+/// are written. At most `in_flight` chunks (`2 * threads` unless changed) are
+/// read but not yet written at any time, so memory stays bounded no matter how
+/// large the entry is, while workers keep compressing the next chunks during
+/// slow writes.
+#[cfg(any(
+    feature = "flate2",
+    feature = "brotli",
+    feature = "lz4",
+    feature = "snap",
+    feature = "bzip2"
+))]
+struct ChunkPipeline {
+    in_flight: usize,
+    thread_pool: rayon::ThreadPool,
+    input_buffers: Vec<Vec<u8>>,
+}
+
+#[cfg(any(
+    feature = "flate2",
+    feature = "brotli",
+    feature = "lz4",
+    feature = "snap",
+    feature = "bzip2"
+))]
+impl ChunkPipeline {
+    fn new(threads: usize) -> crate::Result<Self> {
+        Ok(Self {
+            in_flight: threads.max(1) * 2,
+            thread_pool: rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()?,
+            input_buffers: Vec::new(),
+        })
+    }
+
+    fn compress<R: Read, W: Write + Send>(
+        &mut self,
+        input: &mut R,
+        chunks: usize,
+        chunk_size: u32,
+        chunk_writer: &mut ChunkWriter<&mut W>,
+        compress: impl Fn(&[u8]) -> std::io::Result<Vec<u8>> + Sync,
+    ) -> std::io::Result<()> {
+        let limit = self.in_flight;
+        let compress = &compress;
+        let input_buffers = &mut self.input_buffers;
+
+        self.thread_pool.in_place_scope(|scope| {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            let mut completed = std::collections::BTreeMap::new();
+
+            let (mut read, mut received, mut written) = (0, 0, 0);
+            loop {
+                while read < chunks && read - written < limit {
+                    let mut buffer = input_buffers.pop().unwrap_or_default();
+                    read_chunk(input, &mut buffer, chunk_size)?;
+                    if buffer.is_empty() {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::UnexpectedEof,
+                            "input ended before the entry's recorded size",
+                        ));
+                    }
+
+                    let sender = sender.clone();
+                    let index = read;
+                    scope.spawn(move |_| {
+                        let result = compress(&buffer);
+                        sender.send((index, result, buffer)).ok();
+                    });
+
+                    read += 1;
+                }
+
+                if received == read {
+                    return Ok(());
+                }
+
+                let (index, result, buffer) = receiver.recv().map_err(|_| {
+                    std::io::Error::other("compression worker stopped unexpectedly")
+                })?;
+                received += 1;
+                input_buffers.push(buffer);
+                completed.insert(index, result?);
+
+                while let Some(chunk) = completed.remove(&written) {
+                    chunk_writer.write_chunk(&chunk)?;
+                    written += 1;
+                }
+            }
+        })
+    }
+}
+
+/// Decodes one chunk per input on `thread_pool` and appends them to
+/// `archive_output` in input order. Every chunk's result is collected once all
+/// of them are decoded, so a failed chunk is reported instead of its partial
+/// output being written.
+#[cfg(any(
+    feature = "flate2",
+    feature = "brotli",
+    feature = "lz4",
+    feature = "snap",
+    feature = "bzip2"
+))]
+fn decode_chunks(
+    thread_pool: &rayon::ThreadPool,
+    chunk_buffers: &mut Vec<Vec<u8>>,
+    inputs: Vec<Vec<u8>>,
+    archive_output: &mut Vec<u8>,
+    chunk_size: u32,
+    decode: impl Fn(&[u8], &mut Vec<u8>) -> std::io::Result<()> + Sync,
+) -> std::io::Result<()> {
+    use rayon::iter::{
+        IndexedParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator,
+        ParallelIterator,
+    };
+
+    if chunk_buffers.len() < inputs.len() {
+        chunk_buffers.resize_with(inputs.len(), || Vec::with_capacity(chunk_size as usize));
+    }
+    let chunk_buffers = &mut chunk_buffers[..inputs.len()];
+
+    thread_pool.install(|| {
+        inputs
+            .par_iter()
+            .zip(chunk_buffers.par_iter_mut())
+            .map(|(input, chunk_buffer)| {
+                chunk_buffer.clear();
+                decode(input, chunk_buffer)
+            })
+            .collect::<std::io::Result<()>>()
+    })?;
+
+    for chunk_buffer in chunk_buffers.iter() {
+        archive_output.write_all(chunk_buffer)?;
+    }
+
+    Ok(())
+}
+
 pub trait Compressor<W: Write + Send, R: Read> {
     fn name(&self) -> &'static str;
 
+    /// The levels accepted by `set_level`, from fastest to best compression.
+    fn levels(&self) -> std::ops::RangeInclusive<u32> {
+        0..=0
+    }
+
+    /// Changes the level used for all chunks compressed after this call.
+    fn set_level(&mut self, _level: u32) {}
+
+    /// Caps the chunks read but not yet written, which bounds the memory of
+    /// compressing an entry to about that many chunks whatever the thread
+    /// count. Compressors working on one chunk at a time ignore it.
+    fn set_in_flight(&mut self, _chunks: usize) {}
+
     fn compress(
         &mut self,
         input: &mut R,
@@ -105,6 +356,39 @@ pub trait Decompressor {
     ) -> std::io::Result<()>;
 }
 
+/// Stores chunks as they are. Every chunk but the last is filled completely,
+/// even from readers that return less than requested per `read`.
+///
+/// ```
+/// use ataf::{
+///     archive::{read::Archive, write::ArchiveWriter},
+///     compression::{NoCompressor, NoDecompressor},
+///     spec::ArchiveEntryHeader,
+/// };
+/// use std::io::Read;
+///
+/// struct OneByteReader<'a>(&'a [u8]);
+///
+/// impl Read for OneByteReader<'_> {
+///     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+///         Read::take(&mut self.0, 1).read(buf)
+///     }
+/// }
+///
+/// let data: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+/// let header = ArchiveEntryHeader::file("data", data.len() as u64);
+///
+/// let mut archive = Vec::new();
+/// ArchiveWriter::new(&mut archive, Box::new(NoCompressor::new()), 1024)
+///     .unwrap()
+///     .write_entry(header, OneByteReader(&data))
+///     .unwrap();
+///
+/// let mut archive = Archive::new(&archive[..]);
+/// let mut entries = archive.entries(Box::new(NoDecompressor)).unwrap();
+/// let (_, read) = entries.next_file().unwrap().unwrap();
+/// assert_eq!(read, data);
+/// ```
 pub struct NoCompressor {
     chunk_buffer: Vec<u8>,
 }
@@ -135,37 +419,75 @@ impl<W: Write + Send, R: Read> Compressor<W, R> for NoCompressor {
         chunk_size: u32,
         chunk_writer: &mut ChunkWriter<&mut W>,
     ) -> std::io::Result<()> {
-        if self.chunk_buffer.capacity() < chunk_size as usize {
-            self.chunk_buffer = vec![0; chunk_size as usize];
-        }
-
-        let bytes_copied = input.take(chunk_size as u64).read(&mut self.chunk_buffer)?;
-        chunk_writer.write_chunk(&self.chunk_buffer[..bytes_copied])?;
+        // a single read may return less than a full chunk, for example at the
+        // boundaries between the entries of solid archives
+        self.chunk_buffer.clear();
+        input
+            .take(chunk_size as u64)
+            .read_to_end(&mut self.chunk_buffer)?;
+        chunk_writer.write_chunk(&self.chunk_buffer)?;
 
         Ok(())
     }
 }
 
+/// Compresses chunks with zlib. Chunks finish compressing in any order but are
+/// always written in the order they were read, which this checks with many
+/// threads and chunks that take very different times to compress.
+///
+/// ```
+/// use ataf::{
+///     archive::{read::Archive, write::ArchiveWriter},
+///     compression::{Flate2Compressor, Flate2Decompressor},
+///     spec::ArchiveEntryHeader,
+/// };
+///
+/// const CHUNK_SIZE: usize = 4096;
+///
+/// // a trivially compressible chunk after every two incompressible ones, far
+/// // more chunks than the threads have in flight at once
+/// let mut state = 0x9e3779b9u32;
+/// let data: Vec<u8> = (0..CHUNK_SIZE * 300 + 123)
+///     .map(|i| match (i / CHUNK_SIZE) % 3 {
+///         0 => (i / CHUNK_SIZE) as u8,
+///         _ => {
+///             state ^= state << 13;
+///             state ^= state >> 17;
+///             state ^= state << 5;
+///             state as u8
+///         }
+///     })
+///     .collect();
+/// let header = ArchiveEntryHeader::file("random.bin", data.len() as u64);
+///
+/// for _ in 0..4 {
+///     let mut archive = Vec::new();
+///     let compressor =
+///         Flate2Compressor::new(16, ataf::compression::flate2::Compression::best()).unwrap();
+///     ArchiveWriter::new(&mut archive, Box::new(compressor), CHUNK_SIZE as u32)
+///         .unwrap()
+///         .write_entry(header.clone(), &data[..])
+///         .unwrap();
+///
+///     let mut archive = Archive::new(&archive[..]);
+///     let mut entries = archive.entries(Box::new(Flate2Decompressor::new(16).unwrap())).unwrap();
+///     let (_, read) = entries.next_file().unwrap().unwrap();
+///     assert!(read == data, "chunks were written out of order");
+/// }
+/// ```
 #[cfg(feature = "flate2")]
 pub struct Flate2Compressor {
-    threads: usize,
     compression: flate2::Compression,
-    input_buffers: Vec<Vec<u8>>,
-    thread_pool: rayon::ThreadPool,
+    pipeline: ChunkPipeline,
 }
 
 #[cfg(feature = "flate2")]
 impl Flate2Compressor {
-    pub fn new(threads: usize, compression: flate2::Compression) -> Self {
-        Self {
-            threads,
+    pub fn new(threads: usize, compression: flate2::Compression) -> crate::Result<Self> {
+        Ok(Self {
             compression,
-            input_buffers: Vec::new(),
-            thread_pool: rayon::ThreadPoolBuilder::new()
-                .num_threads(threads)
-                .build()
-                .unwrap(),
-        }
+            pipeline: ChunkPipeline::new(threads)?,
+        })
     }
 }
 
@@ -175,6 +497,18 @@ impl<W: Write + Send, R: Read> Compressor<W, R> for Flate2Compressor {
         "flate2"
     }
 
+    fn set_in_flight(&mut self, chunks: usize) {
+        self.pipeline.in_flight = chunks.max(1);
+    }
+
+    fn levels(&self) -> std::ops::RangeInclusive<u32> {
+        0..=9
+    }
+
+    fn set_level(&mut self, level: u32) {
+        self.compression = flate2::Compression::new(level);
+    }
+
     fn compress(
         &mut self,
         input: &mut R,
@@ -182,126 +516,62 @@ impl<W: Write + Send, R: Read> Compressor<W, R> for Flate2Compressor {
         chunk_size: u32,
         chunk_writer: &mut ChunkWriter<&mut W>,
     ) -> std::io::Result<()> {
-        let threads = std::cmp::min(self.threads, remaining_chunks);
-
-        if self.input_buffers.len() < threads {
-            self.input_buffers.resize_with(threads, Vec::new);
-        }
-        self.input_buffers.truncate(threads);
-
-        for i in 0..threads {
-            let buffer = &mut self.input_buffers[i];
-            if buffer.capacity() < chunk_size as usize {
-                buffer.reserve(chunk_size as usize - buffer.capacity());
-            }
-            buffer.clear();
-            buffer.resize(chunk_size as usize, 0);
-        }
-
-        let mut io_slices = Vec::new();
-        io_slices.reserve_exact(threads);
-        for buffer in &mut self.input_buffers {
-            io_slices.push(std::io::IoSliceMut::new(buffer));
-        }
-
-        let mut slices_to_read = &mut io_slices[..];
-        let mut chunks_with_data = threads;
-
-        while !slices_to_read.is_empty() {
-            match input.read_vectored(slices_to_read)? {
-                0 => {
-                    chunks_with_data = threads - slices_to_read.len();
-                    break;
-                }
-                n => {
-                    let mut bytes_read = n;
-                    let mut slices_read = 0;
-
-                    for slice in slices_to_read.iter() {
-                        if bytes_read >= slice.len() {
-                            bytes_read -= slice.len();
-                            slices_read += 1;
-                        } else {
-                            break;
-                        }
-                    }
-
-                    if slices_read > 0 {
-                        slices_to_read = &mut slices_to_read[slices_read..];
-                    }
-
-                    if bytes_read > 0 && !slices_to_read.is_empty() {
-                        let current_slice_index = threads - slices_to_read.len();
-                        self.input_buffers[current_slice_index].truncate(bytes_read);
-                        chunks_with_data = current_slice_index + 1;
-                        break;
-                    }
-                }
-            }
-        }
-
-        let chunk_writer = Arc::new(Mutex::new(chunk_writer));
-
-        self.thread_pool.in_place_scope(|scope| {
-            let error = Arc::new(Mutex::new(None));
-
-            for i in 0..chunks_with_data {
-                let input_data = &self.input_buffers[i];
-                let compression = self.compression;
-                let chunk_writer = Arc::clone(&chunk_writer);
-                let error = Arc::clone(&error);
-
-                scope.spawn(move |_| {
-                    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), compression);
-                    if let Err(err) = encoder.write_all(input_data) {
-                        *error.lock().unwrap() = Some(err);
-                        return;
-                    }
-
-                    match encoder.finish() {
-                        Ok(result) => {
-                            if let Err(err) = chunk_writer.lock().unwrap().write_chunk(&result) {
-                                *error.lock().unwrap() = Some(err);
-                            }
-                        }
-                        Err(err) => {
-                            *error.lock().unwrap() = Some(err);
-                        }
-                    }
-                });
-            }
-
-            if let Some(err) = error.lock().unwrap().take() {
-                return Err(err);
-            }
-
-            Ok(())
-        })?;
-
-        Ok(())
+        let compression = self.compression;
+
+        self.pipeline
+            .compress(input, remaining_chunks, chunk_size, chunk_writer, |data| {
+                let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), compression);
+                encoder.write_all(data)?;
+                encoder.finish()
+            })
     }
 }
 
+/// Compresses chunks with brotli using `params`, of which `quality` and
+/// `lgwin` matter most. The window is `2^lgwin` bytes, up to 16 MiB at 24, and
+/// every compressing thread holds a few times that in memory while the
+/// decompressing ones need one window each. Chunks are compressed on their own,
+/// so windows larger than the chunk size only cost memory.
+///
+/// ```
+/// use ataf::{
+///     archive::{read::Archive, write::ArchiveWriter},
+///     compression::{BrotliCompressor, BrotliDecompressor},
+///     spec::ArchiveEntryHeader,
+/// };
+///
+/// let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8 ^ (i / 1000) as u8).collect();
+/// let header = ArchiveEntryHeader::file("data", data.len() as u64);
+///
+/// let params = ataf::compression::brotli::enc::BrotliEncoderParams {
+///     quality: 11,
+///     lgwin: 24,
+///     ..Default::default()
+/// };
+/// let mut archive = Vec::new();
+/// ArchiveWriter::new(&mut archive, Box::new(BrotliCompressor::new(2, params).unwrap()), 1 << 20)
+///     .unwrap()
+///     .write_entry(header, &data[..])
+///     .unwrap();
+///
+/// let mut archive = Archive::new(&archive[..]);
+/// let mut entries = archive.entries(Box::new(BrotliDecompressor::new(2).unwrap())).unwrap();
+/// let (_, read) = entries.next_file().unwrap().unwrap();
+/// assert_eq!(read, data);
+/// ```
 #[cfg(feature = "brotli")]
 pub struct BrotliCompressor {
-    threads: usize,
     params: Arc<brotli::enc::BrotliEncoderParams>,
-    input_buffers: Vec<Vec<u8>>,
-    thread_pool: rayon::ThreadPool,
+    pipeline: ChunkPipeline,
 }
 
 #[cfg(feature = "brotli")]
 impl BrotliCompressor {
-    pub fn new(threads: usize, params: brotli::enc::BrotliEncoderParams) -> Self {
-        Self {
-            threads,
+    pub fn new(threads: usize, params: brotli::enc::BrotliEncoderParams) -> crate::Result<Self> {
+        Ok(Self {
             params: Arc::new(params),
-            input_buffers: Vec::new(),
-            thread_pool: rayon::ThreadPoolBuilder::new()
-                .num_threads(threads)
-                .build()
-                .unwrap(),
-        }
+            pipeline: ChunkPipeline::new(threads)?,
+        })
     }
 }
 
@@ -311,6 +581,18 @@ impl<W: Write + Send, R: Read> Compressor<W, R> for BrotliCompressor {
         "brotli"
     }
 
+    fn set_in_flight(&mut self, chunks: usize) {
+        self.pipeline.in_flight = chunks.max(1);
+    }
+
+    fn levels(&self) -> std::ops::RangeInclusive<u32> {
+        0..=11
+    }
+
+    fn set_level(&mut self, level: u32) {
+        Arc::make_mut(&mut self.params).quality = level as i32;
+    }
+
     fn compress(
         &mut self,
         input: &mut R,
@@ -318,123 +600,57 @@ impl<W: Write + Send, R: Read> Compressor<W, R> for BrotliCompressor {
         chunk_size: u32,
         chunk_writer: &mut ChunkWriter<&mut W>,
     ) -> std::io::Result<()> {
-        let threads = std::cmp::min(self.threads, remaining_chunks);
-
-        if self.input_buffers.len() < threads {
-            self.input_buffers.resize_with(threads, Vec::new);
-        }
-        self.input_buffers.truncate(threads);
-
-        for i in 0..threads {
-            let buffer = &mut self.input_buffers[i];
-            if buffer.capacity() < chunk_size as usize {
-                buffer.reserve(chunk_size as usize - buffer.capacity());
-            }
-            buffer.clear();
-            buffer.resize(chunk_size as usize, 0);
-        }
-
-        let mut io_slices = Vec::new();
-        io_slices.reserve_exact(threads);
-        for buffer in &mut self.input_buffers {
-            io_slices.push(std::io::IoSliceMut::new(buffer));
-        }
-
-        let mut slices_to_read = &mut io_slices[..];
-        let mut chunks_with_data = threads;
-
-        while !slices_to_read.is_empty() {
-            match input.read_vectored(slices_to_read)? {
-                0 => {
-                    chunks_with_data = threads - slices_to_read.len();
-                    break;
-                }
-                n => {
-                    let mut bytes_read = n;
-                    let mut slices_read = 0;
-
-                    for slice in slices_to_read.iter() {
-                        if bytes_read >= slice.len() {
-                            bytes_read -= slice.len();
-                            slices_read += 1;
-                        } else {
-                            break;
-                        }
-                    }
-
-                    if slices_read > 0 {
-                        slices_to_read = &mut slices_to_read[slices_read..];
-                    }
-
-                    if bytes_read > 0 && !slices_to_read.is_empty() {
-                        let current_slice_index = threads - slices_to_read.len();
-                        self.input_buffers[current_slice_index].truncate(bytes_read);
-                        chunks_with_data = current_slice_index + 1;
-                        break;
-                    }
-                }
-            }
-        }
+        let params = &self.params;
 
-        let chunk_writer = Arc::new(Mutex::new(chunk_writer));
+        self.pipeline
+            .compress(input, remaining_chunks, chunk_size, chunk_writer, |data| {
+                let mut result = Vec::new();
+                brotli::enc::BrotliCompress(&mut std::io::Cursor::new(data), &mut result, params)?;
 
-        self.thread_pool.in_place_scope(|scope| {
-            let error = Arc::new(Mutex::new(None));
-
-            for i in 0..chunks_with_data {
-                let input_data = &self.input_buffers[i];
-                let params = Arc::clone(&self.params);
-                let chunk_writer = Arc::clone(&chunk_writer);
-                let error = Arc::clone(&error);
-
-                scope.spawn(move |_| {
-                    let mut result = Vec::new();
-                    if let Err(err) = brotli::enc::BrotliCompress(
-                        &mut std::io::Cursor::new(input_data),
-                        &mut result,
-                        &params,
-                    ) {
-                        *error.lock().unwrap() = Some(err);
-                        return;
-                    };
-
-                    if let Err(err) = chunk_writer.lock().unwrap().write_chunk(&result) {
-                        *error.lock().unwrap() = Some(err);
-                    }
-                });
-            }
-
-            if let Some(err) = error.lock().unwrap().take() {
-                return Err(err);
-            }
-
-            Ok(())
-        })?;
-
-        Ok(())
+                Ok(result)
+            })
     }
 }
 
+/// Compresses chunks as lz4 frames. Levels below 3 use the fast compressor,
+/// higher ones lz4's slower high compression mode.
+///
+/// ```
+/// use ataf::{
+///     archive::{read::Archive, write::ArchiveWriter},
+///     compression::{Lz4Compressor, Lz4Decompressor},
+///     spec::ArchiveEntryHeader,
+/// };
+///
+/// let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8 ^ (i / 1000) as u8).collect();
+/// for level in [0, 16] {
+///     let header = ArchiveEntryHeader::file("data", data.len() as u64);
+///
+///     let mut archive = Vec::new();
+///     ArchiveWriter::new(&mut archive, Box::new(Lz4Compressor::new(2, level).unwrap()), 16384)
+///         .unwrap()
+///         .write_entry(header, &data[..])
+///         .unwrap();
+///
+///     let mut archive = Archive::new(&archive[..]);
+///     let mut entries = archive.entries(Box::new(Lz4Decompressor::new(2).unwrap())).unwrap();
+///     let (_, read) = entries.next_file().unwrap().unwrap();
+///     assert_eq!(read, data);
+/// }
+/// ```
 #[cfg(feature = "lz4")]
 pub struct Lz4Compressor {
-    threads: usize,
     level: u32,
-    input_buffers: Vec<Vec<u8>>,
-    thread_pool: rayon::ThreadPool,
+    pipeline: ChunkPipeline,
 }
 
 #[cfg(feature = "lz4")]
 impl Lz4Compressor {
-    pub fn new(threads: usize, level: u32) -> Self {
-        Self {
-            threads,
+    pub fn new(threads: usize, level: u32) -> crate::Result<Self> {
+        Ok(Self {
             level,
-            input_buffers: Vec::new(),
-            thread_pool: rayon::ThreadPoolBuilder::new()
-                .num_threads(threads)
-                .build()
-                .unwrap(),
-        }
+            pipeline: ChunkPipeline::new(threads)?,
+        })
     }
 }
 
@@ -444,6 +660,18 @@ impl<W: Write + Send, R: Read> Compressor<W, R> for Lz4Compressor {
         "lz4"
     }
 
+    fn set_in_flight(&mut self, chunks: usize) {
+        self.pipeline.in_flight = chunks.max(1);
+    }
+
+    fn levels(&self) -> std::ops::RangeInclusive<u32> {
+        0..=16
+    }
+
+    fn set_level(&mut self, level: u32) {
+        self.level = level;
+    }
+
     fn compress(
         &mut self,
         input: &mut R,
@@ -451,106 +679,179 @@ impl<W: Write + Send, R: Read> Compressor<W, R> for Lz4Compressor {
         chunk_size: u32,
         chunk_writer: &mut ChunkWriter<&mut W>,
     ) -> std::io::Result<()> {
-        let threads = std::cmp::min(self.threads, remaining_chunks);
+        let level = self.level;
 
-        if self.input_buffers.len() < threads {
-            self.input_buffers.resize_with(threads, Vec::new);
-        }
-        self.input_buffers.truncate(threads);
+        self.pipeline
+            .compress(input, remaining_chunks, chunk_size, chunk_writer, |data| {
+                let mut encoder = lz4::EncoderBuilder::new().level(level).build(Vec::new())?;
+                encoder.write_all(data)?;
 
-        for i in 0..threads {
-            let buffer = &mut self.input_buffers[i];
-            if buffer.capacity() < chunk_size as usize {
-                buffer.reserve(chunk_size as usize - buffer.capacity());
-            }
-            buffer.clear();
-            buffer.resize(chunk_size as usize, 0);
-        }
+                let (result, finished) = encoder.finish();
+                finished.map(|()| result)
+            })
+    }
+}
 
-        let mut io_slices = Vec::new();
-        io_slices.reserve_exact(threads);
-        for buffer in &mut self.input_buffers {
-            io_slices.push(std::io::IoSliceMut::new(buffer));
-        }
+/// Compresses chunks with Snappy's raw format, trading ratio for very cheap
+/// compression and decompression. Snappy has no levels.
+///
+/// ```
+/// use ataf::{
+///     archive::{read::Archive, write::ArchiveWriter},
+///     compression::{SnappyCompressor, SnappyDecompressor},
+///     spec::ArchiveEntryHeader,
+/// };
+///
+/// let mut state = 0x2545f491u32;
+/// let binary: Vec<u8> = (0..100_000)
+///     .map(|_| {
+///         state ^= state << 13;
+///         state ^= state >> 17;
+///         state ^= state << 5;
+///         state as u8
+///     })
+///     .collect();
+/// let text = "the quick brown fox jumps over the lazy dog\n".repeat(2_000).into_bytes();
+///
+/// let mut archive = Vec::new();
+/// let compressor = SnappyCompressor::new(2).unwrap();
+/// let mut writer = ArchiveWriter::new(&mut archive, Box::new(compressor), 16384).unwrap();
+/// writer
+///     .write_entry(ArchiveEntryHeader::file("data.bin", binary.len() as u64), &binary[..])
+///     .unwrap();
+/// writer
+///     .write_entry(ArchiveEntryHeader::file("text.txt", text.len() as u64), &text[..])
+///     .unwrap();
+/// writer.finish().unwrap();
+///
+/// let mut archive = Archive::new(&archive[..]);
+/// assert_eq!(archive.header().unwrap().compression, "snappy");
+/// let mut entries = archive.entries(Box::new(SnappyDecompressor::new(2).unwrap())).unwrap();
+/// let (header, read) = entries.next_file().unwrap().unwrap();
+/// assert_eq!((header.path.as_str(), read), ("data.bin", binary));
+/// let (header, read) = entries.next_file().unwrap().unwrap();
+/// assert_eq!((header.path.as_str(), read), ("text.txt", text));
+/// assert!(entries.next_entry().is_none());
+/// ```
+#[cfg(feature = "snap")]
+pub struct SnappyCompressor {
+    pipeline: ChunkPipeline,
+}
 
-        let mut slices_to_read = &mut io_slices[..];
-        let mut chunks_with_data = threads;
+#[cfg(feature = "snap")]
+impl SnappyCompressor {
+    pub fn new(threads: usize) -> crate::Result<Self> {
+        Ok(Self {
+            pipeline: ChunkPipeline::new(threads)?,
+        })
+    }
+}
 
-        while !slices_to_read.is_empty() {
-            match input.read_vectored(slices_to_read)? {
-                0 => {
-                    chunks_with_data = threads - slices_to_read.len();
-                    break;
-                }
-                n => {
-                    let mut bytes_read = n;
-                    let mut slices_read = 0;
-
-                    for slice in slices_to_read.iter() {
-                        if bytes_read >= slice.len() {
-                            bytes_read -= slice.len();
-                            slices_read += 1;
-                        } else {
-                            break;
-                        }
-                    }
+#[cfg(feature = "snap")]
+impl<W: Write + Send, R: Read> Compressor<W, R> for SnappyCompressor {
+    fn name(&self) -> &'static str {
+        "snappy"
+    }
 
-                    if slices_read > 0 {
-                        slices_to_read = &mut slices_to_read[slices_read..];
-                    }
+    fn set_in_flight(&mut self, chunks: usize) {
+        self.pipeline.in_flight = chunks.max(1);
+    }
 
-                    if bytes_read > 0 && !slices_to_read.is_empty() {
-                        let current_slice_index = threads - slices_to_read.len();
-                        self.input_buffers[current_slice_index].truncate(bytes_read);
-                        chunks_with_data = current_slice_index + 1;
-                        break;
-                    }
-                }
-            }
-        }
+    fn compress(
+        &mut self,
+        input: &mut R,
+        remaining_chunks: usize,
+        chunk_size: u32,
+        chunk_writer: &mut ChunkWriter<&mut W>,
+    ) -> std::io::Result<()> {
+        self.pipeline
+            .compress(input, remaining_chunks, chunk_size, chunk_writer, |data| {
+                Ok(snap::raw::Encoder::new().compress_vec(data)?)
+            })
+    }
+}
 
-        let chunk_writer = Arc::new(Mutex::new(chunk_writer));
+/// Compresses chunks as bzip2 streams at `compression`, levels 1 to 9 pick
+/// the block size from 100 to 900 kB. Slow, but often the best ratio on text,
+/// as long as chunks are at least a block large.
+///
+/// ```
+/// use ataf::{
+///     archive::{read::Archive, write::ArchiveWriter},
+///     compression::{Bzip2Compressor, Bzip2Decompressor, bzip2::Compression},
+///     spec::ArchiveEntryHeader,
+/// };
+///
+/// // spans 7 chunks, the last one partial
+/// let log: Vec<u8> = (0..2_000)
+///     .flat_map(|i| format!("{i:05} INFO request handled in {}ms\n", i % 97).into_bytes())
+///     .collect();
+///
+/// let mut archive = Vec::new();
+/// let compressor = Bzip2Compressor::new(2, Compression::new(1)).unwrap();
+/// let mut writer = ArchiveWriter::new(&mut archive, Box::new(compressor), 10_000).unwrap();
+/// writer.write_entry(ArchiveEntryHeader::file("empty.log", 0), &[][..]).unwrap();
+/// writer.write_entry(ArchiveEntryHeader::file("app.log", log.len() as u64), &log[..]).unwrap();
+/// writer.finish().unwrap();
+///
+/// let mut archive = Archive::new(&archive[..]);
+/// assert_eq!(archive.header().unwrap().compression, "bzip2");
+/// let mut entries = archive.entries(Box::new(Bzip2Decompressor::new(2).unwrap())).unwrap();
+/// let (header, read) = entries.next_file().unwrap().unwrap();
+/// assert_eq!((header.path.as_str(), read), ("empty.log", Vec::new()));
+/// let (header, read) = entries.next_file().unwrap().unwrap();
+/// assert_eq!((header.path.as_str(), read), ("app.log", log));
+/// assert!(entries.next_entry().is_none());
+/// ```
+#[cfg(feature = "bzip2")]
+pub struct Bzip2Compressor {
+    compression: bzip2::Compression,
+    pipeline: ChunkPipeline,
+}
 
-        self.thread_pool.in_place_scope(|scope| {
-            let error = Arc::new(Mutex::new(None));
-
-            for i in 0..chunks_with_data {
-                let input_data = &self.input_buffers[i];
-                let level = self.level;
-                let chunk_writer = Arc::clone(&chunk_writer);
-                let error = Arc::clone(&error);
-
-                scope.spawn(move |_| {
-                    let mut encoder = lz4::EncoderBuilder::new()
-                        .level(level)
-                        .build(Vec::new())
-                        .unwrap();
-                    if let Err(err) = encoder.write_all(input_data) {
-                        *error.lock().unwrap() = Some(err);
-                        return;
-                    }
+#[cfg(feature = "bzip2")]
+impl Bzip2Compressor {
+    pub fn new(threads: usize, compression: bzip2::Compression) -> crate::Result<Self> {
+        Ok(Self {
+            compression,
+            pipeline: ChunkPipeline::new(threads)?,
+        })
+    }
+}
 
-                    match encoder.finish() {
-                        (result, Ok(())) => {
-                            if let Err(err) = chunk_writer.lock().unwrap().write_chunk(&result) {
-                                *error.lock().unwrap() = Some(err);
-                            }
-                        }
-                        (_, Err(err)) => {
-                            *error.lock().unwrap() = Some(err);
-                        }
-                    }
-                });
-            }
+#[cfg(feature = "bzip2")]
+impl<W: Write + Send, R: Read> Compressor<W, R> for Bzip2Compressor {
+    fn name(&self) -> &'static str {
+        "bzip2"
+    }
 
-            if let Some(err) = error.lock().unwrap().take() {
-                return Err(err);
-            }
+    fn set_in_flight(&mut self, chunks: usize) {
+        self.pipeline.in_flight = chunks.max(1);
+    }
+
+    fn levels(&self) -> std::ops::RangeInclusive<u32> {
+        1..=9
+    }
 
-            Ok(())
-        })?;
+    fn set_level(&mut self, level: u32) {
+        self.compression = bzip2::Compression::new(level);
+    }
 
-        Ok(())
+    fn compress(
+        &mut self,
+        input: &mut R,
+        remaining_chunks: usize,
+        chunk_size: u32,
+        chunk_writer: &mut ChunkWriter<&mut W>,
+    ) -> std::io::Result<()> {
+        let compression = self.compression;
+
+        self.pipeline
+            .compress(input, remaining_chunks, chunk_size, chunk_writer, |data| {
+                let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), compression);
+                encoder.write_all(data)?;
+                encoder.finish()
+            })
     }
 }
 
@@ -579,20 +880,19 @@ impl Decompressor for NoDecompressor {
 pub struct Flate2Decompressor {
     threads: usize,
     thread_pool: rayon::ThreadPool,
-    chunk_buffers: Vec<Arc<Mutex<Vec<u8>>>>,
+    chunk_buffers: Vec<Vec<u8>>,
 }
 
 #[cfg(feature = "flate2")]
 impl Flate2Decompressor {
-    pub fn new(threads: usize) -> Self {
-        Self {
+    pub fn new(threads: usize) -> crate::Result<Self> {
+        Ok(Self {
             threads,
             thread_pool: rayon::ThreadPoolBuilder::new()
                 .num_threads(threads)
-                .build()
-                .unwrap(),
+                .build()?,
             chunk_buffers: Vec::new(),
-        }
+        })
     }
 }
 
@@ -608,46 +908,17 @@ impl Decompressor for Flate2Decompressor {
         archive_output: &mut Vec<u8>,
         chunk_size: u32,
     ) -> std::io::Result<()> {
-        if self.chunk_buffers.len() < inputs.len() {
-            self.chunk_buffers.resize_with(inputs.len(), || {
-                Arc::new(Mutex::new(vec![0; chunk_size as usize]))
-            });
-        }
-
-        let inputs_len = inputs.len();
-
-        self.thread_pool.in_place_scope(|scope| {
-            let error = Arc::new(Mutex::new(None));
-
-            for (input, chunk_buffer) in inputs.into_iter().zip(self.chunk_buffers.iter().cloned())
-            {
-                let error = Arc::clone(&error);
-
-                scope.spawn(move |_| {
-                    let mut decoder = flate2::read::ZlibDecoder::new(&input[..]);
-                    let mut chunk_buffer = chunk_buffer.lock().unwrap();
-
-                    match decoder.read_to_end(&mut chunk_buffer) {
-                        Ok(n) => chunk_buffer.truncate(n),
-                        Err(err) => {
-                            *error.lock().unwrap() = Some(err);
-                        }
-                    }
-                });
-            }
-
-            if let Some(err) = error.lock().unwrap().take() {
-                return Err(err);
-            }
-
-            Ok(())
-        })?;
-
-        for chunk_buffer in self.chunk_buffers.iter().take(inputs_len) {
-            archive_output.write_all(&chunk_buffer.lock().unwrap())?;
-        }
-
-        Ok(())
+        decode_chunks(
+            &self.thread_pool,
+            &mut self.chunk_buffers,
+            inputs,
+            archive_output,
+            chunk_size,
+            |input, chunk_buffer| {
+                flate2::read::ZlibDecoder::new(input).read_to_end(chunk_buffer)?;
+                Ok(())
+            },
+        )
     }
 }
 
@@ -655,20 +926,19 @@ impl Decompressor for Flate2Decompressor {
 pub struct BrotliDecompressor {
     threads: usize,
     thread_pool: rayon::ThreadPool,
-    chunk_buffers: Vec<Arc<Mutex<Vec<u8>>>>,
+    chunk_buffers: Vec<Vec<u8>>,
 }
 
 #[cfg(feature = "brotli")]
 impl BrotliDecompressor {
-    pub fn new(threads: usize) -> Self {
-        Self {
+    pub fn new(threads: usize) -> crate::Result<Self> {
+        Ok(Self {
             threads,
             thread_pool: rayon::ThreadPoolBuilder::new()
                 .num_threads(threads)
-                .build()
-                .unwrap(),
+                .build()?,
             chunk_buffers: Vec::new(),
-        }
+        })
     }
 }
 
@@ -684,49 +954,14 @@ impl Decompressor for BrotliDecompressor {
         archive_output: &mut Vec<u8>,
         chunk_size: u32,
     ) -> std::io::Result<()> {
-        if self.chunk_buffers.len() < inputs.len() {
-            self.chunk_buffers.resize_with(inputs.len(), || {
-                Arc::new(Mutex::new(vec![0; chunk_size as usize]))
-            });
-        }
-
-        let inputs_len = inputs.len();
-
-        self.thread_pool.in_place_scope(|scope| {
-            let error = Arc::new(Mutex::new(None));
-
-            for (input, chunk_buffer) in inputs.into_iter().zip(self.chunk_buffers.iter().cloned())
-            {
-                let error = Arc::clone(&error);
-
-                scope.spawn(move |_| {
-                    let mut chunk_buffer = chunk_buffer.lock().unwrap();
-                    let mut write_counter = WriteCounter::new(&mut *chunk_buffer);
-
-                    if let Err(err) = brotli::BrotliDecompress(
-                        &mut std::io::Cursor::new(input),
-                        &mut write_counter,
-                    ) {
-                        *error.lock().unwrap() = Some(err);
-                    };
-
-                    let n = write_counter.into_written();
-                    chunk_buffer.truncate(n);
-                });
-            }
-
-            if let Some(err) = error.lock().unwrap().take() {
-                return Err(err);
-            }
-
-            Ok(())
-        })?;
-
-        for chunk_buffer in self.chunk_buffers.iter().take(inputs_len) {
-            archive_output.write_all(&chunk_buffer.lock().unwrap())?;
-        }
-
-        Ok(())
+        decode_chunks(
+            &self.thread_pool,
+            &mut self.chunk_buffers,
+            inputs,
+            archive_output,
+            chunk_size,
+            |input, chunk_buffer| brotli::BrotliDecompress(&mut &input[..], chunk_buffer),
+        )
     }
 }
 
@@ -734,20 +969,19 @@ impl Decompressor for BrotliDecompressor {
 pub struct Lz4Decompressor {
     threads: usize,
     thread_pool: rayon::ThreadPool,
-    chunk_buffers: Vec<Arc<Mutex<Vec<u8>>>>,
+    chunk_buffers: Vec<Vec<u8>>,
 }
 
 #[cfg(feature = "lz4")]
 impl Lz4Decompressor {
-    pub fn new(threads: usize) -> Self {
-        Self {
+    pub fn new(threads: usize) -> crate::Result<Self> {
+        Ok(Self {
             threads,
             thread_pool: rayon::ThreadPoolBuilder::new()
                 .num_threads(threads)
-                .build()
-                .unwrap(),
+                .build()?,
             chunk_buffers: Vec::new(),
-        }
+        })
     }
 }
 
@@ -763,45 +997,386 @@ impl Decompressor for Lz4Decompressor {
         archive_output: &mut Vec<u8>,
         chunk_size: u32,
     ) -> std::io::Result<()> {
-        if self.chunk_buffers.len() < inputs.len() {
-            self.chunk_buffers.resize_with(inputs.len(), || {
-                Arc::new(Mutex::new(vec![0; chunk_size as usize]))
-            });
-        }
+        decode_chunks(
+            &self.thread_pool,
+            &mut self.chunk_buffers,
+            inputs,
+            archive_output,
+            chunk_size,
+            |input, chunk_buffer| {
+                lz4::Decoder::new(input)?.read_to_end(chunk_buffer)?;
+                Ok(())
+            },
+        )
+    }
+}
 
-        let inputs_len = inputs.len();
+/// Decodes raw snappy chunks on a thread pool. The length a chunk declares is
+/// checked against the chunk size before its output is allocated.
+///
+/// ```
+/// use ataf::compression::{Decompressor, SnappyDecompressor};
+///
+/// // claims to decompress to 4 GiB
+/// let hostile = vec![0xff, 0xff, 0xff, 0xff, 0x0f];
+///
+/// let mut output = Vec::new();
+/// let mut decompressor = SnappyDecompressor::new(2).unwrap();
+/// assert!(decompressor.decompress(vec![hostile], &mut output, 1024).is_err());
+/// assert!(output.is_empty());
+/// ```
+#[cfg(feature = "snap")]
+pub struct SnappyDecompressor {
+    threads: usize,
+    thread_pool: rayon::ThreadPool,
+    chunk_buffers: Vec<Vec<u8>>,
+}
 
-        self.thread_pool.in_place_scope(|scope| {
-            let error = Arc::new(Mutex::new(None));
+#[cfg(feature = "snap")]
+impl SnappyDecompressor {
+    pub fn new(threads: usize) -> crate::Result<Self> {
+        Ok(Self {
+            threads,
+            thread_pool: rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()?,
+            chunk_buffers: Vec::new(),
+        })
+    }
+}
 
-            for (input, chunk_buffer) in inputs.into_iter().zip(self.chunk_buffers.iter().cloned())
-            {
-                let error = Arc::clone(&error);
+#[cfg(feature = "snap")]
+impl Decompressor for SnappyDecompressor {
+    fn decompress_inputs(&mut self) -> usize {
+        self.threads
+    }
 
-                scope.spawn(move |_| {
-                    let mut decoder = lz4::Decoder::new(&input[..]).unwrap();
-                    let mut chunk_buffer = chunk_buffer.lock().unwrap();
+    fn decompress(
+        &mut self,
+        inputs: Vec<Vec<u8>>,
+        archive_output: &mut Vec<u8>,
+        chunk_size: u32,
+    ) -> std::io::Result<()> {
+        decode_chunks(
+            &self.thread_pool,
+            &mut self.chunk_buffers,
+            inputs,
+            archive_output,
+            chunk_size,
+            |input, chunk_buffer| {
+                // raw chunks start with their decompressed length, which is
+                // checked before anything is allocated for it
+                let length = snap::raw::decompress_len(input)?;
+                if length > chunk_size as usize {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("chunk decompresses to {length} bytes, more than the chunk size"),
+                    ));
+                }
 
-                    match decoder.read_to_end(&mut chunk_buffer) {
-                        Ok(n) => chunk_buffer.truncate(n),
-                        Err(err) => {
-                            *error.lock().unwrap() = Some(err);
-                        }
-                    }
-                });
-            }
+                chunk_buffer.resize(length, 0);
+                snap::raw::Decoder::new().decompress(input, chunk_buffer)?;
+                Ok(())
+            },
+        )
+    }
+}
 
-            if let Some(err) = error.lock().unwrap().take() {
-                return Err(err);
-            }
+/// Decodes bzip2 chunks on a thread pool. A chunk that fails to decode fails
+/// the whole call, whichever thread it was decoded on.
+///
+/// ```
+/// use ataf::compression::{Bzip2Decompressor, Decompressor, bzip2};
+/// use std::io::Write;
+///
+/// let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::best());
+/// encoder.write_all(b"chunk").unwrap();
+/// let chunk = encoder.finish().unwrap();
+///
+/// let mut decompressor = Bzip2Decompressor::new(2).unwrap();
+/// let mut output = Vec::new();
+/// decompressor.decompress(vec![chunk.clone(), chunk.clone()], &mut output, 1024).unwrap();
+/// assert_eq!(output, b"chunkchunk");
+///
+/// let corrupt = b"not a bzip2 stream".to_vec();
+/// let mut output = Vec::new();
+/// let inputs = vec![chunk.clone(), corrupt, chunk];
+/// assert!(decompressor.decompress(inputs, &mut output, 1024).is_err());
+/// assert!(output.is_empty());
+/// ```
+#[cfg(feature = "bzip2")]
+pub struct Bzip2Decompressor {
+    threads: usize,
+    thread_pool: rayon::ThreadPool,
+    chunk_buffers: Vec<Vec<u8>>,
+}
+
+#[cfg(feature = "bzip2")]
+impl Bzip2Decompressor {
+    pub fn new(threads: usize) -> crate::Result<Self> {
+        Ok(Self {
+            threads,
+            thread_pool: rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()?,
+            chunk_buffers: Vec::new(),
+        })
+    }
+}
+
+#[cfg(feature = "bzip2")]
+impl Decompressor for Bzip2Decompressor {
+    fn decompress_inputs(&mut self) -> usize {
+        self.threads
+    }
 
-            Ok(())
-        })?;
+    fn decompress(
+        &mut self,
+        inputs: Vec<Vec<u8>>,
+        archive_output: &mut Vec<u8>,
+        chunk_size: u32,
+    ) -> std::io::Result<()> {
+        decode_chunks(
+            &self.thread_pool,
+            &mut self.chunk_buffers,
+            inputs,
+            archive_output,
+            chunk_size,
+            |input, chunk_buffer| {
+                bzip2::read::BzDecoder::new(input).read_to_end(chunk_buffer)?;
+                Ok(())
+            },
+        )
+    }
+}
 
-        for chunk_buffer in self.chunk_buffers.iter().take(inputs_len) {
-            archive_output.write_all(&chunk_buffer.lock().unwrap())?;
+type MakeCompressor<W, R> = Box<dyn Fn(usize) -> crate::Result<Box<dyn Compressor<W, R>>>>;
+type MakeDecompressor = Box<dyn Fn(usize) -> crate::Result<Box<dyn Decompressor>>>;
+
+/// Maps the compression names stored in archive headers to constructors for
+/// their compressor and decompressor, which are given the number of threads
+/// to use and fail if those can't be started. The default registry has every
+/// format built into this crate with its default level, further formats can
+/// be registered under any name.
+///
+/// ```
+/// use ataf::{
+///     archive::{read::Archive, write::{ArchiveOptions, ArchiveWriter, ChunkWriter}},
+///     compression::{CompressionRegistry, Compressor, Decompressor},
+///     spec::ArchiveEntryHeader,
+/// };
+/// use std::io::Read;
+///
+/// // stores every byte inverted
+/// struct Invert;
+///
+/// impl<R: Read> Compressor<Vec<u8>, R> for Invert {
+///     fn name(&self) -> &'static str {
+///         "invert"
+///     }
+///
+///     fn compress(
+///         &mut self,
+///         input: &mut R,
+///         _remaining_chunks: usize,
+///         chunk_size: u32,
+///         chunk_writer: &mut ChunkWriter<&mut Vec<u8>>,
+///     ) -> std::io::Result<()> {
+///         let mut chunk = Vec::new();
+///         input.take(chunk_size as u64).read_to_end(&mut chunk)?;
+///         chunk.iter_mut().for_each(|byte| *byte = !*byte);
+///         chunk_writer.write_chunk(&chunk)
+///     }
+/// }
+///
+/// impl Decompressor for Invert {
+///     fn decompress_inputs(&mut self) -> usize {
+///         1
+///     }
+///
+///     fn decompress(&mut self, inputs: Vec<Vec<u8>>, output: &mut Vec<u8>, _: u32) -> std::io::Result<()> {
+///         output.extend(inputs.concat().iter().map(|byte| !byte));
+///         Ok(())
+///     }
+/// }
+///
+/// let mut registry = CompressionRegistry::<Vec<u8>, &[u8]>::default();
+/// registry.register("invert", |_| Ok(Box::new(Invert)), |_| Ok(Box::new(Invert)));
+/// assert!(registry.names().any(|name| name == "none"));
+///
+/// let header = ArchiveEntryHeader::file("note.txt", 6);
+///
+/// let compressor = registry.compressor("invert", 1).unwrap();
+/// let mut writer =
+///     ArchiveWriter::with_options(Vec::new(), compressor, 1024, ArchiveOptions::default()).unwrap();
+/// writer.write_entry(header, &b"secret"[..]).unwrap();
+/// let bytes = writer.finish().unwrap();
+/// assert!(!bytes.windows(6).any(|window| window == b"secret"));
+///
+/// let mut archive = Archive::new(&bytes[..]);
+/// let mut entries = archive.entries_from(&registry, 1).unwrap();
+/// let (_, data) = entries.next_file().unwrap().unwrap();
+/// assert_eq!(data, b"secret");
+///
+/// let mut archive = Archive::new(&bytes[..]);
+/// let empty = CompressionRegistry::<Vec<u8>, &[u8]>::empty();
+/// assert!(matches!(
+///     archive.entries_from(&empty, 1),
+///     Err(ataf::Error::UnsupportedCompression(_))
+/// ));
+/// ```
+pub struct CompressionRegistry<W: Write + Send, R: Read> {
+    formats: Vec<(String, MakeCompressor<W, R>, MakeDecompressor)>,
+}
+
+impl<W: Write + Send, R: Read> CompressionRegistry<W, R> {
+    /// A registry without any formats, not even `none`.
+    pub fn empty() -> Self {
+        Self {
+            formats: Vec::new(),
         }
+    }
 
-        Ok(())
+    /// Adds a format, replacing any registered under the same name. `name`
+    /// has to match the `name` of the compressors `make_compressor` returns,
+    /// since that is what archive headers store.
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        make_compressor: impl Fn(usize) -> crate::Result<Box<dyn Compressor<W, R>>> + 'static,
+        make_decompressor: impl Fn(usize) -> crate::Result<Box<dyn Decompressor>> + 'static,
+    ) {
+        let name = name.into();
+        self.formats
+            .retain(|(registered, _, _)| *registered != name);
+        self.formats
+            .push((name, Box::new(make_compressor), Box::new(make_decompressor)));
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.formats.iter().map(|(name, _, _)| name.as_str())
+    }
+
+    pub fn compressor(
+        &self,
+        name: &str,
+        threads: usize,
+    ) -> crate::Result<Box<dyn Compressor<W, R>>> {
+        let (_, make_compressor, _) = self
+            .formats
+            .iter()
+            .find(|(registered, _, _)| registered == name)
+            .ok_or_else(|| crate::Error::UnsupportedCompression(String::from(name)))?;
+
+        make_compressor(threads)
+    }
+
+    /// Starts the decompressor registered under `name`. For a missing format
+    /// the error says how it could be read: the feature to build in for
+    /// built-in formats, otherwise that the archive may come from a newer
+    /// version or use a custom format.
+    ///
+    /// ```
+    /// use ataf::compression::{CompressionRegistry, missing_feature};
+    ///
+    /// let registry = CompressionRegistry::<Vec<u8>, &[u8]>::default();
+    /// assert!(registry.decompressor("none", 1).is_ok());
+    ///
+    /// let err = registry.decompressor("zstd", 1).err().unwrap();
+    /// assert!(matches!(&err, ataf::Error::UnsupportedCompression(name) if name == "zstd"));
+    /// assert!(err.to_string().contains("newer version of ataf"));
+    ///
+    /// // formats that are built in only lack their feature, never a newer version
+    /// for name in ["flate2", "brotli", "lz4", "snappy", "bzip2"] {
+    ///     if let Some(feature) = missing_feature(name) {
+    ///         let err = registry.decompressor(name, 1).err().unwrap();
+    ///         assert!(err.to_string().contains(&format!("--features {feature}")));
+    ///     }
+    /// }
+    /// ```
+    pub fn decompressor(&self, name: &str, threads: usize) -> crate::Result<Box<dyn Decompressor>> {
+        let (_, _, make_decompressor) = self
+            .formats
+            .iter()
+            .find(|(registered, _, _)| registered == name)
+            .ok_or_else(|| crate::Error::UnsupportedCompression(String::from(name)))?;
+
+        make_decompressor(threads)
+    }
+}
+
+/// The cargo feature that builds in the compression format `name`, when it is
+/// one of the built-in formats this build leaves out.
+pub fn missing_feature(name: &str) -> Option<&'static str> {
+    match name {
+        #[cfg(not(feature = "flate2"))]
+        "flate2" => Some("flate2"),
+        #[cfg(not(feature = "brotli"))]
+        "brotli" => Some("brotli"),
+        #[cfg(not(feature = "lz4"))]
+        "lz4" => Some("lz4"),
+        #[cfg(not(feature = "snap"))]
+        "snappy" => Some("snap"),
+        #[cfg(not(feature = "bzip2"))]
+        "bzip2" => Some("bzip2"),
+        _ => None,
+    }
+}
+
+impl<W: Write + Send, R: Read> Default for CompressionRegistry<W, R> {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(
+            "none",
+            |_| Ok(Box::new(NoCompressor::new())),
+            |_| Ok(Box::new(NoDecompressor)),
+        );
+        #[cfg(feature = "flate2")]
+        registry.register(
+            "flate2",
+            |threads| {
+                Ok(Box::new(Flate2Compressor::new(
+                    threads,
+                    flate2::Compression::best(),
+                )?))
+            },
+            |threads| Ok(Box::new(Flate2Decompressor::new(threads)?)),
+        );
+        #[cfg(feature = "brotli")]
+        registry.register(
+            "brotli",
+            |threads| {
+                Ok(Box::new(BrotliCompressor::new(
+                    threads,
+                    brotli::enc::BrotliEncoderParams::default(),
+                )?))
+            },
+            |threads| Ok(Box::new(BrotliDecompressor::new(threads)?)),
+        );
+        #[cfg(feature = "lz4")]
+        registry.register(
+            "lz4",
+            |threads| Ok(Box::new(Lz4Compressor::new(threads, 9)?)),
+            |threads| Ok(Box::new(Lz4Decompressor::new(threads)?)),
+        );
+        #[cfg(feature = "snap")]
+        registry.register(
+            "snappy",
+            |threads| Ok(Box::new(SnappyCompressor::new(threads)?)),
+            |threads| Ok(Box::new(SnappyDecompressor::new(threads)?)),
+        );
+        #[cfg(feature = "bzip2")]
+        registry.register(
+            "bzip2",
+            |threads| {
+                Ok(Box::new(Bzip2Compressor::new(
+                    threads,
+                    bzip2::Compression::best(),
+                )?))
+            },
+            |threads| Ok(Box::new(Bzip2Decompressor::new(threads)?)),
+        );
+
+        registry
     }
 }
@@ -0,0 +1,35 @@
+/// The CRC-32 (IEEE) lookup table, built at compile time.
+const TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+};
+
+/// The CRC-32 checksum used by zlib, gzip and zip, as stored for chunks.
+///
+/// ```
+/// assert_eq!(ataf::crc32::crc32(b"123456789"), 0xcbf43926);
+/// ```
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, byte| {
+        (crc >> 8) ^ TABLE[((crc ^ *byte as u32) & 0xff) as usize]
+    })
+}
@@ -0,0 +1,467 @@
+use crate::{
+    Error,
+    spec::{Deserialize, ENCRYPTION_MAGIC, Serialize},
+};
+use aes_gcm::{
+    Aes256Gcm, KeyInit, Nonce,
+    aead::{Aead, OsRng, Payload, rand_core::RngCore},
+};
+use std::io::{Read, Write};
+use zeroize::Zeroizing;
+
+/// The version of the encryption layer, independent of the archive version.
+pub const ENCRYPTION_VERSION: u8 = 1;
+
+/// The bytes of plaintext in every frame but the last, which is shorter.
+pub const FRAME_SIZE: usize = 64 * 1024;
+
+/// The most memory an archive may ask Argon2 for, in KiB, so a crafted header
+/// can't make reading it allocate without bounds.
+pub const MAX_KDF_MEMORY: u32 = 4 * 1024 * 1024;
+
+const TAG_SIZE: usize = 16;
+const NONCE_PREFIX_SIZE: usize = 7;
+const SALT_SIZE: usize = 16;
+
+const KDF_NONE: u8 = 0;
+const KDF_ARGON2ID: u8 = 1;
+
+/// How the key of an archive encrypted with a passphrase is derived, with
+/// Argon2id. Stored in the header, so reading only needs the passphrase.
+///
+/// ```
+/// use ataf::encryption::Argon2Parameters;
+///
+/// let hex = |key: &[u8; 32]| key.iter().map(|byte| format!("{byte:02x}")).collect::<String>();
+/// let parameters = Argon2Parameters {
+///     salt: std::array::from_fn(|i| i as u8),
+///     memory: 256,
+///     iterations: 3,
+///     parallelism: 4,
+/// };
+/// let key = parameters.derive_key(b"correct horse battery staple").unwrap();
+/// assert_eq!(
+///     hex(&key),
+///     "9792635aae9d41586e2b4385924b030bf1ec52f80feda58b7e47c4ca36cf88f0"
+/// );
+///
+/// // the same passphrase and salt always give the same key, another salt doesn't
+/// assert_eq!(parameters.derive_key(b"correct horse battery staple").unwrap(), key);
+/// let other = Argon2Parameters {
+///     salt: std::array::from_fn(|i| i as u8 + 1),
+///     ..parameters.clone()
+/// };
+/// assert_eq!(
+///     hex(&other.derive_key(b"correct horse battery staple").unwrap()),
+///     "a77f1b9ae906c706d7f1b54709a3902d110080a8e33a653b83b20a44b8e003ce"
+/// );
+///
+/// // too little memory for the parallelism
+/// assert!(Argon2Parameters { memory: 4, ..parameters }.derive_key(b"x").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Argon2Parameters {
+    pub salt: [u8; SALT_SIZE],
+    /// In KiB.
+    pub memory: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Argon2Parameters {
+    /// The second recommendation of RFC 9106, for machines that can't spare
+    /// gigabytes of memory.
+    pub const DEFAULT_MEMORY: u32 = 64 * 1024;
+    pub const DEFAULT_ITERATIONS: u32 = 3;
+    pub const DEFAULT_PARALLELISM: u32 = 4;
+
+    /// Parameters with a fresh random salt.
+    pub fn new(memory: u32, iterations: u32, parallelism: u32) -> Self {
+        let mut salt = [0; SALT_SIZE];
+        OsRng.fill_bytes(&mut salt);
+
+        Self {
+            salt,
+            memory,
+            iterations,
+            parallelism,
+        }
+    }
+
+    /// The AES-256 key for `passphrase`, erased from memory once dropped.
+    pub fn derive_key(&self, passphrase: &[u8]) -> crate::Result<Zeroizing<[u8; 32]>> {
+        if self.memory > MAX_KDF_MEMORY {
+            return Err(Error::InvalidData(format!(
+                "the key derivation asks for {} KiB of memory, at most {} KiB are allowed",
+                self.memory, MAX_KDF_MEMORY
+            )));
+        }
+
+        let invalid =
+            |err: argon2::Error| Error::InvalidData(format!("invalid key derivation: {err}"));
+        let params = argon2::Params::new(self.memory, self.iterations, self.parallelism, Some(32))
+            .map_err(invalid)?;
+
+        let mut key = Zeroizing::new([0; 32]);
+        argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+            .hash_password_into(passphrase, &self.salt, key.as_mut())
+            .map_err(invalid)?;
+
+        Ok(key)
+    }
+}
+
+impl Default for Argon2Parameters {
+    fn default() -> Self {
+        Self::new(
+            Self::DEFAULT_MEMORY,
+            Self::DEFAULT_ITERATIONS,
+            Self::DEFAULT_PARALLELISM,
+        )
+    }
+}
+
+/// Stored in front of the encrypted archive. Its serialized bytes are
+/// authenticated with every frame, so changing any of them fails decryption.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncryptionHeader {
+    /// Random per archive, completed by the frame counter and a flag marking
+    /// the last frame to the 12 byte nonce of each frame.
+    pub nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+    /// Set when the key is derived from a passphrase, `None` for a key that
+    /// is given as is.
+    pub kdf: Option<Argon2Parameters>,
+}
+
+impl EncryptionHeader {
+    /// A header with a fresh random nonce prefix, for a key given as is.
+    pub fn new() -> Self {
+        let mut nonce_prefix = [0; NONCE_PREFIX_SIZE];
+        OsRng.fill_bytes(&mut nonce_prefix);
+
+        Self {
+            nonce_prefix,
+            kdf: None,
+        }
+    }
+
+    fn nonce(&self, counter: u32, last: bool) -> [u8; 12] {
+        let mut nonce = [0; 12];
+        nonce[..NONCE_PREFIX_SIZE].copy_from_slice(&self.nonce_prefix);
+        nonce[NONCE_PREFIX_SIZE..11].copy_from_slice(&counter.to_be_bytes());
+        nonce[11] = last as u8;
+
+        nonce
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.serialize(&mut bytes)
+            .expect("writing to a Vec doesn't fail");
+
+        bytes
+    }
+}
+
+impl Default for EncryptionHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Serialize for EncryptionHeader {
+    fn serialize(&self, mut output: impl Write) -> crate::Result<()> {
+        output.write_all(&ENCRYPTION_MAGIC)?;
+        output.write_all(&[ENCRYPTION_VERSION])?;
+        output.write_all(&self.nonce_prefix)?;
+
+        match &self.kdf {
+            None => output.write_all(&[KDF_NONE])?,
+            Some(kdf) => {
+                output.write_all(&[KDF_ARGON2ID])?;
+                output.write_all(&kdf.salt)?;
+                output.write_all(&kdf.memory.to_le_bytes())?;
+                output.write_all(&kdf.iterations.to_le_bytes())?;
+                output.write_all(&kdf.parallelism.to_le_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Deserialize for EncryptionHeader {
+    fn deserialize(mut input: impl Read) -> crate::Result<Self> {
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic)?;
+        if magic != ENCRYPTION_MAGIC {
+            return Err(Error::InvalidData(String::from(
+                "the input is not an encrypted archive",
+            )));
+        }
+
+        let mut version = [0; 1];
+        input.read_exact(&mut version)?;
+        if version[0] != ENCRYPTION_VERSION {
+            return Err(Error::InvalidData(format!(
+                "unsupported encryption version {}, version {} can be read",
+                version[0], ENCRYPTION_VERSION
+            )));
+        }
+
+        let mut nonce_prefix = [0; NONCE_PREFIX_SIZE];
+        input.read_exact(&mut nonce_prefix)?;
+
+        let mut kdf = [0; 1];
+        input.read_exact(&mut kdf)?;
+        let kdf = match kdf[0] {
+            KDF_NONE => None,
+            KDF_ARGON2ID => {
+                let mut salt = [0; SALT_SIZE];
+                input.read_exact(&mut salt)?;
+
+                let mut read_u32 = || -> crate::Result<u32> {
+                    let mut bytes = [0; 4];
+                    input.read_exact(&mut bytes)?;
+                    Ok(u32::from_le_bytes(bytes))
+                };
+
+                Some(Argon2Parameters {
+                    salt,
+                    memory: read_u32()?,
+                    iterations: read_u32()?,
+                    parallelism: read_u32()?,
+                })
+            }
+            kdf => {
+                return Err(Error::InvalidData(format!(
+                    "unknown key derivation {kdf} in the encryption header"
+                )));
+            }
+        };
+
+        Ok(Self { nonce_prefix, kdf })
+    }
+}
+
+fn frame_error(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// Encrypts everything written to it with AES-256-GCM, in frames of
+/// `FRAME_SIZE` bytes that are each authenticated on their own, so the
+/// archive can be decrypted while it streams. `finish` has to be called to
+/// write the last frame, without it the archive reads as truncated.
+///
+/// ```
+/// use ataf::{
+///     encryption::{DecryptingReader, EncryptingWriter, EncryptionHeader, FRAME_SIZE},
+///     spec::Deserialize,
+/// };
+/// use std::io::{Read, Write};
+///
+/// let key = [7; 32];
+/// let data: Vec<u8> = (0..FRAME_SIZE * 2 + 100).map(|i| i as u8).collect();
+///
+/// // exactly full frames still end with a last one, which is empty
+/// let mut writer = EncryptingWriter::new(Vec::new(), &key, EncryptionHeader::new()).unwrap();
+/// writer.write_all(&data[..FRAME_SIZE]).unwrap();
+/// let full = writer.finish().unwrap();
+/// assert_eq!(full.len(), 13 + FRAME_SIZE + 16 + 16);
+///
+/// let mut writer = EncryptingWriter::new(Vec::new(), &key, EncryptionHeader::new()).unwrap();
+/// writer.write_all(&data).unwrap();
+/// let encrypted = writer.finish().unwrap();
+///
+/// let read = |encrypted: &[u8], key: &[u8; 32]| {
+///     let mut input = encrypted;
+///     let header = EncryptionHeader::deserialize(&mut input).unwrap();
+///     let mut decrypted = Vec::new();
+///     DecryptingReader::new(input, key, header)
+///         .read_to_end(&mut decrypted)
+///         .map(|_| decrypted)
+/// };
+/// assert_eq!(read(&encrypted, &key).unwrap(), data);
+///
+/// // a wrong key, a flipped bit and a missing last frame all fail
+/// assert!(read(&encrypted, &[8; 32]).is_err());
+/// let mut tampered = encrypted.clone();
+/// tampered[100] ^= 1;
+/// assert!(read(&tampered, &key).is_err());
+/// assert!(read(&encrypted[..encrypted.len() - 116], &key).is_err());
+/// ```
+pub struct EncryptingWriter<W: Write> {
+    writer: W,
+    cipher: Aes256Gcm,
+    header: EncryptionHeader,
+    header_bytes: Vec<u8>,
+    counter: u32,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    /// Writes the header right away.
+    pub fn new(mut writer: W, key: &[u8; 32], header: EncryptionHeader) -> crate::Result<Self> {
+        let header_bytes = header.to_bytes();
+        writer.write_all(&header_bytes)?;
+
+        Ok(Self {
+            writer,
+            cipher: Aes256Gcm::new_from_slice(key).expect("the key is 32 bytes"),
+            header,
+            header_bytes,
+            counter: 0,
+            buffer: Vec::with_capacity(FRAME_SIZE),
+        })
+    }
+
+    fn write_frame(&mut self, last: bool) -> std::io::Result<()> {
+        let nonce = self.header.nonce(self.counter, last);
+        let frame = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &self.buffer,
+                    aad: &self.header_bytes,
+                },
+            )
+            .map_err(|_| frame_error(String::from("encrypting a frame failed")))?;
+        self.writer.write_all(&frame)?;
+
+        self.buffer.clear();
+        self.counter = self.counter.checked_add(1).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "too much data for one encrypted archive",
+            )
+        })?;
+
+        Ok(())
+    }
+
+    /// Writes the last frame, which is shorter than the others and may be
+    /// empty, and returns the inner writer.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        if self.buffer.len() == FRAME_SIZE {
+            self.write_frame(false)?;
+        }
+        self.write_frame(true)?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // a full frame is only written once more data follows, since the last
+        // frame has to be shorter
+        if self.buffer.len() == FRAME_SIZE {
+            self.write_frame(false)?;
+        }
+
+        let length = buf.len().min(FRAME_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..length]);
+
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Decrypts what `EncryptingWriter` wrote, given the header it was read with.
+/// Every frame is authenticated before any of it is returned, an input that
+/// ends without the last frame is an error rather than a shorter archive.
+pub struct DecryptingReader<R: Read> {
+    reader: R,
+    cipher: Aes256Gcm,
+    header: EncryptionHeader,
+    header_bytes: Vec<u8>,
+    counter: u32,
+    frame: Vec<u8>,
+    offset: usize,
+    finished: bool,
+}
+
+impl<R: Read> DecryptingReader<R> {
+    pub fn new(reader: R, key: &[u8; 32], header: EncryptionHeader) -> Self {
+        Self {
+            reader,
+            cipher: Aes256Gcm::new_from_slice(key).expect("the key is 32 bytes"),
+            header_bytes: header.to_bytes(),
+            header,
+            counter: 0,
+            frame: Vec::new(),
+            offset: 0,
+            finished: false,
+        }
+    }
+
+    fn read_frame(&mut self) -> std::io::Result<()> {
+        let mut encrypted = Vec::with_capacity(FRAME_SIZE + TAG_SIZE);
+        (&mut self.reader)
+            .take((FRAME_SIZE + TAG_SIZE) as u64)
+            .read_to_end(&mut encrypted)?;
+
+        // only the last frame is shorter than a full one
+        let last = encrypted.len() < FRAME_SIZE + TAG_SIZE;
+        if last && encrypted.len() < TAG_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "the encrypted archive ends before its last frame",
+            ));
+        }
+
+        let nonce = self.header.nonce(self.counter, last);
+        self.frame = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &encrypted,
+                    aad: &self.header_bytes,
+                },
+            )
+            .map_err(|_| {
+                frame_error(format!(
+                    "encrypted frame {} failed authentication, the key is wrong or the archive was modified",
+                    self.counter
+                ))
+            })?;
+        self.offset = 0;
+        self.finished = last;
+        // a wrapped counter would reuse nonces, no writer gets that far
+        self.counter = self.counter.checked_add(1).ok_or_else(|| {
+            frame_error(String::from(
+                "the encrypted archive has more frames than a writer can produce",
+            ))
+        })?;
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.offset == self.frame.len() {
+            if self.finished || buf.is_empty() {
+                return Ok(0);
+            }
+
+            self.read_frame()?;
+        }
+
+        let length = buf.len().min(self.frame.len() - self.offset);
+        buf[..length].copy_from_slice(&self.frame[self.offset..self.offset + length]);
+        self.offset += length;
+
+        Ok(length)
+    }
+}
@@ -0,0 +1,134 @@
+use std::path::PathBuf;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Everything that can go wrong reading, writing or extracting archives.
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    /// The input starts neither with `ARCHIVE_MAGIC` nor with the version of
+    /// an archive from before the magic, so it's no ataf archive at all.
+    BadMagic,
+    UnsupportedVersion(u32),
+    /// A string field of a header isn't valid UTF-8, the field is named.
+    InvalidUtf8(&'static str),
+    PathTooLong(u64),
+    /// A path or symlink target that would lead outside of the output
+    /// directory when extracted.
+    UnsafePath(PathBuf),
+    UnsupportedCompression(String),
+    ChecksumMismatch {
+        path: String,
+        chunk: u64,
+    },
+    /// The archive ends at byte `offset`, within the data of the entry at
+    /// `path`.
+    Truncated {
+        path: String,
+        offset: u64,
+    },
+    /// Any other malformed data, described by the message.
+    InvalidData(String),
+    /// The writer was used in a way its format options don't allow, like
+    /// writing single entries to a solid archive.
+    InvalidOperation(&'static str),
+}
+
+impl Error {
+    /// The closest io error kind, for callers that handle both alike.
+    pub fn kind(&self) -> std::io::ErrorKind {
+        match self {
+            Self::Io(err) => err.kind(),
+            Self::UnsupportedCompression(_) | Self::InvalidOperation(_) => {
+                std::io::ErrorKind::Unsupported
+            }
+            Self::Truncated { .. } => std::io::ErrorKind::UnexpectedEof,
+            _ => std::io::ErrorKind::InvalidData,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => err.fmt(f),
+            Self::BadMagic => write!(
+                f,
+                "not an ataf archive, the input doesn't start with the ATAF magic"
+            ),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported archive version {version}, versions up to {} can be read",
+                crate::spec::ARCHIVE_VERSION
+            ),
+            Self::InvalidUtf8(field) => write!(f, "invalid UTF-8 in {field}"),
+            Self::PathTooLong(length) => write!(
+                f,
+                "entry path of {length} bytes exceeds the maximum of {}",
+                crate::spec::MAX_PATH_LENGTH
+            ),
+            Self::UnsafePath(path) => write!(
+                f,
+                "{} leads outside of the output directory",
+                path.display()
+            ),
+            Self::UnsupportedCompression(compression) => {
+                match crate::compression::missing_feature(compression) {
+                    Some(feature) => write!(
+                        f,
+                        "unsupported compression format: {compression}, this build of ataf leaves it out, rebuild it with `--features {feature}`"
+                    ),
+                    None => write!(
+                        f,
+                        "unsupported compression format: {compression}, the archive may come from a newer version of ataf or use a custom format"
+                    ),
+                }
+            }
+            Self::ChecksumMismatch { path, chunk } => {
+                write!(f, "checksum mismatch in chunk {chunk} of {path}")
+            }
+            Self::Truncated { path, offset } => write!(
+                f,
+                "the archive is cut off at byte {offset} within the data of {path}"
+            ),
+            Self::InvalidData(message) => f.write_str(message),
+            Self::InvalidOperation(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        // errors passed through `Read` impls come back out unwrapped
+        if err.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            return *err.into_inner().unwrap().downcast::<Error>().unwrap();
+        }
+
+        Self::Io(err)
+    }
+}
+
+/// Thread pools that can't be started, usually as no threads are left.
+impl From<rayon::ThreadPoolBuildError> for Error {
+    fn from(err: rayon::ThreadPoolBuildError) -> Self {
+        Self::Io(std::io::Error::other(err))
+    }
+}
+
+impl From<Error> for std::io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(err) => err,
+            err => std::io::Error::new(err.kind(), err),
+        }
+    }
+}
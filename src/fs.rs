@@ -0,0 +1,93 @@
+use std::path::Path;
+
+#[cfg(target_os = "linux")]
+const FS_IMMUTABLE_FL: libc::c_int = 0x00000010;
+#[cfg(target_os = "linux")]
+const FS_APPEND_FL: libc::c_int = 0x00000020;
+
+/// Reads the immutable and append-only flags of a file or directory. Returns
+/// `None` when neither is set or the filesystem does not support flags.
+#[cfg(target_os = "linux")]
+pub fn get_flags(path: &Path) -> Option<u32> {
+    use std::os::fd::AsRawFd;
+
+    let file = std::fs::File::open(path).ok()?;
+    let mut flags: libc::c_int = 0;
+
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags) } != 0 {
+        return None;
+    }
+
+    match flags & (FS_IMMUTABLE_FL | FS_APPEND_FL) {
+        0 => None,
+        flags => Some(flags as u32),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn get_flags(_path: &Path) -> Option<u32> {
+    None
+}
+
+/// Adds the stored flags to a file or directory, this usually requires root.
+#[cfg(target_os = "linux")]
+pub fn set_flags(path: &Path, flags: u32) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let file = std::fs::File::open(path)?;
+    let mut current: libc::c_int = 0;
+
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut current) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let flags = current | (flags as libc::c_int & (FS_IMMUTABLE_FL | FS_APPEND_FL));
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_SETFLAGS, &flags) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_flags(_path: &Path, _flags: u32) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "file flags are not supported on this platform",
+    ))
+}
+
+/// The umask of the process, which can only be read by setting it.
+#[cfg(target_family = "unix")]
+pub fn umask() -> u32 {
+    let umask = unsafe { libc::umask(0) };
+    unsafe { libc::umask(umask) };
+
+    umask as u32
+}
+
+/// Guesses whether a file is meant to be executed on Unix, since Windows has no
+/// exec bit: by extension, or by a `#!` line at the start of the file.
+#[cfg(target_family = "windows")]
+pub fn is_executable(path: &Path) -> bool {
+    use std::io::Read;
+
+    const EXTENSIONS: [&str; 6] = ["exe", "com", "bat", "cmd", "sh", "py"];
+
+    if path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            EXTENSIONS
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(extension))
+        })
+    {
+        return true;
+    }
+
+    let mut shebang = [0; 2];
+    std::fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut shebang))
+        .is_ok_and(|()| &shebang == b"#!")
+}
@@ -0,0 +1,185 @@
+use std::path::Path;
+
+/// Matches `path` against a glob pattern: `*` matches within one path
+/// component, `**` across components, `?` one character and `[a-z]` or
+/// `[!a-z]` one character of a set.
+fn matches(pattern: &[char], path: &[char]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // `a/**/b` also matches `a/b`
+            rest.strip_prefix(&['/'])
+                .is_some_and(|rest| matches(rest, path))
+                || (0..=path.len()).any(|i| matches(rest, &path[i..]))
+        }
+        ['*', rest @ ..] => (0..=path.len())
+            .take_while(|i| *i == 0 || path[i - 1] != '/')
+            .any(|i| matches(rest, &path[i..])),
+        ['?', rest @ ..] => path
+            .split_first()
+            .is_some_and(|(c, path)| *c != '/' && matches(rest, path)),
+        ['[', rest @ ..] => match (rest.iter().skip(1).position(|c| *c == ']'), path) {
+            (Some(end), [c, path @ ..]) => {
+                let (set, rest) = (&rest[..end + 1], &rest[end + 2..]);
+                let (negated, set) = match set {
+                    ['!', set @ ..] => (true, set),
+                    set => (false, set),
+                };
+
+                let mut found = false;
+                let mut i = 0;
+                while i < set.len() {
+                    if i + 2 < set.len() && set[i + 1] == '-' {
+                        found |= (set[i]..=set[i + 2]).contains(c);
+                        i += 3;
+                    } else {
+                        found |= set[i] == *c;
+                        i += 1;
+                    }
+                }
+
+                found != negated && *c != '/' && matches(rest, path)
+            }
+            // an unclosed `[` is matched literally
+            (None, [c, path @ ..]) => *c == '[' && matches(rest, path),
+            (_, []) => false,
+        },
+        [p, rest @ ..] => path
+            .split_first()
+            .is_some_and(|(c, path)| c == p && matches(rest, path)),
+    }
+}
+
+struct Pattern {
+    pattern: Vec<char>,
+    /// Matched against the whole path instead of the last component.
+    anchored: bool,
+    directory_only: bool,
+}
+
+/// Glob patterns matched against archive paths, those to leave out of an
+/// archive or to extract. Patterns containing a `/` are matched against the
+/// whole archive path, others against the last component only, so
+/// `node_modules` or `*.o` apply at any depth. A trailing `/` limits a pattern
+/// to directories.
+pub struct Patterns {
+    patterns: Vec<Pattern>,
+}
+
+impl Patterns {
+    pub fn new(patterns: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            patterns: patterns
+                .into_iter()
+                .map(|pattern| {
+                    let trimmed = pattern.trim_end_matches('/');
+
+                    Pattern {
+                        pattern: trimmed.trim_start_matches('/').chars().collect(),
+                        anchored: trimmed.contains('/'),
+                        directory_only: trimmed.len() < pattern.len(),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Reads one pattern per line, skipping empty lines and `#` comments.
+    pub fn read_patterns(path: &Path) -> std::io::Result<Vec<String>> {
+        Ok(std::fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect())
+    }
+
+    pub fn is_match(&self, path: &str, is_dir: bool) -> bool {
+        let path: Vec<char> = path.chars().collect();
+        let name = match path.iter().rposition(|c| *c == '/') {
+            Some(separator) => &path[separator + 1..],
+            None => &path[..],
+        };
+
+        self.patterns.iter().any(|pattern| {
+            (is_dir || !pattern.directory_only)
+                && matches(
+                    &pattern.pattern,
+                    if pattern.anchored { &path } else { name },
+                )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(pattern: &str, path: &str) -> bool {
+        matches(
+            &pattern.chars().collect::<Vec<_>>(),
+            &path.chars().collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn double_star_crosses_separators() {
+        assert!(glob("a/**/b", "a/x/y/b"));
+        assert!(glob("a/**/b", "a/x/b"));
+        assert!(glob("a/**/b", "a/b"));
+        assert!(glob("**/*.rs", "src/commands/extract.rs"));
+        assert!(glob("a/**", "a/x/y"));
+        assert!(!glob("a/**/b", "c/x/b"));
+    }
+
+    #[test]
+    fn star_stays_within_a_component() {
+        assert!(glob("*.o", "main.o"));
+        assert!(glob("src/*.rs", "src/main.rs"));
+        assert!(glob("a*b", "ab"));
+        assert!(!glob("*.o", "build/main.o"));
+        assert!(!glob("src/*.rs", "src/commands/extract.rs"));
+        assert!(!glob("a?b", "a/b"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(glob("file[0-9].txt", "file7.txt"));
+        assert!(glob("[abc]", "b"));
+        assert!(!glob("[abc]", "d"));
+        assert!(glob("[!abc]", "d"));
+        assert!(!glob("[!abc]", "a"));
+        assert!(!glob("file[!0-9].txt", "file7.txt"));
+        assert!(glob("file[!0-9].txt", "fileX.txt"));
+        assert!(!glob("a[!x]b", "a/b"));
+        // an unclosed class is literal
+        assert!(glob("a[b", "a[b"));
+    }
+
+    #[test]
+    fn anchored_and_unanchored() {
+        let patterns = Patterns::new([String::from("*.o"), String::from("/build/out")]);
+        assert!(patterns.is_match("main.o", false));
+        assert!(patterns.is_match("src/deep/main.o", false));
+        assert!(patterns.is_match("build/out", false));
+        assert!(!patterns.is_match("src/build/out", false));
+
+        let patterns = Patterns::new([String::from("src/*.rs")]);
+        assert!(patterns.is_match("src/main.rs", false));
+        assert!(!patterns.is_match("lib/src/main.rs", false));
+        assert!(!patterns.is_match("main.rs", false));
+    }
+
+    #[test]
+    fn trailing_slash_matches_directories_only() {
+        let patterns = Patterns::new([String::from("node_modules/")]);
+        assert!(patterns.is_match("node_modules", true));
+        assert!(patterns.is_match("web/node_modules", true));
+        assert!(!patterns.is_match("node_modules", false));
+        assert!(!patterns.is_match("web/node_modules", false));
+
+        let patterns = Patterns::new([String::from("node_modules")]);
+        assert!(patterns.is_match("node_modules", true));
+        assert!(patterns.is_match("node_modules", false));
+    }
+}
@@ -0,0 +1,50 @@
+use std::io::Read;
+
+/// Streams the body of an archive served over HTTP or HTTPS. Nothing is
+/// buffered on disk and nothing is seeked, so the archive is read front to
+/// back like from stdin.
+struct Download {
+    url: String,
+    body: ureq::BodyReader<'static>,
+    received: u64,
+}
+
+impl Read for Download {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.body.read(buf).map_err(|err| {
+            std::io::Error::new(
+                err.kind(),
+                format!(
+                    "download of {} failed after {} bytes: {}",
+                    self.url, self.received, err
+                ),
+            )
+        })?;
+        self.received += read as u64;
+
+        Ok(read)
+    }
+}
+
+/// Sends a GET request to `url` and returns its body to read. Error statuses
+/// fail here instead of being read as an archive.
+pub fn open(url: &str) -> std::io::Result<Box<dyn Read>> {
+    let response = ureq::get(url).call().map_err(|err| match err {
+        ureq::Error::StatusCode(status) => std::io::Error::new(
+            match status {
+                404 | 410 => std::io::ErrorKind::NotFound,
+                401 | 403 => std::io::ErrorKind::PermissionDenied,
+                _ => std::io::ErrorKind::Other,
+            },
+            format!("the server answered {url} with status {status}"),
+        ),
+        ureq::Error::Io(err) => err,
+        err => std::io::Error::other(format!("failed to request {url}: {err}")),
+    })?;
+
+    Ok(Box::new(Download {
+        url: url.to_string(),
+        body: response.into_body().into_reader(),
+        received: 0,
+    }))
+}
@@ -0,0 +1,38 @@
+use std::path::Path;
+
+/// `None` for anything but pairs of hex digits, an odd one out included.
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    (0..text.len())
+        .step_by(2)
+        .map(|i| {
+            let pair = text.get(i..i + 2)?;
+            pair.bytes()
+                .all(|byte| byte.is_ascii_hexdigit())
+                .then(|| u8::from_str_radix(pair, 16).ok())?
+        })
+        .collect()
+}
+
+/// Reads a file holding `N` bytes, either raw or as hex on one line.
+pub fn read_bytes<const N: usize>(path: &Path) -> std::io::Result<[u8; N]> {
+    let contents = std::fs::read(path)?;
+    let bytes = match contents.len() {
+        length if length == N => Some(contents),
+        _ => std::str::from_utf8(&contents)
+            .ok()
+            .and_then(|text| decode_hex(text.trim())),
+    };
+
+    bytes
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "{} holds neither {N} bytes nor {} hex digits",
+                    path.display(),
+                    N * 2
+                ),
+            )
+        })
+}
@@ -1,3 +1,12 @@
 pub mod archive;
 pub mod compression;
+pub mod crc32;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+mod error;
 pub mod spec;
+#[cfg(test)]
+mod test_util;
+
+pub use archive::write::build_archive;
+pub use error::{Error, Result};
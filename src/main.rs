@@ -2,7 +2,21 @@ use ataf::compression::CompressionFormat;
 use clap::{Arg, Command};
 use std::{io::IsTerminal, path::PathBuf};
 
+mod checkpoint;
 mod commands;
+mod fs;
+mod glob;
+#[cfg(feature = "http")]
+mod http;
+#[cfg(any(feature = "ed25519", feature = "encryption"))]
+mod keys;
+mod progress;
+mod report;
+mod sha256;
+#[cfg(feature = "ed25519")]
+mod signature;
+mod tee;
+mod users;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -13,6 +27,47 @@ fn cli() -> Command {
         .arg_required_else_help(true)
         .allow_external_subcommands(true)
         .version(VERSION)
+        .arg(
+            Arg::new("error_format")
+                .help("The format errors are printed to stderr in, json needs the json feature")
+                .long("error-format")
+                .num_args(1)
+                .default_value("human")
+                .value_parser(["human", "json"])
+                .global(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("key_file")
+                .help("The AES-256 key of encrypted archives (32 bytes, raw or hex), needs the encryption feature")
+                .long("key-file")
+                .value_name("KEYFILE")
+                .num_args(1)
+                .value_parser(clap::value_parser!(PathBuf))
+                .conflicts_with_all(["passphrase", "passphrase_file"])
+                .global(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("passphrase")
+                .help("The passphrase of encrypted archives, visible to other users of the machine while ataf runs, prefer --passphrase-file. Needs the encryption feature")
+                .long("passphrase")
+                .value_name("PASSPHRASE")
+                .num_args(1)
+                .conflicts_with("passphrase_file")
+                .global(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("passphrase_file")
+                .help("A file holding the passphrase of encrypted archives, one trailing line break is ignored. Needs the encryption feature")
+                .long("passphrase-file")
+                .value_name("FILE")
+                .num_args(1)
+                .value_parser(clap::value_parser!(PathBuf))
+                .global(true)
+                .required(false),
+        )
         .subcommand(
             Command::new("create")
                 .about("Creates an ataf archive")
@@ -38,14 +93,255 @@ fn cli() -> Command {
                 )
                 .arg(
                     Arg::new("chunk_size")
-                        .help("The chunk size to use for each compression block")
+                        .help("The chunk size to use for each compression block, defaults to a size suited to the compression format")
                         .short('s')
                         .long("chunk-size")
                         .num_args(1)
-                        .default_value("65535")
                         .value_parser(clap::value_parser!(u32).range(1024..16777216))
                         .required(false),
                 )
+                .arg(
+                    Arg::new("level")
+                        .help("The compression level, 0-9 for flate2 (defaults to 9), 0-11 for brotli (defaults to 11), 0-16 for lz4 (defaults to 9) and 1-9 for bzip2 (defaults to 9), ignored for none and snappy")
+                        .short('l')
+                        .long("level")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(u32))
+                        .conflicts_with("compress_level_auto")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("in_flight")
+                        .help("The most chunks held in memory at once while compressing, read but not yet written, defaults to twice the threads")
+                        .long("in-flight")
+                        .value_name("CHUNKS")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(usize).range(1..))
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("brotli_quality")
+                        .help("The brotli quality, 0-11 (defaults to 11), ignored for other formats")
+                        .long("brotli-quality")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(i32).range(0..=11))
+                        .conflicts_with_all(["level", "compress_level_auto"])
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("brotli_window")
+                        .help("The brotli window size as a power of two, 10-24 (defaults to 22), ignored for other formats. Every compressing and extracting thread needs memory for a few windows, and windows larger than the chunk size gain nothing")
+                        .long("brotli-window")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(i32).range(10..=24))
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("compress_level_auto")
+                        .help("Start at the best compression level and lower it between entries to stay within --time-budget")
+                        .long("compress-level-auto")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .requires("time_budget")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("time_budget")
+                        .help("The number of seconds creating the archive should take at most")
+                        .long("time-budget")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(u64))
+                        .requires("compress_level_auto")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("checkpoint")
+                        .help("Print a status line every N entries")
+                        .long("checkpoint")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("checkpoint_interval")
+                        .help("Print a status line every N seconds")
+                        .long("checkpoint-interval")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("progress")
+                        .help("Show the bytes processed so far and the current entry on a status line on stderr")
+                        .long("progress")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("dedup")
+                        .help("Store files with identical contents once, duplicates reference the first copy and can only be extracted alongside it")
+                        .long("dedup")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("no_empty_dirs")
+                        .help("Omit directories that end up without any archived entries below them")
+                        .long("no-empty-dirs")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("solid")
+                        .help("Compress the data of all entries as one stream, extraction has to read the archive sequentially")
+                        .long("solid")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("compress_level_auto")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("prefix_paths")
+                        .help("Store each entry path relative to the previous one, shrinking archives of deep trees, extraction has to read the archive sequentially")
+                        .long("prefix-paths")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("tee")
+                        .help("Also extract every entry to this directory while it is archived, failures there are reported but never stop the archive")
+                        .long("tee")
+                        .value_name("DIR")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .conflicts_with("solid")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("checksums")
+                        .help("Store a CRC-32 with every chunk so corruption is detected when extracting, same as --checksum crc32")
+                        .long("checksums")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("checksum")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("checksum")
+                        .help("The checksum stored with every chunk so corruption is detected when extracting, xxh3 costs far less than crc32 on fast codecs")
+                        .long("checksum")
+                        .value_name("ALGORITHM")
+                        .num_args(1)
+                        .value_parser(["xxh3", "crc32", "none"])
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("exclude")
+                        .help("Leave out paths matching this glob, can be given multiple times. Patterns with a / match the whole path inside the archive, others any file or directory name, a trailing / only matches directories. Excluded directories are not descended into")
+                        .long("exclude")
+                        .value_name("GLOB")
+                        .action(clap::ArgAction::Append)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("exclude_from")
+                        .help("Read exclude patterns from this file, one per line, skipping empty lines and lines starting with #")
+                        .long("exclude-from")
+                        .value_name("FILE")
+                        .action(clap::ArgAction::Append)
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("index")
+                        .help("Write an index of all entries to the end of the archive, so listing archive files doesn't have to read through them")
+                        .long("index")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("solid")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("comment")
+                        .help("A note to store in the archive header, like a build id or the source host, shown by `list --header`")
+                        .long("comment")
+                        .num_args(1)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("dereference")
+                        .help("Archive the files and directories symlinks point to instead of the symlinks. Directories that lead back into one being archived, through a symlink or a bind mount, are skipped with a warning with or without this")
+                        .short('H')
+                        .long("dereference")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("sparse")
+                        .help("Store only the data of sparse files, leaving out their holes, which are recreated on extraction (detected on Linux, FreeBSD and macOS)")
+                        .short('S')
+                        .long("sparse")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("mmap")
+                        .help("Read files through a memory map instead of read calls, which can be faster for large files on fast storage. Files must not shrink while they are archived, other inputs, sparse files and --solid read normally")
+                        .long("mmap")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("one_file_system")
+                        .help("Don't descend into directories on other filesystems than the input's, like /proc, /sys or other mount points, they are skipped with a warning (no effect on Windows)")
+                        .short('x')
+                        .long("one-file-system")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("stats")
+                        .help("Print the uncompressed and compressed size, ratio, time and throughput once the archive is written, even when stdout is not a terminal")
+                        .long("stats")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("max_depth")
+                        .help("Don't descend more than this many directory levels below each input, deeper entries are skipped with a warning")
+                        .long("max-depth")
+                        .value_name("DEPTH")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(u64))
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("record_abspath")
+                        .help("Record the absolute source path of every entry as metadata, this reveals local paths to anyone reading the archive")
+                        .long("record-abspath")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("volume_size")
+                        .help("Split the archive into volumes of about this many bytes, named like the output with .001, .002, ... appended, extract and list read them given the first volume. A volume ends at the first chunk boundary past the size, so it can be larger by up to a chunk and holds whole chunks only")
+                        .long("volume-size")
+                        .value_name("BYTES")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                        .requires("output")
+                        .required(false),
+                )
                 .arg(
                     Arg::new("output")
                         .help("The output file to write the archive to")
@@ -55,6 +351,54 @@ fn cli() -> Command {
                         .value_parser(clap::value_parser!(PathBuf))
                         .required(std::io::stdout().is_terminal()),
                 )
+                .arg(
+                    Arg::new("encrypt")
+                        .help("Encrypt the whole archive after compression with AES-256-GCM, using the key from --key-file or one derived from --passphrase or --passphrase-file with Argon2id. Reading it back needs the same key or passphrase, needs the encryption feature")
+                        .long("encrypt")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("kdf_memory")
+                        .help("The memory Argon2id derives the key from a passphrase with, in KiB [default: 65536]")
+                        .long("kdf-memory")
+                        .value_name("KIB")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(u32).range(8..))
+                        .requires("encrypt")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("kdf_iterations")
+                        .help("The passes Argon2id makes over its memory [default: 3]")
+                        .long("kdf-iterations")
+                        .value_name("ITERATIONS")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(u32).range(1..))
+                        .requires("encrypt")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("kdf_parallelism")
+                        .help("The lanes of Argon2id, part of the key like the other parameters [default: 4]")
+                        .long("kdf-parallelism")
+                        .value_name("LANES")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(u32).range(1..16777216))
+                        .requires("encrypt")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("sign")
+                        .help("Sign the archive with this ed25519 secret key (32 bytes, raw or hex) and write the signature next to it as OUTPUT.sig, needs the ed25519 feature")
+                        .long("sign")
+                        .value_name("KEYFILE")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .requires("output")
+                        .required(false),
+                )
                 .arg(
                     Arg::new("input")
                         .help("The input files or directories to archive")
@@ -79,21 +423,418 @@ fn cli() -> Command {
                 )
                 .arg(
                     Arg::new("input")
-                        .help("The input archive to extract")
+                        .help("The input archive to extract, or its http:// or https:// URL with the http feature")
                         .short('i')
                         .long("input")
                         .num_args(1)
                         .value_parser(clap::value_parser!(PathBuf))
                         .required(std::io::stdout().is_terminal()),
                 )
+                .arg(
+                    Arg::new("verify")
+                        .help("Check the archive against its INPUT.sig signature with this ed25519 public key (32 bytes, raw or hex) before extracting anything, nothing is extracted when it doesn't match. Reads the archive twice through the same open files, so it can't be swapped in between, and doesn't take URLs. Needs the ed25519 feature")
+                        .long("verify")
+                        .value_name("PUBKEY")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .requires("input")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("preserve_owner")
+                        .help("Restore the stored owners, by the stored user and group names where they exist here and by the stored uids/gids otherwise. Usually requires root and falls back to the extracting user with a warning otherwise (no effect on Windows)")
+                        .long("preserve-owner")
+                        .visible_alias("same-owner")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("numeric_owner")
+                        .help("Restore the stored uids/gids as they are, without looking up the stored user and group names, implies --preserve-owner")
+                        .long("numeric-owner")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("owner_map")
+                        .help("A file of `olduid:newuid` lines used to remap stored uids, implies --numeric-owner since the map is of ids. Gids are mapped with --group-map")
+                        .long("owner-map")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("owner_map_default")
+                        .help("The uid to use for uids missing from the owner map, instead of passing them through")
+                        .long("owner-map-default")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(u32))
+                        .requires("owner_map")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("group_map")
+                        .help("A file of `oldgid:newgid` lines used to remap stored gids, implies --numeric-owner like --owner-map")
+                        .long("group-map")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("group_map_default")
+                        .help("The gid to use for gids missing from the group map, instead of passing them through")
+                        .long("group-map-default")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(u32))
+                        .requires("group_map")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("verify_manifest")
+                        .help("Check every extracted file against a sha256sum style manifest (`<hex>  <path>` lines), failing if any differ or are missing")
+                        .long("verify-manifest")
+                        .value_name("MANIFEST")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("exec_ext")
+                        .help("Make extracted files with this extension executable, for archives created on Windows without executable hints")
+                        .long("exec-ext")
+                        .value_name("EXTENSION")
+                        .action(clap::ArgAction::Append)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("preserve_flags")
+                        .help("Restore stored immutable/append-only flags, usually requires root (Linux only)")
+                        .long("preserve-flags")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("checkpoint")
+                        .help("Print a status line every N entries")
+                        .long("checkpoint")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("checkpoint_interval")
+                        .help("Print a status line every N seconds")
+                        .long("checkpoint-interval")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("progress")
+                        .help("Show the bytes processed so far and the current entry on a status line on stderr")
+                        .long("progress")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("delete")
+                        .help("After extracting, DELETE every file and directory in the output directory that is not in the archive, making it an exact mirror. Nothing is deleted when any entry could not be extracted")
+                        .long("delete")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("ignore_header_chunk_size")
+                        .help("Salvage option: follow the chunk framing and entry sizes instead of the header's chunk size and skip the header checksum, best-effort and only useful when just that field is corrupt")
+                        .long("ignore-header-chunk-size")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("duplicate_policy")
+                        .help("What to do with entries whose path was already extracted or lies below an extracted non-directory")
+                        .long("duplicate-policy")
+                        .num_args(1)
+                        .default_value("warn")
+                        .value_parser(["warn", "error", "overwrite"])
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("no_same_permissions")
+                        .help("Extract without the setuid, setgid and sticky bits and with the umask applied, instead of the stored permissions as they are (no effect on Windows)")
+                        .long("no-same-permissions")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("strict")
+                        .help("Stop at the first entry that can't be extracted instead of reporting it and going on, either way the exit code is non-zero")
+                        .long("strict")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("parallel")
+                        .help("Write files of up to 4 MiB on N threads while the archive is read on, for archives of many small files. Entries are extracted as stored, without the per-entry options")
+                        .long("parallel")
+                        .value_name("N")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                        .conflicts_with_all([
+                            "preserve_owner",
+                            "numeric_owner",
+                            "owner_map",
+                            "group_map",
+                            "verify_manifest",
+                            "exec_ext",
+                            "preserve_flags",
+                            "checkpoint",
+                            "checkpoint_interval",
+                            "delete",
+                            "file",
+                            "include",
+                            "keep_old_files",
+                            "no_same_permissions",
+                            "resume",
+                            "strip_components",
+                        ])
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("keep_old_files")
+                        .help("Skip entries whose destination already exists instead of overwriting it, with a warning")
+                        .short('k')
+                        .long("keep-old-files")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("overwrite")
+                        .help("Overwrite existing files, the default, undoing an earlier --keep-old-files")
+                        .long("overwrite")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .overrides_with("keep_old_files")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("resume")
+                        .help("Continue an interrupted extraction: skip files that already exist with their stored size and modification time, and other entries that already exist, without decompressing them")
+                        .long("resume")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("keep_old_files")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("strip_components")
+                        .help("Remove the first N components from every entry path (and hard link or reference target), entries with nothing left are skipped. Absolute roots and `.` are removed first and `..` is refused as always")
+                        .long("strip-components")
+                        .value_name("N")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(usize))
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("file")
+                        .help("Only extract the entry with this path, can be given multiple times. Other entries are skipped without decompressing them unless the archive is solid. Hard links and references fail unless the entry they point to is extracted too")
+                        .long("file")
+                        .value_name("PATH")
+                        .action(clap::ArgAction::Append)
+                        .conflicts_with("delete")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("include")
+                        .help("Only extract entries matching this glob pattern, can be given multiple times and combined with --file. Patterns work like --exclude of create, and the directories above an extracted entry are extracted with it. Hard links and references fail unless the entry they point to is extracted too")
+                        .long("include")
+                        .value_name("PATTERN")
+                        .action(clap::ArgAction::Append)
+                        .conflicts_with("delete")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("stdout")
+                        .help("Write the contents of the files selected with --file to stdout instead of extracting them, also done for `--output -`. Hard links and references write the data of the file they point to, which reads the archive again and isn't possible from stdin. Of entries sharing a path --duplicate-policy warn writes the first and stops reading once all of them were found, error reports later ones and overwrite writes the last, reading the archive again. Selecting anything but a file or link is an error")
+                        .long("stdout")
+                        .visible_alias("to-stdout")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .requires("file")
+                        .required(false),
+                )
                 .arg(
                     Arg::new("output")
-                        .help("The output directory to extract the archive to")
+                        .help("The output directory to extract the archive to, `-` writes the files selected with --file to stdout like --stdout")
                         .short('o')
                         .long("output")
                         .num_args(1)
                         .value_parser(clap::value_parser!(PathBuf))
-                        .required(true),
+                        .required_unless_present("stdout"),
+                )
+                .arg_required_else_help(false),
+        )
+        .subcommand(
+            Command::new("list")
+                .about("Lists the entries of an ataf archive")
+                .arg(
+                    Arg::new("threads")
+                        .help("The number of threads to use for decompression")
+                        .short('t')
+                        .long("threads")
+                        .num_args(1)
+                        .default_value("1")
+                        .value_parser(clap::value_parser!(usize))
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("input")
+                        .help("The input archive to list, or its http:// or https:// URL with the http feature")
+                        .short('i')
+                        .long("input")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .required(std::io::stdin().is_terminal()),
+                )
+                .arg(
+                    Arg::new("long")
+                        .help("Show permissions, owner, size and modification time like `ls -l`")
+                        .short('l')
+                        .long("long")
+                        .visible_alias("list-long")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("header")
+                        .help("Show the archive header, its version, compression, options and comment, before the entries")
+                        .long("header")
+                        .num_args(0)
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("format")
+                        .help("Print every entry as one JSON object per line with its path, type, mode, uid, gid, mtime and size instead of the human readable listing, needs the json feature")
+                        .long("format")
+                        .num_args(1)
+                        .default_value("human")
+                        .value_parser(["human", "json"])
+                        .conflicts_with("header")
+                        .required(false),
+                )
+                .arg_required_else_help(false),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Checks that every entry of an ataf archive decompresses to its stored size, without writing any files")
+                .visible_alias("test")
+                .arg(
+                    Arg::new("threads")
+                        .help("The number of threads to use for decompression")
+                        .short('t')
+                        .long("threads")
+                        .num_args(1)
+                        .default_value("1")
+                        .value_parser(clap::value_parser!(usize))
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("input")
+                        .help("The input archive to verify, or its http:// or https:// URL with the http feature")
+                        .short('i')
+                        .long("input")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .required(std::io::stdin().is_terminal()),
+                )
+                .arg(
+                    Arg::new("format")
+                        .help("Print every entry as one JSON object per line like list does, with a status of ok or failed, and no summary. Needs the json feature")
+                        .long("format")
+                        .num_args(1)
+                        .default_value("human")
+                        .value_parser(["human", "json"])
+                        .required(false),
+                )
+                .arg_required_else_help(false),
+        )
+        .subcommand(
+            Command::new("recompress")
+                .about("Rewrites an ataf archive with another compression format or chunk size, keeping every entry header as it is")
+                .arg(
+                    Arg::new("compression_format")
+                        .help("The compression format to use")
+                        .short('c')
+                        .long("compression-format")
+                        .num_args(1)
+                        .required(true)
+                        .value_parser(clap::value_parser!(CompressionFormat)),
+                )
+                .arg(
+                    Arg::new("threads")
+                        .help("The number of threads to use for decompression and compression")
+                        .short('t')
+                        .long("threads")
+                        .num_args(1)
+                        .default_value("1")
+                        .value_parser(clap::value_parser!(usize))
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("chunk_size")
+                        .help("The chunk size to use for each compression block, defaults to a size suited to the compression format")
+                        .short('s')
+                        .long("chunk-size")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(u32).range(1024..16777216))
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("level")
+                        .help("The compression level, see create")
+                        .short('l')
+                        .long("level")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(u32))
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("in_flight")
+                        .help("The most chunks held in memory at once while compressing, see create")
+                        .long("in-flight")
+                        .value_name("CHUNKS")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(usize).range(1..))
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("input")
+                        .help("The archive to recompress, or its http:// or https:// URL with the http feature")
+                        .short('i')
+                        .long("input")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .required(std::io::stdin().is_terminal()),
+                )
+                .arg(
+                    Arg::new("output")
+                        .help("Where to write the recompressed archive, the input archive is replaced without one")
+                        .short('o')
+                        .long("output")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .required(false),
                 )
                 .arg_required_else_help(false),
         )
@@ -101,10 +842,30 @@ fn cli() -> Command {
 
 fn main() {
     let matches = cli().get_matches();
+    let json_errors = matches
+        .get_one::<String>("error_format")
+        .is_some_and(|format| format == "json");
+    #[cfg(feature = "json")]
+    report::set_json(json_errors);
+    #[cfg(not(feature = "json"))]
+    if json_errors {
+        report::error(
+            "unsupported_json",
+            None,
+            None,
+            format_args!("--error-format json needs ataf built with the json feature"),
+        );
+        std::process::exit(1);
+    }
 
     match matches.subcommand() {
         Some(("create", sub_matches)) => std::process::exit(commands::create::run(sub_matches)),
         Some(("extract", sub_matches)) => std::process::exit(commands::extract::run(sub_matches)),
+        Some(("list", sub_matches)) => std::process::exit(commands::list::run(sub_matches)),
+        Some(("verify", sub_matches)) => std::process::exit(commands::verify::run(sub_matches)),
+        Some(("recompress", sub_matches)) => {
+            std::process::exit(commands::recompress::run(sub_matches))
+        }
         _ => cli().print_help().unwrap(),
     }
 }
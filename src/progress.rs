@@ -0,0 +1,42 @@
+use ataf::archive::progress::{ProgressCallback, ProgressEvent, ProgressStage};
+use std::{io::Write, time::Instant};
+
+/// Renders progress events as one status line on stderr that is rewritten in
+/// place, call `finish` once done to end the line. With the number of entries
+/// known up front, from an archive header that stores it, the line also shows
+/// how many of them are done.
+pub fn status_line(total_entries: u64) -> ProgressCallback {
+    let started = Instant::now();
+    let mut finished_entries = 0;
+
+    Box::new(move |event: ProgressEvent<'_>| {
+        if event.stage == ProgressStage::Finished {
+            finished_entries += 1;
+        }
+
+        let mut stderr = std::io::stderr().lock();
+        if total_entries > 0 {
+            write!(
+                stderr,
+                "\r\x1b[K{}/{} entries, ",
+                finished_entries, total_entries
+            )
+            .ok();
+        } else {
+            write!(stderr, "\r\x1b[K").ok();
+        }
+        write!(
+            stderr,
+            "{:.1} MiB, {}s: {}",
+            event.processed_bytes as f64 / (1024.0 * 1024.0),
+            started.elapsed().as_secs(),
+            event.path
+        )
+        .ok();
+        stderr.flush().ok();
+    })
+}
+
+pub fn finish() {
+    eprintln!();
+}
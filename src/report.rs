@@ -0,0 +1,62 @@
+use std::path::Path;
+#[cfg(feature = "json")]
+use std::{
+    io::Write,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+#[cfg(feature = "json")]
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Switches error output between `ERROR ...` lines and one JSON object per line.
+#[cfg(feature = "json")]
+pub fn set_json(json: bool) {
+    JSON.store(json, Ordering::Relaxed);
+}
+
+/// Errors that can be reported, io errors and the library's own.
+pub trait Reportable: std::fmt::Display {
+    fn kind(&self) -> std::io::ErrorKind;
+}
+
+impl Reportable for std::io::Error {
+    fn kind(&self) -> std::io::ErrorKind {
+        self.kind()
+    }
+}
+
+impl Reportable for ataf::Error {
+    fn kind(&self) -> std::io::ErrorKind {
+        self.kind()
+    }
+}
+
+/// Reports a failure to stderr. `code` identifies what failed, `kind` is taken
+/// from the underlying io error if there is one.
+// the code and path only show in JSON errors
+#[cfg_attr(not(feature = "json"), allow(unused_variables))]
+pub fn error(
+    code: &str,
+    path: Option<&Path>,
+    err: Option<&dyn Reportable>,
+    message: std::fmt::Arguments,
+) {
+    let message = match err {
+        Some(err) => format!("{}: {}", message, err),
+        None => message.to_string(),
+    };
+
+    #[cfg(feature = "json")]
+    if JSON.load(Ordering::Relaxed) {
+        let json = serde_json::json!({
+            "code": code,
+            "path": path.map(|path| path.to_string_lossy()),
+            "message": message,
+            "kind": err.map(|err| format!("{:?}", err.kind())),
+        });
+        writeln!(std::io::stderr(), "{json}").ok();
+        return;
+    }
+
+    eprintln!("ERROR {}", message);
+}
@@ -0,0 +1,52 @@
+use sha2::{Digest, Sha256};
+use std::io::Read;
+
+/// Hashes everything `input` returns.
+pub fn digest(mut input: impl Read) -> std::io::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut input, &mut hasher)?;
+
+    Ok(hasher.finalize().into())
+}
+
+/// Hashes everything `input` returns, formatted as lowercase hex.
+pub fn hex_digest(input: impl Read) -> std::io::Result<String> {
+    Ok(digest(input)?
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(data: &[u8]) -> String {
+        hex_digest(data).unwrap()
+    }
+
+    // the examples of FIPS 180-4 and its companion test vectors
+    #[test]
+    fn known_digests() {
+        assert_eq!(
+            hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn million_a() {
+        assert_eq!(
+            hex(&vec![b'a'; 1_000_000]),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+}
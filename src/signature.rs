@@ -0,0 +1,122 @@
+use ataf::archive::volume::{first_volume_base, volume_path};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    io::{Read, Seek, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// Hashes everything written through it, so an archive is signed while it is
+/// written instead of being read again afterwards.
+pub struct HashingWriter {
+    pub writer: Box<dyn Write + Send>,
+    pub hasher: Arc<Mutex<Sha256>>,
+}
+
+impl Write for HashingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.hasher.lock().unwrap().update(&buf[..written]);
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// The sidecar holding the signature of the archive at `archive`, next to the
+/// volumes for split archives.
+pub fn signature_path(archive: &Path) -> PathBuf {
+    let mut path = archive.as_os_str().to_owned();
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+fn invalid_data(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// Reads an ed25519 secret key, the 32 byte seed.
+pub fn signing_key(path: &Path) -> std::io::Result<SigningKey> {
+    Ok(SigningKey::from_bytes(&crate::keys::read_bytes(path)?))
+}
+
+/// Reads an ed25519 public key.
+pub fn verifying_key(path: &Path) -> std::io::Result<VerifyingKey> {
+    VerifyingKey::from_bytes(&crate::keys::read_bytes(path)?)
+        .map_err(|err| invalid_data(format!("{} is not a public key: {err}", path.display())))
+}
+
+/// Signs the SHA-256 of an archive, writing the signature as hex.
+pub fn write_signature(key: &SigningKey, digest: [u8; 32], path: &Path) -> std::io::Result<()> {
+    let hex: String = key
+        .sign(&digest)
+        .to_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+
+    std::fs::write(path, format!("{hex}\n"))
+}
+
+/// Opens every volume of the split archive at `base`, up to the first missing
+/// one like `VolumeReader` reads them.
+fn open_volumes(base: &Path) -> std::io::Result<Vec<File>> {
+    let mut volumes = vec![File::open(volume_path(base, 1))?];
+    loop {
+        match File::open(volume_path(base, volumes.len() as u32 + 1)) {
+            Ok(volume) => volumes.push(volume),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(volumes),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Checks the signature of the archive at `input` against its sidecar and
+/// returns the verified archive to read, rewound. It's hashed through the
+/// same open files it's read from afterwards, so replacing the archive in
+/// between can't get an unverified one extracted. Split archives are given
+/// by their first volume and all of their volumes are opened up front. URLs
+/// can't be rewound and are refused.
+pub fn verify_archive(input: &Path, key: &VerifyingKey) -> std::io::Result<Box<dyn Read>> {
+    if crate::commands::is_url(input) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "only archive files can be verified, a download can't be read twice",
+        ));
+    }
+
+    let base = first_volume_base(input);
+    let path = signature_path(base.as_deref().unwrap_or(input));
+    let signature = Signature::from_bytes(&crate::keys::read_bytes(&path)?);
+
+    let mut files = match &base {
+        Some(base) => open_volumes(base)?,
+        None => vec![File::open(input)?],
+    };
+    let digest = crate::sha256::digest(files.iter().fold(
+        Box::new(std::io::empty()) as Box<dyn Read>,
+        |archive, file| Box::new(archive.chain(file)),
+    ))?;
+
+    key.verify(&digest, &signature).map_err(|_| {
+        invalid_data(format!(
+            "the signature in {} doesn't match the archive",
+            path.display()
+        ))
+    })?;
+
+    for file in &mut files {
+        file.rewind()?;
+    }
+
+    Ok(files
+        .into_iter()
+        .fold(Box::new(std::io::empty()), |archive, file| {
+            Box::new(archive.chain(file))
+        }))
+}
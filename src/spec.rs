@@ -1,15 +1,18 @@
+use crate::Error;
 use std::{
     fmt::Debug,
     io::{Read, Write},
     ops::Deref,
+    path::PathBuf,
+    time::{Duration, SystemTime},
 };
 
 pub trait Serialize {
-    fn serialize(&self, output: impl Write) -> std::io::Result<()>;
+    fn serialize(&self, output: impl Write) -> crate::Result<()>;
 }
 
 pub trait Deserialize: Sized {
-    fn deserialize(input: impl Read) -> std::io::Result<Self>;
+    fn deserialize(input: impl Read) -> crate::Result<Self>;
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -36,7 +39,7 @@ impl Deref for VariableSizedU32 {
 }
 
 impl Serialize for VariableSizedU32 {
-    fn serialize(&self, mut output: impl Write) -> std::io::Result<()> {
+    fn serialize(&self, mut output: impl Write) -> crate::Result<()> {
         let mut value = self.0;
         let mut bytes = Vec::new();
 
@@ -57,7 +60,7 @@ impl Serialize for VariableSizedU32 {
 }
 
 impl Deserialize for VariableSizedU32 {
-    fn deserialize(mut input: impl Read) -> std::io::Result<Self> {
+    fn deserialize(mut input: impl Read) -> crate::Result<Self> {
         let mut value = 0u32;
         let mut shift = 0;
 
@@ -73,10 +76,9 @@ impl Deserialize for VariableSizedU32 {
             shift += 7;
 
             if shift >= 32 {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
+                return Err(Error::InvalidData(String::from(
                     "VariableSizedU32 is too large",
-                ));
+                )));
             }
         }
 
@@ -108,7 +110,7 @@ impl Deref for VariableSizedU64 {
 }
 
 impl Serialize for VariableSizedU64 {
-    fn serialize(&self, mut output: impl Write) -> std::io::Result<()> {
+    fn serialize(&self, mut output: impl Write) -> crate::Result<()> {
         let mut value = self.0;
         let mut bytes = Vec::new();
 
@@ -129,7 +131,7 @@ impl Serialize for VariableSizedU64 {
 }
 
 impl Deserialize for VariableSizedU64 {
-    fn deserialize(mut input: impl Read) -> std::io::Result<Self> {
+    fn deserialize(mut input: impl Read) -> crate::Result<Self> {
         let mut value = 0u64;
         let mut shift = 0;
 
@@ -145,10 +147,9 @@ impl Deserialize for VariableSizedU64 {
             shift += 7;
 
             if shift >= 64 {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
+                return Err(Error::InvalidData(String::from(
                     "VariableSizedU64 is too large",
-                ));
+                )));
             }
         }
 
@@ -156,54 +157,568 @@ impl Deserialize for VariableSizedU64 {
     }
 }
 
+/// The archive format version written by this crate, and the newest one it
+/// reads. Newer archive and entry headers are refused with
+/// `Error::UnsupportedVersion` instead of being parsed as an older layout.
+///
+/// ```
+/// use ataf::{
+///     archive::read::Archive,
+///     spec::{ARCHIVE_MAGIC, ARCHIVE_VERSION, ArchiveEntryHeader, ArchiveHeader, Deserialize},
+/// };
+///
+/// let mut input = ARCHIVE_MAGIC.to_vec();
+/// input.extend_from_slice(&999u32.to_le_bytes());
+/// input.extend_from_slice(&[0; 64]);
+///
+/// let err = ArchiveHeader::deserialize(&input[..]).unwrap_err();
+/// assert!(matches!(err, ataf::Error::UnsupportedVersion(999)));
+/// assert!(err.to_string().contains("999"));
+/// assert!(err.to_string().contains(&ARCHIVE_VERSION.to_string()));
+///
+/// let err = Archive::new(&input[..]).header().unwrap_err();
+/// assert!(matches!(err, ataf::Error::UnsupportedVersion(999)));
+///
+/// let err = ArchiveEntryHeader::deserialize_version(&[0; 64][..], 999).unwrap_err();
+/// assert!(matches!(err, ataf::Error::UnsupportedVersion(999)));
+///
+/// // the next version is just as unknown
+/// input[4..8].copy_from_slice(&(ARCHIVE_VERSION + 1).to_le_bytes());
+/// let err = ArchiveHeader::deserialize(&input[..]).unwrap_err();
+/// assert!(matches!(err, ataf::Error::UnsupportedVersion(v) if v == ARCHIVE_VERSION + 1));
+/// ```
+pub const ARCHIVE_VERSION: u32 = 12;
+
+/// Identifies ataf archives, written before the version since version 5.
+/// Older archives start with the version directly, which is told apart by it
+/// being one of the versions 1 to 4.
+///
+/// ```
+/// use ataf::spec::{ArchiveHeader, Deserialize};
+///
+/// let err = ArchiveHeader::deserialize(&b"#!/bin/sh\necho hello\n"[..]).unwrap_err();
+/// assert!(matches!(err, ataf::Error::BadMagic));
+/// ```
+pub const ARCHIVE_MAGIC: [u8; 4] = *b"ATAF";
+
+/// Starts encrypted archives in place of `ARCHIVE_MAGIC`, the archive follows
+/// encrypted after an `encryption::EncryptionHeader` (with the `encryption`
+/// feature). Known without the feature, so such archives can be told apart.
+pub const ENCRYPTION_MAGIC: [u8; 4] = *b"ATAE";
+
 #[derive(Debug, Clone)]
 pub struct ArchiveHeader {
     pub version: u32,
 
     pub compression: String,
     pub compression_chunk_size: u32,
+
+    /// Whether all entry headers are stored up front, followed by the data of
+    /// every entry as a single chunk stream (since version 2).
+    pub solid: bool,
+
+    /// Whether entry paths are front-coded, storing only the length of the
+    /// prefix shared with the previous entry's path and the differing suffix
+    /// (since version 3). Such archives can only be read sequentially.
+    pub prefix_paths: bool,
+
+    /// The checksum following every chunk length, which readers verify
+    /// before decompressing (CRC-32 since version 4, XXH3 since version 11).
+    pub checksum: ChunkChecksum,
+
+    /// Whether the entries are followed by `ENTRIES_END`, an `ArchiveIndex`
+    /// and a trailer pointing to it (since version 6). The index is only
+    /// written once the archive is finished, so it may still be missing.
+    pub index: bool,
+
+    /// A free-form note about the archive, empty when there is none (since
+    /// version 9).
+    pub comment: String,
+
+    /// The number of entries and the sum of their sizes (since version 12).
+    /// Only known once everything is written, so they are filled in
+    /// afterwards where the archive can be rewritten, see
+    /// `write::write_totals`. Archives streamed to a pipe or written through
+    /// a sink that can't seek keep them at zero, which means unknown.
+    pub entry_count: u64,
+    pub total_uncompressed_size: u64,
+}
+
+const ARCHIVE_FLAG_SOLID: u8 = 1 << 0;
+const ARCHIVE_FLAG_PREFIX_PATHS: u8 = 1 << 1;
+const ARCHIVE_FLAG_CHECKSUMS: u8 = 1 << 2;
+const ARCHIVE_FLAG_INDEX: u8 = 1 << 3;
+const ARCHIVE_FLAG_XXH3: u8 = 1 << 4;
+
+/// The checksum stored with every chunk of an archive, computed over the
+/// chunk as stored so it is verified before decompressing.
+///
+/// ```
+/// use ataf::spec::ChunkChecksum;
+///
+/// for checksum in [ChunkChecksum::None, ChunkChecksum::Crc32, ChunkChecksum::Xxh3] {
+///     let mut stored = Vec::new();
+///     checksum.write(b"chunk", &mut stored).unwrap();
+///     assert_eq!(stored.len(), checksum.size());
+///     assert!(checksum.matches(b"chunk", &stored));
+///     assert_eq!(checksum.matches(b"chunc", &stored), checksum == ChunkChecksum::None);
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChunkChecksum {
+    #[default]
+    None,
+    /// The CRC-32 used by zlib, gzip and zip.
+    Crc32,
+    /// The 64 bit XXH3, several times faster than CRC-32 on large chunks.
+    Xxh3,
+}
+
+impl ChunkChecksum {
+    /// The number of bytes stored in front of every chunk.
+    pub fn size(self) -> usize {
+        match self {
+            Self::None => 0,
+            Self::Crc32 => 4,
+            Self::Xxh3 => 8,
+        }
+    }
+
+    /// Writes the checksum of `chunk` as stored in the archive.
+    pub fn write(self, chunk: &[u8], mut output: impl Write) -> std::io::Result<()> {
+        match self {
+            Self::None => Ok(()),
+            Self::Crc32 => output.write_all(&crate::crc32::crc32(chunk).to_be_bytes()),
+            Self::Xxh3 => output.write_all(&xxhash_rust::xxh3::xxh3_64(chunk).to_be_bytes()),
+        }
+    }
+
+    /// Whether `stored`, the checksum read in front of `chunk`, matches it.
+    pub fn matches(self, chunk: &[u8], stored: &[u8]) -> bool {
+        match self {
+            Self::None => true,
+            Self::Crc32 => crate::crc32::crc32(chunk).to_be_bytes()[..] == *stored,
+            Self::Xxh3 => xxhash_rust::xxh3::xxh3_64(chunk).to_be_bytes()[..] == *stored,
+        }
+    }
+
+    fn from_flags(flags: u8) -> Self {
+        match (
+            flags & ARCHIVE_FLAG_CHECKSUMS != 0,
+            flags & ARCHIVE_FLAG_XXH3 != 0,
+        ) {
+            (false, _) => Self::None,
+            (true, false) => Self::Crc32,
+            (true, true) => Self::Xxh3,
+        }
+    }
 }
 
 impl Serialize for ArchiveHeader {
-    fn serialize(&self, mut output: impl Write) -> std::io::Result<()> {
-        output.write_all(&self.version.to_le_bytes())?;
-        output.write_all(&(self.compression.len() as u16).to_le_bytes())?;
-        output.write_all(self.compression.as_bytes())?;
-        output.write_all(&self.compression_chunk_size.to_le_bytes())?;
+    fn serialize(&self, mut output: impl Write) -> crate::Result<()> {
+        // collected first, since version 11 ends with a checksum over it all
+        let mut bytes = Vec::new();
+
+        if self.version >= 5 {
+            bytes.write_all(&ARCHIVE_MAGIC)?;
+        }
+        bytes.write_all(&self.version.to_le_bytes())?;
+        bytes.write_all(&(self.compression.len() as u16).to_le_bytes())?;
+        bytes.write_all(self.compression.as_bytes())?;
+        bytes.write_all(&self.compression_chunk_size.to_le_bytes())?;
+
+        if self.version >= 2 {
+            let mut flags = 0;
+            if self.solid {
+                flags |= ARCHIVE_FLAG_SOLID;
+            }
+            if self.prefix_paths {
+                flags |= ARCHIVE_FLAG_PREFIX_PATHS;
+            }
+            match self.checksum {
+                ChunkChecksum::None => {}
+                ChunkChecksum::Crc32 => flags |= ARCHIVE_FLAG_CHECKSUMS,
+                ChunkChecksum::Xxh3 if self.version >= 11 => {
+                    flags |= ARCHIVE_FLAG_CHECKSUMS | ARCHIVE_FLAG_XXH3
+                }
+                ChunkChecksum::Xxh3 => {
+                    return Err(Error::InvalidData(String::from(
+                        "XXH3 checksums need version 11",
+                    )));
+                }
+            }
+            if self.index {
+                flags |= ARCHIVE_FLAG_INDEX;
+            }
+
+            bytes.write_all(&[flags])?;
+        }
+
+        if self.version >= 9 {
+            let length = u16::try_from(self.comment.len())
+                .map_err(|_| Error::InvalidData(String::from("comment is too long")))?;
+            bytes.write_all(&length.to_le_bytes())?;
+            bytes.write_all(self.comment.as_bytes())?;
+        }
+
+        // fixed size, so they can be rewritten in place
+        if self.version >= 12 {
+            bytes.write_all(&self.entry_count.to_le_bytes())?;
+            bytes.write_all(&self.total_uncompressed_size.to_le_bytes())?;
+        }
+
+        if self.version >= 11 {
+            let checksum = crate::crc32::crc32(&bytes);
+            bytes.write_all(&checksum.to_le_bytes())?;
+        }
+
+        output.write_all(&bytes)?;
 
         Ok(())
     }
 }
 
-impl Deserialize for ArchiveHeader {
-    fn deserialize(mut input: impl Read) -> std::io::Result<Self> {
-        let mut version_bytes = [0; 4];
-        input.read_exact(&mut version_bytes)?;
-        let version = u32::from_le_bytes(version_bytes);
+/// Keeps a copy of everything read through it.
+struct RecordingReader<R> {
+    inner: R,
+    read: Vec<u8>,
+}
+
+impl<R: Read> Read for RecordingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read.extend_from_slice(&buf[..read]);
+
+        Ok(read)
+    }
+}
 
+impl ArchiveHeader {
+    fn deserialize_v1(mut input: impl Read) -> crate::Result<Self> {
         let mut length_bytes = [0; 2];
         input.read_exact(&mut length_bytes)?;
         let length = u16::from_le_bytes(length_bytes) as usize;
 
         let mut compression = vec![0; length];
         input.read_exact(&mut compression)?;
-        let compression = String::from_utf8(compression).map_err(|_| {
-            std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Invalid UTF-8 in compression string",
-            )
-        })?;
+        let compression =
+            String::from_utf8(compression).map_err(|_| Error::InvalidUtf8("compression string"))?;
 
         let mut chunk_size_bytes = [0; 4];
         input.read_exact(&mut chunk_size_bytes)?;
         let compression_chunk_size = u32::from_le_bytes(chunk_size_bytes);
 
         Ok(ArchiveHeader {
-            version,
+            version: 1,
             compression,
             compression_chunk_size,
+            solid: false,
+            prefix_paths: false,
+            checksum: ChunkChecksum::None,
+            index: false,
+            comment: String::new(),
+            entry_count: 0,
+            total_uncompressed_size: 0,
         })
     }
+
+    /// Version 2 appends a flags byte.
+    fn deserialize_v2(mut input: impl Read) -> crate::Result<Self> {
+        let header = Self::deserialize_v1(&mut input)?;
+
+        let mut flags = [0; 1];
+        input.read_exact(&mut flags)?;
+
+        Ok(ArchiveHeader {
+            version: 2,
+            solid: flags[0] & ARCHIVE_FLAG_SOLID != 0,
+            ..header
+        })
+    }
+
+    /// Version 3 adds the prefix paths flag.
+    fn deserialize_v3(mut input: impl Read) -> crate::Result<Self> {
+        let mut header = Self::deserialize_v1(&mut input)?;
+
+        let mut flags = [0; 1];
+        input.read_exact(&mut flags)?;
+
+        header.version = 3;
+        header.solid = flags[0] & ARCHIVE_FLAG_SOLID != 0;
+        header.prefix_paths = flags[0] & ARCHIVE_FLAG_PREFIX_PATHS != 0;
+
+        Ok(header)
+    }
+
+    /// Version 4 adds the chunk checksums flag, version 5 only adds the magic
+    /// in front of the version and is otherwise identical.
+    fn deserialize_v4(mut input: impl Read) -> crate::Result<Self> {
+        let mut header = Self::deserialize_v1(&mut input)?;
+
+        let mut flags = [0; 1];
+        input.read_exact(&mut flags)?;
+
+        header.version = 4;
+        header.solid = flags[0] & ARCHIVE_FLAG_SOLID != 0;
+        header.prefix_paths = flags[0] & ARCHIVE_FLAG_PREFIX_PATHS != 0;
+        header.checksum = ChunkChecksum::from_flags(flags[0]);
+
+        Ok(header)
+    }
+
+    /// Version 6 adds the index flag, versions 7 and 8 only change the entry
+    /// headers and are otherwise identical. The XXH3 flag is only ever set
+    /// since version 11.
+    fn deserialize_v6(mut input: impl Read) -> crate::Result<Self> {
+        let mut header = Self::deserialize_v1(&mut input)?;
+
+        let mut flags = [0; 1];
+        input.read_exact(&mut flags)?;
+
+        header.version = 6;
+        header.solid = flags[0] & ARCHIVE_FLAG_SOLID != 0;
+        header.prefix_paths = flags[0] & ARCHIVE_FLAG_PREFIX_PATHS != 0;
+        header.checksum = ChunkChecksum::from_flags(flags[0]);
+        header.index = flags[0] & ARCHIVE_FLAG_INDEX != 0;
+
+        Ok(header)
+    }
+
+    /// Version 9 appends the comment, length-prefixed like the compression.
+    /// Version 10 only widens the chunk lengths and is otherwise identical.
+    ///
+    /// ```
+    /// use ataf::spec::{ArchiveHeader, ChunkChecksum, Deserialize, Serialize};
+    ///
+    /// let mut header = ArchiveHeader {
+    ///     version: 9,
+    ///     compression: String::from("zstd"),
+    ///     compression_chunk_size: 1024,
+    ///     solid: false,
+    ///     prefix_paths: false,
+    ///     checksum: ChunkChecksum::None,
+    ///     index: true,
+    ///     comment: String::new(),
+    ///     entry_count: 0,
+    ///     total_uncompressed_size: 0,
+    /// };
+    ///
+    /// for comment in ["", "build 1234 on ci-runner-7"] {
+    ///     header.comment = String::from(comment);
+    ///     let mut serialized = Vec::new();
+    ///     header.serialize(&mut serialized).unwrap();
+    ///
+    ///     let read = ArchiveHeader::deserialize(&serialized[..]).unwrap();
+    ///     assert_eq!(read.comment, comment);
+    ///     assert!(read.index);
+    /// }
+    ///
+    /// // older versions have no comment field
+    /// header.version = 1;
+    /// let mut serialized = Vec::new();
+    /// header.serialize(&mut serialized).unwrap();
+    /// assert_eq!(serialized.len(), 4 + 2 + 4 + 4);
+    ///
+    /// let read = ArchiveHeader::deserialize(&serialized[..]).unwrap();
+    /// assert_eq!((read.version, read.compression.as_str()), (1, "zstd"));
+    /// assert!(read.comment.is_empty());
+    ///
+    /// header.version = 9;
+    /// header.comment = "x".repeat(usize::from(u16::MAX) + 1);
+    /// assert!(header.serialize(&mut Vec::new()).is_err());
+    /// ```
+    fn deserialize_v9(mut input: impl Read) -> crate::Result<Self> {
+        let mut header = Self::deserialize_v6(&mut input)?;
+
+        let mut length_bytes = [0; 2];
+        input.read_exact(&mut length_bytes)?;
+        let length = u16::from_le_bytes(length_bytes) as usize;
+
+        let mut comment = vec![0; length];
+        input.read_exact(&mut comment)?;
+
+        header.version = 9;
+        header.comment =
+            String::from_utf8(comment).map_err(|_| Error::InvalidUtf8("comment string"))?;
+
+        Ok(header)
+    }
+
+    /// Version 11 appends the CRC-32 of every header byte before it, starting
+    /// with the magic, so a damaged compression or chunk size is caught before
+    /// any entry is read. Older headers have no checksum and aren't verified.
+    ///
+    /// ```
+    /// use ataf::spec::{ArchiveHeader, ChunkChecksum, Deserialize, Serialize};
+    ///
+    /// let header = ArchiveHeader {
+    ///     version: 11,
+    ///     compression: String::from("zstd"),
+    ///     compression_chunk_size: 1024,
+    ///     solid: false,
+    ///     prefix_paths: false,
+    ///     checksum: ChunkChecksum::Xxh3,
+    ///     index: true,
+    ///     comment: String::from("nightly"),
+    ///     entry_count: 0,
+    ///     total_uncompressed_size: 0,
+    /// };
+    /// let mut serialized = Vec::new();
+    /// header.serialize(&mut serialized).unwrap();
+    ///
+    /// let read = ArchiveHeader::deserialize(&serialized[..]).unwrap();
+    /// assert_eq!((read.version, read.compression.as_str()), (11, "zstd"));
+    /// assert_eq!(read.checksum, ChunkChecksum::Xxh3);
+    ///
+    /// // a flipped bit in the chunk size
+    /// serialized[14] ^= 0x04;
+    /// let err = ArchiveHeader::deserialize(&serialized[..]).unwrap_err();
+    /// assert!(matches!(err, ataf::Error::InvalidData(_)));
+    ///
+    /// let read = ArchiveHeader::deserialize_unverified(&serialized[..]).unwrap();
+    /// assert_eq!(read.compression_chunk_size, 1024 ^ 0x04);
+    ///
+    /// // the same header without its checksum reads as version 10, which only
+    /// // knows CRC-32 chunk checksums
+    /// let mut old = header.clone();
+    /// old.version = 10;
+    /// assert!(old.serialize(&mut Vec::new()).is_err());
+    /// old.checksum = ChunkChecksum::Crc32;
+    /// let mut serialized = Vec::new();
+    /// old.serialize(&mut serialized).unwrap();
+    /// assert_eq!(ArchiveHeader::deserialize(&serialized[..]).unwrap().version, 10);
+    /// ```
+    fn deserialize_v11(input: impl Read, verify: bool) -> crate::Result<Self> {
+        Self::deserialize_checksummed(input, 11, verify, |_, _| Ok(()))
+    }
+
+    /// Version 12 adds the entry count and the total uncompressed size after
+    /// the comment, as fixed size `u64`s.
+    ///
+    /// ```
+    /// use ataf::spec::{ARCHIVE_VERSION, ArchiveHeader, ChunkChecksum, Deserialize, Serialize};
+    ///
+    /// let mut header = ArchiveHeader {
+    ///     version: ARCHIVE_VERSION,
+    ///     compression: String::from("lz4"),
+    ///     compression_chunk_size: 1024,
+    ///     solid: false,
+    ///     prefix_paths: false,
+    ///     checksum: ChunkChecksum::Crc32,
+    ///     index: false,
+    ///     comment: String::new(),
+    ///     entry_count: 0,
+    ///     total_uncompressed_size: 0,
+    /// };
+    /// let mut unknown = Vec::new();
+    /// header.serialize(&mut unknown).unwrap();
+    ///
+    /// header.entry_count = 456;
+    /// header.total_uncompressed_size = 7 << 40;
+    /// let mut known = Vec::new();
+    /// header.serialize(&mut known).unwrap();
+    /// assert_eq!(known.len(), unknown.len());
+    ///
+    /// let read = ArchiveHeader::deserialize(&known[..]).unwrap();
+    /// assert_eq!((read.version, read.entry_count, read.total_uncompressed_size), (12, 456, 7 << 40));
+    ///
+    /// // version 11 headers have no totals
+    /// header.version = 11;
+    /// let mut serialized = Vec::new();
+    /// header.serialize(&mut serialized).unwrap();
+    /// assert_eq!(serialized.len(), known.len() - 16);
+    /// let read = ArchiveHeader::deserialize(&serialized[..]).unwrap();
+    /// assert_eq!((read.entry_count, read.total_uncompressed_size), (0, 0));
+    /// ```
+    fn deserialize_v12(input: impl Read, verify: bool) -> crate::Result<Self> {
+        Self::deserialize_checksummed(input, 12, verify, |input, header| {
+            let mut count_bytes = [0; 8];
+            input.read_exact(&mut count_bytes)?;
+            header.entry_count = u64::from_le_bytes(count_bytes);
+
+            input.read_exact(&mut count_bytes)?;
+            header.total_uncompressed_size = u64::from_le_bytes(count_bytes);
+
+            Ok(())
+        })
+    }
+
+    /// Reads the fields of version 9 and those `read_fields` adds after them,
+    /// followed by the checksum over everything before it since version 11.
+    fn deserialize_checksummed(
+        input: impl Read,
+        version: u32,
+        verify: bool,
+        read_fields: impl FnOnce(&mut dyn Read, &mut Self) -> crate::Result<()>,
+    ) -> crate::Result<Self> {
+        let mut recording = RecordingReader {
+            inner: input,
+            read: [ARCHIVE_MAGIC, version.to_le_bytes()].concat(),
+        };
+        let mut header = Self::deserialize_v9(&mut recording)?;
+        read_fields(&mut recording, &mut header)?;
+
+        let mut checksum_bytes = [0; 4];
+        recording.inner.read_exact(&mut checksum_bytes)?;
+        if verify && crate::crc32::crc32(&recording.read) != u32::from_le_bytes(checksum_bytes) {
+            return Err(Error::InvalidData(String::from(
+                "archive header checksum mismatch",
+            )));
+        }
+
+        Ok(ArchiveHeader { version, ..header })
+    }
+}
+
+impl ArchiveHeader {
+    /// Like `deserialize`, without verifying the checksum of version 11
+    /// headers. Only meant for salvaging archives whose header is damaged.
+    pub fn deserialize_unverified(input: impl Read) -> crate::Result<Self> {
+        Self::deserialize_checked(input, false)
+    }
+
+    fn deserialize_checked(mut input: impl Read, verify: bool) -> crate::Result<Self> {
+        let mut version_bytes = [0; 4];
+        input.read_exact(&mut version_bytes)?;
+
+        if version_bytes != ARCHIVE_MAGIC {
+            // archives before version 5 have no magic
+            return match u32::from_le_bytes(version_bytes) {
+                1 => Self::deserialize_v1(input),
+                2 => Self::deserialize_v2(input),
+                3 => Self::deserialize_v3(input),
+                4 => Self::deserialize_v4(input),
+                _ => Err(Error::BadMagic),
+            };
+        }
+
+        input.read_exact(&mut version_bytes)?;
+        match u32::from_le_bytes(version_bytes) {
+            5 => Ok(ArchiveHeader {
+                version: 5,
+                ..Self::deserialize_v4(input)?
+            }),
+            6 => Self::deserialize_v6(input),
+            version @ (7 | 8) => Ok(ArchiveHeader {
+                version,
+                ..Self::deserialize_v6(input)?
+            }),
+            9 => Self::deserialize_v9(input),
+            10 => Ok(ArchiveHeader {
+                version: 10,
+                ..Self::deserialize_v9(input)?
+            }),
+            11 => Self::deserialize_v11(input, verify),
+            12 => Self::deserialize_v12(input, verify),
+            version => Err(Error::UnsupportedVersion(version)),
+        }
+    }
+}
+
+impl Deserialize for ArchiveHeader {
+    fn deserialize(input: impl Read) -> crate::Result<Self> {
+        Self::deserialize_checked(input, true)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -213,21 +728,63 @@ pub enum ArchiveEntryHeaderType {
     Directory,
     SymlinkFile,
     SymlinkDirectory,
+    /// A file whose contents are identical to an earlier entry, the data is
+    /// the path of that entry.
+    Reference,
+    /// Another name for the file of an earlier entry, extracted as a hard link
+    /// to it. The data is the path of that entry.
+    HardLink,
+    /// A named pipe, without data.
+    Fifo,
+    /// A unix domain socket, without data. Only the file is recreated, nothing
+    /// listens on it.
+    Socket,
+    /// A block device node, the data is its device number (`st_rdev`) as
+    /// 8 little endian bytes.
+    BlockDevice,
+    /// A character device node, with the device number as data like
+    /// `BlockDevice`.
+    CharDevice,
+}
+
+impl ArchiveEntryHeaderType {
+    /// Whether entries of this type are FIFOs, sockets or device nodes.
+    ///
+    /// ```
+    /// use ataf::spec::ArchiveEntryHeaderType;
+    ///
+    /// assert!(ArchiveEntryHeaderType::Fifo.is_special());
+    /// assert!(!ArchiveEntryHeaderType::File.is_special());
+    /// ```
+    pub fn is_special(&self) -> bool {
+        matches!(
+            self,
+            Self::Fifo | Self::Socket | Self::BlockDevice | Self::CharDevice
+        )
+    }
 }
 
 impl Serialize for ArchiveEntryHeaderType {
-    fn serialize(&self, mut output: impl Write) -> std::io::Result<()> {
+    fn serialize(&self, mut output: impl Write) -> crate::Result<()> {
         output.write_all(&[match self {
             Self::File => 0,
             Self::Directory => 1,
             Self::SymlinkFile => 2,
             Self::SymlinkDirectory => 3,
-        }])
+            Self::Reference => 4,
+            Self::HardLink => 5,
+            Self::Fifo => 6,
+            Self::Socket => 7,
+            Self::BlockDevice => 8,
+            Self::CharDevice => 9,
+        }])?;
+
+        Ok(())
     }
 }
 
 impl Deserialize for ArchiveEntryHeaderType {
-    fn deserialize(mut input: impl Read) -> std::io::Result<Self> {
+    fn deserialize(mut input: impl Read) -> crate::Result<Self> {
         let mut byte = [0; 1];
         input.read_exact(&mut byte)?;
 
@@ -236,13 +793,95 @@ impl Deserialize for ArchiveEntryHeaderType {
             1 => Ok(Self::Directory),
             2 => Ok(Self::SymlinkFile),
             3 => Ok(Self::SymlinkDirectory),
-            byte => Err(std::io::Error::other(format!(
+            4 => Ok(Self::Reference),
+            5 => Ok(Self::HardLink),
+            6 => Ok(Self::Fifo),
+            7 => Ok(Self::Socket),
+            8 => Ok(Self::BlockDevice),
+            9 => Ok(Self::CharDevice),
+            byte => Err(Error::InvalidData(format!(
                 "invalid archive header type: {byte}"
             ))),
         }
     }
 }
 
+/// Written in place of an entry type after the last entry of archives with an
+/// index, so sequential readers stop there.
+pub const ENTRIES_END: u8 = 0xff;
+
+/// Ends the trailer of archives with an index, following the little endian
+/// offset of the index. The trailer is the last 16 bytes of the archive.
+pub const ARCHIVE_INDEX_MAGIC: [u8; 8] = *b"ATAFINDX";
+
+/// The size of the trailer at the very end of archives with an index.
+pub const ARCHIVE_INDEX_TRAILER_SIZE: u64 = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveIndexEntry {
+    pub r#type: ArchiveEntryHeaderType,
+    pub path: String,
+    pub size: u64,
+    /// Where the entry header starts, counted from the start of the archive.
+    pub offset: u64,
+}
+
+/// The table of contents written after the entries, in entry order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveIndex {
+    pub entries: Vec<ArchiveIndexEntry>,
+}
+
+impl Serialize for ArchiveIndex {
+    fn serialize(&self, mut output: impl Write) -> crate::Result<()> {
+        VariableSizedU64(self.entries.len() as u64).serialize(&mut output)?;
+
+        for entry in &self.entries {
+            if entry.path.len() as u64 > MAX_PATH_LENGTH {
+                return Err(Error::PathTooLong(entry.path.len() as u64));
+            }
+
+            entry.r#type.serialize(&mut output)?;
+            VariableSizedU64(entry.path.len() as u64).serialize(&mut output)?;
+            output.write_all(entry.path.as_bytes())?;
+            VariableSizedU64(entry.size).serialize(&mut output)?;
+            VariableSizedU64(entry.offset).serialize(&mut output)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Deserialize for ArchiveIndex {
+    fn deserialize(mut input: impl Read) -> crate::Result<Self> {
+        let count = VariableSizedU64::deserialize(&mut input)?.0;
+
+        // the count isn't trusted for preallocation, each entry takes 4 bytes
+        // at least so a corrupt one ends at the end of the input instead
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let r#type = ArchiveEntryHeaderType::deserialize(&mut input)?;
+
+            let length = VariableSizedU64::deserialize(&mut input)?.0;
+            if length > MAX_PATH_LENGTH {
+                return Err(Error::PathTooLong(length));
+            }
+            let mut path = vec![0; length as usize];
+            input.read_exact(&mut path)?;
+            let path = String::from_utf8(path).map_err(|_| Error::InvalidUtf8("index path"))?;
+
+            entries.push(ArchiveIndexEntry {
+                r#type,
+                path,
+                size: VariableSizedU64::deserialize(&mut input)?.0,
+                offset: VariableSizedU64::deserialize(&mut input)?.0,
+            });
+        }
+
+        Ok(Self { entries })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ArchiveEntryHeader {
     pub r#type: ArchiveEntryHeaderType,
@@ -253,39 +892,527 @@ pub struct ArchiveEntryHeader {
     pub uid: VariableSizedU32,
     pub gid: VariableSizedU32,
 
+    /// The names of the owning user and group, which are looked up on
+    /// extraction before falling back to the ids (since version 8).
+    pub uname: Option<String>,
+    pub gname: Option<String>,
+
     pub mtime: VariableSizedU64,
+    /// The sub-second part of `mtime`, below 1_000_000_000 (since version 7).
+    pub mtime_nanos: VariableSizedU32,
 
     pub size: VariableSizedU64,
+
+    /// Filesystem flags (such as immutable or append-only), stored in the
+    /// extension area when present.
+    pub flags: Option<u32>,
+
+    /// The compression format of this entry's chunks when it differs from the
+    /// archive's, symlink targets for example are always stored as `none`.
+    pub compression: Option<String>,
+
+    /// Marks files as executable independently of `mode`, for creators on
+    /// platforms whose modes cannot express it (Windows).
+    pub executable: bool,
+
+    /// The absolute path the entry was created from, for auditing only, it
+    /// does not affect where the entry is extracted to.
+    pub original_path: Option<String>,
+
+    /// For sparse files, where the data of the entry belongs in the file.
+    /// `size` then counts the stored data only, without the holes.
+    pub sparse: Option<SparseMap>,
+
+    /// The exact bytes of a path that isn't valid UTF-8, `path` then holds
+    /// its lossy conversion for display and matching.
+    pub raw_path: Option<Vec<u8>>,
 }
 
-impl Serialize for ArchiveEntryHeader {
-    fn serialize(&self, mut output: impl Write) -> std::io::Result<()> {
+/// A run of data in a sparse file, everything between runs is a hole.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SparseSegment {
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// The layout of a sparse file: its full size and the runs of data in it, in
+/// ascending order. The data of the entry is the runs one after another.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparseMap {
+    pub size: u64,
+    pub segments: Vec<SparseSegment>,
+}
+
+impl Serialize for SparseMap {
+    fn serialize(&self, mut output: impl Write) -> crate::Result<()> {
+        VariableSizedU64(self.size).serialize(&mut output)?;
+        VariableSizedU64(self.segments.len() as u64).serialize(&mut output)?;
+        for segment in &self.segments {
+            VariableSizedU64(segment.offset).serialize(&mut output)?;
+            VariableSizedU64(segment.length).serialize(&mut output)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Deserialize for SparseMap {
+    fn deserialize(mut input: impl Read) -> crate::Result<Self> {
+        let size = VariableSizedU64::deserialize(&mut input)?.0;
+        let count = VariableSizedU64::deserialize(&mut input)?.0;
+
+        let mut segments = Vec::new();
+        let mut end = 0;
+        for _ in 0..count {
+            let segment = SparseSegment {
+                offset: VariableSizedU64::deserialize(&mut input)?.0,
+                length: VariableSizedU64::deserialize(&mut input)?.0,
+            };
+
+            if segment.offset < end
+                || segment
+                    .offset
+                    .checked_add(segment.length)
+                    .is_none_or(|segment_end| segment_end > size)
+            {
+                return Err(Error::InvalidData(String::from(
+                    "sparse segments overlap or exceed the file size",
+                )));
+            }
+
+            end = segment.offset + segment.length;
+            segments.push(segment);
+        }
+
+        Ok(Self { size, segments })
+    }
+}
+
+/// The longest entry path that is written or read, comfortably above the
+/// limits of common platforms (Windows allows 32767 UTF-16 units). Longer
+/// declared lengths are rejected before anything is allocated for them.
+///
+/// ```
+/// use ataf::spec::{ArchiveEntryHeader, Deserialize};
+///
+/// // hand-crafted: a file entry declaring a path of 2^63 bytes
+/// let input: &[u8] = &[0, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01, b'a'];
+///
+/// let err = ArchiveEntryHeader::deserialize(input).unwrap_err();
+/// assert!(matches!(err, ataf::Error::PathTooLong(_)));
+/// ```
+pub const MAX_PATH_LENGTH: u64 = 64 * 1024;
+
+/// The longest user or group name that is read, far above what systems allow.
+const MAX_OWNER_NAME_LENGTH: u64 = 1024;
+
+const ENTRY_EXTENSION_FLAGS: u8 = 0;
+const ENTRY_EXTENSION_COMPRESSION: u8 = 1;
+const ENTRY_EXTENSION_EXECUTABLE: u8 = 2;
+const ENTRY_EXTENSION_ORIGINAL_PATH: u8 = 3;
+const ENTRY_EXTENSION_SPARSE: u8 = 4;
+const ENTRY_EXTENSION_RAW_PATH: u8 = 5;
+
+impl ArchiveEntryHeader {
+    fn serialize_extensions(&self, mut output: impl Write) -> crate::Result<()> {
+        let mut extensions: Vec<(u8, Vec<u8>)> = Vec::new();
+
+        if let Some(flags) = self.flags {
+            let mut data = Vec::new();
+            VariableSizedU32(flags).serialize(&mut data)?;
+            extensions.push((ENTRY_EXTENSION_FLAGS, data));
+        }
+
+        if let Some(compression) = &self.compression {
+            extensions.push((ENTRY_EXTENSION_COMPRESSION, compression.as_bytes().to_vec()));
+        }
+
+        if self.executable {
+            extensions.push((ENTRY_EXTENSION_EXECUTABLE, Vec::new()));
+        }
+
+        if let Some(original_path) = &self.original_path {
+            extensions.push((
+                ENTRY_EXTENSION_ORIGINAL_PATH,
+                original_path.as_bytes().to_vec(),
+            ));
+        }
+
+        if let Some(sparse) = &self.sparse {
+            let mut data = Vec::new();
+            sparse.serialize(&mut data)?;
+            extensions.push((ENTRY_EXTENSION_SPARSE, data));
+        }
+
+        if let Some(raw_path) = &self.raw_path {
+            extensions.push((ENTRY_EXTENSION_RAW_PATH, raw_path.clone()));
+        }
+
+        VariableSizedU32(extensions.len() as u32).serialize(&mut output)?;
+        for (tag, data) in extensions {
+            output.write_all(&[tag])?;
+            VariableSizedU64(data.len() as u64).serialize(&mut output)?;
+            output.write_all(&data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Extension data is read as it arrives, a corrupt length fails at the end
+    /// of the input instead of allocating what it declares.
+    ///
+    /// ```
+    /// use ataf::spec::{ARCHIVE_VERSION, ArchiveEntryHeader, Serialize};
+    ///
+    /// let header = ArchiveEntryHeader::file("a", 0);
+    /// let mut input = Vec::new();
+    /// header.serialize(&mut input).unwrap();
+    ///
+    /// // replaces the empty extension area with one declaring 2^62 bytes
+    /// input.pop();
+    /// input.extend([1, 3, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x40, b'x']);
+    ///
+    /// let err = ArchiveEntryHeader::deserialize_version(&input[..], ARCHIVE_VERSION).unwrap_err();
+    /// assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    /// ```
+    fn deserialize_extensions(&mut self, mut input: impl Read) -> crate::Result<()> {
+        let count = VariableSizedU32::deserialize(&mut input)?.0;
+
+        for _ in 0..count {
+            let mut tag = [0; 1];
+            input.read_exact(&mut tag)?;
+
+            let length = VariableSizedU64::deserialize(&mut input)?.0;
+            let mut data = Vec::new();
+            (&mut input).take(length).read_to_end(&mut data)?;
+            if (data.len() as u64) < length {
+                return Err(Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "entry header ended partway through an extension",
+                )));
+            }
+
+            match tag[0] {
+                ENTRY_EXTENSION_FLAGS => {
+                    self.flags = Some(VariableSizedU32::deserialize(&data[..])?.0);
+                }
+                ENTRY_EXTENSION_COMPRESSION => {
+                    self.compression = Some(
+                        String::from_utf8(data)
+                            .map_err(|_| Error::InvalidUtf8("entry compression string"))?,
+                    );
+                }
+                ENTRY_EXTENSION_EXECUTABLE => self.executable = true,
+                ENTRY_EXTENSION_ORIGINAL_PATH => {
+                    self.original_path = Some(
+                        String::from_utf8(data)
+                            .map_err(|_| Error::InvalidUtf8("original path string"))?,
+                    );
+                }
+                ENTRY_EXTENSION_SPARSE => self.sparse = Some(SparseMap::deserialize(&data[..])?),
+                ENTRY_EXTENSION_RAW_PATH => self.raw_path = Some(data),
+                // unknown extensions are skipped so newer writers stay readable
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A file entry of `size` bytes at `path`, with mode 0644, owned by root,
+    /// modified at the epoch and without any extensions. Other entries are
+    /// built from it with struct update syntax.
+    ///
+    /// ```
+    /// use ataf::spec::{ArchiveEntryHeader, ArchiveEntryHeaderType};
+    ///
+    /// let header = ArchiveEntryHeader::file("a.txt", 5);
+    /// assert_eq!((header.path.as_str(), *header.size, header.mode), ("a.txt", 5, 0o644));
+    ///
+    /// let directory = ArchiveEntryHeader {
+    ///     r#type: ArchiveEntryHeaderType::Directory,
+    ///     mode: 0o755,
+    ///     ..ArchiveEntryHeader::file("dir", 0)
+    /// };
+    /// assert_eq!(directory.r#type, ArchiveEntryHeaderType::Directory);
+    /// ```
+    pub fn file(path: impl Into<String>, size: u64) -> Self {
+        Self {
+            r#type: ArchiveEntryHeaderType::File,
+            path: path.into(),
+            mode: 0o644,
+            uid: VariableSizedU32::new(0),
+            gid: VariableSizedU32::new(0),
+            uname: None,
+            gname: None,
+            mtime: VariableSizedU64::new(0),
+            mtime_nanos: VariableSizedU32::new(0),
+            size: VariableSizedU64::new(size),
+            flags: None,
+            compression: None,
+            executable: false,
+            original_path: None,
+            sparse: None,
+            raw_path: None,
+        }
+    }
+
+    /// The modification time with its nanoseconds, as given to `set_modified`.
+    ///
+    /// ```
+    /// use ataf::compression::CompressionFormat;
+    /// use ataf::spec::{ArchiveEntryHeader, Deserialize, Serialize};
+    /// use ataf::spec::{VariableSizedU32, VariableSizedU64};
+    /// use std::time::{Duration, SystemTime};
+    ///
+    /// let header = ArchiveEntryHeader {
+    ///     mtime: VariableSizedU64::new(1_700_000_000),
+    ///     mtime_nanos: VariableSizedU32::new(123_456_789),
+    ///     ..ArchiveEntryHeader::file("a.txt", 0)
+    /// };
+    ///
+    /// let mut serialized = Vec::new();
+    /// header.serialize(&mut serialized).unwrap();
+    /// let header = ArchiveEntryHeader::deserialize(&serialized[..]).unwrap();
+    /// let modified = SystemTime::UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789);
+    /// assert_eq!(header.modified(), modified);
+    ///
+    /// // extracting keeps them too
+    /// use ataf::{
+    ///     archive::{extract::{ExtractAction, extract_filtered}, read::Archive},
+    ///     compression::NoDecompressor,
+    /// };
+    ///
+    /// let mut archive = Vec::new();
+    /// let data: Box<dyn std::io::Read> = Box::new(&b""[..]);
+    /// ataf::build_archive(&mut archive, CompressionFormat::None, [(header, data)]).unwrap();
+    ///
+    /// let output = std::env::temp_dir().join(format!("ataf-doctest-mtime-{}", std::process::id()));
+    /// let mut reader = Archive::new(&archive[..]);
+    /// let mut entries = reader.entries(Box::new(NoDecompressor)).unwrap();
+    /// extract_filtered(&mut entries, &output, |_| ExtractAction::Extract).unwrap();
+    /// let extracted = std::fs::metadata(output.join("a.txt")).unwrap().modified().unwrap();
+    /// assert_eq!(extracted, modified);
+    ///
+    /// std::fs::remove_dir_all(output).unwrap();
+    /// ```
+    pub fn modified(&self) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::new(self.mtime.0, self.mtime_nanos.0)
+    }
+
+    /// The path to extract the entry to, from `raw_path` on unix where any
+    /// bytes make a valid path, from `path` otherwise.
+    ///
+    /// ```
+    /// use ataf::spec::{ArchiveEntryHeader, Deserialize, Serialize};
+    ///
+    /// let name = b"caf\xe9.txt";
+    /// let header = ArchiveEntryHeader {
+    ///     raw_path: Some(name.to_vec()),
+    ///     ..ArchiveEntryHeader::file(String::from_utf8_lossy(name).into_owned(), 0)
+    /// };
+    ///
+    /// let mut serialized = Vec::new();
+    /// header.serialize(&mut serialized).unwrap();
+    /// let header = ArchiveEntryHeader::deserialize(&serialized[..]).unwrap();
+    /// assert_eq!(header.path, "caf\u{fffd}.txt");
+    /// assert_eq!(header.raw_path.as_deref(), Some(&name[..]));
+    ///
+    /// #[cfg(target_family = "unix")]
+    /// {
+    ///     use std::os::unix::ffi::OsStrExt;
+    ///
+    ///     assert_eq!(header.native_path().as_os_str().as_bytes(), name);
+    /// }
+    /// #[cfg(not(target_family = "unix"))]
+    /// assert_eq!(header.native_path(), std::path::Path::new("caf\u{fffd}.txt"));
+    /// ```
+    pub fn native_path(&self) -> PathBuf {
+        #[cfg(target_family = "unix")]
+        if let Some(raw_path) = &self.raw_path {
+            use std::os::unix::ffi::OsStrExt;
+
+            return PathBuf::from(std::ffi::OsStr::from_bytes(raw_path));
+        }
+
+        PathBuf::from(&self.path)
+    }
+
+    /// Reads an entry header as written by the given archive format version,
+    /// each version keeps its own parsing path.
+    ///
+    /// ```
+    /// use ataf::spec::{ArchiveEntryHeader, ArchiveEntryHeaderType, ArchiveHeader, Deserialize};
+    ///
+    /// // a version 1 archive header and entry header, as written by ataf 0.1
+    /// let archive: &[u8] = &[
+    ///     1, 0, 0, 0, 4, 0, b'n', b'o', b'n', b'e', 0xff, 0xff, 0, 0,
+    ///     0, 5, b'a', b'.', b't', b'x', b't', 0xa4, 0x81, 0, 0, 0xe8, 0x07, 0xe8, 0x07, 42, 3,
+    /// ];
+    ///
+    /// let mut input = archive;
+    /// let header = ArchiveHeader::deserialize(&mut input).unwrap();
+    /// assert_eq!((header.version, header.compression.as_str()), (1, "none"));
+    /// assert_eq!(header.compression_chunk_size, 65535);
+    ///
+    /// let entry = ArchiveEntryHeader::deserialize_version(&mut input, header.version).unwrap();
+    /// assert_eq!(entry.r#type, ArchiveEntryHeaderType::File);
+    /// assert_eq!((entry.path.as_str(), entry.mode), ("a.txt", 0o100644));
+    /// assert_eq!((*entry.uid, *entry.gid, *entry.mtime, *entry.size), (1000, 1000, 42, 3));
+    /// assert!(input.is_empty());
+    /// ```
+    ///
+    /// Owner names are only stored since version 8, older entries have none:
+    ///
+    /// ```
+    /// use ataf::spec::{ARCHIVE_VERSION, ArchiveEntryHeader, Serialize};
+    ///
+    /// // a version 7 file entry, with the nanoseconds and an empty extension area
+    /// let entry: &[u8] = &[0, 1, b'a', 0xa4, 0x81, 0, 0, 0xe8, 0x07, 0xe8, 0x07, 42, 0, 5, 0];
+    /// let mut header = ArchiveEntryHeader::deserialize_version(entry, 7).unwrap();
+    /// assert_eq!((*header.uid, *header.mtime_nanos), (1000, 5));
+    /// assert_eq!((header.uname.as_deref(), header.gname.as_deref()), (None, None));
+    ///
+    /// header.uname = Some(String::from("alice"));
+    /// header.gname = Some(String::from("staff"));
+    /// let mut serialized = Vec::new();
+    /// header.serialize(&mut serialized).unwrap();
+    ///
+    /// let header = ArchiveEntryHeader::deserialize_version(&serialized[..], ARCHIVE_VERSION).unwrap();
+    /// assert_eq!(header.uname.as_deref(), Some("alice"));
+    /// assert_eq!(header.gname.as_deref(), Some("staff"));
+    /// ```
+    pub fn deserialize_version(input: impl Read, version: u32) -> crate::Result<Self> {
+        Self::deserialize_version_after(input, version, None)
+    }
+
+    /// Like `deserialize_version`, for archives with front-coded paths where
+    /// `previous_path` is the path of the entry before (`None` otherwise).
+    pub fn deserialize_version_after(
+        input: impl Read,
+        version: u32,
+        previous_path: Option<&str>,
+    ) -> crate::Result<Self> {
+        match version {
+            1 => Self::deserialize_v1(input, previous_path),
+            2..=6 => Self::deserialize_v2(input, previous_path),
+            7 => Self::deserialize_v7(input, previous_path),
+            8..=12 => Self::deserialize_v8(input, previous_path),
+            version => Err(Error::UnsupportedVersion(version)),
+        }
+    }
+
+    /// Writes the header with its path front-coded against `previous_path`.
+    ///
+    /// ```
+    /// use ataf::spec::{ARCHIVE_VERSION, ArchiveEntryHeader, Serialize};
+    ///
+    /// let paths: Vec<String> = (0..3)
+    ///     .flat_map(|a| (0..3).map(move |b| format!("src/very/deep/tree/{a}/nested/{b}/file.rs")))
+    ///     .collect();
+    /// let headers: Vec<ArchiveEntryHeader> = paths
+    ///     .iter()
+    ///     .map(|path| ArchiveEntryHeader::file(path.clone(), 0))
+    ///     .collect();
+    ///
+    /// let (mut full, mut coded) = (Vec::new(), Vec::new());
+    /// let mut previous = "";
+    /// for header in &headers {
+    ///     header.serialize(&mut full).unwrap();
+    ///     header.serialize_after(&mut coded, previous).unwrap();
+    ///     previous = &header.path;
+    /// }
+    /// assert!(coded.len() < full.len());
+    ///
+    /// let mut input = &coded[..];
+    /// let mut previous = String::new();
+    /// for path in &paths {
+    ///     let header =
+    ///         ArchiveEntryHeader::deserialize_version_after(&mut input, ARCHIVE_VERSION, Some(&previous))
+    ///             .unwrap();
+    ///     assert_eq!(&header.path, path);
+    ///     previous = header.path;
+    /// }
+    /// ```
+    pub fn serialize_after(&self, output: impl Write, previous_path: &str) -> crate::Result<()> {
+        self.serialize_fields(output, Some(previous_path))
+    }
+
+    fn serialize_fields(
+        &self,
+        mut output: impl Write,
+        previous_path: Option<&str>,
+    ) -> crate::Result<()> {
+        if self.path.len() as u64 > MAX_PATH_LENGTH {
+            return Err(Error::PathTooLong(self.path.len() as u64));
+        }
+
         self.r#type.serialize(&mut output)?;
-        VariableSizedU64(self.path.len() as u64).serialize(&mut output)?;
-        output.write_all(self.path.as_bytes())?;
+
+        let prefix_length = match previous_path {
+            Some(previous_path) => {
+                let prefix_length = previous_path
+                    .bytes()
+                    .zip(self.path.bytes())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                VariableSizedU64(prefix_length as u64).serialize(&mut output)?;
+
+                prefix_length
+            }
+            None => 0,
+        };
+        let suffix = &self.path.as_bytes()[prefix_length..];
+        VariableSizedU64(suffix.len() as u64).serialize(&mut output)?;
+        output.write_all(suffix)?;
+
         output.write_all(&self.mode.to_le_bytes())?;
         self.uid.serialize(&mut output)?;
         self.gid.serialize(&mut output)?;
         self.mtime.serialize(&mut output)?;
         self.size.serialize(&mut output)?;
+        self.mtime_nanos.serialize(&mut output)?;
+        for name in [&self.uname, &self.gname] {
+            let name = name.as_deref().unwrap_or_default();
+            VariableSizedU64(name.len() as u64).serialize(&mut output)?;
+            output.write_all(name.as_bytes())?;
+        }
+        self.serialize_extensions(&mut output)?;
 
         Ok(())
     }
-}
 
-impl Deserialize for ArchiveEntryHeader {
-    fn deserialize(mut input: impl Read) -> std::io::Result<Self> {
+    /// Version 1 has no extension area.
+    fn deserialize_v1(mut input: impl Read, previous_path: Option<&str>) -> crate::Result<Self> {
         let r#type = ArchiveEntryHeaderType::deserialize(&mut input)?;
-        let path_length = VariableSizedU64::deserialize(&mut input)?.0;
 
-        let mut path_bytes = vec![0u8; path_length as usize];
-        input.read_exact(&mut path_bytes)?;
-        let path = String::from_utf8(path_bytes).map_err(|_| {
-            std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Invalid UTF-8 in path string",
-            )
-        })?;
+        let mut path_bytes = match previous_path {
+            Some(previous_path) => {
+                let prefix_length = VariableSizedU64::deserialize(&mut input)?.0 as usize;
+
+                previous_path
+                    .as_bytes()
+                    .get(..prefix_length)
+                    .ok_or_else(|| {
+                        Error::InvalidData(String::from(
+                            "path prefix is longer than the previous path",
+                        ))
+                    })?
+                    .to_vec()
+            }
+            None => Vec::new(),
+        };
+
+        let suffix_length = VariableSizedU64::deserialize(&mut input)?.0;
+        let prefix_length = path_bytes.len();
+        if suffix_length > MAX_PATH_LENGTH - prefix_length as u64 {
+            return Err(Error::PathTooLong(
+                (prefix_length as u64).saturating_add(suffix_length),
+            ));
+        }
+        path_bytes.resize(prefix_length + suffix_length as usize, 0);
+        input.read_exact(&mut path_bytes[prefix_length..])?;
+        let path = String::from_utf8(path_bytes).map_err(|_| Error::InvalidUtf8("path string"))?;
 
         let mut mode_bytes = [0u8; 4];
         input.read_exact(&mut mode_bytes)?;
@@ -303,8 +1430,154 @@ impl Deserialize for ArchiveEntryHeader {
             mode,
             uid,
             gid,
+            uname: None,
+            gname: None,
             mtime,
+            mtime_nanos: VariableSizedU32(0),
             size,
+            flags: None,
+            compression: None,
+            executable: false,
+            original_path: None,
+            sparse: None,
+            raw_path: None,
         })
     }
+
+    /// Version 2 appends the extension area.
+    fn deserialize_v2(mut input: impl Read, previous_path: Option<&str>) -> crate::Result<Self> {
+        let mut header = Self::deserialize_v1(&mut input, previous_path)?;
+        header.deserialize_extensions(&mut input)?;
+
+        Ok(header)
+    }
+
+    fn deserialize_mtime_nanos(&mut self, input: impl Read) -> crate::Result<()> {
+        self.mtime_nanos = VariableSizedU32::deserialize(input)?;
+        if self.mtime_nanos.0 >= 1_000_000_000 {
+            return Err(Error::InvalidData(format!(
+                "invalid modification time nanoseconds: {}",
+                self.mtime_nanos.0
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Reads a user or group name, where an empty one means none was stored.
+    fn deserialize_owner_name(
+        mut input: impl Read,
+        field: &'static str,
+    ) -> crate::Result<Option<String>> {
+        let length = VariableSizedU64::deserialize(&mut input)?.0;
+        if length > MAX_OWNER_NAME_LENGTH {
+            return Err(Error::InvalidData(format!(
+                "{field} of {length} bytes exceeds the maximum of {MAX_OWNER_NAME_LENGTH}"
+            )));
+        }
+
+        let mut name = vec![0; length as usize];
+        input.read_exact(&mut name)?;
+
+        match String::from_utf8(name) {
+            Ok(name) if name.is_empty() => Ok(None),
+            Ok(name) => Ok(Some(name)),
+            Err(_) => Err(Error::InvalidUtf8(field)),
+        }
+    }
+
+    /// Version 7 adds the nanoseconds of the modification time before the
+    /// extension area.
+    fn deserialize_v7(mut input: impl Read, previous_path: Option<&str>) -> crate::Result<Self> {
+        let mut header = Self::deserialize_v1(&mut input, previous_path)?;
+        header.deserialize_mtime_nanos(&mut input)?;
+        header.deserialize_extensions(&mut input)?;
+
+        Ok(header)
+    }
+
+    /// Version 8 adds the user and group names after the nanoseconds, versions
+    /// 9 to 12 only change the archive header.
+    fn deserialize_v8(mut input: impl Read, previous_path: Option<&str>) -> crate::Result<Self> {
+        let mut header = Self::deserialize_v1(&mut input, previous_path)?;
+        header.deserialize_mtime_nanos(&mut input)?;
+        header.uname = Self::deserialize_owner_name(&mut input, "user name")?;
+        header.gname = Self::deserialize_owner_name(&mut input, "group name")?;
+        header.deserialize_extensions(&mut input)?;
+
+        Ok(header)
+    }
+}
+
+impl Serialize for ArchiveEntryHeader {
+    fn serialize(&self, output: impl Write) -> crate::Result<()> {
+        self.serialize_fields(output, None)
+    }
+}
+
+impl Deserialize for ArchiveEntryHeader {
+    fn deserialize(input: impl Read) -> crate::Result<Self> {
+        Self::deserialize_version(input, ARCHIVE_VERSION)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        archive::read::Archive,
+        compression::NoDecompressor,
+        test_util::{TempDir, archive, entry, extract},
+    };
+
+    #[test]
+    fn entry_types_round_trip() {
+        for r#type in [
+            ArchiveEntryHeaderType::HardLink,
+            ArchiveEntryHeaderType::Fifo,
+            ArchiveEntryHeaderType::Socket,
+            ArchiveEntryHeaderType::BlockDevice,
+            ArchiveEntryHeaderType::CharDevice,
+        ] {
+            let mut serialized = Vec::new();
+            r#type.serialize(&mut serialized).unwrap();
+            assert_eq!(
+                ArchiveEntryHeaderType::deserialize(&serialized[..]).unwrap(),
+                r#type
+            );
+            assert_eq!(
+                r#type.is_special(),
+                r#type != ArchiveEntryHeaderType::HardLink
+            );
+        }
+    }
+
+    #[test]
+    fn hard_links_share_the_original() {
+        let archive = archive([
+            entry(ArchiveEntryHeaderType::File, "a.txt", "shared"),
+            entry(ArchiveEntryHeaderType::HardLink, "b.txt", "a.txt"),
+        ]);
+
+        let mut reader = Archive::new(&archive[..]);
+        let mut entries = reader.entries(Box::new(NoDecompressor)).unwrap();
+        entries.next_entry().unwrap().unwrap().skip().unwrap();
+        let link = entries.next_entry().unwrap().unwrap();
+        assert_eq!(link.header().r#type, ArchiveEntryHeaderType::HardLink);
+        assert_eq!(link.header().path, "b.txt");
+        drop(link);
+
+        let output = TempDir::new("link");
+        extract(&archive, &output).unwrap();
+        assert_eq!(std::fs::read(output.join("b.txt")).unwrap(), b"shared");
+
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            let a = std::fs::metadata(output.join("a.txt")).unwrap();
+            let b = std::fs::metadata(output.join("b.txt")).unwrap();
+            assert_eq!((a.ino(), a.nlink()), (b.ino(), 2));
+        }
+    }
 }
@@ -0,0 +1,223 @@
+use ataf::{
+    archive::sparse::SparseWriter,
+    spec::{ArchiveEntryHeader, ArchiveEntryHeaderType, SparseMap},
+};
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// Extracts entries to a directory while they are written to the archive, by
+/// copying each entry's data as the compressor reads it.
+///
+/// The archive always takes priority: when extracting an entry fails the error
+/// is reported and that entry is skipped in the directory, but the archive is
+/// still written completely. An error writing the archive ends the process as
+/// usual, leaving the directory with the entries written up to that point.
+pub struct Tee {
+    directory: PathBuf,
+}
+
+impl Tee {
+    pub fn new(directory: PathBuf) -> Self {
+        Self { directory }
+    }
+
+    /// Extracts the entry, returning the input to archive in its place.
+    pub fn entry(&self, entry: &ArchiveEntryHeader, mut input: Box<dyn Read>) -> Box<dyn Read> {
+        let destination = match crate::commands::entry_destination(
+            &self.directory,
+            entry.native_path(),
+        )
+        .and_then(|destination| {
+            ataf::archive::extract::check_destination(&self.directory, &destination)
+                .map(|()| destination)
+        }) {
+            Ok(destination) => destination,
+            Err(err) => {
+                report(&self.directory.join(&entry.path), &err);
+                return input;
+            }
+        };
+
+        match entry.r#type {
+            ArchiveEntryHeaderType::File => {
+                let mut reader = TeeReader {
+                    input,
+                    destination,
+                    #[cfg(target_family = "unix")]
+                    mode: entry.mode,
+                    modified: entry.modified(),
+                    sparse: entry.sparse.clone(),
+                    written: 0,
+                    file: None,
+                    failed: false,
+                };
+
+                // empty files are never read, so they are created right away
+                if *entry.size == 0 {
+                    reader.file();
+                }
+
+                return Box::new(reader);
+            }
+            ArchiveEntryHeaderType::Directory => {
+                if let Err(err) = std::fs::create_dir_all(&destination) {
+                    report(&destination, &err);
+                }
+
+                return input;
+            }
+            r#type if r#type.is_special() => {
+                let mut data = Vec::new();
+                let result = input.read_to_end(&mut data).and_then(|_| {
+                    ataf::archive::extract::create_special_file(&destination, entry, &data)
+                });
+                match result {
+                    Ok(()) => {}
+                    Err(err) if err.kind() == std::io::ErrorKind::Unsupported => {}
+                    Err(err) => report(&destination, &err),
+                }
+
+                return Box::new(std::io::Cursor::new(data));
+            }
+            _ => {}
+        }
+
+        // symlink targets and reference paths are tiny, so they are read up
+        // front and handed to the archive from memory
+        let mut data = String::new();
+        if let Err(err) = input.read_to_string(&mut data) {
+            report(&destination, &err);
+            return Box::new(std::io::Cursor::new(data.into_bytes()));
+        }
+
+        let result = match entry.r#type {
+            ArchiveEntryHeaderType::Reference => {
+                crate::commands::entry_destination(&self.directory, &data)
+                    .map_err(std::io::Error::from)
+                    .and_then(|source| std::fs::copy(source, &destination))
+                    .map(|_| ())
+            }
+            ArchiveEntryHeaderType::HardLink => {
+                crate::commands::entry_destination(&self.directory, &data)
+                    .map_err(std::io::Error::from)
+                    .and_then(|source| std::fs::hard_link(source, &destination))
+            }
+            _ if let Err(err) = ataf::archive::extract::check_symlink_target(
+                &self.directory,
+                &destination,
+                &data,
+            ) =>
+            {
+                Err(err.into())
+            }
+            #[cfg(target_family = "unix")]
+            _ => std::os::unix::fs::symlink(&data, &destination),
+            #[cfg(target_family = "windows")]
+            ArchiveEntryHeaderType::SymlinkDirectory => {
+                std::os::windows::fs::symlink_dir(&data, &destination)
+            }
+            #[cfg(target_family = "windows")]
+            _ => std::os::windows::fs::symlink_file(&data, &destination),
+        };
+        if let Err(err) = result {
+            report(&destination, &err);
+        }
+
+        Box::new(std::io::Cursor::new(data.into_bytes()))
+    }
+}
+
+fn report(destination: &Path, err: &dyn crate::report::Reportable) {
+    crate::report::error(
+        "tee",
+        Some(destination),
+        Some(err),
+        format_args!("failed to extract {}", destination.display()),
+    );
+}
+
+/// Writes everything read from `input` to `destination`, creating the file on
+/// the first read.
+struct TeeReader {
+    input: Box<dyn Read>,
+    destination: PathBuf,
+    #[cfg(target_family = "unix")]
+    mode: u32,
+    modified: SystemTime,
+    sparse: Option<SparseMap>,
+    /// The amount of data written so far, where sparse data goes depends on it.
+    written: u64,
+
+    file: Option<std::fs::File>,
+    failed: bool,
+}
+
+impl TeeReader {
+    fn file(&mut self) -> Option<&mut std::fs::File> {
+        if self.file.is_none() && !self.failed {
+            match std::fs::File::create(&self.destination) {
+                Ok(file) => {
+                    #[cfg(target_family = "unix")]
+                    {
+                        use std::os::unix::fs::PermissionsExt;
+
+                        file.set_permissions(std::fs::Permissions::from_mode(self.mode))
+                            .ok();
+                    }
+
+                    // leaves the holes between the data written later
+                    if let Some(sparse) = &self.sparse
+                        && let Err(err) = file.set_len(sparse.size)
+                    {
+                        report(&self.destination, &err);
+                        self.failed = true;
+                        return None;
+                    }
+
+                    self.file = Some(file);
+                }
+                Err(err) => {
+                    report(&self.destination, &err);
+                    self.failed = true;
+                }
+            }
+        }
+
+        self.file.as_mut()
+    }
+}
+
+impl Read for TeeReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.input.read(buf)?;
+
+        if read > 0
+            && self.file().is_some()
+            && let Some(mut file) = self.file.as_ref()
+            && let Err(err) = match &self.sparse {
+                Some(sparse) => {
+                    SparseWriter::new(file, sparse, self.written).write_all(&buf[..read])
+                }
+                None => file.write_all(&buf[..read]),
+            }
+        {
+            report(&self.destination, &err);
+            self.file = None;
+            self.failed = true;
+        }
+        self.written += read as u64;
+
+        Ok(read)
+    }
+}
+
+impl Drop for TeeReader {
+    fn drop(&mut self) {
+        if let Some(file) = &self.file {
+            file.set_modified(self.modified).ok();
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Helpers shared by the unit tests.
+
+use crate::{
+    archive::{
+        extract::{ExtractAction, extract_filtered},
+        read::Archive,
+        write::ArchiveStats,
+    },
+    compression::{CompressionFormat, NoDecompressor},
+    spec::{ArchiveEntryHeader, ArchiveEntryHeaderType},
+};
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+/// An entry of the given type with `data` as its data.
+pub fn entry(
+    r#type: ArchiveEntryHeaderType,
+    path: &str,
+    data: impl Into<Vec<u8>>,
+) -> (ArchiveEntryHeader, Box<dyn Read>) {
+    let data = data.into();
+    let header = ArchiveEntryHeader {
+        r#type,
+        ..ArchiveEntryHeader::file(path, data.len() as u64)
+    };
+
+    (header, Box::new(std::io::Cursor::new(data)))
+}
+
+/// An uncompressed archive of `entries`.
+pub fn archive(entries: impl IntoIterator<Item = (ArchiveEntryHeader, Box<dyn Read>)>) -> Vec<u8> {
+    let mut archive = Vec::new();
+    crate::build_archive(&mut archive, CompressionFormat::None, entries).unwrap();
+
+    archive
+}
+
+/// Extracts all of an uncompressed archive to `output`.
+pub fn extract(archive: &[u8], output: &Path) -> crate::Result<ArchiveStats> {
+    let mut archive = Archive::new(archive);
+    let mut entries = archive.entries(Box::new(NoDecompressor))?;
+
+    extract_filtered(&mut entries, output, |_| ExtractAction::Extract)
+}
+
+/// A directory below the temporary directory that is removed when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("ataf-test-{name}-{}", std::process::id()));
+        std::fs::remove_dir_all(&path).ok();
+
+        Self(path)
+    }
+}
+
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.0).ok();
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+/// Looks up user and group names and ids, remembering every answer since the
+/// entries of an archive usually share a few owners.
+#[derive(Default)]
+pub struct Accounts {
+    user_names: HashMap<u32, Option<String>>,
+    group_names: HashMap<u32, Option<String>>,
+    user_ids: HashMap<String, Option<u32>>,
+    group_ids: HashMap<String, Option<u32>>,
+}
+
+impl Accounts {
+    pub fn user_name(&mut self, uid: u32) -> Option<String> {
+        self.user_names
+            .entry(uid)
+            .or_insert_with(|| lookup_user_name(uid))
+            .clone()
+    }
+
+    pub fn group_name(&mut self, gid: u32) -> Option<String> {
+        self.group_names
+            .entry(gid)
+            .or_insert_with(|| lookup_group_name(gid))
+            .clone()
+    }
+
+    pub fn user_id(&mut self, name: &str) -> Option<u32> {
+        if let Some(uid) = self.user_ids.get(name) {
+            return *uid;
+        }
+
+        *self
+            .user_ids
+            .entry(name.to_string())
+            .or_insert_with(|| lookup_user_id(name))
+    }
+
+    pub fn group_id(&mut self, name: &str) -> Option<u32> {
+        if let Some(gid) = self.group_ids.get(name) {
+            return *gid;
+        }
+
+        *self
+            .group_ids
+            .entry(name.to_string())
+            .or_insert_with(|| lookup_group_id(name))
+    }
+}
+
+/// Calls one of the reentrant `getpw*_r` or `getgr*_r` functions, growing the
+/// buffer for the strings of the entry until they fit.
+#[cfg(target_family = "unix")]
+fn lookup<T, R>(
+    call: impl Fn(*mut T, *mut libc::c_char, libc::size_t, *mut *mut T) -> libc::c_int,
+    read: impl FnOnce(&T) -> Option<R>,
+) -> Option<R> {
+    let mut entry = std::mem::MaybeUninit::<T>::uninit();
+    let mut buffer: Vec<libc::c_char> = vec![0; 1024];
+
+    loop {
+        let mut result = std::ptr::null_mut();
+        match call(
+            entry.as_mut_ptr(),
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        ) {
+            0 if !result.is_null() => return read(unsafe { &*result }),
+            libc::ERANGE if buffer.len() < 1024 * 1024 => buffer.resize(buffer.len() * 2, 0),
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(target_family = "unix")]
+fn name(name: *const libc::c_char) -> Option<String> {
+    unsafe { std::ffi::CStr::from_ptr(name) }
+        .to_str()
+        .ok()
+        .map(String::from)
+}
+
+#[cfg(target_family = "unix")]
+fn lookup_user_name(uid: u32) -> Option<String> {
+    lookup(
+        |entry, buffer, length, result| unsafe {
+            libc::getpwuid_r(uid, entry, buffer, length, result)
+        },
+        |passwd: &libc::passwd| name(passwd.pw_name),
+    )
+}
+
+#[cfg(target_family = "unix")]
+fn lookup_group_name(gid: u32) -> Option<String> {
+    lookup(
+        |entry, buffer, length, result| unsafe {
+            libc::getgrgid_r(gid, entry, buffer, length, result)
+        },
+        |group: &libc::group| name(group.gr_name),
+    )
+}
+
+#[cfg(target_family = "unix")]
+fn lookup_user_id(user: &str) -> Option<u32> {
+    let user = std::ffi::CString::new(user).ok()?;
+
+    lookup(
+        |entry, buffer, length, result| unsafe {
+            libc::getpwnam_r(user.as_ptr(), entry, buffer, length, result)
+        },
+        |passwd: &libc::passwd| Some(passwd.pw_uid),
+    )
+}
+
+#[cfg(target_family = "unix")]
+fn lookup_group_id(group: &str) -> Option<u32> {
+    let group = std::ffi::CString::new(group).ok()?;
+
+    lookup(
+        |entry, buffer, length, result| unsafe {
+            libc::getgrnam_r(group.as_ptr(), entry, buffer, length, result)
+        },
+        |group: &libc::group| Some(group.gr_gid),
+    )
+}
+
+// ownership isn't archived on Windows, every id is 0
+
+#[cfg(target_family = "windows")]
+fn lookup_user_name(_uid: u32) -> Option<String> {
+    None
+}
+
+#[cfg(target_family = "windows")]
+fn lookup_group_name(_gid: u32) -> Option<String> {
+    None
+}
+
+#[cfg(target_family = "windows")]
+fn lookup_user_id(_user: &str) -> Option<u32> {
+    None
+}
+
+#[cfg(target_family = "windows")]
+fn lookup_group_id(_group: &str) -> Option<u32> {
+    None
+}